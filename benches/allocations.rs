@@ -0,0 +1,132 @@
+// what a message costs in allocations on its way to everyone in the room, the listeners' own
+// reads included. a message is shared by everyone it goes to and rendered into each writer's
+// scratch buffer, as plain text or json, so the count per round should stay the same however
+// many listeners there are rather than growing with them
+//
+//     cargo bench --bench allocations
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use common::{Fanout, Speak};
+use criterion::{
+    criterion_group, criterion_main,
+    measurement::{Measurement, ValueFormatter},
+    BenchmarkId, Criterion, Throughput,
+};
+use rustlang_chat_server::Config;
+
+// the system allocator, counting how often it is asked for memory
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// has criterion count allocations where it would otherwise time things
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = u64;
+    type Value = u64;
+
+    fn start(&self) -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: u64) -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &u64, v2: &u64) -> u64 {
+        v1 + v2
+    }
+
+    fn zero(&self) -> u64 {
+        0
+    }
+
+    fn to_f64(&self, value: &u64) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &Allocations
+    }
+}
+
+impl ValueFormatter for Allocations {
+    fn scale_values(&self, _typical: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    // per delivery rather than per second
+    fn scale_throughputs(
+        &self,
+        _typical: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        let per = match *throughput {
+            Throughput::Elements(n) | Throughput::Bytes(n) | Throughput::BytesDecimal(n) => n,
+        };
+        for value in values {
+            *value /= per.max(1) as f64;
+        }
+        "allocs/delivery"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+fn per_round(c: &mut Criterion<Allocations>) {
+    let runtime = common::runtime();
+    let mut group = c.benchmark_group("allocations per round");
+    for speak in [Speak::Plain, Speak::Json] {
+        for listeners in [1, 16, 64] {
+            let mut fanout =
+                runtime.block_on(Fanout::start(Config::default(), speak, 4, listeners));
+            group.throughput(Throughput::Elements(fanout.deliveries()));
+            let id = BenchmarkId::new(format!("{speak:?}").to_lowercase(), listeners);
+            group.bench_function(id, |b| {
+                b.iter_custom(|rounds| {
+                    let start = ALLOCATIONS.load(Ordering::Relaxed);
+                    runtime.block_on(fanout.rounds(rounds));
+                    ALLOCATIONS.load(Ordering::Relaxed) - start
+                })
+            });
+            runtime.block_on(fanout.shutdown());
+        }
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // the counts hardly vary, which the plots can't draw
+    config = Criterion::default().with_measurement(Allocations).without_plots();
+    targets = per_round
+}
+criterion_main!(benches);
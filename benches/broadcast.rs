@@ -0,0 +1,32 @@
+// throughput of the broadcaster: a few senders to a room full of listeners, timed from the
+// first message of a round until every listener has read all of them. the senders' connections
+// only hand their lines to the broadcaster task, which numbers and stores them and does the
+// fanout, so they contend on its queue rather than on the broadcast channel
+//
+//     cargo bench --bench broadcast
+mod common;
+
+use common::{Fanout, Speak};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustlang_chat_server::Config;
+
+fn fanout(c: &mut Criterion) {
+    let runtime = common::runtime();
+    let mut group = c.benchmark_group("broadcast");
+    for senders in [1, 4, 16] {
+        for listeners in [16, 64, 256] {
+            let start = Fanout::start(Config::default(), Speak::Plain, senders, listeners);
+            let mut fanout = runtime.block_on(start);
+            group.throughput(Throughput::Elements(fanout.deliveries()));
+            let id = BenchmarkId::new(format!("{senders} senders"), listeners);
+            group.bench_function(id, |b| {
+                b.iter_custom(|rounds| runtime.block_on(fanout.rounds(rounds)))
+            });
+            runtime.block_on(fanout.shutdown());
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
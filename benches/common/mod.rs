@@ -0,0 +1,244 @@
+// shared by the benchmarks: a server with a room full of listeners and a few senders, and
+// rounds of messages timed from the first one sent until every listener has read them all.
+// not every bench uses every helper
+#![allow(dead_code)]
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rustlang_chat_server::{ChatServer, Config, ShutdownHandle};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    runtime::Runtime,
+    sync::Notify,
+    task::JoinHandle,
+};
+
+// messages per round, split evenly between the senders. a round stays well inside a client's
+// queue, so what is measured is delivery and not how quickly the queues overflow
+pub const ROUND: usize = 32;
+
+// how the clients talk to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speak {
+    Plain,
+    Json,
+}
+
+// what every listener has read so far, and a wake up for whoever waits on it
+#[derive(Default)]
+struct Progress {
+    received: Vec<AtomicUsize>,
+    changed: Notify,
+}
+
+impl Progress {
+    fn slowest(&self) -> usize {
+        let counts = self.received.iter().map(|r| r.load(Ordering::Relaxed));
+        counts.min().unwrap_or(usize::MAX)
+    }
+}
+
+pub struct Fanout {
+    pub server: Arc<ChatServer>,
+    handle: ShutdownHandle,
+    running: JoinHandle<()>,
+    speak: Speak,
+    // what each sender writes in a round, made once so the bench itself allocates nothing
+    batch: Vec<u8>,
+    senders: Vec<OwnedWriteHalf>,
+    // the other clients' write halves, dropping one would close its connection
+    others: Vec<OwnedWriteHalf>,
+    progress: Arc<Progress>,
+    // everyone but the listeners only has its lines read and thrown away
+    draining: Vec<JoinHandle<()>>,
+    // chat lines each listener has been sent so far
+    sent: usize,
+}
+
+impl Fanout {
+    pub async fn start(
+        mut config: Config,
+        speak: Speak,
+        senders: usize,
+        listeners: usize,
+    ) -> Fanout {
+        config.listen = "127.0.0.1:0".to_string();
+        // every message is distinct anyway, but nothing here should be throttled
+        config.max_repeats = 0;
+        // the listeners pick chat out by the sender's name at the start of the line
+        config.room_tags = false;
+        let server = Arc::new(
+            ChatServer::bind(config)
+                .await
+                .expect("bind the bench server"),
+        );
+        let handle = server.shutdown_handle();
+        let running = {
+            let server = server.clone();
+            tokio::spawn(async move {
+                let _ = server.run().await;
+            })
+        };
+        let progress = Arc::new(Progress {
+            received: (0..listeners).map(|_| AtomicUsize::new(0)).collect(),
+            changed: Notify::new(),
+        });
+        let share = ROUND / senders.max(1);
+        let batch = (0..share)
+            .map(|n| match speak {
+                Speak::Plain => format!("message {n}\n"),
+                Speak::Json => format!("{{\"type\":\"message\",\"body\":\"message {n}\"}}\n"),
+            })
+            .collect::<String>()
+            .into_bytes();
+        let mut fanout = Fanout {
+            server,
+            handle,
+            running,
+            speak,
+            batch,
+            senders: Vec::new(),
+            others: Vec::new(),
+            progress,
+            draining: Vec::new(),
+            sent: 0,
+        };
+        for i in 0..listeners {
+            let mut reader = fanout.connect(&format!("listener{i}")).await;
+            let progress = fanout.progress.clone();
+            tokio::spawn(async move {
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    match reader.read_line(&mut line).await {
+                        Ok(n) if n > 0 => {}
+                        _ => break,
+                    }
+                    // anything but chat, like join notices, doesn't count
+                    if line.starts_with("sender") || line.contains(r#""from":"sender"#) {
+                        progress.received[i].fetch_add(1, Ordering::Relaxed);
+                        progress.changed.notify_waiters();
+                    }
+                }
+            });
+        }
+        for i in 0..senders {
+            let (reader, writer) = fanout.split(&format!("sender{i}")).await;
+            fanout.drain(reader);
+            fanout.senders.push(writer);
+        }
+        fanout
+    }
+
+    // a client that doesn't want to see anything from the name, it isn't counted
+    pub async fn ignoring(&mut self, name: &str, who: usize) {
+        let (mut reader, mut writer) = self.split(&format!("ignoring{who}")).await;
+        // commands are plain lines whatever the protocol
+        let command = format!("/ignore {name}\n");
+        writer.write_all(command.as_bytes()).await.unwrap();
+        let mut line = String::new();
+        while !line.contains("Ignoring") {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+        }
+        self.drain(reader);
+        self.others.push(writer);
+    }
+
+    // every sender sends its share of ROUND messages, as many rounds as asked, waiting for them all to
+    // be read before the next round. the time taken is what comes back
+    pub async fn rounds(&mut self, rounds: u64) -> Duration {
+        let start = Instant::now();
+        for _ in 0..rounds {
+            self.round().await;
+        }
+        start.elapsed()
+    }
+
+    pub async fn round(&mut self) {
+        for writer in &mut self.senders {
+            writer.write_all(&self.batch).await.unwrap();
+        }
+        self.sent += self.per_round();
+        loop {
+            let changed = self.progress.changed.notified();
+            if self.progress.slowest() >= self.sent {
+                break;
+            }
+            changed.await;
+        }
+    }
+
+    // what a round delivers, one chat line to each listener for every message sent
+    pub fn deliveries(&self) -> u64 {
+        (self.per_round() * self.progress.received.len()) as u64
+    }
+
+    // ROUND, unless the senders don't divide it
+    fn per_round(&self) -> usize {
+        self.senders.len() * (ROUND / self.senders.len().max(1))
+    }
+
+    pub async fn shutdown(self) {
+        self.handle.shutdown().await;
+        let _ = self.running.await;
+        for task in self.draining {
+            task.abort();
+        }
+    }
+
+    async fn split(&self, name: &str) -> (BufReader<OwnedReadHalf>, OwnedWriteHalf) {
+        let (reader, writer) = self.connect(name).await.into_inner().into_split();
+        (BufReader::new(reader), writer)
+    }
+
+    // connects and registers, the welcome has been read by the time it comes back
+    async fn connect(&self, name: &str) -> BufReader<TcpStream> {
+        let addr = self.server.local_addr().unwrap();
+        let mut reader = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let mut line = String::new();
+        // the name prompt
+        reader.read_line(&mut line).await.unwrap();
+        let (hello, welcome) = match self.speak {
+            Speak::Plain => (format!("{name}\n"), format!("Welcome, {name}!")),
+            Speak::Json => (
+                format!("{{\"type\":\"hello\",\"name\":\"{name}\"}}\n"),
+                r#""type":"welcome""#.to_string(),
+            ),
+        };
+        reader.get_mut().write_all(hello.as_bytes()).await.unwrap();
+        while !line.contains(&welcome) {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+        }
+        reader
+    }
+
+    fn drain(&mut self, mut reader: BufReader<OwnedReadHalf>) {
+        self.draining.push(tokio::spawn(async move {
+            let mut line = String::new();
+            loop {
+                line.clear();
+                match reader.read_line(&mut line).await {
+                    Ok(n) if n > 0 => {}
+                    _ => break,
+                }
+            }
+        }));
+    }
+}
+
+// the runtime the server and the clients run on, criterion itself is synchronous
+pub fn runtime() -> Runtime {
+    Runtime::new().expect("start a tokio runtime")
+}
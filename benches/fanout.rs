@@ -0,0 +1,43 @@
+// the two ways a chat message reaches a room: to every member, which each connection picks
+// out by itself, or to a list the broadcaster makes from the registry because somebody in the
+// room ignores the sender. one sender and a room full of listeners, with and without one more
+// member ignoring the sender
+//
+//     cargo bench --bench fanout
+mod common;
+
+use common::{Fanout, Speak};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustlang_chat_server::Config;
+
+fn paths(c: &mut Criterion) {
+    let runtime = common::runtime();
+    let mut group = c.benchmark_group("fanout");
+    // a round to a thousand listeners takes about a tenth of a second
+    group.sample_size(20);
+    for ignored in [false, true] {
+        for listeners in [64, 256, 1024] {
+            let mut fanout = runtime.block_on(async {
+                let mut fanout = Fanout::start(Config::default(), Speak::Plain, 1, listeners).await;
+                if ignored {
+                    fanout.ignoring("sender0", 0).await;
+                }
+                fanout
+            });
+            group.throughput(Throughput::Elements(fanout.deliveries()));
+            let path = if ignored {
+                "recipient list"
+            } else {
+                "whole room"
+            };
+            group.bench_function(BenchmarkId::new(path, listeners), |b| {
+                b.iter_custom(|rounds| runtime.block_on(fanout.rounds(rounds)))
+            });
+            runtime.block_on(fanout.shutdown());
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, paths);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+// the same rounds as the broadcast bench, once with every line written to its socket as soon
+// as it is ready and once batched. batching writes less often but does more work per line, so
+// which comes out ahead depends on what a write costs. examples/throughput.rs counts the
+// socket writes as well
+//
+//     cargo bench --bench write_policy
+mod common;
+
+use common::{Fanout, Speak};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustlang_chat_server::{Config, WritePolicy};
+
+fn policies(c: &mut Criterion) {
+    let runtime = common::runtime();
+    let mut group = c.benchmark_group("write policy");
+    for write_policy in [WritePolicy::Immediate, WritePolicy::Batched] {
+        for listeners in [16, 64, 256] {
+            let config = Config {
+                write_policy,
+                ..Config::default()
+            };
+            let mut fanout = runtime.block_on(Fanout::start(config, Speak::Plain, 4, listeners));
+            group.throughput(Throughput::Elements(fanout.deliveries()));
+            let id = BenchmarkId::new(write_policy.to_string(), listeners);
+            group.bench_function(id, |b| {
+                b.iter_custom(|rounds| runtime.block_on(fanout.rounds(rounds)))
+            });
+            runtime.block_on(fanout.shutdown());
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, policies);
+criterion_main!(benches);
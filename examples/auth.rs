@@ -0,0 +1,47 @@
+// a chat server with a hard coded list of accounts, to show what plugging in an Authenticator
+// looks like. a real one would ask a database or a directory instead
+//
+//     cargo run --example auth -- [listen address]
+//
+// then connect and send `alice wonderland` to get in as a user, `mod gavel` as a moderator or
+// `root hunter2` as an admin. any other name or secret is turned away. signing in again under
+// a name that is already connected throws the old connection out
+use rustlang_chat_server::{
+    AuthFuture, AuthResult, Authenticator, ChatServer, Config, Role, SessionPolicy,
+};
+
+const ACCOUNTS: [(&str, &str, Role); 3] = [
+    ("alice", "wonderland", Role::User),
+    ("mod", "gavel", Role::Moderator),
+    ("root", "hunter2", Role::Admin),
+];
+
+struct Accounts;
+
+impl Authenticator for Accounts {
+    fn authenticate<'a>(&'a self, name: &'a str, secret: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            match ACCOUNTS.iter().find(|(user, _, _)| *user == name) {
+                Some((_, password, role)) if *password == secret => AuthResult::Allow(*role),
+                _ => AuthResult::Deny("Unknown name or wrong password".to_string()),
+            }
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let config = Config {
+        listen: std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "localhost:8080".to_string()),
+        ..Config::default()
+    };
+    let server = ChatServer::bind(config).await.unwrap();
+    server.set_authenticator(Accounts);
+    server.set_session_policy(SessionPolicy::Replace);
+    println!("listening on {}", server.local_addr().unwrap());
+    if let Err(err) = server.run().await {
+        eprintln!("can't accept connections anymore: {err}");
+    }
+}
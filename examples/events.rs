@@ -0,0 +1,39 @@
+// runs a chat server and prints everything that happens on it, which is about the smallest
+// bridge that can be built on ChatServer::events
+//
+//     cargo run --example events -- [listen address]
+use rustlang_chat_server::{ChatServer, Config, ServerEvent};
+
+#[tokio::main]
+async fn main() {
+    let config = Config {
+        listen: std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "localhost:8080".to_string()),
+        ..Config::default()
+    };
+    let server = ChatServer::bind(config).await.unwrap();
+    println!("listening on {}", server.local_addr().unwrap());
+
+    let mut events = server.events();
+    tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            match event {
+                ServerEvent::Connected { id, name, addr } => {
+                    println!("{name} connected from {addr} as client {id}")
+                }
+                ServerEvent::Disconnected { name, .. } => println!("{name} disconnected"),
+                ServerEvent::Join { name, room, .. } => println!("{name} joined {room}"),
+                ServerEvent::Leave { name, room, .. } => println!("{name} left {room}"),
+                ServerEvent::Message {
+                    seq,
+                    room,
+                    from,
+                    text,
+                } => println!("{room} #{seq} {from}: {text}"),
+            }
+        }
+    });
+
+    server.run().await.unwrap();
+}
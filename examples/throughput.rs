@@ -0,0 +1,176 @@
+// a rough throughput check for the broadcast path: a few clients send as fast as they can to
+// a room full of listeners, and we time how long it takes everything to be delivered. senders
+// keep at most WINDOW messages ahead of the slowest listener, otherwise we'd only be measuring
+// how quickly the per client queues overflow. the write policy is immediate unless the fourth
+// argument says batched, running it both ways shows what batching the writes buys: the
+// socket writes are counted along with the deliveries. so are allocations, the listeners'
+// own included: a message is shared by everyone it goes to and rendered into each writer's
+// scratch buffer, so the count per message stays the same however many listeners there are
+//
+//     cargo run --release --example throughput -- [senders] [messages per sender] [listeners] [policy]
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rustlang_chat_server::{ChatServer, Config, WritePolicy};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+
+const WINDOW: usize = 32;
+
+// the system allocator, counting how often it is asked for memory
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn arg(index: usize, default: usize) -> usize {
+    std::env::args()
+        .nth(index)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(default)
+}
+
+async fn connect(addr: &str, name: &str) -> (BufReader<TcpStream>, String) {
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut reader = BufReader::new(socket);
+    let mut line = String::new();
+    // the name prompt, then the welcome
+    reader.read_line(&mut line).await.unwrap();
+    reader
+        .get_mut()
+        .write_all(format!("{name}\n").as_bytes())
+        .await
+        .unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    (reader, line)
+}
+
+#[tokio::main]
+async fn main() {
+    let (senders, per_sender, listeners) = (arg(1, 4), arg(2, 250), arg(3, 16));
+    let write_policy: WritePolicy = std::env::args()
+        .nth(4)
+        .map_or(Ok(WritePolicy::Immediate), |policy| policy.parse())
+        .unwrap();
+    let config = Config {
+        listen: "127.0.0.1:0".to_string(),
+        // every message is distinct anyway, but nothing here should be throttled
+        max_repeats: 0,
+        // the listeners pick chat out by the sender's name at the start of the line
+        room_tags: false,
+        write_policy,
+        ..Config::default()
+    };
+    let server = Arc::new(ChatServer::bind(config).await.unwrap());
+    let addr = server.local_addr().unwrap().to_string();
+    let handle = server.shutdown_handle();
+    let running = {
+        let server = server.clone();
+        tokio::spawn(async move { server.run().await })
+    };
+
+    let expected = senders * per_sender;
+    let sent = Arc::new(AtomicUsize::new(0));
+    let received: Arc<Vec<AtomicUsize>> =
+        Arc::new((0..listeners).map(|_| AtomicUsize::new(0)).collect());
+    let mut receiving = Vec::new();
+    for i in 0..listeners {
+        let (mut reader, mut line) = connect(&addr, &format!("listener{i}")).await;
+        let progress = received.clone();
+        receiving.push(tokio::spawn(async move {
+            let mut received = 0;
+            // anything but chat, like join notices, doesn't count
+            while received < expected {
+                line.clear();
+                match timeout(Duration::from_secs(2), reader.read_line(&mut line)).await {
+                    Ok(Ok(n)) if n > 0 => received += usize::from(line.starts_with("sender")),
+                    _ => break,
+                }
+                progress[i].store(received, Ordering::Relaxed);
+            }
+            received
+        }));
+    }
+    let mut sending = Vec::new();
+    for i in 0..senders {
+        let (reader, _) = connect(&addr, &format!("sender{i}")).await;
+        sending.push(reader);
+    }
+
+    // the welcomes and join notices so far aren't part of the run
+    sleep(Duration::from_millis(100)).await;
+    let writes_before = server.stats().writes_total;
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut tasks = Vec::new();
+    for mut reader in sending {
+        let (sent, received) = (sent.clone(), received.clone());
+        tasks.push(tokio::spawn(async move {
+            for n in 0..per_sender {
+                loop {
+                    let slowest = received.iter().map(|r| r.load(Ordering::Relaxed)).min();
+                    if sent.load(Ordering::Relaxed) < slowest.unwrap_or(0) + WINDOW {
+                        break;
+                    }
+                    sleep(Duration::from_micros(50)).await;
+                }
+                sent.fetch_add(1, Ordering::Relaxed);
+                let line = format!("message {n}\n");
+                reader.get_mut().write_all(line.as_bytes()).await.unwrap();
+            }
+            reader
+        }));
+    }
+    // the senders stay connected until the end, closing a socket with unread data resets it
+    // and the server would lose whatever it hadn't read yet
+    let mut senders_done = Vec::new();
+    for task in tasks {
+        senders_done.push(task.await.unwrap());
+    }
+    let mut delivered = 0;
+    for task in receiving {
+        delivered += task.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    let writes = server.stats().writes_total - writes_before;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+
+    println!(
+        "{senders} senders x {per_sender} messages to {listeners} listeners, {write_policy} writes: {delivered} of {} delivered in {:.2?}, {:.0} deliveries/s, {writes} socket writes ({:.2} per delivery), {allocations} allocations ({:.1} per message)",
+        expected * listeners,
+        elapsed,
+        delivered as f64 / elapsed.as_secs_f64(),
+        writes as f64 / delivered.max(1) as f64,
+        allocations as f64 / expected.max(1) as f64
+    );
+    handle.shutdown().await;
+    let _ = running.await;
+}
@@ -0,0 +1,75 @@
+// systemd socket activation: when started by a .socket unit the listening sockets are already
+// open as file descriptors 3 and up, described by LISTEN_PID, LISTEN_FDS and LISTEN_FDNAMES.
+// sockets named chat, admin, http, metrics or tls (FileDescriptorName= in the unit) go to that
+// listener, unnamed ones are handed out in that order
+use std::{collections::HashMap, io, net::TcpListener};
+
+#[cfg(unix)]
+const LISTENERS: [&str; 5] = ["chat", "admin", "http", "metrics", "tls"];
+
+// the inherited listeners by which one they are for, empty when not socket activated. only the
+// first call gets them, a second server bound in the same process listens on its own
+#[cfg(unix)]
+pub fn listeners() -> io::Result<HashMap<&'static str, TcpListener>> {
+    use std::sync::{Mutex, OnceLock};
+
+    static INHERITED: OnceLock<Mutex<HashMap<&'static str, TcpListener>>> = OnceLock::new();
+
+    let inherited = INHERITED.get_or_init(|| Mutex::new(adopt()));
+    let listeners = std::mem::take(&mut *inherited.lock().unwrap_or_else(|err| err.into_inner()));
+    for listener in listeners.values() {
+        listener.set_nonblocking(true)?;
+    }
+    Ok(listeners)
+}
+
+// wraps the descriptors systemd passed, which has to happen at most once per process: two
+// TcpListeners on one descriptor would close it twice
+#[cfg(unix)]
+fn adopt() -> HashMap<&'static str, TcpListener> {
+    use std::{env, os::unix::io::FromRawFd};
+
+    // the first inherited descriptor, SD_LISTEN_FDS_START in sd-daemon
+    const FIRST_FD: i32 = 3;
+
+    let mut listeners = HashMap::new();
+    // the variables may have been inherited from a parent that was itself activated
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .unwrap_or(0);
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    // like sd_listen_fds(1): the descriptors are ours now, and nothing started from this
+    // process should think they are its own
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    if !for_us || count <= 0 {
+        return listeners;
+    }
+    let mut names = names.split(':');
+    let mut unnamed = LISTENERS.iter();
+    for fd in FIRST_FD..FIRST_FD + count {
+        let name = names.next().unwrap_or_default();
+        let which = match LISTENERS.iter().find(|known| **known == name) {
+            Some(known) => known,
+            None => match unnamed.find(|known| !listeners.contains_key(**known)) {
+                Some(known) => known,
+                None => continue,
+            },
+        };
+        // systemd hands each descriptor over once, and adopt only ever runs once
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        listeners.insert(*which, listener);
+    }
+    listeners
+}
+
+#[cfg(not(unix))]
+pub fn listeners() -> io::Result<HashMap<&'static str, TcpListener>> {
+    Ok(HashMap::new())
+}
@@ -0,0 +1,87 @@
+// the admin query protocol, only spoken on connections that came in through the admin listener.
+// it is deliberately separate from chat commands: queries are bare upper case words and every
+// reply is a single line of json so scripts and dashboards can consume it without scraping text
+use std::time::Instant;
+
+use crate::{auth::Role, errors::quoted, json::Value, state::Shared};
+
+// returns None when the line is not an admin query so it can be handled as chat
+pub fn dispatch(shared: &Shared, line: &str) -> Option<Value> {
+    let mut words = line.split_whitespace();
+    let reply = match (words.next()?, words.next(), words.next()) {
+        ("STATS", None, _) => stats(shared),
+        ("LIST", Some("CLIENTS"), None) => list_clients(shared),
+        ("LIST", Some("ROOMS"), None) => list_rooms(shared),
+        ("STATS" | "LIST", _, _) => error(&format!("unknown query: {}", quoted(line.trim()))),
+        _ => return None,
+    };
+    Some(reply)
+}
+
+fn error(text: &str) -> Value {
+    Value::object([("error", text.into())])
+}
+
+fn stats(shared: &Shared) -> Value {
+    let stats = shared.snapshot();
+    Value::object([
+        ("connections", stats.active_connections.into()),
+        ("connections_total", stats.connections_total.into()),
+        ("messages_total", stats.messages_total.into()),
+        ("bytes_total", stats.bytes_total.into()),
+        ("lag_events", stats.lag_events.into()),
+        ("writes_total", stats.writes_total.into()),
+        ("overloaded", stats.overloaded.into()),
+        ("rooms", stats.rooms.into()),
+        ("uptime_secs", stats.uptime.as_secs().into()),
+    ])
+}
+
+fn list_clients(shared: &Shared) -> Value {
+    let registry = shared.registry();
+    let mut clients: Vec<_> = registry.clients.iter().collect();
+    clients.sort_by_key(|(id, _)| **id);
+    let clients = clients
+        .into_iter()
+        .map(|(id, client)| {
+            let mut rooms: Vec<&str> = client.rooms.iter().map(String::as_str).collect();
+            rooms.sort_unstable();
+            let rooms: Vec<Value> = rooms.into_iter().map(Value::from).collect();
+            Value::object([
+                ("id", (*id).into()),
+                ("name", client.name.as_str().into()),
+                ("addr", client.addr.to_string().into()),
+                ("transport", client.transport.as_str().into()),
+                ("room", client.room.as_str().into()),
+                ("rooms", rooms.into()),
+                ("admin", (client.role == Role::Admin).into()),
+                ("role", client.role.as_str().into()),
+                (
+                    "connected_secs",
+                    client.connected_at.elapsed().as_secs().into(),
+                ),
+            ])
+        })
+        .collect::<Vec<_>>();
+    Value::object([("clients", clients.into())])
+}
+
+fn list_rooms(shared: &Shared) -> Value {
+    let registry = shared.registry();
+    let now = Instant::now();
+    let mut rooms: Vec<_> = registry.rooms.iter().collect();
+    rooms.sort_by_key(|(name, _)| name.as_str());
+    let rooms = rooms
+        .into_iter()
+        .map(|(name, room)| {
+            let slow_mode = room.slow_mode.unwrap_or_default().as_secs();
+            Value::object([
+                ("name", name.as_str().into()),
+                ("members", room.members.into()),
+                ("rate", room.rate(now, shared.config.flood_window).into()),
+                ("slow_mode_secs", slow_mode.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    Value::object([("rooms", rooms.into())])
+}
@@ -0,0 +1,112 @@
+// lets an embedding program decide who gets in, checked once a client has picked a name and
+// before it is registered. without one set everybody gets in as a plain user, like always.
+// a resume token is proof enough on its own, resuming never asks again
+use std::{future::Future, pin::Pin, str::FromStr};
+
+// each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    User,
+    // an operator of every room
+    Moderator,
+    // the same rights as a connection from the admin listener, which always gets this
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!(
+                "unknown role {s}, expected user, moderator or admin"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthResult {
+    Allow(Role),
+    // the text is passed on to the client, which can try again
+    Deny(String),
+}
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = AuthResult> + Send + 'a>>;
+
+// what happens when someone gets in under a name that is already connected. names are unique,
+// so only one session per name is ever live
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionPolicy {
+    // the newcomer is told the name is taken, same as without an authenticator
+    #[default]
+    Reject,
+    // the old session is disconnected and the newcomer gets the name. only worth it with an
+    // authenticator, otherwise anyone could throw anyone else out
+    Replace,
+}
+
+// the secret is whatever the client sent after its name, empty when it sent nothing. a plain
+// text client sends `name secret`, a json one a "secret" field in its hello
+pub trait Authenticator: Send + Sync {
+    fn authenticate<'a>(&'a self, name: &'a str, secret: &'a str) -> AuthFuture<'a>;
+}
+
+// lets everyone in as a user, whatever they send
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoAuth;
+
+impl Authenticator for NoAuth {
+    fn authenticate<'a>(&'a self, _name: &'a str, _secret: &'a str) -> AuthFuture<'a> {
+        Box::pin(async { AuthResult::Allow(Role::User) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_parse_from_their_names() {
+        for role in [Role::User, Role::Moderator, Role::Admin] {
+            assert_eq!(role.as_str().parse(), Ok(role));
+        }
+        assert_eq!(
+            "Admin".parse::<Role>(),
+            Err("unknown role Admin, expected user, moderator or admin".to_string())
+        );
+        assert!("".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn each_role_outranks_the_ones_before_it() {
+        assert!(Role::User < Role::Moderator);
+        assert!(Role::Moderator < Role::Admin);
+        assert_eq!(Role::User.max(Role::Admin), Role::Admin);
+    }
+
+    #[tokio::test]
+    async fn no_auth_lets_everyone_in_as_a_user() {
+        assert_eq!(
+            NoAuth.authenticate("alice", "").await,
+            AuthResult::Allow(Role::User)
+        );
+        assert_eq!(
+            NoAuth.authenticate("admin", "whatever").await,
+            AuthResult::Allow(Role::User)
+        );
+    }
+}
@@ -0,0 +1,125 @@
+// address ranges for --allow, written as 10.0.0.0/8 or fd00::/8. a bare address is a range
+// of one. the matching is ipnet's, this adds bare addresses, mapped ipv4 clients and errors
+// that say what was wrong
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use ipnet::IpNet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr(IpNet);
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // a dual stack listener reports ipv4 clients as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        self.0.contains(&ip)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let (addr, prefix) = s.split_once('/').map_or((s, None), |(a, p)| (a, Some(p)));
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("not an ip address: {addr}"))?;
+        let Some(prefix) = prefix else {
+            return Ok(Cidr(IpNet::from(addr)));
+        };
+        prefix
+            .parse()
+            .ok()
+            .and_then(|prefix| IpNet::new(addr, prefix).ok())
+            .map(Cidr)
+            .ok_or_else(|| {
+                let max = if addr.is_ipv4() { 32 } else { 128 };
+                format!("bad prefix length in {s}, expected 0 to {max}")
+            })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_ranges_and_bare_addresses() {
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+        assert_eq!(cidr("192.168.1.7").to_string(), "192.168.1.7/32");
+        assert_eq!(cidr("fd00::/8").to_string(), "fd00::/8");
+        assert_eq!(cidr("::1").to_string(), "::1/128");
+    }
+
+    #[test]
+    fn turns_down_what_isnt_a_range() {
+        assert_eq!(
+            "10.0.0/8".parse::<Cidr>(),
+            Err("not an ip address: 10.0.0".to_string())
+        );
+        assert_eq!(
+            "10.0.0.0/33".parse::<Cidr>(),
+            Err("bad prefix length in 10.0.0.0/33, expected 0 to 32".to_string())
+        );
+        assert_eq!(
+            "fd00::/129".parse::<Cidr>(),
+            Err("bad prefix length in fd00::/129, expected 0 to 128".to_string())
+        );
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/-1".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn matches_only_within_the_prefix() {
+        let range = cidr("10.1.0.0/16");
+        assert!(range.contains(ip("10.1.0.0")));
+        assert!(range.contains(ip("10.1.255.255")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        // host bits in the range itself don't matter
+        assert!(cidr("10.1.2.3/16").contains(ip("10.1.200.1")));
+        let single = cidr("127.0.0.1");
+        assert!(single.contains(ip("127.0.0.1")));
+        assert!(!single.contains(ip("127.0.0.2")));
+    }
+
+    #[test]
+    fn zero_and_full_prefixes() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn ipv6_ranges() {
+        let range = cidr("fd00::/8");
+        assert!(range.contains(ip("fd12:3456::1")));
+        assert!(!range.contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn mapped_ipv4_clients_match_ipv4_ranges() {
+        assert!(cidr("127.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+        // and never an ipv6 range
+        assert!(!cidr("::/0").contains(ip("::ffff:127.0.0.1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("::1")));
+    }
+}
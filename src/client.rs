@@ -0,0 +1,49 @@
+// a bare bones line based client, started with --connect, so the server can be used without telnet
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+// runs until either side is done and returns the process exit code.
+// closing stdin is a normal way to leave, losing the server is not
+pub async fn run(addr: &str) -> i32 {
+    let socket = match TcpStream::connect(addr).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("could not connect to {addr}: {err}");
+            return 1;
+        }
+    };
+    let (reader, mut writer) = socket.into_split();
+    let mut server_lines = BufReader::new(reader).lines();
+    let mut stdin_lines = BufReader::new(io::stdin()).lines();
+    let mut stdout = io::stdout();
+
+    loop {
+        tokio::select! {
+            line = stdin_lines.next_line() => {
+                let Ok(Some(mut line)) = line else {
+                    // stdin hit eof, let the server see a clean close before leaving
+                    let _ = writer.shutdown().await;
+                    return 0;
+                };
+                line.push('\n');
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    eprintln!("connection closed by server");
+                    return 1;
+                }
+            }
+            line = server_lines.next_line() => {
+                let Ok(Some(mut line)) = line else {
+                    eprintln!("connection closed by server");
+                    return 1;
+                };
+                line.push('\n');
+                if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                    // nobody is reading our output anymore, so there is no point carrying on
+                    return 0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1279 @@
+// chat commands, any line starting with a slash is treated as one instead of being broadcast
+use std::{borrow::Cow, collections::HashMap, fmt::Write, sync::Arc, time::Duration};
+
+use crate::{
+    auth::Role,
+    connection::{post_error, Session},
+    errors::{quoted, ChatError, ErrorCode},
+    outbound::Outgoing,
+    protocol::Protocol,
+    room::{self, MAX_HISTORY, MAX_PINS},
+    state::{
+        validate_room_name, DeleteRoomError, Event, JoinError, KickError, LeaveError, OpError,
+        Presence, RenameRoomError, Shared, RECENT_DISCONNECTS,
+    },
+    upload,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Join(String),
+    // None leaves the current room
+    Leave(Option<String>),
+    Switch(String),
+    // pages count from 1
+    Rooms(usize),
+    DelRoom(String),
+    RenameRoom(String, String),
+    Msg {
+        to: String,
+        text: String,
+    },
+    Dnd(bool),
+    Echo(bool),
+    // minutes east of utc, None turns the time off
+    Tz(Option<i32>),
+    SlowMode(u64),
+    QuietJoins(bool),
+    // true for /giveop, false for /deop
+    SetOp(String, bool),
+    Share(String),
+    // None for the current room
+    Export(Option<String>),
+    // None shows the topic
+    Topic(Option<String>),
+    TopicLog,
+    ClearHistory,
+    HistLimit(usize),
+    Pin(u64),
+    Unpin(u64),
+    Pins,
+    Ignore(String),
+    Unignore(String),
+    Ignores,
+    // true for /ban, which keeps the client's address out as well
+    Kick {
+        name: String,
+        reason: Option<String>,
+        ban: bool,
+    },
+    Announce(String),
+    QuitAll(Option<String>),
+    // None lists the client's aliases, no expansion removes one
+    Alias(Option<(String, Option<String>)>),
+    Help,
+    Stats,
+    Version,
+    // None for the default count
+    Recent(Option<usize>),
+    Seen(String),
+    Whois(String),
+    Quit,
+}
+
+// what a line from a client that hasn't picked a name yet comes to, see lobby()
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lobby {
+    Reply(String),
+    // everything after /nick, the name and possibly a secret
+    Nick(String),
+}
+
+// shortcuts everyone has, resolved by the parser
+const BUILTIN_ALIASES: [(&str, &str); 3] = [("w", "msg"), ("j", "join"), ("q", "quit")];
+
+// every command name the parser knows, none of these can be taken by an alias
+const COMMANDS: [&str; 41] = [
+    "join",
+    "leave",
+    "switch",
+    "rooms",
+    "delroom",
+    "renameroom",
+    "msg",
+    "dnd",
+    "echo",
+    "tz",
+    "slowmode",
+    "quietjoins",
+    "giveop",
+    "deop",
+    "topic",
+    "topiclog",
+    "clearhistory",
+    "histlimit",
+    "pin",
+    "unpin",
+    "pins",
+    "share",
+    "export",
+    "ignore",
+    "unignore",
+    "ignores",
+    "kick",
+    "ban",
+    "announce",
+    "quitall",
+    "recent",
+    "seen",
+    "whois",
+    "alias",
+    "help",
+    "stats",
+    "version",
+    "quit",
+    "w",
+    "j",
+    "q",
+];
+
+// disconnects /recent lists when it isn't given a count
+const RECENT_DEFAULT: usize = 10;
+
+// aliases may expand to other aliases, but not without end
+const MAX_ALIAS_DEPTH: usize = 8;
+// how many aliases one client can have
+const MAX_ALIASES: usize = 32;
+
+const HELP: &str = "Commands: /join #room, /leave [#room], /switch #room, /rooms [page], /msg name message, \
+    /dnd on|off, /echo on|off, /tz utc|+hh:mm|off, /ignore name, /unignore name, /ignores, \
+    /seen name, /alias [short [expansion]], /pins, /topic, /share token, /export [#room], /help, /stats, /version, /quit. \
+    Room operators: /slowmode seconds, /quietjoins on|off, /giveop name, /deop name, /renameroom #old #new, /topic text, /topiclog, \
+    /clearhistory, /histlimit N, /pin seq, /unpin seq. \
+    Moderators: /kick name [reason], /ban name [reason], /announce text. \
+    Admins: /delroom #room, /quitall [message], /recent [N], /whois name. Aliases: /w = /msg, /j = /join, /q = /quit";
+
+const LOBBY_HELP: &str = "You are in the lobby. Commands: /nick name to pick your name and start \
+    chatting, /stats, /version, /help";
+
+// replaces the client's own aliases at the start of a command line with what they stand for,
+// anything after the alias is kept after its expansion. lines that aren't commands or don't
+// start with an alias come back as they are
+pub fn expand<'a>(
+    line: &'a str,
+    aliases: &HashMap<String, String>,
+) -> Result<Cow<'a, str>, ChatError> {
+    let mut line = Cow::Borrowed(line);
+    for _ in 0..MAX_ALIAS_DEPTH {
+        let Some(rest) = line.trim_start().strip_prefix('/') else {
+            return Ok(line);
+        };
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let Some(expansion) = aliases.get(name) else {
+            return Ok(line);
+        };
+        let args = args.trim();
+        line = Cow::Owned(if args.is_empty() {
+            expansion.clone()
+        } else {
+            format!("{expansion} {args}")
+        });
+    }
+    Err(ChatError::new(
+        ErrorCode::Usage,
+        format!("Aliases nested more than {MAX_ALIAS_DEPTH} deep, is one of them a loop?"),
+    ))
+}
+
+// returns None for ordinary chat lines, and an error for commands we can't make sense of
+pub fn parse(line: &str) -> Option<Result<Command, ChatError>> {
+    let line = line.trim();
+    let rest = line.strip_prefix('/')?;
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let name = BUILTIN_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, command)| command);
+    let args = args.trim();
+    let mut words = args.split_whitespace();
+    let arg = words.next();
+    let command = match (name, arg) {
+        ("join", Some(room)) => match room_name(room) {
+            Ok(()) => Command::Join(room.to_string()),
+            Err(err) => return Some(Err(err)),
+        },
+        ("join", None) => return Some(Err(usage("/join #room"))),
+        ("leave", Some(room)) if words.next().is_none() => match room_name(room) {
+            Ok(()) => Command::Leave(Some(room.to_string())),
+            Err(err) => return Some(Err(err)),
+        },
+        ("leave", None) => Command::Leave(None),
+        ("leave", _) => return Some(Err(usage("/leave [#room]"))),
+        ("switch", Some(room)) if words.next().is_none() => match room_name(room) {
+            Ok(()) => Command::Switch(room.to_string()),
+            Err(err) => return Some(Err(err)),
+        },
+        ("switch", _) => return Some(Err(usage("/switch #room"))),
+        ("rooms", None) => Command::Rooms(1),
+        ("rooms", Some(page)) if words.next().is_none() => match page.parse() {
+            Ok(page) if page > 0 => Command::Rooms(page),
+            _ => return Some(Err(usage("/rooms [page]"))),
+        },
+        ("rooms", _) => return Some(Err(usage("/rooms [page]"))),
+        ("delroom", Some(room)) => match room_name(room) {
+            Ok(()) => Command::DelRoom(room.to_string()),
+            Err(err) => return Some(Err(err)),
+        },
+        ("delroom", None) => return Some(Err(usage("/delroom #room"))),
+        ("renameroom", Some(old)) => match (words.next(), words.next()) {
+            (Some(new), None) => match room_name(old).and_then(|()| room_name(new)) {
+                Ok(()) => Command::RenameRoom(old.to_string(), new.to_string()),
+                Err(err) => return Some(Err(err)),
+            },
+            _ => return Some(Err(usage("/renameroom #old #new"))),
+        },
+        ("renameroom", None) => return Some(Err(usage("/renameroom #old #new"))),
+        ("msg", Some(to)) if words.next().is_some() => {
+            let text = args[to.len()..].trim_start().to_string();
+            Command::Msg {
+                to: to.to_string(),
+                text,
+            }
+        }
+        ("msg", _) => return Some(Err(usage("/msg name message"))),
+        ("dnd", Some("on")) => Command::Dnd(true),
+        ("dnd", Some("off")) => Command::Dnd(false),
+        ("dnd", _) => return Some(Err(usage("/dnd on|off"))),
+        ("echo", Some("on")) => Command::Echo(true),
+        ("echo", Some("off")) => Command::Echo(false),
+        ("echo", _) => return Some(Err(usage("/echo on|off"))),
+        ("tz", Some("off")) if words.next().is_none() => Command::Tz(None),
+        ("tz", Some(zone)) if words.next().is_none() => match utc_offset(zone) {
+            Some(offset) => Command::Tz(Some(offset)),
+            None => return Some(Err(usage("/tz utc|+hh:mm|-hh:mm|off"))),
+        },
+        ("tz", _) => return Some(Err(usage("/tz utc|+hh:mm|-hh:mm|off"))),
+        ("slowmode", Some(secs)) if secs.parse::<u64>().is_ok() => {
+            Command::SlowMode(secs.parse().unwrap_or_default())
+        }
+        ("slowmode", _) => return Some(Err(usage("/slowmode seconds"))),
+        ("quietjoins", Some("on")) => Command::QuietJoins(true),
+        ("quietjoins", Some("off")) => Command::QuietJoins(false),
+        ("quietjoins", _) => return Some(Err(usage("/quietjoins on|off"))),
+        ("giveop", Some(name)) if words.next().is_none() => Command::SetOp(name.to_string(), true),
+        ("giveop", _) => return Some(Err(usage("/giveop name"))),
+        ("deop", Some(name)) if words.next().is_none() => Command::SetOp(name.to_string(), false),
+        ("deop", _) => return Some(Err(usage("/deop name"))),
+        ("topic", None) => Command::Topic(None),
+        ("topic", Some(_)) => Command::Topic(Some(args.to_string())),
+        ("topiclog", None) => Command::TopicLog,
+        ("topiclog", _) => return Some(Err(usage("/topiclog"))),
+        ("clearhistory", None) => Command::ClearHistory,
+        ("clearhistory", _) => return Some(Err(usage("/clearhistory"))),
+        ("histlimit", Some(size)) if words.next().is_none() => match size.parse() {
+            Ok(size) if size <= MAX_HISTORY => Command::HistLimit(size),
+            Ok(_) => {
+                return Some(Err(ChatError::new(
+                    ErrorCode::Usage,
+                    format!("History can be at most {MAX_HISTORY} messages"),
+                )))
+            }
+            Err(_) => return Some(Err(usage("/histlimit N"))),
+        },
+        ("histlimit", _) => return Some(Err(usage("/histlimit N"))),
+        ("pin", Some(seq)) if words.next().is_none() => match seq.trim_start_matches('#').parse() {
+            Ok(seq) => Command::Pin(seq),
+            Err(_) => return Some(Err(usage("/pin seq"))),
+        },
+        ("pin", _) => return Some(Err(usage("/pin seq"))),
+        ("unpin", Some(seq)) if words.next().is_none() => {
+            match seq.trim_start_matches('#').parse() {
+                Ok(seq) => Command::Unpin(seq),
+                Err(_) => return Some(Err(usage("/unpin seq"))),
+            }
+        }
+        ("unpin", _) => return Some(Err(usage("/unpin seq"))),
+        ("pins", None) => Command::Pins,
+        ("pins", _) => return Some(Err(usage("/pins"))),
+        ("share", Some(token)) if words.next().is_none() => Command::Share(token.to_string()),
+        ("share", _) => return Some(Err(usage("/share token"))),
+        ("export", None) => Command::Export(None),
+        ("export", Some(room)) if words.next().is_none() => match room_name(room) {
+            Ok(()) => Command::Export(Some(room.to_string())),
+            Err(err) => return Some(Err(err)),
+        },
+        ("export", _) => return Some(Err(usage("/export [#room]"))),
+        ("ignore", Some(name)) => Command::Ignore(name.to_string()),
+        ("ignore", None) => return Some(Err(usage("/ignore name"))),
+        ("unignore", Some(name)) => Command::Unignore(name.to_string()),
+        ("unignore", None) => return Some(Err(usage("/unignore name"))),
+        ("kick" | "ban", Some(user)) => {
+            let reason = args[user.len()..].trim();
+            Command::Kick {
+                name: user.to_string(),
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+                ban: name == "ban",
+            }
+        }
+        ("kick" | "ban", None) => return Some(Err(usage(&format!("/{name} name [reason]")))),
+        ("announce", Some(_)) => Command::Announce(args.to_string()),
+        ("announce", None) => return Some(Err(usage("/announce text"))),
+        ("quitall", _) => Command::QuitAll((!args.is_empty()).then(|| args.to_string())),
+        ("alias", None) => Command::Alias(None),
+        ("alias", Some(short)) => {
+            let short = short.strip_prefix('/').unwrap_or(short).to_string();
+            let expansion = args[args.find(char::is_whitespace).unwrap_or(args.len())..].trim();
+            let expansion = match expansion {
+                "" => None,
+                command if command.starts_with('/') => Some(command.to_string()),
+                command => Some(format!("/{command}")),
+            };
+            Command::Alias(Some((short, expansion)))
+        }
+        ("help", _) => Command::Help,
+        ("stats", None) => Command::Stats,
+        ("stats", _) => return Some(Err(usage("/stats"))),
+        ("version", None) => Command::Version,
+        ("version", _) => return Some(Err(usage("/version"))),
+        ("recent", None) => Command::Recent(None),
+        ("recent", Some(count)) if words.next().is_none() => match count.parse() {
+            Ok(count) if count > 0 => Command::Recent(Some(count)),
+            _ => return Some(Err(usage("/recent [N]"))),
+        },
+        ("recent", _) => return Some(Err(usage("/recent [N]"))),
+        ("seen", Some(name)) if words.next().is_none() => Command::Seen(name.to_string()),
+        ("seen", _) => return Some(Err(usage("/seen name"))),
+        ("whois", Some(name)) if words.next().is_none() => Command::Whois(name.to_string()),
+        ("whois", _) => return Some(Err(usage("/whois name"))),
+        ("quit", _) => Command::Quit,
+        ("ignores", None) => Command::Ignores,
+        ("ignores", _) => return Some(Err(usage("/ignores"))),
+        _ => {
+            return Some(Err(ChatError::new(
+                ErrorCode::UnknownCommand,
+                format!("Unknown command: /{}", quoted(name)),
+            )))
+        }
+    };
+    Some(Ok(command))
+}
+
+// with --lobby a new client can look around before picking a name. only /help, /stats and
+// /version work until it does, and picking one takes /nick rather than just sending the name
+pub fn lobby(shared: &Shared, line: &str) -> Result<Lobby, ChatError> {
+    let line = line.trim();
+    let Some(rest) = line.strip_prefix('/') else {
+        return Err(ChatError::new(
+            ErrorCode::NotRegistered,
+            "Pick a name with /nick name before chatting",
+        ));
+    };
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if name == "nick" {
+        return match args.trim() {
+            "" => Err(usage("/nick name")),
+            name => Ok(Lobby::Nick(name.to_string())),
+        };
+    }
+    match parse(line) {
+        Some(Ok(Command::Help)) => Ok(Lobby::Reply(LOBBY_HELP.to_string())),
+        Some(Ok(Command::Stats)) => Ok(Lobby::Reply(stats(shared))),
+        // nothing has been negotiated yet, a json client would have said hello by now
+        Some(Ok(Command::Version)) => Ok(Lobby::Reply(version(Protocol::Plain))),
+        Some(Err(err)) if matches!(name, "help" | "stats" | "version") => Err(err),
+        _ => Err(ChatError::new(
+            ErrorCode::NotRegistered,
+            format!("/{name} needs a name, pick one with /nick name"),
+        )),
+    }
+}
+
+fn stats(shared: &Shared) -> String {
+    let stats = shared.snapshot();
+    let users = match stats.active_connections {
+        1 => "1 user".to_string(),
+        n => format!("{n} users"),
+    };
+    let rooms = match stats.rooms {
+        1 => "1 room".to_string(),
+        n => format!("{n} rooms"),
+    };
+    let minutes = stats.uptime.as_secs() / 60;
+    format!(
+        "{users} online in {rooms}, up for {}h {}m",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+// a room's history for /export, a line per message with when it was sent in utc. messages past
+// --history-ttl are left out even if the sweep hasn't got to them yet. members can export their
+// rooms, moderators and admins any room
+pub fn transcript(
+    shared: &Shared,
+    session: &Session,
+    room: Option<&str>,
+) -> Result<(String, Vec<String>), ChatError> {
+    require_role(session, shared.config.export_role)?;
+    let room = room.unwrap_or(&session.room).to_string();
+    if session.role < Role::Moderator && !session.rooms.contains(&room) {
+        return Err(ChatError::new(
+            ErrorCode::NotInRoom,
+            format!("You are not in {room}"),
+        ));
+    }
+    let registry = shared.registry();
+    let Some(history) = registry.rooms.get(&room).map(|room| &room.history) else {
+        return Err(ChatError::new(
+            ErrorCode::NoSuchRoom,
+            format!("No such room: {room}"),
+        ));
+    };
+    let ttl = shared.config.history_ttl;
+    let lines = history
+        .iter()
+        .filter(|entry| ttl.is_zero() || entry.sent_at.elapsed() < ttl)
+        .map(|entry| {
+            let mut line = format!("{} UTC ", room::utc_datetime(entry.ts));
+            entry.format_at(false, None, &mut line);
+            line
+        })
+        .collect();
+    Ok((room, lines))
+}
+
+// GIT_COMMIT is picked up from the environment at build time, when whoever builds sets it
+fn version(protocol: Protocol) -> String {
+    let mut text = format!("rustlang-chat-server {}", env!("CARGO_PKG_VERSION"));
+    if let Some(commit) = option_env!("GIT_COMMIT") {
+        let _ = write!(text, " ({commit})");
+    }
+    let _ = write!(text, ", protocol {}", protocol.as_str());
+    text
+}
+
+// short and rounded down, like 1h 5m or 42s
+fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+// utc, or an offset from it like +2, +05:30, -0800 or utc-3, in minutes. zone names would
+// need the tz database, which the server doesn't carry
+fn utc_offset(zone: &str) -> Option<i32> {
+    let lower = zone.to_ascii_lowercase();
+    let offset = lower
+        .strip_prefix("utc")
+        .or_else(|| lower.strip_prefix("gmt"))
+        .unwrap_or(&lower);
+    if offset.is_empty() || offset == "z" || lower == "z" {
+        return Some(0);
+    }
+    let (sign, rest) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() > 2 => rest.split_at(rest.len() - 2),
+        None => (rest, "0"),
+    };
+    if hours.is_empty() || !(hours.len() <= 2 && minutes.len() <= 2) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    let offset = sign * (hours * 60 + minutes);
+    // the furthest any zone is from utc
+    (minutes < 60 && (-12 * 60..=14 * 60).contains(&offset)).then_some(offset)
+}
+
+fn usage(text: &str) -> ChatError {
+    ChatError::new(ErrorCode::Usage, format!("Usage: {text}"))
+}
+
+fn room_name(room: &str) -> Result<(), ChatError> {
+    validate_room_name(room).map_err(|err| ChatError::new(ErrorCode::InvalidRoomName, err))
+}
+
+// the one check for commands that need more than being a user, everyone turned away gets
+// the same answer whatever they tried
+fn require_role(session: &Session, min: Role) -> Result<(), ChatError> {
+    if session.role >= min {
+        Ok(())
+    } else {
+        Err(permission_denied())
+    }
+}
+
+fn permission_denied() -> ChatError {
+    ChatError::new(ErrorCode::PermissionDenied, "Permission denied")
+}
+
+fn not_op(room: &str) -> ChatError {
+    ChatError::new(
+        ErrorCode::PermissionDenied,
+        format!("You are not an operator of {room}"),
+    )
+}
+
+// runs a command for the client behind the session and returns the reply for that client,
+// commands that announce themselves to the room don't need a separate reply
+pub fn run(
+    shared: &Arc<Shared>,
+    session: &mut Session,
+    command: Command,
+) -> Result<Option<String>, ChatError> {
+    match command {
+        Command::Join(room) => {
+            if room == session.room {
+                return Ok(Some(format!("*** you are already in {room} ***")));
+            }
+            // joining a room the client is already in only makes it the current one
+            let reply = match shared.registry().join(session.id, &room) {
+                Ok(true) => format!("*** you joined {room} ***"),
+                Ok(false) => format!("*** you are now chatting in {room} ***"),
+                Err(JoinError::TooManyRooms) => {
+                    return Err(ChatError::new(
+                        ErrorCode::TooManyRooms,
+                        "You are in too many rooms",
+                    ))
+                }
+                Err(JoinError::Insecure) => {
+                    return Err(ChatError::new(
+                        ErrorCode::SecureOnly,
+                        "This room requires a secure connection",
+                    ))
+                }
+            };
+            if session.rooms.insert(room.clone()) {
+                shared.announce(session.id, &room, &session.name, Presence::Joined);
+            }
+            session.room = room;
+            Ok(Some(reply))
+        }
+        // unlike /join this never subscribes, it only picks where plain lines go
+        Command::Switch(room) => {
+            if !shared.registry().switch(session.id, &room) {
+                return Err(ChatError::new(
+                    ErrorCode::NotInRoom,
+                    format!("You are not in {room}, /join it first"),
+                ));
+            }
+            let reply = format!("Now chatting in {room}");
+            session.room = room;
+            Ok(Some(reply))
+        }
+        Command::Leave(room) => {
+            let room = room.unwrap_or_else(|| session.room.clone());
+            let (current, promoted) = match shared.registry().leave_room(session.id, &room) {
+                Ok(left) => left,
+                Err(LeaveError::NotInRoom) => {
+                    return Err(ChatError::new(
+                        ErrorCode::NotInRoom,
+                        format!("You are not in {room}"),
+                    ))
+                }
+                Err(LeaveError::LastRoom) => {
+                    return Err(ChatError::new(
+                        ErrorCode::ProtectedRoom,
+                        "You can't leave your last room",
+                    ))
+                }
+            };
+            session.rooms.remove(&room);
+            shared.announce(session.id, &room, &session.name, Presence::Left);
+            if let Some(name) = promoted {
+                shared.promoted(&room, &name);
+            }
+            let reply = if current == session.room {
+                format!("*** you left {room} ***")
+            } else {
+                format!("*** you left {room}, now chatting in {current} ***")
+            };
+            session.room = current;
+            Ok(Some(reply))
+        }
+        // busiest first, ties by name so the pages don't shuffle between two calls
+        Command::Rooms(page) => {
+            let mut rooms: Vec<(String, usize)> = shared
+                .registry()
+                .rooms
+                .iter()
+                .map(|(name, room)| (name.clone(), room.members))
+                .collect();
+            rooms.sort_unstable_by(|(a, a_members), (b, b_members)| {
+                b_members.cmp(a_members).then_with(|| a.cmp(b))
+            });
+            let per_page = shared.config.rooms_page_size;
+            let pages = rooms.len().div_ceil(per_page).max(1);
+            if page > pages {
+                let text = match pages {
+                    1 => "There is only 1 page of rooms".to_string(),
+                    _ => format!("There are only {pages} pages of rooms"),
+                };
+                return Err(ChatError::new(ErrorCode::Usage, text));
+            }
+            let listed: Vec<String> = rooms
+                .iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .map(|(name, members)| format!("{name} ({members})"))
+                .collect();
+            let mut reply = format!("Rooms, page {page} of {pages}: {}", listed.join(", "));
+            if page < pages {
+                let _ = write!(reply, " (/rooms {} for more)", page + 1);
+            }
+            Ok(Some(reply))
+        }
+        Command::DelRoom(room) => {
+            require_role(session, Role::Admin)?;
+            match shared.registry().delete_room(&room) {
+                Ok(_) => {
+                    // members find out through the broadcast, including us if we were in there
+                    shared.publish(Event::RoomClosed { room: room.clone() });
+                    Ok(Some(format!("*** {room} deleted ***")))
+                }
+                Err(DeleteRoomError::DefaultRoom) => Err(ChatError::new(
+                    ErrorCode::ProtectedRoom,
+                    format!("{room} can't be deleted"),
+                )),
+                Err(DeleteRoomError::NoSuchRoom) => Err(ChatError::new(
+                    ErrorCode::NoSuchRoom,
+                    format!("No such room: {room}"),
+                )),
+            }
+        }
+        // members find out through the broadcast and follow the room to its new name
+        Command::RenameRoom(old, new) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &old) {
+                return Err(not_op(&old));
+            }
+            match registry.rename_room(&old, &new) {
+                Ok(()) => {}
+                Err(RenameRoomError::NoSuchRoom) => {
+                    return Err(ChatError::new(
+                        ErrorCode::NoSuchRoom,
+                        format!("No such room: {old}"),
+                    ))
+                }
+                Err(RenameRoomError::DefaultRoom) => {
+                    return Err(ChatError::new(
+                        ErrorCode::ProtectedRoom,
+                        format!("{old} can't be renamed"),
+                    ))
+                }
+                Err(RenameRoomError::NameTaken) => {
+                    return Err(ChatError::new(
+                        ErrorCode::Usage,
+                        format!("{new} is already taken"),
+                    ))
+                }
+                Err(RenameRoomError::Secure) => {
+                    return Err(ChatError::new(
+                        ErrorCode::SecureOnly,
+                        format!("{new} is only for secure connections"),
+                    ))
+                }
+            }
+            // published with the registry still locked, so no message under the new name can
+            // overtake it
+            let reply = format!("*** {old} is now {new} ***");
+            shared.publish(Event::RoomRenamed {
+                old: old.clone(),
+                new,
+            });
+            drop(registry);
+            // a moderator running someone else's room hears it here instead
+            Ok((!session.rooms.contains(&old)).then_some(reply))
+        }
+        Command::Msg { to, text } => {
+            let registry = shared.registry();
+            let Some(client) = registry.clients.values().find(|c| c.name == to) else {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchUser,
+                    format!("No such user: {}", quoted(&to)),
+                ));
+            };
+            if client.dnd {
+                return Err(ChatError::new(
+                    ErrorCode::NotAcceptingMessages,
+                    format!("{to} is not accepting direct messages"),
+                ));
+            }
+            let reply = format!("[dm to {to}] {text}");
+            // straight into the recipient's queue, nobody else has to look at it
+            if !client.ignored.contains(&session.name) {
+                let _ = client.outbound.push(Outgoing::Direct {
+                    from: session.name.clone(),
+                    text,
+                });
+            }
+            Ok(Some(reply))
+        }
+        Command::Dnd(on) => {
+            if let Some(client) = shared.registry().clients.get_mut(&session.id) {
+                client.dnd = on;
+            }
+            Ok(Some(if on {
+                "Do not disturb is on, direct messages will be refused".to_string()
+            } else {
+                "Do not disturb is off".to_string()
+            }))
+        }
+        // the writer renders the time, so it is told in line with the messages it is writing
+        Command::Tz(offset) => {
+            if let Some(client) = shared.registry().clients.get(&session.id) {
+                let _ = client.outbound.push(Outgoing::SetClock(offset));
+            }
+            Ok(Some(match offset {
+                None => "Times are off".to_string(),
+                Some(0) => "Times are shown in UTC".to_string(),
+                Some(offset) => format!(
+                    "Times are shown at UTC{}{:02}:{:02}",
+                    if offset < 0 { '-' } else { '+' },
+                    offset.abs() / 60,
+                    offset.abs() % 60
+                ),
+            }))
+        }
+        Command::Echo(on) => {
+            session.echo = on;
+            Ok(Some(if on {
+                "Echo is on, your own messages will be sent back to you".to_string()
+            } else {
+                "Echo is off".to_string()
+            }))
+        }
+        Command::SlowMode(secs) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            if let Some(room) = registry.rooms.get_mut(&session.room) {
+                room.slow_mode = (secs > 0).then(|| Duration::from_secs(secs));
+                // set by hand, so the flood detector leaves it alone from here on
+                room.auto_slow = false;
+            }
+            drop(registry);
+            let notice = if secs == 0 {
+                format!("*** slow mode turned off by {} ***", session.name)
+            } else {
+                format!(
+                    "*** slow mode set to one message every {secs}s by {} ***",
+                    session.name
+                )
+            };
+            shared.send_notice(&session.room, notice);
+            Ok(None)
+        }
+        // only messages still in the history can be pinned, the pin keeps them from then on
+        Command::Pin(seq) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            let Some(room) = registry.rooms.get_mut(&session.room) else {
+                return Ok(None);
+            };
+            if room.pins.iter().any(|pin| pin.seq == seq) {
+                return Ok(Some(format!("#{seq} is already pinned")));
+            }
+            let Some(index) = room.position(seq) else {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchMessage,
+                    format!("No message #{seq} in {}", session.room),
+                ));
+            };
+            if room.pins.len() >= MAX_PINS {
+                return Err(ChatError::new(
+                    ErrorCode::TooManyPins,
+                    format!("A room can have at most {MAX_PINS} pinned messages"),
+                ));
+            }
+            let entry = room.history[index].clone();
+            let at = room.pins.partition_point(|pin| pin.seq < seq);
+            room.pins.insert(at, entry);
+            drop(registry);
+            shared.send_notice(
+                &session.room,
+                format!("*** {} pinned #{seq} ***", session.name),
+            );
+            Ok(None)
+        }
+        Command::Unpin(seq) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            let Some(room) = registry.rooms.get_mut(&session.room) else {
+                return Ok(None);
+            };
+            let Some(at) = room.pins.iter().position(|pin| pin.seq == seq) else {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchMessage,
+                    format!("#{seq} isn't pinned in {}", session.room),
+                ));
+            };
+            room.pins.remove(at);
+            drop(registry);
+            shared.send_notice(
+                &session.room,
+                format!("*** {} unpinned #{seq} ***", session.name),
+            );
+            Ok(None)
+        }
+        // the connection runs it instead, a long transcript is stored as a file, see
+        // connection::export
+        Command::Export(_) => Ok(None),
+        // the file itself went up over http, this only tells the room where to get it
+        Command::Share(token) => {
+            let Some(uploads) = &shared.uploads else {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchFile,
+                    "File sharing is off on this server",
+                ));
+            };
+            let Some(upload) = uploads.get(&token) else {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchFile,
+                    format!("No shared file {token}, or it has expired"),
+                ));
+            };
+            let text = format!(
+                "shared {} ({}): {}",
+                upload.name,
+                upload::size(upload.size),
+                shared.config.file_url(&token)
+            );
+            match shared.post(session.id, text, None, None) {
+                Ok(()) => Ok(None),
+                Err(err) => post_error(shared, session, err).map_or(Ok(None), Err),
+            }
+        }
+        Command::Pins => {
+            let pins = shared.pins(&session.room);
+            if pins.is_empty() {
+                return Ok(Some(format!("Nothing is pinned in {}", session.room)));
+            }
+            Ok(Some(format!(
+                "Pinned in {}: {}",
+                session.room,
+                pins.join(" | ")
+            )))
+        }
+        Command::ClearHistory => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            // anyone joining or resuming from here on gets no replay until new messages arrive
+            if let Some(room) = registry.rooms.get_mut(&session.room) {
+                room.history.clear();
+                room.reactions.clear();
+            }
+            drop(registry);
+            shared.send_notice(
+                &session.room,
+                "*** history cleared by an operator ***".to_string(),
+            );
+            Ok(None)
+        }
+        // members are still counted and listed, only the notices go
+        Command::QuietJoins(on) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            if let Some(room) = registry.rooms.get_mut(&session.room) {
+                room.quiet_joins = on;
+            }
+            drop(registry);
+            let notice = if on {
+                format!(
+                    "*** join and leave notices turned off by {} ***",
+                    session.name
+                )
+            } else {
+                format!(
+                    "*** join and leave notices turned on by {} ***",
+                    session.name
+                )
+            };
+            shared.send_notice(&session.room, notice);
+            Ok(None)
+        }
+        // handing a room over is /giveop then /deop of yourself
+        Command::SetOp(name, op) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            let room = &session.room;
+            match registry.set_op(room, &name, op) {
+                Ok(true) => {}
+                Ok(false) if op => return Ok(Some(format!("{name} is already an operator"))),
+                Ok(false) => return Ok(Some(format!("{name} isn't an operator"))),
+                Err(OpError::NoSuchUser) => {
+                    return Err(ChatError::new(
+                        ErrorCode::NoSuchUser,
+                        format!("No such user: {name}"),
+                    ))
+                }
+                Err(OpError::NotInRoom) => {
+                    return Err(ChatError::new(
+                        ErrorCode::NotInRoom,
+                        format!("{name} is not in {room}"),
+                    ))
+                }
+                Err(OpError::LastOp) => {
+                    return Err(ChatError::new(
+                        ErrorCode::Usage,
+                        format!(
+                            "{name} is the last operator of {room}, /giveop someone else first"
+                        ),
+                    ))
+                }
+            }
+            drop(registry);
+            let notice = if op {
+                format!("*** {name} is now an operator ***")
+            } else {
+                format!("*** {name} is no longer an operator ***")
+            };
+            shared.send_notice(room, notice);
+            Ok(None)
+        }
+        Command::Topic(None) => {
+            let registry = shared.registry();
+            let topic = registry
+                .rooms
+                .get(&session.room)
+                .and_then(|room| room.topic.as_deref());
+            Ok(Some(match topic {
+                Some(topic) => format!("The topic of {} is: {topic}", session.room),
+                None => format!("{} has no topic", session.room),
+            }))
+        }
+        Command::Topic(Some(topic)) => {
+            if shared.too_long(&topic) {
+                let max = shared.config.max_message_len;
+                return Err(ChatError::new(
+                    ErrorCode::TooLarge,
+                    format!("Topic too long, at most {max} characters"),
+                ));
+            }
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            if let Some(room) = registry.rooms.get_mut(&session.room) {
+                room.set_topic(&session.name, topic.clone());
+            }
+            drop(registry);
+            let notice = format!("*** {} set the topic to: {topic} ***", session.name);
+            shared.send_notice(&session.room, notice);
+            Ok(None)
+        }
+        // newest first
+        Command::TopicLog => {
+            let registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            let changes: Vec<String> = registry
+                .rooms
+                .get(&session.room)
+                .map(|room| {
+                    room.topic_log
+                        .iter()
+                        .rev()
+                        .map(|change| {
+                            format!(
+                                "{} ago {}: {} -> {}",
+                                duration(change.at.elapsed()),
+                                change.by,
+                                change.old.as_deref().unwrap_or("(none)"),
+                                change.new
+                            )
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            if changes.is_empty() {
+                return Ok(Some(format!(
+                    "The topic of {} has never been changed",
+                    session.room
+                )));
+            }
+            Ok(Some(format!(
+                "Topic changes in {}: {}",
+                session.room,
+                changes.join(" | ")
+            )))
+        }
+        // shrinking drops the oldest messages straight away, growing only makes room for more
+        Command::HistLimit(limit) => {
+            let mut registry = shared.registry();
+            if !registry.is_op(session.id, &session.room) {
+                return Err(not_op(&session.room));
+            }
+            if let Some(room) = registry.rooms.get_mut(&session.room) {
+                room.history_limit = Some(limit);
+                room.trim_history(limit);
+            }
+            drop(registry);
+            let notice = format!(
+                "*** {} now keeps the last {limit} messages, set by {} ***",
+                session.room, session.name
+            );
+            shared.send_notice(&session.room, notice);
+            Ok(None)
+        }
+        // only ever affects what this client is sent, the other side isn't told
+        Command::Ignore(name) => {
+            if name == session.name {
+                return Err(ChatError::new(
+                    ErrorCode::Usage,
+                    "You can't ignore yourself",
+                ));
+            }
+            shared.registry().set_ignored(session.id, &name, true);
+            session.ignored.insert(name.clone());
+            Ok(Some(format!(
+                "Ignoring {name}, their messages won't be shown to you"
+            )))
+        }
+        Command::Unignore(name) => {
+            if !session.ignored.remove(&name) {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchUser,
+                    format!("You aren't ignoring {name}"),
+                ));
+            }
+            shared.registry().set_ignored(session.id, &name, false);
+            Ok(Some(format!("No longer ignoring {name}")))
+        }
+        Command::Kick { name, reason, ban } => {
+            require_role(session, Role::Moderator)?;
+            match shared.kick((&session.name, session.role), &name, reason.as_deref(), ban) {
+                Ok(()) => Ok(Some(format!(
+                    "{name} was {}",
+                    if ban { "banned" } else { "kicked" }
+                ))),
+                Err(KickError::NoSuchUser) => Err(ChatError::new(
+                    ErrorCode::NoSuchUser,
+                    format!("No such user: {}", quoted(&name)),
+                )),
+                Err(KickError::Outranked) => Err(permission_denied()),
+            }
+        }
+        // to every client with a name, whatever rooms it is in or who it ignores
+        Command::Announce(text) => {
+            require_role(session, Role::Moderator)?;
+            let text = format!("*** announcement from {}: {text} ***", session.name);
+            for client in shared.registry().clients.values() {
+                let _ = client.outbound.push(text.clone());
+            }
+            Ok(None)
+        }
+        Command::QuitAll(text) => {
+            require_role(session, Role::Admin)?;
+            let text = text.unwrap_or_else(|| {
+                "*** the server is being cleared for maintenance, please reconnect later ***"
+                    .to_string()
+            });
+            let evicted = shared.quit_all(text);
+            let clients = if evicted == 1 { "client" } else { "clients" };
+            Ok(Some(format!("*** disconnecting {evicted} {clients} ***")))
+        }
+        Command::Alias(None) => {
+            if session.aliases.is_empty() {
+                return Ok(Some("You have no aliases".to_string()));
+            }
+            let mut aliases: Vec<_> = session
+                .aliases
+                .iter()
+                .map(|(short, expansion)| format!("/{short} = {expansion}"))
+                .collect();
+            aliases.sort_unstable();
+            Ok(Some(format!("Aliases: {}", aliases.join(", "))))
+        }
+        Command::Alias(Some((short, None))) => {
+            if session.aliases.remove(&short).is_none() {
+                return Err(usage(&format!(
+                    "/alias {short} expansion, there is no /{short} yet"
+                )));
+            }
+            if let Some(client) = shared.registry().clients.get_mut(&session.id) {
+                client.aliases.remove(&short);
+            }
+            Ok(Some(format!("Removed /{short}")))
+        }
+        Command::Alias(Some((short, Some(expansion)))) => {
+            if short.is_empty() || short.chars().any(|c| c.is_whitespace() || c == '/') {
+                return Err(usage("/alias short expansion"));
+            }
+            if COMMANDS.contains(&short.as_str()) {
+                return Err(ChatError::new(
+                    ErrorCode::Usage,
+                    format!("/{short} is a command already"),
+                ));
+            }
+            if !session.aliases.contains_key(&short) && session.aliases.len() >= MAX_ALIASES {
+                return Err(ChatError::new(
+                    ErrorCode::Usage,
+                    format!("You can have at most {MAX_ALIASES} aliases"),
+                ));
+            }
+            if let Some(client) = shared.registry().clients.get_mut(&session.id) {
+                client.aliases.insert(short.clone(), expansion.clone());
+            }
+            let reply = format!("/{short} now stands for {expansion}");
+            session.aliases.insert(short, expansion);
+            Ok(Some(reply))
+        }
+        Command::Help => Ok(Some(HELP.to_string())),
+        Command::Stats => Ok(Some(stats(shared))),
+        Command::Version => Ok(Some(version(session.protocol))),
+        // newest first, on one line like every other reply
+        Command::Recent(count) => {
+            require_role(session, Role::Admin)?;
+            let count = count.unwrap_or(RECENT_DEFAULT).min(RECENT_DISCONNECTS);
+            let registry = shared.registry();
+            if registry.recent.is_empty() {
+                return Ok(Some("Nobody has disconnected yet".to_string()));
+            }
+            let recent: Vec<String> = registry
+                .recent
+                .iter()
+                .rev()
+                .take(count)
+                .map(|gone| {
+                    format!(
+                        "{} ({}, {}, {})",
+                        gone.name,
+                        gone.addr.ip(),
+                        duration(gone.connected_for),
+                        gone.reason.as_str()
+                    )
+                })
+                .collect();
+            Ok(Some(format!("Recent disconnects: {}", recent.join("; "))))
+        }
+        Command::Seen(name) => {
+            let registry = shared.registry();
+            if registry.clients.values().any(|client| client.name == name) {
+                return Ok(Some(format!("{name} is online")));
+            }
+            Ok(Some(match registry.last_seen.get(&name) {
+                Some(at) => format!("{name} was last seen {} ago", duration(at.elapsed())),
+                None => format!("{name} hasn't been seen since the server started"),
+            }))
+        }
+        // how the client got in as well as where it is, so admins can tell tls clients apart
+        Command::Whois(name) => {
+            require_role(session, Role::Admin)?;
+            let registry = shared.registry();
+            let Some(client) = registry.clients.values().find(|client| client.name == name) else {
+                return Err(ChatError::new(
+                    ErrorCode::NoSuchUser,
+                    format!("No such user: {}", quoted(&name)),
+                ));
+            };
+            let mut rooms: Vec<&str> = client.rooms.iter().map(String::as_str).collect();
+            rooms.sort();
+            Ok(Some(format!(
+                "{name} ({}) connected over {} from {} for {}, in {} (talking in {})",
+                client.role.as_str(),
+                client.transport.as_str(),
+                client.addr,
+                duration(client.connected_at.elapsed()),
+                rooms.join(", "),
+                client.room,
+            )))
+        }
+        Command::Quit => {
+            session.quit = true;
+            Ok(Some("Goodbye!".to_string()))
+        }
+        Command::Ignores => {
+            if session.ignored.is_empty() {
+                return Ok(Some("You aren't ignoring anyone".to_string()));
+            }
+            let mut names: Vec<_> = session.ignored.iter().map(String::as_str).collect();
+            names.sort_unstable();
+            Ok(Some(format!("Ignoring: {}", names.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_are_read_in_minutes() {
+        for utc in ["utc", "UTC", "z", "gmt", "utc+0", "+00:00"] {
+            assert_eq!(utc_offset(utc), Some(0), "{utc}");
+        }
+        assert_eq!(utc_offset("+2"), Some(120));
+        assert_eq!(utc_offset("+05:30"), Some(330));
+        assert_eq!(utc_offset("-0800"), Some(-480));
+        assert_eq!(utc_offset("utc-3"), Some(-180));
+        assert_eq!(utc_offset("GMT+14"), Some(14 * 60));
+        assert_eq!(utc_offset("-12:00"), Some(-12 * 60));
+    }
+
+    #[test]
+    fn offsets_no_zone_has_are_turned_down() {
+        for bad in [
+            "+15",
+            "-13",
+            "+05:60",
+            "+123:00",
+            "+",
+            "2",
+            "Europe/Paris",
+            "utc+x",
+        ] {
+            assert_eq!(utc_offset(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn tz_takes_off_or_an_offset() {
+        assert!(matches!(parse("/tz off"), Some(Ok(Command::Tz(None)))));
+        assert!(matches!(
+            parse("/tz +01:00"),
+            Some(Ok(Command::Tz(Some(60))))
+        ));
+        for bad in ["/tz", "/tz mars", "/tz utc +1"] {
+            let Some(Err(err)) = parse(bad) else {
+                panic!("{bad} was taken");
+            };
+            assert_eq!(err.text, "Usage: /tz utc|+hh:mm|-hh:mm|off", "{bad}");
+        }
+    }
+
+    #[test]
+    fn kick_and_ban_take_a_name_and_maybe_a_reason() {
+        assert_eq!(
+            parse("/kick bob"),
+            Some(Ok(Command::Kick {
+                name: "bob".to_string(),
+                reason: None,
+                ban: false,
+            }))
+        );
+        assert_eq!(
+            parse("/ban bob  spamming links "),
+            Some(Ok(Command::Kick {
+                name: "bob".to_string(),
+                reason: Some("spamming links".to_string()),
+                ban: true,
+            }))
+        );
+        assert_eq!(parse("/ban"), Some(Err(usage("/ban name [reason]"))));
+        assert_eq!(
+            parse("/announce back in 5"),
+            Some(Ok(Command::Announce("back in 5".to_string())))
+        );
+        assert_eq!(parse("/announce"), Some(Err(usage("/announce text"))));
+    }
+}
@@ -0,0 +1,937 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    ops::RangeInclusive,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{
+    auth::Role,
+    cidr::Cidr,
+    framing::Delimiter,
+    greeting::{self, GreetingStep},
+    motd::{Motd, MotdMode},
+    outbound::WritePolicy,
+    protocol::PROTOCOL_VERSION,
+    room::MAX_HISTORY,
+    state::{validate_room_name, DEFAULT_ROOM},
+    tls,
+    webhook::WebhookUrl,
+};
+
+// settings the server is started with, filled in from the command line
+#[derive(Debug, Clone)]
+pub struct Config {
+    // address the chat listener binds to
+    pub listen: String,
+    // connections the kernel queues for each listener until they are accepted
+    pub backlog: u32,
+    // address of the admin interface, disabled unless given
+    pub admin_listen: Option<String>,
+    // address of the http long-poll interface, disabled unless given
+    pub http_listen: Option<String>,
+    // address prometheus scrapes the counters from, disabled unless given
+    pub metrics_listen: Option<String>,
+    // address for chat clients over tls, with the certificate chain and private key in pem.
+    // disabled unless given, and then all three are needed
+    pub tls_listen: Option<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    // the bearer token bots need to post through the http interface, no bot endpoint without it
+    pub bot_token: Option<String>,
+    // every chat message is posted here as json, off unless given
+    pub webhook: Option<WebhookUrl>,
+    // every chat message is appended to this file as a line of json, off unless given
+    pub store_file: Option<PathBuf>,
+    // the same into a sqlite database instead, only with the sqlite feature
+    pub store_db: Option<PathBuf>,
+    // where files shared over the http interface are kept, no uploads unless given. the limits
+    // are per file and for everything stored at once, in bytes
+    pub upload_dir: Option<PathBuf>,
+    pub upload_max_bytes: usize,
+    pub upload_total_bytes: usize,
+    // how long an uploaded file can be fetched for
+    pub upload_ttl: Duration,
+    // what download links start with, for when clients reach the http interface under another
+    // name than --http-listen, through a proxy say
+    pub upload_url: Option<String>,
+    // when not empty only clients from these ranges may connect, on either listener
+    pub allow: Vec<Cidr>,
+    // where a json snapshot of the rooms and connections is kept up to date, and how often
+    pub state_file: Option<PathBuf>,
+    pub state_interval: Duration,
+    // a file or directory with the message of the day, and how one is picked from it
+    pub motd: Option<PathBuf>,
+    pub motd_mode: MotdMode,
+    // chat connections allowed at once, zero for no limit. admin connections don't count
+    pub max_clients: usize,
+    // what a client turned away by that limit is told, {cap} is replaced by the limit
+    pub server_full_message: String,
+    // the longest pause between two chat accepts once the server is under load, zero for none
+    pub accept_delay: Duration,
+    // kernel keepalive on accepted sockets, probing after this long without traffic, zero for off
+    pub tcp_keepalive: Duration,
+    // tell every new client how many people are online
+    pub show_occupancy: bool,
+    // what new connections are sent in which order, None for the usual, see greeting_steps
+    pub greeting: Option<Vec<GreetingStep>>,
+    // column to wrap the server's own text at for plain text clients, zero leaves it alone
+    pub wrap_width: usize,
+    // new clients can use /help and /stats before picking a name with /nick
+    pub lobby: bool,
+    // the longest line a json client may send, and any client before it has registered, in
+    // bytes. zero for no limit
+    pub max_json_bytes: usize,
+    // longest name a client can pick, at most MAX_USERNAME_LEN
+    pub max_username_len: usize,
+    // longest chat message in characters, whatever the protocol, zero for no limit
+    pub max_message_len: usize,
+    // a message over max_message_len is cut to fit instead of being turned down
+    pub truncate_long_messages: bool,
+    // rooms a single client may be in at once, at least one
+    pub max_rooms: usize,
+    // rooms listed per page of /rooms, at least one
+    pub rooms_page_size: usize,
+    // the least a client's role has to be for /export
+    pub export_role: Role,
+    // rooms only clients on a secure transport may join
+    pub secure_rooms: HashSet<String>,
+    // how many messages each room keeps around for replay, unless it has a size of its own
+    pub history_size: usize,
+    // messages older than this are dropped from the history and the store, zero keeps them
+    pub history_ttl: Duration,
+    // how long a room is kept once its last member has left, zero removes it right away
+    pub empty_room_grace: Duration,
+    pub room_history: HashMap<String, usize>,
+    // how long a message may take to arrive once part of it has, zero waits forever
+    pub partial_timeout: Duration,
+    // a client hanging up halfway through a message still gets that message sent, rather
+    // than it being dropped
+    pub keep_unterminated: bool,
+    // how long a new connection may sit at the name prompt without answering, zero waits forever
+    pub registration_timeout: Duration,
+    // clients speaking an older protocol than this are turned away at registration, 0 takes
+    // plain text clients too
+    pub min_protocol_version: u64,
+    // how long a registered client may go without sending anything, zero lets it idle forever
+    pub idle_timeout: Duration,
+    // how long before an idle disconnect the client is warned, zero for no warning
+    pub idle_warning: Duration,
+    // how long a connection may last however busy it is, zero for as long as it likes
+    pub max_lifetime: Duration,
+    // how long after a disconnect a resume token can still be used, zero turns resuming off
+    pub resume_window: Duration,
+    // identical lines in a row a client may send before the rest are dropped, zero allows any
+    pub max_repeats: usize,
+    // chat messages a client may send per second once its burst is used up, zero for no limit
+    pub rate_limit: usize,
+    pub rate_burst: usize,
+    // how long after registering a client may send a bigger burst than that
+    pub rate_grace: Duration,
+    // lines of any kind, commands included, a client may send per second before it is
+    // disconnected, zero for no limit. meant to sit well above the chat rate limit
+    pub frame_limit: usize,
+    pub frame_burst: usize,
+    // how long after sending a message its sender may still edit or delete it
+    pub edit_window: Duration,
+    // messages per flood window above which a room is put in slow mode, zero turns it off
+    pub flood_threshold: usize,
+    pub flood_window: Duration,
+    // the slow mode interval applied to a flooded room, and to every room while overloaded
+    pub flood_slow_mode: Duration,
+    // percentage of clients falling behind within the window that counts as overloaded,
+    // zero never does
+    pub lag_threshold: usize,
+    pub lag_window: Duration,
+    // log every connection with the peer's reverse dns name, off since lookups can be slow
+    pub resolve_peers: bool,
+    // join and leave notices within the window beyond which they are summed up, zero never does
+    pub join_burst: usize,
+    pub join_window: Duration,
+    // what messages end with, in both directions
+    pub delimiter: Delimiter,
+    pub write_policy: WritePolicy,
+    // how long a batched line may wait to be written when the queue doesn't run dry first
+    pub flush_interval: Duration,
+    // when set we run as a client connected to this address instead of as a server
+    pub connect: Option<String>,
+    // show plain text clients which room each message came from, pointless if nobody ever
+    // leaves the default room
+    pub room_tags: bool,
+    // start plain text clients off with the time, in utc, in front of every message
+    pub timestamps: bool,
+    // diagnostic mode, every line is sent straight back to whoever sent it
+    pub echo: bool,
+    // clients get their own chat messages back, until they turn it off with /echo off
+    pub echo_own: bool,
+    // only check the settings and print them, the server isn't started
+    pub check_config: bool,
+    // run the server against two clients of its own and report, instead of serving
+    pub selftest: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "localhost:8080".to_string(),
+            backlog: 1024,
+            admin_listen: None,
+            http_listen: None,
+            metrics_listen: None,
+            tls_listen: None,
+            tls_cert: None,
+            tls_key: None,
+            webhook: None,
+            store_file: None,
+            store_db: None,
+            upload_dir: None,
+            upload_max_bytes: 8 * 1024 * 1024,
+            upload_total_bytes: 256 * 1024 * 1024,
+            upload_ttl: Duration::from_secs(3600),
+            upload_url: None,
+            bot_token: None,
+            allow: Vec::new(),
+            state_file: None,
+            state_interval: Duration::from_secs(10),
+            motd: None,
+            motd_mode: MotdMode::Static,
+            max_clients: 0,
+            accept_delay: Duration::ZERO,
+            tcp_keepalive: Duration::ZERO,
+            server_full_message: "Server full, try again later".to_string(),
+            show_occupancy: false,
+            greeting: None,
+            wrap_width: 0,
+            lobby: false,
+            max_json_bytes: 16 * 1024,
+            max_username_len: 32,
+            max_message_len: 1024,
+            truncate_long_messages: false,
+            max_rooms: 10,
+            rooms_page_size: 20,
+            export_role: Role::User,
+            secure_rooms: HashSet::new(),
+            history_ttl: Duration::ZERO,
+            empty_room_grace: Duration::ZERO,
+            history_size: 100,
+            room_history: HashMap::new(),
+            partial_timeout: Duration::from_secs(10),
+            keep_unterminated: false,
+            registration_timeout: Duration::from_secs(30),
+            min_protocol_version: 0,
+            idle_timeout: Duration::ZERO,
+            idle_warning: Duration::from_secs(30),
+            max_lifetime: Duration::ZERO,
+            resume_window: Duration::from_secs(60),
+            max_repeats: 3,
+            rate_limit: 0,
+            rate_burst: 5,
+            rate_grace: Duration::from_secs(5),
+            frame_limit: 0,
+            frame_burst: 20,
+            edit_window: Duration::from_secs(300),
+            flood_threshold: 0,
+            flood_window: Duration::from_secs(10),
+            flood_slow_mode: Duration::from_secs(2),
+            lag_threshold: 0,
+            lag_window: Duration::from_secs(10),
+            resolve_peers: false,
+            join_burst: 0,
+            join_window: Duration::from_secs(5),
+            delimiter: Delimiter::Lf,
+            write_policy: WritePolicy::Immediate,
+            flush_interval: Duration::from_millis(5),
+            connect: None,
+            room_tags: true,
+            timestamps: false,
+            echo: false,
+            echo_own: false,
+            check_config: false,
+            selftest: false,
+        }
+    }
+}
+
+const USAGE: &str = "usage: rustlang-chat-server [options]
+    --listen ADDR                address for chat clients, a port range like host:8080-8090 takes the first free one (default localhost:8080)
+    --backlog N                  pending connections queued per listener before new ones are refused (default 1024)
+    --admin-listen ADDR          address for admin clients (default off)
+    --http-listen ADDR           address for the http long-poll interface (default off)
+    --metrics-listen ADDR        address serving the counters for prometheus at /metrics (default off)
+    --tls-listen ADDR            address for chat clients over tls, needs --tls-cert and --tls-key (default off)
+    --tls-cert PATH              the certificate chain for --tls-listen in pem, the server's own first
+    --tls-key PATH               the private key for --tls-listen in pem
+    --bot-token SECRET           let bots post to /bot on the http interface with this bearer token
+    --webhook URL                post every message as json to this http:// url (default off)
+    --store-file PATH            append every message to this file as a line of json (default off)
+    --store-db PATH              keep every message in this sqlite database, needs the sqlite feature (default off)
+    --upload-dir PATH            let clients share files through the http interface, kept in this directory (default off)
+    --upload-max-bytes N         the largest file that can be uploaded (default 8388608)
+    --upload-total-bytes N       how much the uploaded files may take up altogether (default 268435456)
+    --upload-ttl SECS            how long an uploaded file can be downloaded for (default 3600)
+    --upload-url URL             what download links start with (default http:// and the --http-listen address)
+    --allow CIDR                 only accept clients from this range, can be given more than once (default any)
+    --state-file PATH            keep a json snapshot of rooms and connection counts in this file (default off)
+    --state-interval SECS        how often the state file is brought up to date (default 10)
+    --motd PATH                  file or directory with the message of the day (default none)
+    --motd-mode MODE             static shows it all, random or rotate pick a line or file per connection (default static)
+    --max-clients N              chat connections allowed at once, 0 for no limit (default 0)
+    --accept-delay MS            slow accepting down under load, pausing up to this long between connections (default 0)
+    --server-full-message TEXT   what clients over the cap are told, {cap} is the cap (default Server full, try again later)
+    --tcp-keepalive-secs SECS    have the kernel probe connections quiet for SECS and drop dead peers, 0 for off (default 0)
+    --greeting STEPS             what new clients are sent in order, from banner,occupancy,motd,prompt,password and name, where name is when they pick one (default prompt,name,motd)
+    --show-occupancy             greet new clients with how many users are online
+    --wrap-width N               wrap notices and the motd at N characters for plain text clients, 0 for off (default 0)
+    --lobby                      let new clients look around with /help and /stats, /nick name picks a name
+    --max-json-bytes N           longest line from json clients and the name prompt, 0 for no limit (default 16384)
+    --max-username-len N         longest name a client can pick, at most 64 (default 32)
+    --max-message-len N          longest chat message in characters, 0 for no limit (default 1024)
+    --truncate-long-messages     cut longer messages to fit instead of turning them down
+    --max-rooms N                rooms a single client may be in at once (default 10)
+    --rooms-page-size N          rooms listed per page of /rooms, busiest first (default 20)
+    --export-role ROLE           who can /export a transcript, user, moderator or admin (default user)
+    --secure-room #ROOM          only clients on a secure connection may join the room, can be given more than once
+    --history N                  messages kept per room for replay (default 100)
+    --history-ttl SECS           forget messages this old, from the history and the --store-file, 0 to keep them (default 0)
+    --empty-room-grace SECS      keep an empty room with its topic, pins and history this long in case someone comes back (default 0)
+    --room-history #ROOM=N       messages kept for replay in that room, can be given more than once
+    --partial-timeout SECS       how long a half sent message may take to finish, 0 to wait forever (default 10)
+    --keep-unterminated          send a last message that has no delimiter when the client hangs up, instead of dropping it
+    --registration-timeout SECS  how long to wait for a name, 0 to wait forever (default 30)
+    --min-protocol-version N     turn away clients speaking an older protocol, 0 for plain text and 1 for json (default 0)
+    --idle-timeout SECS          disconnect clients that send nothing for this long, 0 for never (default 0)
+    --idle-warning SECS          how long before an idle disconnect to warn the client, 0 for no warning (default 30)
+    --max-lifetime SECS          disconnect clients this long after they connected, 0 for never (default 0)
+    --resume-window SECS         how long resume tokens stay valid after a disconnect (default 60)
+    --max-repeats K              identical messages allowed in a row, 0 for no limit (default 3)
+    --rate-limit N               messages a client may send per second, 0 for no limit (default 0)
+    --rate-burst N               messages a client may send at once before the rate limit applies (default 5)
+    --rate-grace SECS            how long a new client may send four times the burst (default 5)
+    --frame-limit N              lines of any kind a client may send per second before being disconnected, 0 for no limit (default 0)
+    --frame-burst N              lines a client may send at once before the frame limit applies (default 20)
+    --edit-window SECS           how long messages can be edited or deleted by json clients (default 300)
+    --flood-threshold N          messages per window that put a room in slow mode, 0 for off (default 0)
+    --flood-window SECS          window the room message rate is measured over (default 10)
+    --flood-slowmode SECS        slow mode interval used for flooded rooms (default 2)
+    --lag-threshold PCT          percent of clients falling behind that puts everyone in slow mode, 0 for off (default 0)
+    --lag-window SECS            window falling behind is counted over (default 10)
+    --join-burst N               summarise join and leave notices past N per window, 0 for off (default 0)
+    --join-window SECS           window join and leave notices are counted over (default 5)
+    --no-room-tags               leave the [#room] tag off messages for plain text clients
+    --timestamps                 put the time in utc in front of messages for plain text clients, /tz changes it
+    --resolve-peers              log each connection with the reverse dns name of the peer
+    --delimiter lf|crlf|nul      what messages are terminated with (default lf)
+    --write-policy POLICY        immediate writes each line to the socket as it comes, batched gathers them up first (default immediate)
+    --flush-ms MS                longest a batched line waits to be written (default 5)
+    --echo                       echo every line back to its sender instead of chatting
+    --echo-own                   send clients their own messages too, /echo off turns it off for one
+    --connect ADDR               run as a client of the server at ADDR
+    --check-config               check the settings and print them without starting the server
+    --selftest                   start on a free port, check two clients can chat and exit 1 if not";
+
+// names are shown in front of every line, anything longer than this would crowd out the text
+const MAX_USERNAME_LEN: usize = 64;
+
+fn number<T: FromStr>(arg: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{arg} expects a number, got {value}"))
+}
+
+// an address to listen on, with either a single port or a range of them
+fn listen_addr(addr: String) -> Result<String, String> {
+    port_range(&addr)?;
+    Ok(addr)
+}
+
+// splits host:first-last into the host and its ports, None for an address with a single
+// port. listening on a range means taking the first port in it that is free
+pub fn port_range(addr: &str) -> Result<Option<(&str, RangeInclusive<u16>)>, String> {
+    let Some((host, ports)) = addr.rsplit_once(':') else {
+        return Ok(None);
+    };
+    let Some((first, last)) = ports.split_once('-') else {
+        return Ok(None);
+    };
+    match (first.parse::<u16>(), last.parse::<u16>()) {
+        (Ok(first), Ok(last)) if first <= last => Ok(Some((host, first..=last))),
+        _ => Err(format!(
+            "bad port range in {addr}, expected host:first-last"
+        )),
+    }
+}
+
+fn history_size(arg: &str, value: String) -> Result<usize, String> {
+    let size = number(arg, value)?;
+    if size > MAX_HISTORY {
+        return Err(format!("{arg} can be at most {MAX_HISTORY}"));
+    }
+    Ok(size)
+}
+
+impl Config {
+    // parses the process arguments, printing usage and exiting on bad input
+    pub fn from_args() -> Config {
+        match Config::parse(std::env::args().skip(1)) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{err}\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    pub fn parse<I>(args: I) -> Result<Config, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--echo" => config.echo = true,
+                "--echo-own" => config.echo_own = true,
+                "--no-room-tags" => config.room_tags = false,
+                "--timestamps" => config.timestamps = true,
+                "--check-config" => config.check_config = true,
+                // also as a bare word, like a subcommand, for deployment pipelines
+                "--selftest" | "selftest" => config.selftest = true,
+                "--resolve-peers" => config.resolve_peers = true,
+                "--show-occupancy" => config.show_occupancy = true,
+                "--greeting" => config.greeting = Some(greeting::parse_steps(&value()?)?),
+                "--wrap-width" => config.wrap_width = number(&arg, value()?)?,
+                "--lobby" => config.lobby = true,
+                "--listen" => config.listen = listen_addr(value()?)?,
+                "--delimiter" => config.delimiter = value()?.parse()?,
+                "--write-policy" => config.write_policy = value()?.parse()?,
+                "--flush-ms" => {
+                    config.flush_interval = Duration::from_millis(number(&arg, value()?)?)
+                }
+                "--connect" => config.connect = Some(value()?),
+                "--admin-listen" => config.admin_listen = Some(listen_addr(value()?)?),
+                "--http-listen" => config.http_listen = Some(listen_addr(value()?)?),
+                "--metrics-listen" | "--metrics-addr" => {
+                    config.metrics_listen = Some(listen_addr(value()?)?)
+                }
+                "--tls-listen" => config.tls_listen = Some(listen_addr(value()?)?),
+                "--tls-cert" => config.tls_cert = Some(value()?.into()),
+                "--tls-key" => config.tls_key = Some(value()?.into()),
+                "--bot-token" => config.bot_token = Some(value()?),
+                "--webhook" => config.webhook = Some(value()?.parse()?),
+                "--store-file" => config.store_file = Some(value()?.into()),
+                "--store-db" => {
+                    if cfg!(not(feature = "sqlite")) {
+                        return Err(format!(
+                            "{arg} needs the server built with --features sqlite"
+                        ));
+                    }
+                    config.store_db = Some(value()?.into())
+                }
+                "--upload-dir" => config.upload_dir = Some(value()?.into()),
+                "--upload-max-bytes" => config.upload_max_bytes = number(&arg, value()?)?,
+                "--upload-total-bytes" => config.upload_total_bytes = number(&arg, value()?)?,
+                "--upload-url" => {
+                    config.upload_url = Some(value()?.trim_end_matches('/').to_string())
+                }
+                "--upload-ttl" => config.upload_ttl = Duration::from_secs(number(&arg, value()?)?),
+                "--allow" => config.allow.push(value()?.parse()?),
+                "--state-file" => config.state_file = Some(value()?.into()),
+                "--state-interval" => {
+                    config.state_interval = Duration::from_secs(number(&arg, value()?)?);
+                    if config.state_interval.is_zero() {
+                        return Err("--state-interval has to be at least 1".to_string());
+                    }
+                }
+                "--motd" => config.motd = Some(value()?.into()),
+                "--motd-mode" => config.motd_mode = value()?.parse()?,
+                "--server-full-message" => config.server_full_message = value()?,
+                "--backlog" => {
+                    config.backlog = number(&arg, value()?)?;
+                    if config.backlog == 0 {
+                        return Err("--backlog has to be at least 1".to_string());
+                    }
+                }
+                "--max-clients" => config.max_clients = number(&arg, value()?)?,
+                "--accept-delay" => {
+                    config.accept_delay = Duration::from_millis(number(&arg, value()?)?)
+                }
+                "--tcp-keepalive-secs" => {
+                    config.tcp_keepalive = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--max-json-bytes" => config.max_json_bytes = number(&arg, value()?)?,
+                "--max-username-len" => {
+                    config.max_username_len = number(&arg, value()?)?;
+                    if !(1..=MAX_USERNAME_LEN).contains(&config.max_username_len) {
+                        return Err(format!(
+                            "--max-username-len has to be 1 to {MAX_USERNAME_LEN}"
+                        ));
+                    }
+                }
+                "--max-message-len" => config.max_message_len = number(&arg, value()?)?,
+                "--truncate-long-messages" => config.truncate_long_messages = true,
+                "--max-rooms" => {
+                    config.max_rooms = number(&arg, value()?)?;
+                    if config.max_rooms == 0 {
+                        return Err("--max-rooms has to be at least 1".to_string());
+                    }
+                }
+                "--rooms-page-size" => {
+                    config.rooms_page_size = number(&arg, value()?)?;
+                    if config.rooms_page_size == 0 {
+                        return Err("--rooms-page-size has to be at least 1".to_string());
+                    }
+                }
+                "--export-role" => config.export_role = value()?.parse()?,
+                "--secure-room" => {
+                    let room = value()?;
+                    validate_room_name(&room)?;
+                    if room == DEFAULT_ROOM {
+                        return Err(format!(
+                            "{arg} can't be {DEFAULT_ROOM}, every client starts out there"
+                        ));
+                    }
+                    config.secure_rooms.insert(room);
+                }
+                "--history" => config.history_size = history_size(&arg, value()?)?,
+                "--history-ttl" => {
+                    config.history_ttl = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--empty-room-grace" => {
+                    config.empty_room_grace = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--room-history" => {
+                    let value = value()?;
+                    let Some((room, size)) = value.split_once('=') else {
+                        return Err(format!("{arg} expects #room=N, got {value}"));
+                    };
+                    validate_room_name(room)?;
+                    let size = history_size(&arg, size.to_string())?;
+                    config.room_history.insert(room.to_string(), size);
+                }
+                "--max-repeats" => config.max_repeats = number(&arg, value()?)?,
+                "--rate-limit" => config.rate_limit = number(&arg, value()?)?,
+                "--rate-burst" => config.rate_burst = number(&arg, value()?)?,
+                "--rate-grace" => config.rate_grace = Duration::from_secs(number(&arg, value()?)?),
+                "--frame-limit" => config.frame_limit = number(&arg, value()?)?,
+                "--frame-burst" => config.frame_burst = number(&arg, value()?)?,
+                "--lag-threshold" => config.lag_threshold = number(&arg, value()?)?,
+                "--lag-window" => config.lag_window = Duration::from_secs(number(&arg, value()?)?),
+                "--join-burst" => config.join_burst = number(&arg, value()?)?,
+                "--join-window" => {
+                    config.join_window = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--flood-threshold" => config.flood_threshold = number(&arg, value()?)?,
+                "--flood-window" => {
+                    config.flood_window = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--flood-slowmode" => {
+                    config.flood_slow_mode = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--edit-window" => {
+                    config.edit_window = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--partial-timeout" => {
+                    config.partial_timeout = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--keep-unterminated" => config.keep_unterminated = true,
+                "--registration-timeout" => {
+                    config.registration_timeout = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--min-protocol-version" => {
+                    config.min_protocol_version = number(&arg, value()?)?;
+                    if config.min_protocol_version > PROTOCOL_VERSION {
+                        return Err(format!(
+                            "{arg} can be at most {PROTOCOL_VERSION}, no client could get in"
+                        ));
+                    }
+                }
+                "--idle-timeout" => {
+                    config.idle_timeout = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--idle-warning" => {
+                    config.idle_warning = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--max-lifetime" => {
+                    config.max_lifetime = Duration::from_secs(number(&arg, value()?)?)
+                }
+                "--resume-window" => {
+                    config.resume_window = Duration::from_secs(number(&arg, value()?)?)
+                }
+                _ => return Err(format!("unknown argument: {arg}")),
+            }
+        }
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            return Err("--tls-cert and --tls-key go together".to_string());
+        }
+        if config.tls_listen.is_some() && config.tls_cert.is_none() {
+            return Err("--tls-listen needs --tls-cert and --tls-key".to_string());
+        }
+        if config.store_file.is_some() && config.store_db.is_some() {
+            return Err("--store-file and --store-db can't both be given".to_string());
+        }
+        Ok(config)
+    }
+
+    // the rejection for a client over --max-clients, with the placeholders filled in
+    pub fn server_full(&self) -> String {
+        self.server_full_message
+            .replace("{cap}", &self.max_clients.to_string())
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+
+    // what parsing alone can't catch: addresses that don't resolve, files that can't be read,
+    // settings that do nothing. blocks while resolving and reading, so only for --check-config
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let addrs = [
+            ("--listen", Some(&self.listen)),
+            ("--admin-listen", self.admin_listen.as_ref()),
+            ("--http-listen", self.http_listen.as_ref()),
+            ("--metrics-listen", self.metrics_listen.as_ref()),
+            ("--tls-listen", self.tls_listen.as_ref()),
+            ("--connect", self.connect.as_ref()),
+        ];
+        let mut resolved: Vec<(&str, SocketAddr)> = Vec::new();
+        for (arg, addr) in addrs {
+            let Some(addr) = addr else {
+                continue;
+            };
+            // a range is checked by its first port, which ones are free is only known later
+            let probe = match port_range(addr) {
+                Ok(Some((host, ports))) => format!("{host}:{}", ports.start()),
+                _ => addr.clone(),
+            };
+            match probe.to_socket_addrs() {
+                Ok(mut addrs) => match addrs.next() {
+                    Some(first) if arg != "--connect" => {
+                        if let Some((other, _)) = resolved.iter().find(|(_, a)| *a == first) {
+                            problems.push(format!("{arg} {addr} is the same address as {other}"));
+                        }
+                        resolved.push((arg, first));
+                    }
+                    Some(_) => {}
+                    None => problems.push(format!("{arg} {addr} doesn't resolve to anything")),
+                },
+                Err(err) => problems.push(format!("{arg} {addr} is not a usable address: {err}")),
+            }
+        }
+        if let Some(path) = &self.motd {
+            if let Err(err) = Motd::load(path, self.motd_mode) {
+                problems.push(format!("--motd {}: {err}", path.display()));
+            }
+        }
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            if let Err(err) = tls::acceptor(cert, key) {
+                problems.push(err.to_string());
+            }
+        }
+        let mut template = self.server_full_message.as_str();
+        while let Some(start) = template.find('{') {
+            let rest = &template[start..];
+            let end = rest.find('}').map_or(rest.len(), |end| end + 1);
+            if &rest[..end] != "{cap}" {
+                problems.push(format!(
+                    "--server-full-message has {}, only {{cap}} is filled in",
+                    &rest[..end]
+                ));
+            }
+            template = &rest[end..];
+        }
+        if self.show_occupancy && self.greeting.is_some() {
+            problems.push(
+                "--show-occupancy does nothing with --greeting, add occupancy to the steps"
+                    .to_string(),
+            );
+        }
+        if self.bot_token.is_some() && self.http_listen.is_none() {
+            problems.push("--bot-token does nothing without --http-listen".to_string());
+        }
+        if self.upload_dir.is_some() && self.http_listen.is_none() {
+            problems.push("--upload-dir does nothing without --http-listen".to_string());
+        }
+        problems
+    }
+
+    // the --greeting steps, or what --lobby and --show-occupancy make of the usual ones
+    pub fn greeting_steps(&self) -> Vec<GreetingStep> {
+        match &self.greeting {
+            Some(steps) => steps.clone(),
+            None => greeting::default_steps(self.lobby, self.show_occupancy),
+        }
+    }
+
+    // where a shared file can be downloaded from
+    pub fn file_url(&self, token: &str) -> String {
+        match &self.upload_url {
+            Some(base) => format!("{base}/files/{token}"),
+            None => {
+                let addr = self.http_listen.as_deref().unwrap_or_default();
+                format!("http://{addr}/files/{token}")
+            }
+        }
+    }
+
+    // every setting the way it would be given on the command line, one per line
+    pub fn summary(&self) -> String {
+        fn secs(duration: Duration) -> String {
+            duration.as_secs().to_string()
+        }
+        fn or_off(value: Option<impl ToString>) -> String {
+            value.map_or_else(|| "off".to_string(), |value| value.to_string())
+        }
+        let mut room_history: Vec<String> = self
+            .room_history
+            .iter()
+            .map(|(room, size)| format!("{room}={size}"))
+            .collect();
+        room_history.sort();
+        let mut secure_rooms: Vec<&str> = self.secure_rooms.iter().map(String::as_str).collect();
+        secure_rooms.sort();
+        let allow: Vec<String> = self.allow.iter().map(ToString::to_string).collect();
+        let settings = [
+            ("--listen", self.listen.clone()),
+            ("--backlog", self.backlog.to_string()),
+            ("--admin-listen", or_off(self.admin_listen.as_ref())),
+            ("--http-listen", or_off(self.http_listen.as_ref())),
+            ("--metrics-listen", or_off(self.metrics_listen.as_ref())),
+            ("--tls-listen", or_off(self.tls_listen.as_ref())),
+            (
+                "--tls-cert",
+                or_off(self.tls_cert.as_ref().map(|path| path.display())),
+            ),
+            (
+                "--tls-key",
+                or_off(self.tls_key.as_ref().map(|path| path.display())),
+            ),
+            (
+                "--bot-token",
+                or_off(self.bot_token.as_ref().map(|_| "set")),
+            ),
+            ("--webhook", or_off(self.webhook.as_ref())),
+            (
+                "--store-file",
+                or_off(self.store_file.as_ref().map(|path| path.display())),
+            ),
+            (
+                "--store-db",
+                or_off(self.store_db.as_ref().map(|path| path.display())),
+            ),
+            (
+                "--upload-dir",
+                or_off(self.upload_dir.as_ref().map(|path| path.display())),
+            ),
+            ("--upload-max-bytes", self.upload_max_bytes.to_string()),
+            ("--upload-total-bytes", self.upload_total_bytes.to_string()),
+            ("--upload-ttl", secs(self.upload_ttl)),
+            ("--upload-url", self.file_url("TOKEN")),
+            (
+                "--allow",
+                if allow.is_empty() {
+                    "any".to_string()
+                } else {
+                    allow.join(", ")
+                },
+            ),
+            (
+                "--state-file",
+                or_off(self.state_file.as_ref().map(|path| path.display())),
+            ),
+            ("--state-interval", secs(self.state_interval)),
+            (
+                "--motd",
+                or_off(self.motd.as_ref().map(|path| path.display())),
+            ),
+            ("--motd-mode", self.motd_mode.to_string()),
+            ("--max-clients", self.max_clients.to_string()),
+            (
+                "--accept-delay",
+                format!("{}ms", self.accept_delay.as_millis()),
+            ),
+            ("--tcp-keepalive-secs", secs(self.tcp_keepalive)),
+            ("--server-full-message", self.server_full_message.clone()),
+            ("--show-occupancy", self.show_occupancy.to_string()),
+            ("--greeting", greeting::list(&self.greeting_steps())),
+            ("--wrap-width", self.wrap_width.to_string()),
+            ("--lobby", self.lobby.to_string()),
+            ("--max-json-bytes", self.max_json_bytes.to_string()),
+            ("--max-username-len", self.max_username_len.to_string()),
+            ("--max-message-len", self.max_message_len.to_string()),
+            (
+                "--truncate-long-messages",
+                self.truncate_long_messages.to_string(),
+            ),
+            ("--max-rooms", self.max_rooms.to_string()),
+            ("--rooms-page-size", self.rooms_page_size.to_string()),
+            ("--export-role", self.export_role.as_str().to_string()),
+            ("--secure-room", secure_rooms.join(", ")),
+            ("--history", self.history_size.to_string()),
+            ("--history-ttl", secs(self.history_ttl)),
+            ("--empty-room-grace", secs(self.empty_room_grace)),
+            ("--room-history", room_history.join(", ")),
+            ("--partial-timeout", secs(self.partial_timeout)),
+            ("--keep-unterminated", self.keep_unterminated.to_string()),
+            ("--registration-timeout", secs(self.registration_timeout)),
+            (
+                "--min-protocol-version",
+                self.min_protocol_version.to_string(),
+            ),
+            ("--idle-timeout", secs(self.idle_timeout)),
+            ("--idle-warning", secs(self.idle_warning)),
+            ("--max-lifetime", secs(self.max_lifetime)),
+            ("--resume-window", secs(self.resume_window)),
+            ("--max-repeats", self.max_repeats.to_string()),
+            ("--rate-limit", self.rate_limit.to_string()),
+            ("--rate-burst", self.rate_burst.to_string()),
+            ("--rate-grace", secs(self.rate_grace)),
+            ("--frame-limit", self.frame_limit.to_string()),
+            ("--frame-burst", self.frame_burst.to_string()),
+            ("--edit-window", secs(self.edit_window)),
+            ("--flood-threshold", self.flood_threshold.to_string()),
+            ("--flood-window", secs(self.flood_window)),
+            ("--flood-slowmode", secs(self.flood_slow_mode)),
+            ("--lag-threshold", self.lag_threshold.to_string()),
+            ("--lag-window", secs(self.lag_window)),
+            ("--join-burst", self.join_burst.to_string()),
+            ("--join-window", secs(self.join_window)),
+            ("--no-room-tags", (!self.room_tags).to_string()),
+            ("--timestamps", self.timestamps.to_string()),
+            ("--resolve-peers", self.resolve_peers.to_string()),
+            ("--delimiter", self.delimiter.to_string()),
+            ("--write-policy", self.write_policy.to_string()),
+            ("--flush-ms", self.flush_interval.as_millis().to_string()),
+            ("--echo", self.echo.to_string()),
+            ("--echo-own", self.echo_own.to_string()),
+            ("--connect", or_off(self.connect.as_ref())),
+        ];
+        let width = settings.iter().map(|(arg, _)| arg.len()).max().unwrap_or(0);
+        let mut summary = String::new();
+        for (arg, value) in settings {
+            let _ = writeln!(summary, "{arg:width$}  {value}");
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn the_greeting_is_checked_when_parsed() {
+        let config = parse(&["--greeting", "banner,name,motd"]).unwrap();
+        assert_eq!(
+            config.greeting_steps(),
+            [GreetingStep::Banner, GreetingStep::Name, GreetingStep::Motd]
+        );
+        assert!(parse(&["--greeting", "banner,motd"]).is_err());
+        let config = parse(&["--show-occupancy"]).unwrap();
+        assert!(config.greeting_steps().contains(&GreetingStep::Occupancy));
+    }
+
+    #[test]
+    fn a_page_of_rooms_holds_at_least_one() {
+        assert_eq!(
+            parse(&["--rooms-page-size", "5"]).unwrap().rooms_page_size,
+            5
+        );
+        assert_eq!(
+            parse(&["--rooms-page-size", "0"]).err().as_deref(),
+            Some("--rooms-page-size has to be at least 1")
+        );
+    }
+
+    #[test]
+    fn the_minimum_protocol_is_one_the_server_speaks() {
+        let config = parse(&["--min-protocol-version", &PROTOCOL_VERSION.to_string()]).unwrap();
+        assert_eq!(config.min_protocol_version, PROTOCOL_VERSION);
+        let above = (PROTOCOL_VERSION + 1).to_string();
+        assert_eq!(
+            parse(&["--min-protocol-version", &above]).err(),
+            Some(format!(
+                "--min-protocol-version can be at most {PROTOCOL_VERSION}, no client could get in"
+            ))
+        );
+    }
+
+    #[test]
+    fn echo_own_is_not_the_echo_mode() {
+        let config = parse(&["--echo-own"]).unwrap();
+        assert!(config.echo_own && !config.echo);
+        let config = parse(&["--echo"]).unwrap();
+        assert!(config.echo && !config.echo_own);
+    }
+
+    #[test]
+    fn port_ranges() {
+        assert_eq!(port_range("127.0.0.1:8080"), Ok(None));
+        assert_eq!(
+            port_range("127.0.0.1:8080-8090"),
+            Ok(Some(("127.0.0.1", 8080..=8090)))
+        );
+        assert_eq!(
+            port_range("[::1]:9000-9000"),
+            Ok(Some(("[::1]", 9000..=9000)))
+        );
+        assert_eq!(port_range("localhost"), Ok(None));
+        for bad in ["127.0.0.1:8090-8080", "127.0.0.1:80-", "127.0.0.1:1-65536"] {
+            assert_eq!(
+                port_range(bad),
+                Err(format!("bad port range in {bad}, expected host:first-last"))
+            );
+        }
+        assert_eq!(
+            parse(&["--listen", "0.0.0.0:9-1"]).err().as_deref(),
+            Some("bad port range in 0.0.0.0:9-1, expected host:first-last")
+        );
+        let config = parse(&["--http-listen", "127.0.0.1:8000-8010"]).unwrap();
+        assert_eq!(config.http_listen.as_deref(), Some("127.0.0.1:8000-8010"));
+    }
+
+    #[test]
+    fn name_and_message_limits_are_separate() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(
+            (config.max_username_len, config.max_message_len),
+            (32, 1024)
+        );
+        let config = parse(&["--max-username-len", "8", "--max-message-len", "0"]).unwrap();
+        assert_eq!((config.max_username_len, config.max_message_len), (8, 0));
+    }
+
+    #[test]
+    fn the_name_limit_has_to_be_reasonable() {
+        assert_eq!(
+            parse(&["--max-username-len", "64"])
+                .unwrap()
+                .max_username_len,
+            64
+        );
+        assert_eq!(
+            parse(&["--max-username-len", "1"])
+                .unwrap()
+                .max_username_len,
+            1
+        );
+        for bad in ["0", "65"] {
+            assert_eq!(
+                parse(&["--max-username-len", bad]).err().as_deref(),
+                Some("--max-username-len has to be 1 to 64")
+            );
+        }
+        assert_eq!(
+            parse(&["--max-message-len", "lots"]).err().as_deref(),
+            Some("--max-message-len expects a number, got lots")
+        );
+    }
+}
@@ -0,0 +1,1058 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use tokio::{
+    io::{self, AsyncBufRead, AsyncRead, AsyncWrite, BufReader},
+    net::TcpStream,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch,
+    },
+    time::{sleep, sleep_until, timeout, Instant},
+};
+use tokio_rustls::server::TlsStream;
+
+use crate::{
+    admin,
+    auth::{AuthResult, Role},
+    commands::{self, Command, Lobby},
+    config::Config,
+    errors::{reply_error, ChatError, ErrorCode},
+    framing::{sanitize_line, FrameError, Framed},
+    greeting::GreetingStep,
+    json::Value,
+    outbound::{Closed, Outbound, Outgoing, WritePolicy},
+    protocol::{self, Protocol, Request, PROTOCOL_VERSION},
+    ratelimit::TokenBucket,
+    server::stopped,
+    state::{
+        validate_name, ClientId, DisconnectReason, EditError, Event, Peer, PostError,
+        RegisterError, Registration, Shared, DEFAULT_ROOM,
+    },
+    upload::UploadError,
+};
+
+// the per connection view of a client, kept in step with its entry in the registry
+pub struct Session {
+    pub id: ClientId,
+    pub name: String,
+    // where plain chat lines go
+    pub room: String,
+    // every room the client hears from, room among them
+    pub rooms: HashSet<String>,
+    pub role: Role,
+    // see Peer::admin_listener
+    pub admin_listener: bool,
+    pub protocol: Protocol,
+    // a copy of the ignore list in the registry, for edits and what a resume missed. chat
+    // lines are picked out by the broadcaster
+    pub ignored: HashSet<String>,
+    // and of the client's aliases, so commands can be expanded without it
+    pub aliases: HashMap<String, String>,
+    // whether the client's own chat messages come back to it, /echo switches it
+    pub echo: bool,
+    // set by /quit, the connection is closed once the reply is out
+    pub quit: bool,
+}
+
+// runs a single client connection until it disconnects or the server shuts down
+pub async fn handle(
+    socket: TcpStream,
+    peer: Peer,
+    shared: Arc<Shared>,
+    shutdown: watch::Receiver<bool>,
+) {
+    // owned halves, so the write half can move into its own task
+    let (reader, writer) = socket.into_split();
+    serve(reader, writer, peer, shared, shutdown).await;
+}
+
+// the same over tls, once the handshake is done. a tls stream only splits into halves that
+// share a lock, which is taken for each read or write rather than held while waiting
+pub async fn handle_tls(
+    stream: TlsStream<TcpStream>,
+    peer: Peer,
+    shared: Arc<Shared>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let (reader, writer) = io::split(stream);
+    serve(reader, writer, peer, shared, shutdown).await;
+}
+
+async fn serve<R, W>(
+    reader: R,
+    writer: W,
+    peer: Peer,
+    shared: Arc<Shared>,
+    shutdown: watch::Receiver<bool>,
+) where
+    R: AsyncRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let config = &shared.config;
+    let out = Outbound::spawn(
+        writer,
+        config.delimiter.as_str(),
+        config.room_tags,
+        config.wrap_width,
+        config.timestamps.then_some(0),
+        (config.write_policy == WritePolicy::Batched).then_some(config.flush_interval),
+        shared.stats.writes_total.clone(),
+    );
+    converse(reader, &out, shared, peer, shutdown).await;
+    // give the writer a moment to get the last lines out, a client that has stopped reading
+    // doesn't get to hold up a shutdown
+    let _ = timeout(LINGER, out.close()).await;
+}
+
+const LINGER: Duration = Duration::from_secs(1);
+
+// what a client that stopped halfway through a message is told before it is dropped
+const STALLED: &str = "Incomplete message timed out";
+const SHUTTING_DOWN: &str = "*** the server is shutting down ***";
+
+// how long a client taking over a name waits for the old session to go, all told about a second
+const REPLACE_ATTEMPTS: u32 = 20;
+const REPLACE_DELAY: Duration = Duration::from_millis(50);
+// /export sends a transcript this long straight to the client, longer ones go to a file
+const EXPORT_LINES: usize = 50;
+
+async fn converse<R>(
+    reader: R,
+    out: &Outbound,
+    shared: Arc<Shared>,
+    peer: Peer,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    R: AsyncRead + Unpin + Send,
+{
+    // tokio supplies us with BuffReader
+    // a buff reader wraps any kind of reader and maintains its own buffer
+    // and it allows you to run some higher order operations such as reading an entire line of text from a stream
+    // counted from the connect rather than from registering, so the name prompt is included
+    let max_lifetime = shared.config.max_lifetime;
+    let expires_at = Instant::now() + max_lifetime;
+    let reader = BufReader::new(reader);
+    // cuts what comes in into messages on the configured delimiter
+    let mut reader = Framed::new(reader, shared.config.delimiter);
+    let partial_timeout = shared.config.partial_timeout;
+    reader.set_partial_timeout((!partial_timeout.is_zero()).then_some(partial_timeout));
+    reader.set_keep_unterminated(shared.config.keep_unterminated);
+
+    if shared.config.echo {
+        reader.set_limit(plain_limit(&shared.config));
+        tokio::select! {
+            _ = echo(&mut reader, out, plain_limit(&shared.config).unwrap_or_default()) => {}
+            _ = stopped(&mut shutdown) => {}
+        }
+        return;
+    }
+
+    // admins are never thrown out by a /quitall, they're the ones sending it. until a client
+    // has registered only the listener it came in on says whether it is one
+    let mut evict = shared.evictions();
+    // the first line may turn out to be json, and a name never needs to be long anyway
+    let max_json = shared.config.max_json_bytes;
+    reader.set_limit((max_json > 0).then_some(max_json));
+    // the registration removes the client from the registry again when this function returns.
+    // register only ever takes one message at a time off the reader, and the same reader
+    // carries on below, so lines a client sends straight after its name without waiting for
+    // the welcome are still buffered and handled as chat once it is registered
+    let registered = tokio::select! {
+        registered = register(&mut reader, out, &shared, peer) => registered,
+        Ok(()) = evict.changed(), if peer.role != Role::Admin => {
+            hang_up(out, DisconnectReason::Drain, evict.borrow().to_string());
+            None
+        }
+        _ = stopped(&mut shutdown) => {
+            hang_up(out, DisconnectReason::Shutdown, SHUTTING_DOWN);
+            None
+        }
+    };
+    let Some((mut registration, mut session, mut rx)) = registered else {
+        return;
+    };
+    let max_line = match session.protocol {
+        Protocol::Plain => plain_limit(&shared.config),
+        Protocol::Json => (max_json > 0).then_some(max_json),
+    };
+    reader.set_limit(max_line);
+    // an idle client is warned first and dropped if it still says nothing, anything it sends
+    // starts the clock over
+    let idle_timeout = shared.config.idle_timeout;
+    let idle_warning = shared.config.idle_warning.min(idle_timeout);
+    let mut active_at = Instant::now();
+    let mut warned = idle_warning.is_zero();
+    let removed = registration.removed.clone();
+    // every line counts against this, unlike the rate limit which only counts chat messages
+    let config = &shared.config;
+    let mut frames = (config.frame_limit > 0).then(|| {
+        TokenBucket::new(
+            config.frame_limit,
+            config.frame_burst,
+            config.rate_grace,
+            Instant::now().into_std(),
+        )
+    });
+    // this inner infinite loop allows us to keep the connection alive after a message has been written
+    loop {
+        // select - also a golang concept, allows us to run multiple asynchrounous processes concurrently,
+        // and act on the first one that returns a result
+        // it has its own syntax due to its nature as a macro
+        // it requires an identifier, a future, and then its own block of code
+        // it will first run the future, it will assign the result of the future to the identifier that you give it
+        // and then it will run the block of code you give it.
+        tokio::select! {
+            message = reader.next() => {
+                // a read error means the connection is gone just like a zero length read
+                let Some((line, n)) = message else {
+                    registration.reason = DisconnectReason::Closed;
+                    break;
+                };
+                active_at = Instant::now();
+                warned = idle_warning.is_zero();
+                shared.stats.bytes_total.fetch_add(n as u64, Ordering::Relaxed);
+                if frames.as_mut().is_some_and(|frames| frames.take(active_at.into_std()).is_err()) {
+                    hang_up(out, DisconnectReason::Flooding, "Too many requests");
+                    registration.resumable = false;
+                    registration.reason = DisconnectReason::Flooding;
+                    break;
+                }
+                let handled = match line {
+                    Ok(line) => handle_line(&shared, &mut session, out, &sanitize_line(line)).await,
+                    Err(FrameError::TooLong) => too_long(out, max_line.unwrap_or_default()).await,
+                    Err(FrameError::Stalled) => {
+                        hang_up(out, DisconnectReason::Stalled, STALLED);
+                        registration.reason = DisconnectReason::Stalled;
+                        break;
+                    }
+                };
+                if handled.is_err() {
+                    break;
+                }
+                // leaving on purpose, there's nothing to come back to
+                if session.quit {
+                    registration.resumable = false;
+                    registration.reason = DisconnectReason::Quit;
+                    break;
+                }
+            }
+            received = receive(&mut rx, &shared, session.id, out) => {
+                let event = match received {
+                    Received::Event(event) => event,
+                    Received::Skipped => continue,
+                    Received::Gone => break,
+                    Received::Closed => {
+                        registration.reason = DisconnectReason::Shutdown;
+                        break;
+                    }
+                };
+                match event {
+                    // the broadcaster has already left out whoever ignores the sender
+                    Event::Message { from, entry, to } => {
+                        if session.rooms.contains(&*entry.room) && to.is_none_or(|to| to.contains(&session.id)) && (from != session.id || session.echo) && out.push(entry).is_err() {
+                            break;
+                        }
+                    }
+                    Event::Edit { entry } => {
+                        if session.rooms.contains(&*entry.room) && !session.ignored.contains(&entry.from) && out.push(Outgoing::Edit(entry)).is_err() {
+                            break;
+                        }
+                    }
+                    Event::Delete { room, seq } => {
+                        if session.rooms.contains(&*room) && out.push(Outgoing::Delete { room, seq }).is_err() {
+                            break;
+                        }
+                    }
+                    Event::Reaction(reaction) => {
+                        if session.rooms.contains(&*reaction.room) && out.push(Outgoing::Reaction(reaction)).is_err() {
+                            break;
+                        }
+                    }
+                    Event::Notice { room, text, except } => {
+                        if session.rooms.contains(&*room) && except != Some(session.id) && out.push(text).is_err() {
+                            break;
+                        }
+                    }
+                    Event::RoomClosed { room } => {
+                        // the registry was already updated by whoever deleted the room
+                        if session.rooms.remove(&room) {
+                            let notice = if room == session.room {
+                                session.room = DEFAULT_ROOM.to_string();
+                                session.rooms.insert(DEFAULT_ROOM.to_string());
+                                format!("*** {room} is closing, you have been moved to {DEFAULT_ROOM} ***")
+                            } else {
+                                format!("*** {room} is closing ***")
+                            };
+                            if out.push_urgent(notice).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Event::RoomRenamed { old, new } => {
+                        if session.rooms.remove(&old) {
+                            session.rooms.insert(new.clone());
+                            if session.room == old {
+                                session.room = new.clone();
+                            }
+                            if out.push_urgent(format!("*** {old} is now {new} ***")).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            () = sleep_until(idle_deadline(active_at, idle_timeout, idle_warning, warned)), if !idle_timeout.is_zero() => {
+                if warned {
+                    hang_up(out, DisconnectReason::Idle, "*** disconnected for inactivity ***");
+                    registration.reason = DisconnectReason::Idle;
+                    break;
+                }
+                warned = true;
+                let secs = idle_warning.as_secs();
+                let warning = format!("*** you will be disconnected in {secs}s due to inactivity ***");
+                if out.push_urgent(warning).is_err() {
+                    break;
+                }
+            }
+            // the token goes with it, the client has to start over with a fresh connection
+            () = sleep_until(expires_at), if !max_lifetime.is_zero() => {
+                hang_up(out, DisconnectReason::Expired, "Session expired, please reconnect");
+                registration.resumable = false;
+                registration.reason = DisconnectReason::Expired;
+                break;
+            }
+            (reason, notice) = removed.removed() => {
+                hang_up(out, reason, notice);
+                registration.resumable = false;
+                registration.reason = reason;
+                break;
+            }
+            Ok(()) = evict.changed(), if session.role != Role::Admin => {
+                hang_up(out, DisconnectReason::Drain, evict.borrow().to_string());
+                registration.resumable = false;
+                registration.reason = DisconnectReason::Drain;
+                break;
+            }
+            _ = stopped(&mut shutdown) => {
+                hang_up(out, DisconnectReason::Shutdown, SHUTTING_DOWN);
+                registration.reason = DisconnectReason::Shutdown;
+                break;
+            }
+        }
+        // define buffer in the form of a stack array
+        // 0u8;1024 is about one kilobyte
+        // 1024 bytes
+        // this is not a great approach to use as we have to constantly manage it
+        // let mut buffer = [0u8; 1024];
+        // async function, suspends function until read is done and then it will unwrap the results
+        // socket.read() returns the number of bytes that were from the stream onto the buffer
+        // we may receive less bytes than the size we set on our buffer so we use bytes_read to truncate that response
+        // let bytes_read = socket.read(&mut buffer).await.unwrap();
+        // let bytes_read = reader.read_line(&mut line).await.unwrap();
+        // theres a bug above, when we call read_line, it pins the line before above the new message
+        // it is not read_lines job to clear out the input buffer
+        // if bytes_read == 0 {
+        //     break;
+        // }
+        // tx.send(line.clone()).unwrap();
+        // let msg = rx.recv().await.unwrap();
+        // write_all() does not write a message to every single socket that is connected to a TCP listener, it
+        // instead it writes every single byte that is in the input buffer out to the output buffer
+        // socket.write_all(&buffer[..bytes_read]).await.unwrap();
+        // writer.write_all(&msg.as_bytes()).await.unwrap();
+        // clear() - clears out the input buffer
+        // line.clear();
+    }
+}
+
+// what came off the broadcast for one client
+enum Received {
+    Event(Event),
+    // it fell too far behind and skipped ahead, it has been told how much it missed
+    Skipped,
+    // telling it went nowhere, the client is gone
+    Gone,
+    // the sender only goes away with the server, nothing more is coming
+    Closed,
+}
+
+async fn receive(
+    rx: &mut broadcast::Receiver<Event>,
+    shared: &Arc<Shared>,
+    id: ClientId,
+    out: &Outbound,
+) -> Received {
+    match rx.recv().await {
+        Ok(event) => Received::Event(event),
+        // rather than holding anyone up
+        Err(RecvError::Lagged(missed)) => {
+            shared.note_lag(id);
+            match out.push(format!("*** you missed {missed} messages ***")) {
+                Ok(()) => Received::Skipped,
+                Err(Closed) => Received::Gone,
+            }
+        }
+        Err(RecvError::Closed) => Received::Closed,
+    }
+}
+
+// one line from a registered client: an admin query, a json request, a command or chat
+async fn handle_line(
+    shared: &Arc<Shared>,
+    session: &mut Session,
+    out: &Outbound,
+    line: &str,
+) -> Result<(), Closed> {
+    // admin queries are answered straight back to the admin instead of being broadcast. an
+    // admin role from the authenticator or a resume isn't enough, the query has to come in
+    // through the admin listener
+    if session.admin_listener && session.role == Role::Admin {
+        if let Some(reply) = admin::dispatch(shared, line) {
+            return out.send(Outgoing::Json(reply)).await;
+        }
+    }
+    if session.protocol == Protocol::Json && protocol::is_json(line) {
+        return match protocol::parse_request(line) {
+            Ok(Request::Message { body, reply_to, id }) => {
+                post(shared, session, out, body, reply_to, id).await
+            }
+            Ok(Request::Edit { seq, body }) => change(shared, session, out, seq, Some(body)).await,
+            Ok(Request::Delete { seq }) => change(shared, session, out, seq, None).await,
+            Ok(Request::React { seq, emoji }) => match shared.react(session.id, seq, emoji) {
+                Ok(()) => Ok(()),
+                Err(_) => {
+                    let text = format!("No message {seq} in your rooms");
+                    reply_error(out, ErrorCode::NoSuchMessage, &text).await
+                }
+            },
+            Ok(Request::Roster) => {
+                let roster = protocol::roster(&shared.registry());
+                out.send(Outgoing::Json(roster)).await
+            }
+            Ok(Request::Hello { .. } | Request::Resume { .. }) => {
+                reply_error(out, ErrorCode::BadRequest, "already registered").await
+            }
+            Err(err) => reply_error(out, err.code, &err.text).await,
+        };
+    }
+    let expanded = match commands::expand(line, &session.aliases) {
+        Ok(expanded) => expanded,
+        Err(err) => return reply_error(out, err.code, &err.text).await,
+    };
+    if let Some(command) = commands::parse(&expanded) {
+        let rooms = session.rooms.len();
+        let result = match command {
+            Ok(Command::Export(room)) => export(shared, session, out, room.as_deref()).await,
+            command => match command.and_then(|command| commands::run(shared, session, command)) {
+                Ok(Some(reply)) => out.send(reply).await,
+                Ok(None) => Ok(()),
+                Err(err) => reply_error(out, err.code, &err.text).await,
+            },
+        };
+        // a room that was just joined shows its pins, after the reply saying so
+        if session.rooms.len() > rooms {
+            send_pins(out, shared, &session.room).await?;
+        }
+        return result;
+    }
+    let text = line.trim_end_matches(['\r', '\n']).to_string();
+    post(shared, session, out, text, None, None).await
+}
+
+// a short transcript is sent as it is. a longer one is stored as a file to download when file
+// sharing is on, or else only its end is sent
+async fn export(
+    shared: &Shared,
+    session: &Session,
+    out: &Outbound,
+    room: Option<&str>,
+) -> Result<(), Closed> {
+    let (room, lines) = match commands::transcript(shared, session, room) {
+        Ok(transcript) => transcript,
+        Err(err) => return reply_error(out, err.code, &err.text).await,
+    };
+    if lines.is_empty() {
+        let text = format!("Nothing to export, {room} has no history");
+        return out.send(text).await;
+    }
+    let count = match lines.len() {
+        1 => "1 message".to_string(),
+        n => format!("{n} messages"),
+    };
+    let uploads = shared.uploads.as_ref();
+    if let Some(uploads) = uploads.filter(|_| lines.len() > EXPORT_LINES) {
+        let name = format!("{}-transcript.txt", room.trim_start_matches('#'));
+        let mut text = lines.join("\n");
+        text.push('\n');
+        match uploads.store(&name, text.as_bytes()).await {
+            Ok(token) => {
+                let url = shared.config.file_url(&token);
+                return out
+                    .send(format!("Transcript of {room}, {count}: {url}"))
+                    .await;
+            }
+            Err(UploadError::Io(err)) => eprintln!("can't store a transcript: {err}"),
+            // too big for the uploads, the end of it will have to do
+            Err(_) => {}
+        }
+    }
+    let skip = lines.len().saturating_sub(EXPORT_LINES);
+    let header = if skip == 0 {
+        format!("Transcript of {room}, {count}:")
+    } else {
+        format!("Transcript of {room}, the last {EXPORT_LINES} of {count}:")
+    };
+    out.send(header).await?;
+    for line in lines.into_iter().skip(skip) {
+        out.send(line).await?;
+    }
+    Ok(())
+}
+
+// the notice a client is disconnected with, a disconnect frame for json clients
+fn hang_up(out: &Outbound, reason: DisconnectReason, text: impl Into<String>) {
+    let _ = out.push_urgent(Outgoing::Disconnect(reason, text.into()));
+}
+
+async fn too_long(out: &Outbound, limit: usize) -> Result<(), Closed> {
+    let text = format!("Too large, messages can be at most {limit} bytes");
+    reply_error(out, ErrorCode::TooLarge, &text).await
+}
+
+// an edit, or a delete when there is no new text
+async fn change(
+    shared: &Shared,
+    session: &Session,
+    out: &Outbound,
+    seq: u64,
+    body: Option<String>,
+) -> Result<(), Closed> {
+    let (code, text) = match shared.edit(session.id, seq, body) {
+        Ok(()) => return Ok(()),
+        Err(EditError::NoSuchMessage) => (
+            ErrorCode::NoSuchMessage,
+            format!("No message {seq} in your rooms"),
+        ),
+        Err(EditError::NotYours) => (
+            ErrorCode::PermissionDenied,
+            "You can only change your own messages".to_string(),
+        ),
+        Err(EditError::TooOld) => (
+            ErrorCode::PermissionDenied,
+            "That message is too old to change".to_string(),
+        ),
+        Err(EditError::TooLong) => (ErrorCode::TooLarge, message_too_long(shared)),
+    };
+    reply_error(out, code, &text).await
+}
+
+async fn post(
+    shared: &Shared,
+    session: &Session,
+    out: &Outbound,
+    text: String,
+    reply_to: Option<u64>,
+    id: Option<String>,
+) -> Result<(), Closed> {
+    // someone just pressing enter, nothing worth showing the room
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    match shared.post(session.id, text, reply_to, id) {
+        Ok(()) => Ok(()),
+        Err(err) => match post_error(shared, session, err) {
+            Some(err) => reply_error(out, err.code, &err.text).await,
+            None => Ok(()),
+        },
+    }
+}
+
+// what the client is told about a message that didn't go out, if anything
+pub fn post_error(shared: &Shared, session: &Session, err: PostError) -> Option<ChatError> {
+    let (code, text) = match err {
+        // the first one already went out, a resend is quietly dropped
+        PostError::Duplicate => return None,
+        PostError::Repeated => (ErrorCode::Repeated, "Stop repeating yourself".to_string()),
+        PostError::SlowMode(wait) => {
+            // round up, "wait 0 seconds" would just be confusing
+            let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            (
+                ErrorCode::SlowMode,
+                format!("Slow mode: wait {secs} seconds"),
+            )
+        }
+        PostError::RateLimited(wait) => (
+            ErrorCode::RateLimited,
+            format!("Too fast: wait {}ms", wait.as_millis().max(1)),
+        ),
+        PostError::TooLong => (ErrorCode::TooLarge, message_too_long(shared)),
+        PostError::NoSuchParent(parent) => (
+            ErrorCode::NoSuchMessage,
+            format!("No message {parent} in {} to reply to", session.room),
+        ),
+    };
+    Some(ChatError::new(code, text))
+}
+
+// the longest line a plain text client may send, in bytes: a message as long as it may be in
+// characters, each taking the most utf-8 can need, with a command like /msg name in front.
+// anything longer is thrown away as it arrives rather than buffered. --max-message-len 0
+// lifts this as well
+fn plain_limit(config: &Config) -> Option<usize> {
+    // what /msg, /reply and the like put in front of the message, and the name they take
+    const COMMAND_BYTES: usize = 64;
+
+    let max = config.max_message_len;
+    (max > 0).then(|| {
+        max.saturating_mul(4)
+            .saturating_add(config.max_username_len)
+            .saturating_add(COMMAND_BYTES)
+    })
+}
+
+fn message_too_long(shared: &Shared) -> String {
+    let max = shared.config.max_message_len;
+    format!("Message too long, at most {max} characters")
+}
+
+// the --echo mode loop, there is no registration, no rooms and nothing is broadcast
+async fn echo<R>(reader: &mut Framed<R>, out: &Outbound, limit: usize)
+where
+    R: AsyncBufRead + Unpin,
+{
+    while let Some((line, _)) = reader.next().await {
+        let line = match line {
+            Ok(line) => line,
+            Err(FrameError::TooLong) => {
+                if too_long(out, limit).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            Err(FrameError::Stalled) => {
+                let _ = out.push_urgent(STALLED.to_string());
+                break;
+            }
+        };
+        let reply = line.trim_end_matches(['\r', '\n']).to_string();
+        if out.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
+
+// asks for a name until the client picks a free one, or takes a resume token instead
+async fn register<R>(
+    reader: &mut Framed<R>,
+    out: &Outbound,
+    shared: &Arc<Shared>,
+    peer: Peer,
+) -> Option<(Registration, Session, broadcast::Receiver<Event>)>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let lobby = shared.config.lobby;
+    let steps = shared.config.greeting_steps();
+    let (before, after) = steps.split_at(
+        steps
+            .iter()
+            .position(|step| *step == GreetingStep::Name)
+            .unwrap_or(steps.len()),
+    );
+    greet(out, shared, before).await.ok()?;
+    let mut protocol = Protocol::Plain;
+    let limit = shared.config.registration_timeout;
+    loop {
+        let read = reader.next();
+        let message = if limit.is_zero() {
+            read.await
+        } else if let Ok(message) = timeout(limit, read).await {
+            message
+        } else {
+            hang_up(out, DisconnectReason::Idle, "Registration timed out");
+            return None;
+        };
+        let (line, n) = message?;
+        shared
+            .stats
+            .bytes_total
+            .fetch_add(n as u64, Ordering::Relaxed);
+        let line = match line {
+            Ok(line) => sanitize_line(line),
+            Err(FrameError::TooLong) => {
+                too_long(out, shared.config.max_json_bytes).await.ok()?;
+                continue;
+            }
+            Err(FrameError::Stalled) => {
+                hang_up(out, DisconnectReason::Stalled, STALLED);
+                return None;
+            }
+        };
+        let input = line.trim();
+        let speaking = if protocol::is_json(input) {
+            Protocol::Json
+        } else {
+            protocol
+        };
+        let min = shared.config.min_protocol_version;
+        if speaking.version() < min {
+            let _ = out.push_urgent(Outgoing::Error(
+                ErrorCode::ProtocolTooOld,
+                format!(
+                    "This server needs protocol version {min} or later, answer with {{\"type\":\"hello\",\"name\":\"...\"}} instead of plain text"
+                ),
+            ));
+            return None;
+        }
+        // the first json line decides the protocol, everything after it is written as json
+        let request = if protocol::is_json(input) {
+            if protocol == Protocol::Plain {
+                protocol = Protocol::Json;
+                out.send(Outgoing::SetProtocol(protocol)).await.ok()?;
+            }
+            match protocol::parse_request(input) {
+                Ok(request @ (Request::Hello { .. } | Request::Resume { .. })) => request,
+                Ok(_) => {
+                    reply_error(out, ErrorCode::BadRequest, "say hello first")
+                        .await
+                        .ok()?;
+                    continue;
+                }
+                Err(err) => {
+                    reply_error(out, err.code, &err.text).await.ok()?;
+                    continue;
+                }
+            }
+        } else if let Some(token) = input.strip_prefix("RESUME ") {
+            Request::Resume {
+                token: token.trim().to_string(),
+            }
+        } else if lobby {
+            match commands::lobby(shared, input) {
+                Ok(Lobby::Nick(args)) => hello(&args),
+                Ok(Lobby::Reply(text)) => {
+                    out.send(text).await.ok()?;
+                    continue;
+                }
+                Err(err) => {
+                    reply_error(out, err.code, &err.text).await.ok()?;
+                    continue;
+                }
+            }
+        } else {
+            hello(input)
+        };
+
+        let (name, secret) = match request {
+            Request::Resume { token } => {
+                let Ok(resumed) = shared.resume(&token, peer, out.handle()) else {
+                    reply_error(
+                        out,
+                        ErrorCode::InvalidToken,
+                        "Invalid or expired resume token",
+                    )
+                    .await
+                    .ok()?;
+                    continue;
+                };
+                let session = Session {
+                    id: resumed.registration.id,
+                    name: resumed.name,
+                    room: resumed.room,
+                    rooms: resumed.rooms,
+                    role: resumed.role,
+                    admin_listener: peer.admin_listener,
+                    protocol,
+                    ignored: resumed.ignored,
+                    aliases: resumed.aliases,
+                    echo: shared.config.echo_own,
+                    quit: false,
+                };
+                let token = &resumed.registration.token;
+                let welcome = match protocol {
+                    Protocol::Plain => format!(
+                        "Welcome back, {}! You are in {}. Your new resume token is {token}",
+                        session.name,
+                        rooms_line(&session)
+                    )
+                    .into(),
+                    Protocol::Json => Outgoing::Json(welcome_frame(&session, token, true)),
+                };
+                out.send(welcome).await.ok()?;
+                for entry in resumed.missed {
+                    if !session.ignored.contains(&entry.from) {
+                        out.send(entry).await.ok()?;
+                    }
+                }
+                return Some((resumed.registration, session, resumed.rx));
+            }
+            Request::Hello { name, secret } => (name, secret),
+            _ => unreachable!("only hello and resume get this far"),
+        };
+
+        if let Err(err) = validate_name(&name, shared.config.max_username_len) {
+            reply_error(out, ErrorCode::InvalidName, &err).await.ok()?;
+            continue;
+        }
+        let authenticator = shared.authenticator();
+        let peer = match authenticator.authenticate(&name, &secret).await {
+            AuthResult::Allow(role) => Peer {
+                role: peer.role.max(role),
+                ..peer
+            },
+            AuthResult::Deny(text) => {
+                reply_error(out, ErrorCode::AuthFailed, &text).await.ok()?;
+                continue;
+            }
+        };
+        let mut registered = shared.register(peer, out.handle(), &name);
+        // the name's old session is on its way out, give it a moment to finish
+        for _ in 0..REPLACE_ATTEMPTS {
+            if !matches!(registered, Err(RegisterError::Replacing)) {
+                break;
+            }
+            sleep(REPLACE_DELAY).await;
+            registered = shared.register(peer, out.handle(), &name);
+        }
+        match registered {
+            Ok((registration, rx)) => {
+                let session = Session {
+                    id: registration.id,
+                    name,
+                    room: DEFAULT_ROOM.to_string(),
+                    rooms: HashSet::from([DEFAULT_ROOM.to_string()]),
+                    role: peer.role,
+                    admin_listener: peer.admin_listener,
+                    protocol,
+                    ignored: HashSet::new(),
+                    aliases: HashMap::new(),
+                    echo: shared.config.echo_own,
+                    quit: false,
+                };
+                let welcome = match protocol {
+                    Protocol::Plain => format!(
+                        "Welcome, {}! Your resume token is {}",
+                        session.name, registration.token
+                    )
+                    .into(),
+                    Protocol::Json => {
+                        Outgoing::Json(welcome_frame(&session, &registration.token, false))
+                    }
+                };
+                out.send(welcome).await.ok()?;
+                greet(out, shared, after).await.ok()?;
+                send_pins(out, shared, DEFAULT_ROOM).await.ok()?;
+                return Some((registration, session, rx));
+            }
+            Err(RegisterError::NameTaken | RegisterError::Replacing) => {
+                let text = format!("The name {name} is taken, please pick another");
+                reply_error(out, ErrorCode::NameTaken, &text).await.ok()?;
+            }
+        }
+    }
+}
+
+// when the next stage of the idle timer is due, the warning or the disconnect after it
+fn idle_deadline(
+    active_at: Instant,
+    timeout: Duration,
+    warning: Duration,
+    warned: bool,
+) -> Instant {
+    if warned {
+        active_at + timeout
+    } else {
+        active_at + (timeout - warning)
+    }
+}
+
+// a plain text name line, which may have a secret for the authenticator after the name
+fn hello(input: &str) -> Request {
+    let (name, secret) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    Request::Hello {
+        name: name.to_string(),
+        secret: secret.trim().to_string(),
+    }
+}
+
+// runs a stretch of the --greeting steps, the name step itself is the registration loop
+async fn greet(out: &Outbound, shared: &Shared, steps: &[GreetingStep]) -> Result<(), Closed> {
+    for step in steps {
+        match step {
+            GreetingStep::Banner => {
+                out.send(format!(
+                    "rustlang-chat-server {}",
+                    env!("CARGO_PKG_VERSION")
+                ))
+                .await?
+            }
+            GreetingStep::Occupancy => out.send(occupancy(shared)).await?,
+            GreetingStep::Motd => send_motd(out, shared).await?,
+            GreetingStep::Prompt if shared.config.lobby => {
+                out.send(
+                    "Welcome! Look around with /help and /stats, then pick a name with /nick name"
+                        .to_string(),
+                )
+                .await?
+            }
+            GreetingStep::Prompt => {
+                out.send("Welcome! Please enter your name:".to_string())
+                    .await?
+            }
+            GreetingStep::Password => {
+                out.send("Follow your name with your password, like: alice s3cret".to_string())
+                    .await?
+            }
+            GreetingStep::Name => {}
+        }
+    }
+    Ok(())
+}
+
+async fn send_pins(out: &Outbound, shared: &Shared, room: &str) -> Result<(), Closed> {
+    for pin in shared.pins(room) {
+        out.send(pin).await?;
+    }
+    Ok(())
+}
+
+async fn send_motd(out: &Outbound, shared: &Shared) -> Result<(), Closed> {
+    if let Some(motd) = &shared.motd {
+        for line in motd.pick().lines() {
+            out.send(line.to_string()).await?;
+        }
+    }
+    Ok(())
+}
+
+// worked out for each client, so the count includes whoever just joined
+fn occupancy(shared: &Shared) -> String {
+    let online = shared.registry().clients.len();
+    let mut text = match online {
+        1 => "There is 1 user online".to_string(),
+        n => format!("There are {n} users online"),
+    };
+    if shared.config.max_clients > 0 {
+        let _ = write!(text, " (cap {})", shared.config.max_clients);
+    }
+    text.push('.');
+    text
+}
+
+// the client's rooms, the one it is chatting in first and the rest by name
+fn sorted_rooms(session: &Session) -> Vec<&str> {
+    let mut rooms: Vec<&str> = session.rooms.iter().map(String::as_str).collect();
+    rooms.sort_unstable_by_key(|room| (*room != session.room, *room));
+    rooms
+}
+
+fn rooms_line(session: &Session) -> String {
+    match sorted_rooms(session).as_slice() {
+        [] | [_] => session.room.clone(),
+        [current, others @ ..] => format!("{current}, also {}", others.join(", ")),
+    }
+}
+
+fn welcome_frame(session: &Session, token: &str, resumed: bool) -> Value {
+    let rooms = sorted_rooms(session).into_iter().map(Value::from).collect();
+    Value::object([
+        ("type", "welcome".into()),
+        ("name", session.name.as_str().into()),
+        ("room", session.room.as_str().into()),
+        ("rooms", Value::Array(rooms)),
+        ("token", token.into()),
+        ("resumed", resumed.into()),
+        ("version", PROTOCOL_VERSION.into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+
+    use tokio::io::{AsyncBufReadExt, DuplexStream};
+
+    use super::*;
+
+    fn outbound() -> (Outbound, io::Lines<BufReader<DuplexStream>>) {
+        let (writer, reader) = io::duplex(4096);
+        let writes = Arc::new(AtomicU64::new(0));
+        let out = Outbound::spawn(writer, "\n", false, 0, None, None, writes);
+        (out, BufReader::new(reader).lines())
+    }
+
+    fn notice(text: &str) -> Event {
+        Event::Notice {
+            room: Arc::from(DEFAULT_ROOM),
+            text: text.to_string(),
+            except: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_dropped_sender_ends_the_stream() {
+        let shared = Shared::new(Config::default(), None);
+        let (out, _lines) = outbound();
+        let (tx, mut rx) = broadcast::channel(4);
+        tx.send(notice("last one")).unwrap();
+        drop(tx);
+        // whatever was sent before still comes through first
+        let received = receive(&mut rx, &shared, 1, &out).await;
+        assert!(
+            matches!(received, Received::Event(Event::Notice { text, .. }) if text == "last one")
+        );
+        assert!(matches!(
+            receive(&mut rx, &shared, 1, &out).await,
+            Received::Closed
+        ));
+        // and it stays ended rather than turning into an error later on
+        assert!(matches!(
+            receive(&mut rx, &shared, 1, &out).await,
+            Received::Closed
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_task_waiting_on_the_broadcast_exits_when_it_closes() {
+        let shared = Shared::new(Config::default(), None);
+        let (out, _lines) = outbound();
+        let (tx, mut rx) = broadcast::channel::<Event>(4);
+        let task = tokio::spawn(async move {
+            loop {
+                match receive(&mut rx, &shared, 1, &out).await {
+                    Received::Closed => return true,
+                    Received::Gone => return false,
+                    Received::Event(_) | Received::Skipped => {}
+                }
+            }
+        });
+        drop(tx);
+        let clean = timeout(Duration::from_secs(5), task).await;
+        assert!(clean.expect("the task exited").expect("without panicking"));
+    }
+
+    #[tokio::test]
+    async fn a_client_that_lagged_is_told_and_carries_on() {
+        let shared = Shared::new(Config::default(), None);
+        let (out, mut lines) = outbound();
+        let (tx, mut rx) = broadcast::channel(2);
+        for n in 0..5 {
+            tx.send(notice(&n.to_string())).unwrap();
+        }
+        assert!(matches!(
+            receive(&mut rx, &shared, 1, &out).await,
+            Received::Skipped
+        ));
+        let told = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(told, "*** you missed 3 messages ***");
+        assert!(matches!(
+            receive(&mut rx, &shared, 1, &out).await,
+            Received::Event(_)
+        ));
+    }
+}
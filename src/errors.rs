@@ -0,0 +1,150 @@
+// every error a client can get back from the server. errors always go out as
+// `! <CODE> <text>` so clients can pick them out of the chat stream without guessing
+use std::borrow::Cow;
+
+use crate::{
+    framing::truncate_on_char_boundary,
+    outbound::{Closed, Outbound, Outgoing},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownCommand,
+    // a known command with missing or malformed arguments
+    Usage,
+    InvalidName,
+    InvalidRoomName,
+    NameTaken,
+    InvalidToken,
+    PermissionDenied,
+    NoSuchRoom,
+    // the room exists but the operation isn't allowed on it, like deleting the default room
+    ProtectedRoom,
+    Repeated,
+    NoSuchUser,
+    SlowMode,
+    RateLimited,
+    // the recipient has turned on do not disturb
+    NotAcceptingMessages,
+    // a json request that doesn't parse or doesn't make sense
+    BadRequest,
+    // a sequence number that isn't in the room's history
+    NoSuchMessage,
+    // a message over the size limit, it was thrown away unread
+    TooLarge,
+    // the client is in as many rooms as it is allowed
+    TooManyRooms,
+    // a room the client would have to be a member of
+    NotInRoom,
+    // the room only takes clients on a secure connection
+    SecureOnly,
+    // something only a client with a name can do, sent from the lobby
+    NotRegistered,
+    // the authenticator turned the name and secret down
+    AuthFailed,
+    // the room already has as many pinned messages as it can
+    TooManyPins,
+    // a shared file token that was never handed out or has expired
+    NoSuchFile,
+    // the client speaks an older protocol than --min-protocol-version
+    ProtocolTooOld,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnknownCommand => "UNKNOWN_COMMAND",
+            ErrorCode::Usage => "USAGE",
+            ErrorCode::InvalidName => "INVALID_NAME",
+            ErrorCode::InvalidRoomName => "INVALID_ROOM_NAME",
+            ErrorCode::NameTaken => "NAME_TAKEN",
+            ErrorCode::InvalidToken => "INVALID_TOKEN",
+            ErrorCode::PermissionDenied => "PERMISSION_DENIED",
+            ErrorCode::NoSuchRoom => "NO_SUCH_ROOM",
+            ErrorCode::ProtectedRoom => "PROTECTED_ROOM",
+            ErrorCode::Repeated => "REPEATED",
+            ErrorCode::NoSuchUser => "NO_SUCH_USER",
+            ErrorCode::SlowMode => "SLOW_MODE",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::NotAcceptingMessages => "DND",
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::NoSuchMessage => "NO_SUCH_MESSAGE",
+            ErrorCode::TooLarge => "TOO_LARGE",
+            ErrorCode::TooManyRooms => "TOO_MANY_ROOMS",
+            ErrorCode::NotInRoom => "NOT_IN_ROOM",
+            ErrorCode::SecureOnly => "SECURE_ONLY",
+            ErrorCode::NotRegistered => "NOT_REGISTERED",
+            ErrorCode::AuthFailed => "AUTH_FAILED",
+            ErrorCode::TooManyPins => "TOO_MANY_PINS",
+            ErrorCode::NoSuchFile => "NO_SUCH_FILE",
+            ErrorCode::ProtocolTooOld => "PROTOCOL_TOO_OLD",
+        }
+    }
+}
+
+// an error on its way back to the client that caused it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl ChatError {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> ChatError {
+        ChatError {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+// client input repeated back in an error, kept short since the line it came from may have
+// been thousands of bytes long
+pub fn quoted(input: &str) -> Cow<'_, str> {
+    const QUOTE_BYTES: usize = 64;
+
+    let cut = truncate_on_char_boundary(input, QUOTE_BYTES);
+    if cut.len() == input.len() {
+        Cow::Borrowed(input)
+    } else {
+        Cow::Owned(format!("{cut}..."))
+    }
+}
+
+pub fn format_error(code: ErrorCode, text: &str) -> String {
+    format!("! {} {text}", code.as_str())
+}
+
+pub async fn reply_error(sender: &Outbound, code: ErrorCode, text: &str) -> Result<(), Closed> {
+    sender.send(Outgoing::Error(code, text.to_string())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_short_input_as_is() {
+        assert_eq!(quoted("frobnicate"), "frobnicate");
+        assert!(matches!(quoted("frobnicate"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn cuts_long_input_between_characters() {
+        let long = "é".repeat(100);
+        let quote = quoted(&long);
+        // 32 of them make 64 bytes exactly
+        assert_eq!(quote, format!("{}...", "é".repeat(32)));
+        let shifted = format!("x{long}");
+        // one byte in, the 64th byte is the first half of an é
+        assert_eq!(quoted(&shifted), format!("x{}...", "é".repeat(31)));
+    }
+
+    #[test]
+    fn formats_with_the_code_first() {
+        assert_eq!(
+            format_error(ErrorCode::NoSuchRoom, "No such room: #x"),
+            "! NO_SUCH_ROOM No such room: #x"
+        );
+    }
+}
@@ -0,0 +1,64 @@
+// a feed of what happens on the server for programs embedding it, to watch it or to bridge
+// the chat somewhere else. it is separate from the channel the connections listen on, so
+// nothing a subscriber does can slow the chat down
+use std::net::SocketAddr;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// how far a subscriber may fall behind before it starts missing events
+pub const EVENTS_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    // a client picked a name or resumed, ids are never reused
+    Connected {
+        id: u64,
+        name: String,
+        addr: SocketAddr,
+    },
+    Disconnected {
+        id: u64,
+        name: String,
+    },
+    // a chat message as it was stored, including ones posted over http. messages are stored
+    // in the background, so one can arrive after a join or leave that happened just after it
+    Message {
+        seq: u64,
+        room: String,
+        from: String,
+        text: String,
+    },
+    Join {
+        id: u64,
+        name: String,
+        room: String,
+    },
+    Leave {
+        id: u64,
+        name: String,
+        room: String,
+    },
+}
+
+// returned by ChatServer::events. a subscriber that falls more than EVENTS_CAPACITY events
+// behind silently misses the oldest ones and carries on from there
+pub struct ServerEvents {
+    rx: broadcast::Receiver<ServerEvent>,
+}
+
+impl ServerEvents {
+    pub(crate) fn new(rx: broadcast::Receiver<ServerEvent>) -> ServerEvents {
+        ServerEvents { rx }
+    }
+
+    // waits for the next event, None once the server is gone
+    pub async fn next(&mut self) -> Option<ServerEvent> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,528 @@
+// how the byte stream is cut into messages. newline by default, some clients only ever send
+// \r\n or nul terminated messages, and whatever is picked is used in both directions
+use std::{borrow::Cow, fmt, io, str::FromStr, time::Duration};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    time::{error::Elapsed, timeout_at, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delimiter {
+    #[default]
+    Lf,
+    Crlf,
+    Nul,
+}
+
+impl Delimiter {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Delimiter::Lf => "\n",
+            Delimiter::Crlf => "\r\n",
+            Delimiter::Nul => "\0",
+        }
+    }
+
+    // what read_until looks for, a \r\n delimiter is found by its \n
+    fn last_byte(self) -> u8 {
+        match self {
+            Delimiter::Lf | Delimiter::Crlf => b'\n',
+            Delimiter::Nul => 0,
+        }
+    }
+}
+
+impl FromStr for Delimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Delimiter, String> {
+        match s {
+            "lf" => Ok(Delimiter::Lf),
+            "crlf" => Ok(Delimiter::Crlf),
+            "nul" => Ok(Delimiter::Nul),
+            _ => Err(format!("unknown delimiter {s}, expected lf, crlf or nul")),
+        }
+    }
+}
+
+impl fmt::Display for Delimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Delimiter::Lf => "lf",
+            Delimiter::Crlf => "crlf",
+            Delimiter::Nul => "nul",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    // a message longer than the reader's limit. it has been read and thrown away, so the
+    // connection can carry on with the next one
+    TooLong,
+    // part of a message came in and then no delimiter within the partial timeout. what
+    // arrived is still buffered, the connection is not expected to carry on
+    Stalled,
+}
+
+// a reader that hands out one message at a time
+pub struct Framed<R> {
+    reader: R,
+    buf: Vec<u8>,
+    delimiter: Delimiter,
+    // the longest message kept, without its delimiter. None for no limit
+    limit: Option<usize>,
+    // bytes read for the message in progress, including any that were thrown away
+    read: usize,
+    // the message in progress went over the limit, the rest of it is skipped
+    skipping: bool,
+    // the buffer holds a message that has been handed out and is cleared on the next read
+    complete: bool,
+    // how long a message may take to arrive once its first byte has, None to wait forever
+    partial_timeout: Option<Duration>,
+    // when the first byte of the message in progress arrived
+    started: Option<Instant>,
+    // a last message the stream ended in the middle of is handed out rather than dropped
+    keep_unterminated: bool,
+}
+
+impl<R> Framed<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    pub fn new(reader: R, delimiter: Delimiter) -> Framed<R> {
+        Framed {
+            reader,
+            buf: Vec::new(),
+            delimiter,
+            limit: None,
+            read: 0,
+            skipping: false,
+            complete: false,
+            partial_timeout: None,
+            started: None,
+            keep_unterminated: false,
+        }
+    }
+
+    // counts from the first byte of a message, so a slow but steady sender is cut off too
+    pub fn set_partial_timeout(&mut self, partial_timeout: Option<Duration>) {
+        self.partial_timeout = partial_timeout;
+    }
+
+    pub fn set_keep_unterminated(&mut self, keep: bool) {
+        self.keep_unterminated = keep;
+    }
+
+    // applies from the next message on
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    // the next message without its delimiter, along with how many bytes were read for it.
+    // None once the stream has ended, failed, or sent something that isn't utf-8.
+    // safe to use in select!, a partly read message stays buffered until the next call.
+    // a message over the limit is never held in memory as a whole, only counted as it goes by
+    pub async fn next(&mut self) -> Option<(Result<&str, FrameError>, usize)> {
+        if self.complete {
+            self.buf.clear();
+            self.read = 0;
+            self.skipping = false;
+            self.complete = false;
+            self.started = None;
+        }
+        let last = self.delimiter.last_byte();
+        loop {
+            let deadline = self
+                .started
+                .zip(self.partial_timeout)
+                .map(|(started, limit)| started + limit);
+            let available = match fill_by(&mut self.reader, deadline).await {
+                Ok(read) => read.ok()?,
+                Err(_) => return Some((Err(FrameError::Stalled), self.read)),
+            };
+            // an empty buffer is the end of the stream: AsyncBufRead promises that, and a tcp
+            // read only comes back empty once the other side has shut down its half. a reader
+            // that returns nothing without having reached the end, a tls or websocket layer
+            // done wrong, would cut the client off, so it is asked once more, by the same
+            // deadline. at a real end the second read comes back empty straight away
+            if available.is_empty() {
+                match fill_by(&mut self.reader, deadline).await {
+                    Ok(Ok(again)) if !again.is_empty() => continue,
+                    Err(_) => return Some((Err(FrameError::Stalled), self.read)),
+                    Ok(_) => {}
+                }
+                // a last message without a delimiter is dropped, unless it is to be kept
+                if self.read == 0 || !self.keep_unterminated {
+                    return None;
+                }
+                break;
+            }
+            let (chunk, found) = match available.iter().position(|&b| b == last) {
+                Some(end) => (&available[..=end], true),
+                None => (available, false),
+            };
+            let len = chunk.len();
+            if !self.skipping {
+                self.buf.extend_from_slice(chunk);
+                let max = self
+                    .limit
+                    .map(|limit| limit + self.delimiter.as_str().len());
+                if max.is_some_and(|max| self.buf.len() > max) {
+                    self.skipping = true;
+                    self.buf.clear();
+                }
+            }
+            self.read += len;
+            self.started.get_or_insert_with(Instant::now);
+            self.reader.consume(len);
+            if found {
+                break;
+            }
+        }
+        self.complete = true;
+        if self.skipping {
+            return Some((Err(FrameError::TooLong), self.read));
+        }
+        let mut message = &self.buf[..];
+        if self.delimiter == Delimiter::Crlf {
+            message = message.strip_suffix(b"\r\n").unwrap_or(message);
+        }
+        message = message.strip_suffix(&[last]).unwrap_or(message);
+        // a message without its delimiter at the end of the stream can still be too long
+        if self.limit.is_some_and(|limit| message.len() > limit) {
+            return Some((Err(FrameError::TooLong), self.read));
+        }
+        Some((Ok(std::str::from_utf8(message).ok()?), self.read))
+    }
+}
+
+// fill_buf(), given up on at the deadline if there is one
+async fn fill_by<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    deadline: Option<Instant>,
+) -> Result<io::Result<&[u8]>, Elapsed> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, reader.fill_buf()).await,
+        None => Ok(reader.fill_buf().await),
+    }
+}
+
+// the longest start of s that is at most max_bytes long, cut where a character starts so a
+// multi-byte one is never split
+pub fn truncate_on_char_boundary(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    // a char is at most 4 bytes, so this goes round 3 times at most
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// cleans up a message before anything looks at it. some clients start the stream with a byte
+// order mark or send stray control bytes, which end up in the name the first line picks or get
+// replayed to every terminal reading the room. tabs and newlines stay, a terminal escape
+// sequence goes as a whole rather than leaving its [31m behind
+pub fn sanitize_line(line: &str) -> Cow<'_, str> {
+    let line = line.strip_prefix('\u{feff}').unwrap_or(line);
+    if !line
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n'))
+    {
+        return Cow::Borrowed(line);
+    }
+    let mut clean = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\t' | '\n' => clean.push(c),
+            '\u{1b}' => match chars.next() {
+                // csi: parameters and intermediates, up to and including the final byte
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\u{40}'..='\u{7e}').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // osc, like a window title: up to a bel or an esc \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\u{7}' {
+                            break;
+                        }
+                        if c == '\u{1b}' && chars.next_if_eq(&'\\').is_some() {
+                            break;
+                        }
+                    }
+                }
+                // anything else is a two character sequence
+                _ => {}
+            },
+            c if c.is_control() => {}
+            c => clean.push(c),
+        }
+    }
+    Cow::Owned(clean)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn messages(reader: &mut Framed<&[u8]>) -> Vec<Result<String, FrameError>> {
+        let mut out = Vec::new();
+        while let Some((message, _)) = reader.next().await {
+            out.push(message.map(str::to_string));
+        }
+        out
+    }
+
+    async fn split(input: &[u8], delimiter: Delimiter) -> Vec<Result<String, FrameError>> {
+        let mut reader = Framed::new(input, delimiter);
+        messages(&mut reader).await
+    }
+
+    fn ok(lines: &[&str]) -> Vec<Result<String, FrameError>> {
+        lines.iter().map(|line| Ok(line.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn splits_on_each_delimiter() {
+        assert_eq!(
+            split(b"one\ntwo\n", Delimiter::Lf).await,
+            ok(&["one", "two"])
+        );
+        assert_eq!(
+            split(b"one\r\ntwo\r\n", Delimiter::Crlf).await,
+            ok(&["one", "two"])
+        );
+        assert_eq!(
+            split(b"one\0two\0", Delimiter::Nul).await,
+            ok(&["one", "two"])
+        );
+        // a lone \n still ends a message under crlf, it is only not stripped of a \r
+        assert_eq!(
+            split(b"one\ntwo\r\n", Delimiter::Crlf).await,
+            ok(&["one", "two"])
+        );
+    }
+
+    #[test]
+    fn delimiters_parse_from_their_names() {
+        for delimiter in [Delimiter::Lf, Delimiter::Crlf, Delimiter::Nul] {
+            assert_eq!(delimiter.to_string().parse(), Ok(delimiter));
+        }
+        assert_eq!(
+            "cr".parse::<Delimiter>(),
+            Err("unknown delimiter cr, expected lf, crlf or nul".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn nul_leaves_newlines_in_the_message() {
+        assert_eq!(
+            split(b"one\ntwo\0three\r\n\0", Delimiter::Nul).await,
+            ok(&["one\ntwo", "three\r\n"])
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_an_unterminated_message_unless_asked_to_keep_it() {
+        assert_eq!(split(b"one\ntw", Delimiter::Lf).await, ok(&["one"]));
+        let input: &[u8] = b"one\ntw";
+        let mut reader = Framed::new(input, Delimiter::Lf);
+        reader.set_keep_unterminated(true);
+        assert_eq!(messages(&mut reader).await, ok(&["one", "tw"]));
+    }
+
+    #[tokio::test]
+    async fn the_end_of_the_stream_is_the_end() {
+        assert_eq!(split(b"", Delimiter::Lf).await, ok(&[]));
+        let input: &[u8] = b"one\n";
+        let mut reader = Framed::new(input, Delimiter::Lf);
+        reader.set_keep_unterminated(true);
+        assert!(reader.next().await.is_some());
+        // asking again after the end doesn't make up an empty message
+        assert!(reader.next().await.is_none());
+        assert!(reader.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn skips_messages_over_the_limit_and_carries_on() {
+        let input: &[u8] = b"short\nmuch too long\nok\n";
+        let mut reader = Framed::new(input, Delimiter::Lf);
+        reader.set_limit(Some(5));
+        let mut got = Vec::new();
+        while let Some((message, read)) = reader.next().await {
+            got.push((message.map(str::to_string), read));
+        }
+        assert_eq!(
+            got,
+            [
+                (Ok("short".to_string()), 6),
+                (Err(FrameError::TooLong), 14),
+                (Ok("ok".to_string()), 3),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_limit_leaves_room_for_the_delimiter() {
+        let input: &[u8] = b"12345\r\n123456\r\n";
+        let mut reader = Framed::new(input, Delimiter::Crlf);
+        reader.set_limit(Some(5));
+        let got = messages(&mut reader).await;
+        assert_eq!(got, [Ok("12345".to_string()), Err(FrameError::TooLong)]);
+    }
+
+    #[tokio::test]
+    async fn an_unterminated_message_can_be_too_long_too() {
+        let input: &[u8] = b"123456";
+        let mut reader = Framed::new(input, Delimiter::Lf);
+        reader.set_limit(Some(5));
+        reader.set_keep_unterminated(true);
+        assert_eq!(messages(&mut reader).await, [Err(FrameError::TooLong)]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_message_that_stops_halfway() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut reader = Framed::new(tokio::io::BufReader::new(server), Delimiter::Lf);
+        reader.set_partial_timeout(Some(Duration::from_millis(20)));
+        tokio::io::AsyncWriteExt::write_all(&mut client, b"half")
+            .await
+            .unwrap();
+        let (message, read) = reader.next().await.unwrap();
+        assert_eq!((message, read), (Err(FrameError::Stalled), 4));
+    }
+
+    // hands out one chunk per read, an empty one being a read that came back with nothing.
+    // once they run out it is the end of the stream, or if stalling, a read that never returns
+    struct Reads(std::collections::VecDeque<&'static [u8]>, bool);
+
+    impl tokio::io::AsyncRead for Reads {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            match self.0.pop_front() {
+                Some(chunk) => buf.put_slice(chunk),
+                None if self.1 => return std::task::Poll::Pending,
+                None => {}
+            }
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn split_reads(reads: &[&'static [u8]]) -> Vec<Result<String, FrameError>> {
+        let reads = tokio::io::BufReader::new(Reads(reads.iter().copied().collect(), false));
+        let mut reader = Framed::new(reads, Delimiter::Lf);
+        let mut out = Vec::new();
+        while let Some((message, _)) = reader.next().await {
+            out.push(message.map(str::to_string));
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn a_single_empty_read_is_not_the_end() {
+        assert_eq!(
+            split_reads(&[b"one\n", b"", b"two\n"]).await,
+            ok(&["one", "two"])
+        );
+        // halfway through a message too
+        assert_eq!(split_reads(&[b"o", b"", b"ne\n"]).await, ok(&["one"]));
+    }
+
+    #[tokio::test]
+    async fn two_empty_reads_are() {
+        assert_eq!(
+            split_reads(&[b"one\n", b"", b"", b"two\n"]).await,
+            ok(&["one"])
+        );
+    }
+
+    #[tokio::test]
+    async fn asking_again_after_an_empty_read_keeps_to_the_deadline() {
+        let reads = Reads([&b"half"[..], b""].into_iter().collect(), true);
+        let mut reader = Framed::new(tokio::io::BufReader::new(reads), Delimiter::Lf);
+        reader.set_partial_timeout(Some(Duration::from_millis(20)));
+        let (message, read) = tokio::time::timeout(Duration::from_secs(5), reader.next())
+            .await
+            .expect("the second read gave up at the deadline")
+            .unwrap();
+        assert_eq!((message, read), (Err(FrameError::Stalled), 4));
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_ends_the_stream() {
+        assert_eq!(
+            split(b"ok\n\xff\xfe\nafter\n", Delimiter::Lf).await,
+            ok(&["ok"])
+        );
+    }
+
+    #[test]
+    fn truncates_between_characters() {
+        assert_eq!(truncate_on_char_boundary("hello", 10), "hello");
+        assert_eq!(truncate_on_char_boundary("hello", 5), "hello");
+        assert_eq!(truncate_on_char_boundary("hello", 3), "hel");
+        assert_eq!(truncate_on_char_boundary("hello", 0), "");
+        // é is 2 bytes, cutting after its first one keeps it out
+        assert_eq!(truncate_on_char_boundary("café", 4), "caf");
+        assert_eq!(truncate_on_char_boundary("café", 5), "café");
+        // 🎉 is 4 bytes, every cut inside it falls back to before it
+        for max in 2..6 {
+            assert_eq!(truncate_on_char_boundary("ab🎉", max), "ab");
+        }
+        assert_eq!(truncate_on_char_boundary("ab🎉", 6), "ab🎉");
+        // a flag is two chars, the cut can fall between them
+        assert_eq!(truncate_on_char_boundary("🇳🇴", 4), "🇳");
+    }
+
+    #[test]
+    fn sanitize_leaves_clean_lines_alone() {
+        assert!(matches!(
+            sanitize_line("hello\tthere"),
+            Cow::Borrowed("hello\tthere")
+        ));
+    }
+
+    #[test]
+    fn sanitize_strips_the_bom_controls_and_escapes() {
+        assert_eq!(sanitize_line("\u{feff}alice"), "alice");
+        assert_eq!(sanitize_line("a\u{7}b\rc"), "abc");
+        assert_eq!(sanitize_line("\u{1b}[31mred\u{1b}[0m"), "red");
+        assert_eq!(sanitize_line("\u{1b}]0;title\u{7}text"), "text");
+        assert_eq!(sanitize_line("\u{1b}]0;title\u{1b}\\text"), "text");
+    }
+
+    #[test]
+    fn sanitize_keeps_tabs_and_newlines() {
+        assert_eq!(sanitize_line("\u{feff}a\tb\nc\u{0}"), "a\tb\nc");
+    }
+
+    #[test]
+    fn sanitize_only_strips_a_leading_bom() {
+        // further in it is a zero width no-break space, not a control
+        assert_eq!(sanitize_line("a\u{feff}b"), "a\u{feff}b");
+        assert_eq!(sanitize_line("\u{feff}\u{feff}a"), "\u{feff}a");
+    }
+
+    #[test]
+    fn sanitize_strips_c1_and_short_escapes() {
+        assert_eq!(sanitize_line("a\u{85}b\u{9b}c"), "abc");
+        // esc c resets a terminal, esc 7 saves the cursor
+        assert_eq!(sanitize_line("\u{1b}cclear\u{1b}7ed"), "cleared");
+        // a sequence cut off at the end of the line takes the rest with it
+        assert_eq!(sanitize_line("text\u{1b}[1;3"), "text");
+        assert_eq!(sanitize_line("text\u{1b}]0;no end"), "text");
+        assert_eq!(sanitize_line("text\u{1b}"), "text");
+    }
+}
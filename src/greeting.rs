@@ -0,0 +1,138 @@
+// what a new connection is sent, and in which order, set with --greeting as a comma separated
+// list of steps. the name step is where the server waits for the client to pick a name, the
+// steps before it go out on connect and the ones after it follow the welcome line
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GreetingStep {
+    // the server's name and version
+    Banner,
+    // how many people are online
+    Occupancy,
+    Motd,
+    // the line asking for a name, or how to pick one in the lobby
+    Prompt,
+    // how to send a password along with the name, for servers with an authenticator
+    Password,
+    Name,
+}
+
+impl GreetingStep {
+    fn as_str(self) -> &'static str {
+        match self {
+            GreetingStep::Banner => "banner",
+            GreetingStep::Occupancy => "occupancy",
+            GreetingStep::Motd => "motd",
+            GreetingStep::Prompt => "prompt",
+            GreetingStep::Password => "password",
+            GreetingStep::Name => "name",
+        }
+    }
+}
+
+impl FromStr for GreetingStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GreetingStep, String> {
+        match s {
+            "banner" => Ok(GreetingStep::Banner),
+            "occupancy" => Ok(GreetingStep::Occupancy),
+            "motd" => Ok(GreetingStep::Motd),
+            "prompt" => Ok(GreetingStep::Prompt),
+            "password" => Ok(GreetingStep::Password),
+            "name" => Ok(GreetingStep::Name),
+            _ => Err(format!(
+                "unknown greeting step {s}, expected banner, occupancy, motd, prompt, password or name"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for GreetingStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// every step at most once, and the name exactly once since nobody gets in without one
+pub fn parse_steps(list: &str) -> Result<Vec<GreetingStep>, String> {
+    let mut steps = Vec::new();
+    for step in list.split(',').map(str::trim) {
+        let step: GreetingStep = step.parse()?;
+        if steps.contains(&step) {
+            return Err(format!("--greeting has {step} more than once"));
+        }
+        steps.push(step);
+    }
+    if !steps.contains(&GreetingStep::Name) {
+        return Err("--greeting needs a name step".to_string());
+    }
+    Ok(steps)
+}
+
+// the order from before there was a choice. the lobby shows the motd up front, so there is
+// something to read while looking around
+pub fn default_steps(lobby: bool, show_occupancy: bool) -> Vec<GreetingStep> {
+    let mut steps = if lobby {
+        vec![GreetingStep::Prompt, GreetingStep::Motd, GreetingStep::Name]
+    } else {
+        vec![GreetingStep::Prompt, GreetingStep::Name]
+    };
+    if show_occupancy {
+        steps.push(GreetingStep::Occupancy);
+    }
+    if !lobby {
+        steps.push(GreetingStep::Motd);
+    }
+    steps
+}
+
+pub fn list(steps: &[GreetingStep]) -> String {
+    steps
+        .iter()
+        .map(|step| step.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use GreetingStep::*;
+
+    #[test]
+    fn steps_are_read_in_the_order_given() {
+        assert_eq!(
+            parse_steps("password, name,banner").unwrap(),
+            [Password, Name, Banner]
+        );
+        assert_eq!(parse_steps("name").unwrap(), [Name]);
+        let all = "banner,occupancy,motd,prompt,password,name";
+        assert_eq!(list(&parse_steps(all).unwrap()), all);
+    }
+
+    #[test]
+    fn bad_step_lists_are_turned_down() {
+        assert_eq!(
+            parse_steps("banner,name,flag"),
+            Err("unknown greeting step flag, expected banner, occupancy, motd, prompt, password or name".to_string())
+        );
+        assert_eq!(
+            parse_steps("motd,name,motd"),
+            Err("--greeting has motd more than once".to_string())
+        );
+        assert_eq!(
+            parse_steps("banner,prompt"),
+            Err("--greeting needs a name step".to_string())
+        );
+        assert!(parse_steps("").is_err());
+    }
+
+    #[test]
+    fn the_default_steps_are_the_old_order() {
+        assert_eq!(default_steps(false, false), [Prompt, Name, Motd]);
+        assert_eq!(default_steps(false, true), [Prompt, Name, Occupancy, Motd]);
+        assert_eq!(default_steps(true, false), [Prompt, Motd, Name]);
+        assert_eq!(default_steps(true, true), [Prompt, Motd, Name, Occupancy]);
+    }
+}
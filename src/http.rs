@@ -0,0 +1,572 @@
+// a bare bones http/1.1 interface for clients on networks that only let http through. it
+// talks to the same rooms and history as everyone else, one request per connection:
+//   POST /send?room=%23general&name=alice    the body is the message text
+//   GET  /poll?room=%23general&since=41      waits for anything newer than message 41
+//   POST /bot                                 {"room":"#general","from":"ci","body":"..."}
+//   POST /upload?name=notes.txt               the body is the file, answers with its token
+//   GET  /files/TOKEN                         the file itself
+// the bot endpoint is only there with --bot-token, and wants it as a bearer token. the file
+// endpoints are only there with --upload-dir, see upload.rs
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::{broadcast::error::RecvError, watch},
+    time::{sleep, timeout},
+};
+
+use crate::{
+    json::{self, Value},
+    room::HistoryEntry,
+    server::stopped,
+    state::{validate_name, validate_room_name, Event, HttpPostError, Shared, DEFAULT_ROOM},
+    upload::UploadError,
+};
+
+// the request line and headers together, and how many header lines there can be
+const MAX_HEAD: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+const MAX_BODY: usize = 64 * 1024;
+// how long a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// how long a poll waits for something new before answering with nothing
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    // from an authorization: bearer header
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Body,
+}
+
+enum Body {
+    Json(Value),
+    // a download, sent with the name it was uploaded under
+    File { name: String, bytes: Vec<u8> },
+}
+
+fn json(status: u16, body: Value) -> Response {
+    Response {
+        status,
+        body: Body::Json(body),
+    }
+}
+
+fn error(status: u16, text: &str) -> Response {
+    json(status, Value::object([("error", text.into())]))
+}
+
+pub async fn handle(socket: TcpStream, shared: Arc<Shared>, shutdown: watch::Receiver<bool>) {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let upload_limit = shared.uploads.as_ref().map(|uploads| uploads.max_file);
+    let response = match timeout(READ_TIMEOUT, read_request(&mut reader, upload_limit)).await {
+        Ok(Ok(request)) => respond(&shared, request, shutdown).await,
+        Ok(Err(response)) => response,
+        Err(_) => error(408, "request timed out"),
+    };
+    let (content_type, body) = match response.body {
+        Body::Json(value) => (
+            "application/json".to_string(),
+            value.to_string().into_bytes(),
+        ),
+        Body::File { name, bytes } => (
+            format!(
+                "application/octet-stream\r\nContent-Disposition: attachment; filename=\"{name}\""
+            ),
+            bytes,
+        ),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        body.len()
+    );
+    if writer.write_all(head.as_bytes()).await.is_ok() {
+        let _ = writer.write_all(&body).await;
+    }
+    let _ = writer.shutdown().await;
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        507 => "Insufficient Storage",
+        _ => "Error",
+    }
+}
+
+// uploads get a limit of their own, every other body is kept small
+async fn read_request<R>(reader: &mut R, upload_limit: Option<usize>) -> Result<Request, Response>
+where
+    R: AsyncBufRead + Unpin,
+{
+    // nothing past the cap is ever buffered, however long a line goes on for
+    let mut head = (&mut *reader).take(MAX_HEAD as u64);
+    let mut line = String::new();
+    let mut content_length = 0;
+    let mut token = None;
+    let mut request_line = None;
+    let mut headers = 0;
+    loop {
+        line.clear();
+        head.read_line(&mut line)
+            .await
+            .map_err(|_| error(400, "unreadable request"))?;
+        // cut short by the cap, or by the client going away
+        if !line.ends_with('\n') {
+            return Err(if head.limit() == 0 {
+                error(431, "request head too large")
+            } else {
+                error(400, "incomplete request")
+            });
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if request_line.is_none() {
+            request_line = Some(line.to_string());
+            continue;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(error(431, "too many headers"));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| error(400, "bad content-length"))?;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                token = value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .map(|token| token.trim().to_string());
+            }
+        }
+    }
+    let request_line = request_line.unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(error(400, "bad request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let limit = match upload_limit {
+        Some(limit) if method == "POST" && path == "/upload" => limit,
+        _ => MAX_BODY,
+    };
+    if content_length > limit {
+        return Err(error(413, "body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|_| error(400, "incomplete body"))?;
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(key)?, decode(value)?))
+        })
+        .collect::<Result<_, Response>>()?;
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        token,
+        body,
+    })
+}
+
+// percent decoding for query strings, + is a space there
+fn decode(s: &str) -> Result<String, Response> {
+    let bad = || error(400, "bad percent encoding");
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next().ok_or_else(bad)?, iter.next().ok_or_else(bad)?];
+                // from_str_radix alone would take a sign, %+1 among them
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return Err(bad());
+                }
+                let hex = std::str::from_utf8(&hex).map_err(|_| bad())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| bad())?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| bad())
+}
+
+async fn respond(
+    shared: &Arc<Shared>,
+    request: Request,
+    shutdown: watch::Receiver<bool>,
+) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/send") => send(shared, &request).await,
+        ("GET", "/poll") => poll(shared, &request, shutdown).await,
+        ("POST", "/bot") if shared.config.bot_token.is_some() => bot(shared, &request).await,
+        ("POST", "/upload") if shared.uploads.is_some() => upload(shared, &request).await,
+        ("GET", path) if shared.uploads.is_some() && path.starts_with("/files/") => {
+            download(shared, &path["/files/".len()..]).await
+        }
+        (_, "/send" | "/poll") => error(405, "method not allowed"),
+        _ => error(404, "not found"),
+    }
+}
+
+async fn send(shared: &Shared, request: &Request) -> Response {
+    let room = request.param("room").unwrap_or(DEFAULT_ROOM);
+    if let Err(err) = validate_room_name(room) {
+        return error(400, &err);
+    }
+    if insecure(shared, room) {
+        return error(403, SECURE_ONLY);
+    }
+    let Some(name) = request.param("name") else {
+        return error(400, "missing name");
+    };
+    if let Err(err) = validate_name(name, shared.config.max_username_len) {
+        return error(400, &err);
+    }
+    let Ok(text) = std::str::from_utf8(&request.body) else {
+        return error(400, "body must be utf-8");
+    };
+    let text = text.trim_end_matches(['\r', '\n']);
+    if text.is_empty() {
+        return error(400, "empty message");
+    }
+    posted(
+        shared
+            .post_from_http(room, name, text.to_string(), false)
+            .await,
+    )
+}
+
+fn posted(result: Result<u64, HttpPostError>) -> Response {
+    match result {
+        Ok(seq) => json(200, Value::object([("seq", seq.into())])),
+        Err(HttpPostError::NoSuchRoom) => error(404, "no such room"),
+        Err(HttpPostError::NameTaken) => error(409, "that name belongs to a connected client"),
+        Err(HttpPostError::TooLong) => error(413, "message too long"),
+    }
+}
+
+async fn bot(shared: &Shared, request: &Request) -> Response {
+    let expected = shared.config.bot_token.as_deref().unwrap_or_default();
+    match &request.token {
+        Some(token) if same_secret(token, expected) => {}
+        _ => return error(401, "missing or wrong bot token"),
+    }
+    let Some(request) = std::str::from_utf8(&request.body)
+        .ok()
+        .and_then(|body| json::parse(body).ok())
+    else {
+        return error(400, "body must be a json object");
+    };
+    let field = |key| request.get(key).and_then(Value::as_str);
+    let (Some(room), Some(from), Some(text)) = (field("room"), field("from"), field("body")) else {
+        return error(400, "room, from and body are all needed");
+    };
+    if let Err(err) = validate_room_name(room) {
+        return error(400, &err);
+    }
+    if insecure(shared, room) {
+        return error(403, SECURE_ONLY);
+    }
+    if let Err(err) = validate_name(from, shared.config.max_username_len) {
+        return error(400, &err);
+    }
+    if text.trim().is_empty() {
+        return error(400, "empty message");
+    }
+    posted(
+        shared
+            .post_from_http(room, from, text.to_string(), true)
+            .await,
+    )
+}
+
+const SECURE_ONLY: &str = "This room requires a secure connection";
+
+// the http interface is plain text, so it stays out of --secure-room rooms like plain tcp does
+fn insecure(shared: &Shared, room: &str) -> bool {
+    shared.registry().is_secure_room(room)
+}
+
+// compares every byte whatever the input, so how long a guess takes says nothing about how
+// close it was
+fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn poll(shared: &Shared, request: &Request, mut shutdown: watch::Receiver<bool>) -> Response {
+    let room = request.param("room").unwrap_or(DEFAULT_ROOM);
+    if let Err(err) = validate_room_name(room) {
+        return error(400, &err);
+    }
+    if insecure(shared, room) {
+        return error(403, SECURE_ONLY);
+    }
+    let since = match request.param("since").map(str::parse) {
+        None => 0,
+        Some(Ok(since)) => since,
+        Some(Err(_)) => return error(400, "since must be a sequence number"),
+    };
+    let Some((ready, mut rx)) = shared.history_since(room, since) else {
+        return error(404, "no such room");
+    };
+    if !ready.is_empty() {
+        return messages(&ready);
+    }
+    let wait = sleep(POLL_TIMEOUT);
+    tokio::pin!(wait);
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(Event::Message { entry, .. }) if *entry.room == *room => {
+                    return messages(&[entry]);
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return messages(&[]),
+            },
+            _ = &mut wait => return messages(&[]),
+            _ = stopped(&mut shutdown) => return messages(&[]),
+        }
+    }
+}
+
+fn messages(entries: &[Arc<HistoryEntry>]) -> Response {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            Value::object([
+                ("seq", entry.seq.into()),
+                ("room", (*entry.room).into()),
+                ("from", entry.from.as_str().into()),
+                ("body", entry.text.as_str().into()),
+                ("bot", entry.bot.into()),
+            ])
+        })
+        .collect();
+    json(200, Value::object([("messages", Value::Array(entries))]))
+}
+
+// anyone who can reach the http interface can upload, the same as posting with /send
+async fn upload(shared: &Shared, request: &Request) -> Response {
+    let Some(uploads) = &shared.uploads else {
+        return error(404, "not found");
+    };
+    if request.body.is_empty() {
+        return error(400, "empty file");
+    }
+    let name = request.param("name").unwrap_or("file");
+    match uploads.store(name, &request.body).await {
+        Ok(token) => json(
+            200,
+            Value::object([
+                ("token", token.as_str().into()),
+                ("size", request.body.len().into()),
+            ]),
+        ),
+        Err(UploadError::TooLarge) => error(413, "file too large"),
+        Err(UploadError::Full) => error(507, "no room left for uploads"),
+        Err(UploadError::BadName) => error(400, "bad file name"),
+        Err(UploadError::Io(err)) => {
+            eprintln!("can't store an upload: {err}");
+            error(500, "can't store the file")
+        }
+    }
+}
+
+async fn download(shared: &Shared, token: &str) -> Response {
+    let Some(uploads) = &shared.uploads else {
+        return error(404, "not found");
+    };
+    match uploads.read(token).await {
+        Some((upload, bytes)) => Response {
+            status: 200,
+            body: Body::File {
+                name: upload.name,
+                bytes,
+            },
+        },
+        None => error(404, "no such file, or it has expired"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the status and error text a request is turned away with
+    async fn rejected(input: &[u8], upload_limit: Option<usize>) -> (u16, String) {
+        let mut reader = input;
+        match read_request(&mut reader, upload_limit).await {
+            Ok(request) => panic!("{} {} was accepted", request.method, request.path),
+            Err(Response {
+                status,
+                body: Body::Json(body),
+            }) => (status, body.get("error").unwrap().as_str().unwrap().into()),
+            Err(_) => panic!("an error without a json body"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_the_request_line_headers_and_body() {
+        let mut input: &[u8] = b"POST /send?room=%23general&name=alice HTTP/1.1\r\n\
+            Host: chat\r\nAuthorization: Bearer  s3cret \r\nContent-Length: 5\r\n\r\nhello";
+        let request = read_request(&mut input, None).await.ok().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/send")
+        );
+        assert_eq!(request.param("room"), Some("#general"));
+        assert_eq!(request.param("name"), Some("alice"));
+        assert_eq!(request.token.as_deref(), Some("s3cret"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn a_head_over_the_cap_is_turned_away() {
+        // one line that never ends
+        let long = [b'a'; MAX_HEAD + 100];
+        assert_eq!(rejected(&long, None).await.0, 431);
+        // or many short ones
+        let mut input = b"GET /poll HTTP/1.1\r\n".to_vec();
+        while input.len() <= MAX_HEAD {
+            input.extend_from_slice(b"X-Padding: 0123456789abcdef0123456789abcdef\r\n");
+        }
+        input.extend_from_slice(b"\r\n");
+        assert_eq!(rejected(&input, None).await.0, 431);
+    }
+
+    #[tokio::test]
+    async fn so_are_too_many_headers() {
+        let mut input = b"GET /poll HTTP/1.1\r\n".to_vec();
+        for _ in 0..=MAX_HEADERS {
+            input.extend_from_slice(b"X: 1\r\n");
+        }
+        input.extend_from_slice(b"\r\n");
+        assert_eq!(
+            rejected(&input, None).await,
+            (431, "too many headers".into())
+        );
+        let mut input = b"GET /poll HTTP/1.1\r\n".to_vec();
+        for _ in 0..MAX_HEADERS {
+            input.extend_from_slice(b"X: 1\r\n");
+        }
+        input.extend_from_slice(b"\r\n");
+        let mut input = &input[..];
+        assert!(read_request(&mut input, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_bad_head_is_a_bad_request() {
+        for (input, text) in [
+            (
+                &b"GET /poll HTTP/1.1\r\nHost: chat\r\n"[..],
+                "incomplete request",
+            ),
+            (b"", "incomplete request"),
+            (
+                b"POST /send HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+                "bad content-length",
+            ),
+            (
+                b"POST /send HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+                "bad content-length",
+            ),
+            (b"GET\r\n\r\n", "bad request line"),
+        ] {
+            assert_eq!(rejected(input, None).await, (400, text.into()));
+        }
+    }
+
+    #[tokio::test]
+    async fn the_body_must_all_be_there_and_within_the_limit() {
+        assert_eq!(
+            rejected(
+                b"POST /send HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello",
+                None
+            )
+            .await,
+            (400, "incomplete body".into())
+        );
+        let too_long = format!(
+            "POST /send HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(rejected(too_long.as_bytes(), None).await.0, 413);
+        // uploads have a limit of their own
+        let upload = b"POST /upload?name=a.txt HTTP/1.1\r\nContent-Length: 6\r\n\r\nabcdef";
+        assert_eq!(rejected(upload, Some(5)).await.0, 413);
+        let mut input = &upload[..];
+        assert!(read_request(&mut input, Some(6)).await.is_ok());
+    }
+
+    #[test]
+    fn decodes_percent_escapes_and_plus() {
+        assert_eq!(decode("%23general").ok().unwrap(), "#general");
+        assert_eq!(decode("hello+there%21").ok().unwrap(), "hello there!");
+        assert_eq!(decode("caf%C3%A9").ok().unwrap(), "café");
+        assert_eq!(decode("").ok().unwrap(), "");
+    }
+
+    #[test]
+    fn bad_percent_escapes_are_refused() {
+        // cut short, not hex, or not utf-8 once decoded
+        for bad in ["%", "%2", "abc%zz", "%+1", "%ff"] {
+            assert!(decode(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn a_bad_escape_in_the_query_is_a_bad_request() {
+        assert_eq!(
+            rejected(b"GET /poll?room=%2 HTTP/1.1\r\n\r\n", None).await,
+            (400, "bad percent encoding".into())
+        );
+    }
+}
@@ -0,0 +1,60 @@
+// kernel level keepalive on accepted sockets, for peers that vanish without closing their end,
+// like a laptop closing its lid behind a nat that has since forgotten the connection. nothing
+// in tokio or std sets the timings, socket2 does it for whichever platform we're on
+use std::{io, time::Duration};
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+// the first probe goes out after the connection has been quiet for idle, then the rest come a
+// third of that apart, so a dead peer is dropped within about twice idle
+pub fn enable(socket: &TcpStream, idle: Duration) -> io::Result<()> {
+    let idle = idle.max(Duration::from_secs(1));
+    let keepalive = TcpKeepalive::new().with_time(idle);
+    // the other platforms only get the idle time, probing goes by the system defaults
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "windows"
+    ))]
+    let keepalive = {
+        // unanswered probes before the kernel gives up on the peer
+        const PROBES: u32 = 3;
+        keepalive
+            .with_interval((idle / 3).max(Duration::from_secs(1)))
+            .with_retries(PROBES)
+    };
+    SockRef::from(socket).set_tcp_keepalive(&keepalive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn sets_the_timings_on_the_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        enable(&socket, Duration::from_secs(30)).unwrap();
+        let socket = SockRef::from(&socket);
+        assert!(socket.keepalive().unwrap());
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            assert_eq!(
+                socket.tcp_keepalive_time().unwrap(),
+                Duration::from_secs(30)
+            );
+            assert_eq!(
+                socket.tcp_keepalive_interval().unwrap(),
+                Duration::from_secs(10)
+            );
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+        }
+    }
+}
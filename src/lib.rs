@@ -0,0 +1,49 @@
+// the chat server as a library, so it can be embedded in another program or driven from tests.
+// the binary in main.rs is a thin wrapper around ChatServer
+mod activation;
+mod admin;
+mod auth;
+mod cidr;
+pub mod client;
+mod commands;
+mod config;
+mod connection;
+mod errors;
+mod events;
+mod framing;
+mod greeting;
+mod http;
+mod json;
+mod keepalive;
+mod load;
+mod metrics;
+mod motd;
+mod outbound;
+mod protocol;
+mod ratelimit;
+mod resolve;
+mod retention;
+mod room;
+pub mod selftest;
+mod server;
+mod snapshot;
+mod state;
+mod store;
+mod tls;
+mod upload;
+mod webhook;
+
+pub use auth::{AuthFuture, AuthResult, Authenticator, NoAuth, Role, SessionPolicy};
+pub use cidr::Cidr;
+pub use config::Config;
+pub use events::{ServerEvent, ServerEvents};
+pub use framing::Delimiter;
+pub use greeting::GreetingStep;
+pub use motd::MotdMode;
+pub use outbound::WritePolicy;
+pub use server::{ChatServer, ShutdownHandle};
+pub use state::ServerStats;
+#[cfg(feature = "sqlite")]
+pub use store::SqliteStore;
+pub use store::{FileStore, InMemoryStore, MessageStore, StoreFuture, StoredMessage};
+pub use webhook::WebhookUrl;
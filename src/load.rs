@@ -0,0 +1,57 @@
+// tells a server that is struggling as a whole apart from a single slow client, going by how
+// many of the connected clients fell behind the broadcast recently
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::state::ClientId;
+
+// one or two slow clients are their own problem, not a sign of load
+const MIN_LAGGED: usize = 3;
+
+#[derive(Debug, Default)]
+pub struct LagMonitor {
+    // when each client last fell behind, only the ones within the window are kept
+    recent: HashMap<ClientId, Instant>,
+    last_lag: Option<Instant>,
+    // the server is treated as overloaded and everyone is in slow mode
+    pub overloaded: bool,
+}
+
+impl LagMonitor {
+    // records a client falling behind, true when that tips the server into being overloaded.
+    // threshold is the percentage of clients that have to have lagged within the window
+    pub fn note(
+        &mut self,
+        id: ClientId,
+        now: Instant,
+        window: Duration,
+        threshold: usize,
+        clients: usize,
+    ) -> bool {
+        self.recent
+            .retain(|_, lagged| now.duration_since(*lagged) < window);
+        self.recent.insert(id, now);
+        self.last_lag = Some(now);
+        let lagged = self.recent.len();
+        if self.overloaded || lagged < MIN_LAGGED || lagged * 100 <= clients * threshold {
+            return false;
+        }
+        self.overloaded = true;
+        true
+    }
+
+    // ends an overload once nobody has fallen behind for a whole window
+    pub fn calmed(&mut self, now: Instant, window: Duration) -> bool {
+        let quiet = self
+            .last_lag
+            .is_none_or(|lagged| now.duration_since(lagged) >= window);
+        if self.overloaded && quiet {
+            self.overloaded = false;
+            self.recent.clear();
+            return true;
+        }
+        false
+    }
+}
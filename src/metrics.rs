@@ -0,0 +1,162 @@
+// the server's counters in the prometheus text format, for scraping on a listener of its own
+// with --metrics-listen. it only ever answers GET /metrics, one request per connection, so it
+// can sit on a port the chat clients never see
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::state::{ServerStats, Shared};
+
+const MAX_HEAD: usize = 8 * 1024;
+// a scraper sends its request straight away
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn handle(socket: TcpStream, shared: Arc<Shared>) {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    let (status, body) = match timeout(READ_TIMEOUT, request_line(&mut reader)).await {
+        Ok(Some(line)) => {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("GET"), Some("/metrics")) => ("200 OK", render(&shared.snapshot())),
+                (Some("GET"), _) => ("404 Not Found", "only /metrics is here\n".to_string()),
+                _ => ("405 Method Not Allowed", "only GET works\n".to_string()),
+            }
+        }
+        Ok(None) => ("400 Bad Request", "bad request\n".to_string()),
+        Err(_) => ("408 Request Timeout", "request timed out\n".to_string()),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if writer.write_all(head.as_bytes()).await.is_ok() {
+        let _ = writer.write_all(body.as_bytes()).await;
+    }
+    let _ = writer.shutdown().await;
+}
+
+// the first line of the request, once the rest of the head has been read past. headers don't
+// matter here. nothing past MAX_HEAD is read, however long a line goes on for
+async fn request_line(reader: &mut (impl AsyncBufRead + Unpin)) -> Option<String> {
+    let mut reader = reader.take(MAX_HEAD as u64);
+    let mut first = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        // the end of the stream, or of what may be read
+        if !line.ends_with('\n') {
+            return None;
+        }
+        if line.trim_end().is_empty() {
+            return first;
+        }
+        first.get_or_insert_with(|| line.trim_end().to_string());
+    }
+}
+
+fn render(stats: &ServerStats) -> String {
+    let metrics = [
+        (
+            "chat_active_connections",
+            "gauge",
+            "registered clients",
+            stats.active_connections as u64,
+        ),
+        (
+            "chat_connections_total",
+            "counter",
+            "chat connections accepted",
+            stats.connections_total,
+        ),
+        (
+            "chat_messages_total",
+            "counter",
+            "chat messages sent",
+            stats.messages_total,
+        ),
+        (
+            "chat_rooms",
+            "gauge",
+            "rooms that exist right now",
+            stats.rooms as u64,
+        ),
+        (
+            "chat_bytes_total",
+            "counter",
+            "bytes read from clients",
+            stats.bytes_total,
+        ),
+        (
+            "chat_writes_total",
+            "counter",
+            "writes to client sockets",
+            stats.writes_total,
+        ),
+        (
+            "chat_lagged_total",
+            "counter",
+            "times a client fell behind and missed messages",
+            stats.lag_events,
+        ),
+        (
+            "chat_overloaded",
+            "gauge",
+            "1 while everyone is in slow mode because too many clients fall behind",
+            u64::from(stats.overloaded),
+        ),
+        (
+            "chat_uptime_seconds",
+            "gauge",
+            "seconds since the server started",
+            stats.uptime.as_secs(),
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = write!(
+            text,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn first_line(input: &[u8]) -> Option<String> {
+        let mut reader = input;
+        request_line(&mut reader).await
+    }
+
+    #[tokio::test]
+    async fn reads_past_the_headers_to_the_blank_line() {
+        assert_eq!(
+            first_line(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n").await,
+            Some("GET /metrics HTTP/1.1".to_string())
+        );
+        // a head that never ends isn't a request
+        assert_eq!(
+            first_line(b"GET /metrics HTTP/1.1\r\nHost: x\r\n").await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_reading_at_the_cap() {
+        assert_eq!(first_line(&[b'a'; MAX_HEAD + 1]).await, None);
+        let mut input = b"GET /metrics HTTP/1.1\r\n".to_vec();
+        while input.len() <= MAX_HEAD {
+            input.extend_from_slice(b"X: 1\r\n");
+        }
+        input.extend_from_slice(b"\r\n");
+        assert_eq!(first_line(&input).await, None);
+    }
+}
@@ -0,0 +1,106 @@
+// the message of the day, shown to every client right after it registers. it comes from a
+// file, or a directory with one message per file, and the mode decides how one is picked
+use std::{
+    collections::hash_map::RandomState,
+    fmt, fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    path::Path,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MotdMode {
+    // the whole file for everyone, or the first file of a directory
+    #[default]
+    Static,
+    // a line of the file, or a file of the directory, picked at random per connection
+    Random,
+    // the same, but taking turns
+    Rotate,
+}
+
+impl FromStr for MotdMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MotdMode, String> {
+        match s {
+            "static" => Ok(MotdMode::Static),
+            "random" => Ok(MotdMode::Random),
+            "rotate" => Ok(MotdMode::Rotate),
+            _ => Err(format!(
+                "unknown motd mode {s}, expected static, random or rotate"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MotdMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MotdMode::Static => "static",
+            MotdMode::Random => "random",
+            MotdMode::Rotate => "rotate",
+        })
+    }
+}
+
+pub struct Motd {
+    mode: MotdMode,
+    entries: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl Motd {
+    // read once at startup, changing the files later takes a restart
+    pub fn load(path: &Path, mode: MotdMode) -> io::Result<Motd> {
+        let mut entries = if path.is_dir() {
+            let mut files: Vec<_> = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<_, _>>()?;
+            files.retain(|file| file.is_file());
+            files.sort();
+            files
+                .iter()
+                .map(fs::read_to_string)
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            let text = fs::read_to_string(path)?;
+            match mode {
+                MotdMode::Static => vec![text],
+                MotdMode::Random | MotdMode::Rotate => text.lines().map(str::to_string).collect(),
+            }
+        };
+        for entry in &mut entries {
+            entry.truncate(entry.trim_end().len());
+        }
+        entries.retain(|entry| !entry.trim().is_empty());
+        if entries.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no message of the day in {}", path.display()),
+            ));
+        }
+        Ok(Motd {
+            mode,
+            entries,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    // the message for a client that has just connected
+    pub fn pick(&self) -> &str {
+        let index = match self.mode {
+            MotdMode::Static => 0,
+            MotdMode::Random => {
+                // a freshly keyed std hasher is random enough for picking a greeting
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write_usize(self.next.fetch_add(1, Ordering::Relaxed));
+                hasher.finish() as usize % self.entries.len()
+            }
+            MotdMode::Rotate => self.next.fetch_add(1, Ordering::Relaxed) % self.entries.len(),
+        };
+        &self.entries[index]
+    }
+}
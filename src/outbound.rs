@@ -0,0 +1,445 @@
+// the outgoing side of a connection. lines are queued here and written to the socket by a
+// dedicated task, so a client that reads slowly can't stall the select loop of its own handler
+use std::{
+    fmt, io,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    errors::ErrorCode,
+    json::Value,
+    protocol::{self, Protocol},
+    room::{HistoryEntry, Reaction},
+    state::DisconnectReason,
+};
+
+// how many lines may pile up for one client before broadcasts to it start being dropped
+pub const QUEUE_CAPACITY: usize = 64;
+// system notices get a small queue of their own that the writer always empties first
+const URGENT_CAPACITY: usize = 8;
+
+// the writer task has stopped, which only happens once the socket can't be written to anymore
+#[derive(Debug)]
+pub struct Closed;
+
+// when lines are handed to the socket. immediate writes each one as soon as it is rendered,
+// batched collects them and writes once the queue has run dry or the flush interval is up,
+// which saves a syscall per line for clients that get a lot of small ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritePolicy {
+    #[default]
+    Immediate,
+    Batched,
+}
+
+impl FromStr for WritePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<WritePolicy, String> {
+        match s {
+            "immediate" => Ok(WritePolicy::Immediate),
+            "batched" => Ok(WritePolicy::Batched),
+            _ => Err(format!(
+                "unknown write policy {s}, expected immediate or batched"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for WritePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WritePolicy::Immediate => "immediate",
+            WritePolicy::Batched => "batched",
+        })
+    }
+}
+
+// items are only turned into text by the writer task, in whichever protocol the client speaks.
+// chat messages are queued as the shared history entry, so fanning a message out to many
+// clients doesn't allocate a line for each
+pub enum Outgoing {
+    // server text, json clients get it wrapped up as a notice
+    Text(String),
+    Error(ErrorCode, String),
+    Message(Arc<HistoryEntry>),
+    Direct { from: String, text: String },
+    // json only, plain text clients never see edits, deletes and reactions
+    Edit(Arc<HistoryEntry>),
+    Delete { room: Arc<str>, seq: u64 },
+    Reaction(Arc<Reaction>),
+    // already structured, written out as json whatever the protocol
+    Json(Value),
+    // the last thing a client is sent before it is disconnected, plain text clients only get
+    // the text
+    Disconnect(DisconnectReason, String),
+    // switches how everything queued after it is written
+    SetProtocol(Protocol),
+    // from here on plain text chat lines start with the time at this many minutes east of
+    // utc, None for no time
+    SetClock(Option<i32>),
+}
+
+impl From<String> for Outgoing {
+    fn from(text: String) -> Self {
+        Outgoing::Text(text)
+    }
+}
+
+impl From<Arc<HistoryEntry>> for Outgoing {
+    fn from(entry: Arc<HistoryEntry>) -> Self {
+        Outgoing::Message(entry)
+    }
+}
+
+pub struct Outbound {
+    handle: OutboundHandle,
+    task: JoinHandle<()>,
+}
+
+// lets something other than the connection's own task queue lines for the client, the
+// registry keeps one for every client. it never waits, a line for someone else's client
+// isn't worth holding anything up for
+#[derive(Debug, Clone)]
+pub struct OutboundHandle {
+    tx: mpsc::Sender<Outgoing>,
+    urgent: mpsc::Sender<Outgoing>,
+}
+
+impl OutboundHandle {
+    // see Outbound::push_urgent
+    pub fn push_urgent(&self, outgoing: impl Into<Outgoing>) -> Result<(), Closed> {
+        match self.urgent.try_send(outgoing.into()) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(Closed),
+        }
+    }
+
+    // see Outbound::push
+    pub fn push(&self, outgoing: impl Into<Outgoing>) -> Result<(), Closed> {
+        match self.tx.try_send(outgoing.into()) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Closed(_)) => Err(Closed),
+        }
+    }
+}
+
+impl Outbound {
+    // spawns the write task, it runs until the socket fails or the Outbound and every handle
+    // to it are dropped. every item written is followed by the terminator, room_tags puts the
+    // room in front of chat lines for plain text clients. a wrap width above zero breaks the
+    // server's own text for plain text clients into lines of at most that many characters.
+    // clock is the SetClock the client starts with. batch is the flush interval for
+    // WritePolicy::Batched, None writes every line straight away. every write to the socket
+    // is counted in writes
+    pub fn spawn<W>(
+        writer: W,
+        terminator: &'static str,
+        room_tags: bool,
+        wrap_width: usize,
+        mut clock: Option<i32>,
+        batch: Option<Duration>,
+        writes: Arc<AtomicU64>,
+    ) -> Outbound
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::channel::<Outgoing>(QUEUE_CAPACITY);
+        let (urgent, mut urgent_rx) = mpsc::channel::<Outgoing>(URGENT_CAPACITY);
+        let task = tokio::spawn(async move {
+            // one scratch buffer for the life of the connection, cleared between lines
+            let mut buf = String::with_capacity(256);
+            let mut protocol = Protocol::Plain;
+            let mut writer = BufWriter::new(Counted {
+                inner: writer,
+                writes,
+            });
+            // when the oldest line still sitting in the writer's buffer went in
+            let mut buffered_at: Option<Instant> = None;
+            loop {
+                // a batch is written out once the queues run dry, or after the flush interval
+                // for a client whose queue never does
+                let mut next = None;
+                if let (Some(at), Some(interval)) = (buffered_at, batch) {
+                    next = urgent_rx.try_recv().or_else(|_| rx.try_recv()).ok();
+                    if next.is_none() || at.elapsed() >= interval {
+                        buffered_at = None;
+                        if writer.flush().await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                let outgoing = match next {
+                    Some(outgoing) => outgoing,
+                    // biased, so an urgent line goes out next however far behind the client is
+                    None => tokio::select! {
+                        biased;
+                        Some(outgoing) = urgent_rx.recv() => outgoing,
+                        Some(outgoing) = rx.recv() => outgoing,
+                        else => break,
+                    },
+                };
+                match outgoing {
+                    Outgoing::SetProtocol(new) => {
+                        protocol = new;
+                        continue;
+                    }
+                    Outgoing::SetClock(offset) => {
+                        clock = offset;
+                        continue;
+                    }
+                    _ => {}
+                }
+                buf.clear();
+                match &outgoing {
+                    // only server text, what clients write is passed on the way they wrote it
+                    Outgoing::Text(text) | Outgoing::Disconnect(_, text)
+                        if wrap_width > 0 && protocol == Protocol::Plain =>
+                    {
+                        for line in wrap(text, wrap_width) {
+                            buf.push_str(line);
+                            buf.push_str(terminator);
+                        }
+                    }
+                    _ => {
+                        if !protocol::render(protocol, &outgoing, room_tags, clock, &mut buf) {
+                            continue;
+                        }
+                        buf.push_str(terminator);
+                    }
+                }
+                if writer.write_all(buf.as_bytes()).await.is_err() {
+                    break;
+                }
+                if batch.is_some() {
+                    buffered_at.get_or_insert_with(Instant::now);
+                } else if writer.flush().await.is_err() {
+                    break;
+                }
+            }
+            // flushes and ends the stream, which for tls is what tells the client it is over
+            let _ = writer.shutdown().await;
+        });
+        Outbound {
+            handle: OutboundHandle { tx, urgent },
+            task,
+        }
+    }
+
+    pub fn handle(&self) -> OutboundHandle {
+        self.handle.clone()
+    }
+
+    // stops taking new lines and waits until everything already queued has been written.
+    // handles held elsewhere keep the writer going, the registry lets go of its own when the
+    // client is removed, which happens before this is called
+    pub async fn close(self) {
+        drop(self.handle);
+        let _ = self.task.await;
+    }
+
+    // for the system notices a client must not miss even when its queue is backed up: the
+    // server shutting down, the client's room closing, the registration timing out. these
+    // jump ahead of everything queued and never wait, only a full urgent queue drops them
+    pub fn push_urgent(&self, outgoing: impl Into<Outgoing>) -> Result<(), Closed> {
+        self.handle.push_urgent(outgoing)
+    }
+
+    // queues a broadcast without waiting, if the client is too far behind the line is dropped
+    pub fn push(&self, outgoing: impl Into<Outgoing>) -> Result<(), Closed> {
+        self.handle.push(outgoing)
+    }
+
+    // queues a reply to something the client asked for, waiting for room in the queue
+    // so the client applies backpressure to itself rather than losing the answer
+    pub async fn send(&self, outgoing: impl Into<Outgoing>) -> Result<(), Closed> {
+        self.handle
+            .tx
+            .send(outgoing.into())
+            .await
+            .map_err(|_| Closed)
+    }
+}
+
+// a writer that counts the writes that reach it, the BufWriter in front of it decides how
+// many that are
+struct Counted<W> {
+    inner: W,
+    writes: Arc<AtomicU64>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Counted<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(_)) = written {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// breaks text into lines of at most width characters at whitespace, the breaks replacing the
+// whitespace. a word longer than a whole line gets a line to itself rather than being cut up
+fn wrap(text: &str, width: usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        // the line being built as a byte range of the paragraph, and how many chars it has
+        let mut line: Option<(usize, usize)> = None;
+        let mut chars = 0;
+        for (at, word) in words(paragraph) {
+            let end = at + word.len();
+            let word_chars = word.chars().count();
+            match line {
+                Some((start, last)) => {
+                    let with_word = chars + paragraph[last..at].chars().count() + word_chars;
+                    if with_word <= width {
+                        line = Some((start, end));
+                        chars = with_word;
+                    } else {
+                        lines.push(&paragraph[start..last]);
+                        line = Some((at, end));
+                        chars = word_chars;
+                    }
+                }
+                None => {
+                    line = Some((at, end));
+                    chars = word_chars;
+                }
+            }
+        }
+        match line {
+            Some((start, end)) => lines.push(&paragraph[start..end]),
+            None => lines.push(""),
+        }
+    }
+    lines
+}
+
+// the words of a line along with where each one starts
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut rest = 0;
+    std::iter::from_fn(move || {
+        let start = rest + line[rest..].find(|c: char| !c.is_whitespace())?;
+        let len = line[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(line.len() - start);
+        rest = start + len;
+        Some((start, &line[start..rest]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn wraps_at_whitespace() {
+        assert_eq!(wrap("the quick brown fox", 9), ["the quick", "brown fox"]);
+        assert_eq!(wrap("the quick brown fox", 100), ["the quick brown fox"]);
+        // the whitespace a line is broken at goes, whatever there was of it
+        assert_eq!(wrap("one   two", 3), ["one", "two"]);
+    }
+
+    #[test]
+    fn a_line_exactly_as_wide_stays_whole() {
+        assert_eq!(wrap("abcd efgh", 9), ["abcd efgh"]);
+        assert_eq!(wrap("abcd efgh", 8), ["abcd", "efgh"]);
+        assert_eq!(wrap("abcd efgh ij", 9), ["abcd efgh", "ij"]);
+    }
+
+    #[test]
+    fn a_long_word_gets_a_line_of_its_own() {
+        assert_eq!(
+            wrap("a supercalifragilistic b", 5),
+            ["a", "supercalifragilistic", "b"]
+        );
+    }
+
+    #[test]
+    fn counts_characters_not_bytes() {
+        assert_eq!(wrap("éé éé éé", 5), ["éé éé", "éé"]);
+        assert_eq!(wrap("🎉🎉 🎉🎉", 2), ["🎉🎉", "🎉🎉"]);
+    }
+
+    #[test]
+    fn keeps_the_line_breaks_it_was_given() {
+        assert_eq!(wrap("one\ntwo three", 5), ["one", "two", "three"]);
+        assert_eq!(wrap("one\n\ntwo", 10), ["one", "", "two"]);
+        assert_eq!(wrap("", 10), [""]);
+    }
+
+    // queues the lines before the writer gets to run, then reads them back and gives the
+    // number of writes it took
+    async fn writes_for(lines: usize, batch: Option<Duration>) -> u64 {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let writes = Arc::new(AtomicU64::new(0));
+        let out = Outbound::spawn(client, "\n", false, 0, None, batch, writes.clone());
+        for n in 0..lines {
+            out.push(format!("line {n}")).unwrap();
+        }
+        out.close().await;
+        let mut received = String::new();
+        server.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received.lines().count(), lines);
+        writes.load(Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn immediate_writes_every_line() {
+        assert_eq!(writes_for(20, None).await, 20);
+    }
+
+    #[tokio::test]
+    async fn batched_writes_a_backlog_at_once() {
+        assert_eq!(writes_for(20, Some(Duration::from_secs(1))).await, 1);
+    }
+
+    #[tokio::test]
+    async fn batched_writes_a_lone_line_straight_away() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let writes = Arc::new(AtomicU64::new(0));
+        let out = Outbound::spawn(
+            client,
+            "\n",
+            false,
+            0,
+            None,
+            Some(Duration::from_secs(60)),
+            writes,
+        );
+        out.push("hello".to_string()).unwrap();
+        let mut line = [0; 6];
+        tokio::time::timeout(Duration::from_secs(5), server.read_exact(&mut line))
+            .await
+            .expect("the line wasn't held back for the flush interval")
+            .unwrap();
+        assert_eq!(&line, b"hello\n");
+    }
+}
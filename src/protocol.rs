@@ -0,0 +1,296 @@
+// the two ways a client can talk to the server. plain text is the telnet friendly default,
+// a client that answers the name prompt with a json object switches to json for the rest of
+// the connection: one object per line in both directions
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    errors::{format_error, ChatError, ErrorCode},
+    json::{self, JsonStr, Value},
+    outbound::Outgoing,
+    state::Registry,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Plain,
+    Json,
+}
+
+// the newest protocol version this server speaks, sent in the json welcome frame
+pub const PROTOCOL_VERSION: u64 = 1;
+
+impl Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Plain => "plain",
+            Protocol::Json => "json",
+        }
+    }
+
+    // plain text is version 0, the json protocol started out at 1. --min-protocol-version is
+    // checked against this when a client registers
+    pub fn version(self) -> u64 {
+        match self {
+            Protocol::Plain => 0,
+            Protocol::Json => PROTOCOL_VERSION,
+        }
+    }
+}
+
+// what a json client can send, identified by its "type" field
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    // the secret is for the authenticator, empty when the client didn't send one
+    Hello {
+        name: String,
+        secret: String,
+    },
+    Resume {
+        token: String,
+    },
+    // the id is the client's own for the message, a second one with the same id is a resend
+    Message {
+        body: String,
+        reply_to: Option<u64>,
+        id: Option<String>,
+    },
+    Edit {
+        seq: u64,
+        body: String,
+    },
+    Delete {
+        seq: u64,
+    },
+    React {
+        seq: u64,
+        emoji: String,
+    },
+    Roster,
+}
+
+pub fn is_json(line: &str) -> bool {
+    line.trim_start().starts_with('{')
+}
+
+fn bad_request(text: impl Into<String>) -> ChatError {
+    ChatError::new(ErrorCode::BadRequest, text)
+}
+
+fn string_field(value: &Value, key: &str) -> Result<String, ChatError> {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| bad_request(format!("missing string field \"{key}\"")))
+}
+
+fn seq_field(value: &Value) -> Result<u64, ChatError> {
+    value
+        .get("seq")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| bad_request("missing numeric field \"seq\""))
+}
+
+// an emoji can be a handful of code points once modifiers and joiners are in, but not a sentence
+const MAX_EMOJI_CHARS: usize = 8;
+
+// client message ids are only compared, never shown, so anything short will do
+const MAX_MESSAGE_ID_LEN: usize = 64;
+
+fn message_id_field(value: &Value) -> Result<Option<String>, ChatError> {
+    match value.get("id") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(id)) if !id.is_empty() && id.len() <= MAX_MESSAGE_ID_LEN => {
+            Ok(Some(id.clone()))
+        }
+        Some(_) => Err(bad_request(format!(
+            "\"id\" must be a string of 1 to {MAX_MESSAGE_ID_LEN} bytes"
+        ))),
+    }
+}
+
+fn emoji_field(value: &Value) -> Result<String, ChatError> {
+    let emoji = string_field(value, "emoji")?;
+    let chars = emoji.chars().count();
+    if chars == 0 || chars > MAX_EMOJI_CHARS || emoji.chars().any(char::is_whitespace) {
+        return Err(bad_request("\"emoji\" must be a single emoji"));
+    }
+    Ok(emoji)
+}
+
+pub fn parse_request(line: &str) -> Result<Request, ChatError> {
+    let value =
+        json::parse(line.trim()).map_err(|err| bad_request(format!("invalid json: {err}")))?;
+    let kind = value
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| bad_request("missing \"type\""))?;
+    match kind {
+        "hello" => Ok(Request::Hello {
+            name: string_field(&value, "name")?,
+            secret: match value.get("secret") {
+                None | Some(Value::Null) => String::new(),
+                Some(_) => string_field(&value, "secret")?,
+            },
+        }),
+        "resume" => Ok(Request::Resume {
+            token: string_field(&value, "token")?,
+        }),
+        "message" => Ok(Request::Message {
+            body: string_field(&value, "body")?,
+            reply_to: match value.get("reply_to") {
+                None | Some(Value::Null) => None,
+                Some(parent) => Some(
+                    parent
+                        .as_u64()
+                        .ok_or_else(|| bad_request("\"reply_to\" must be a sequence number"))?,
+                ),
+            },
+            id: message_id_field(&value)?,
+        }),
+        "edit" => Ok(Request::Edit {
+            seq: seq_field(&value)?,
+            body: string_field(&value, "body")?,
+        }),
+        "delete" => Ok(Request::Delete {
+            seq: seq_field(&value)?,
+        }),
+        "react" => Ok(Request::React {
+            seq: seq_field(&value)?,
+            emoji: emoji_field(&value)?,
+        }),
+        "roster" => Ok(Request::Roster),
+        other => Err(bad_request(format!("unknown type \"{other}\""))),
+    }
+}
+
+// writes one outgoing item into the connection's scratch buffer, without the line terminator.
+// returns false for items that have nothing to show in this protocol. json messages always
+// carry their room, room_tags decides whether plain text ones show it
+pub fn render(
+    protocol: Protocol,
+    outgoing: &Outgoing,
+    room_tags: bool,
+    clock: Option<i32>,
+    buf: &mut String,
+) -> bool {
+    match (protocol, outgoing) {
+        (_, Outgoing::SetProtocol(_) | Outgoing::SetClock(_))
+        | (Protocol::Plain, Outgoing::Edit(_) | Outgoing::Delete { .. } | Outgoing::Reaction(_)) => {
+            return false
+        }
+        (Protocol::Plain, Outgoing::Text(text) | Outgoing::Disconnect(_, text)) => {
+            buf.push_str(text)
+        }
+        (Protocol::Plain, Outgoing::Error(code, text)) => buf.push_str(&format_error(*code, text)),
+        (Protocol::Plain, Outgoing::Message(entry)) => entry.format_at(room_tags, clock, buf),
+        (Protocol::Plain, Outgoing::Direct { from, text }) => {
+            let _ = write!(buf, "[dm] {from}: {text}");
+        }
+        (Protocol::Json, Outgoing::Text(text)) => {
+            let _ = write!(buf, r#"{{"type":"notice","text":{}}}"#, JsonStr(text));
+        }
+        (Protocol::Json, Outgoing::Error(code, text)) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"error","code":"{}","text":{}}}"#,
+                code.as_str(),
+                JsonStr(text)
+            );
+        }
+        // written by hand rather than through a Value, this is the hot path
+        (Protocol::Json, Outgoing::Message(entry)) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"message","seq":{},"room":{},"from":{},"body":{},"ts":{}"#,
+                entry.seq,
+                JsonStr(&entry.room),
+                JsonStr(&entry.from),
+                JsonStr(&entry.text),
+                entry.ts
+            );
+            if entry.bot {
+                buf.push_str(r#","bot":true"#);
+            }
+            if let Some(parent) = entry.reply_to {
+                let _ = write!(buf, r#","reply_to":{parent}"#);
+            }
+            buf.push('}');
+        }
+        (Protocol::Json, Outgoing::Direct { from, text }) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"direct","from":{},"body":{}}}"#,
+                JsonStr(from),
+                JsonStr(text)
+            );
+        }
+        (Protocol::Json, Outgoing::Edit(entry)) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"edit","seq":{},"room":{},"from":{},"body":{}}}"#,
+                entry.seq,
+                JsonStr(&entry.room),
+                JsonStr(&entry.from),
+                JsonStr(&entry.text)
+            );
+        }
+        (Protocol::Json, Outgoing::Delete { room, seq }) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"delete","seq":{seq},"room":{}}}"#,
+                JsonStr(room)
+            );
+        }
+        (Protocol::Json, Outgoing::Reaction(reaction)) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"reaction","seq":{},"room":{},"from":{},"emoji":{},"count":{}}}"#,
+                reaction.seq,
+                JsonStr(&reaction.room),
+                JsonStr(&reaction.from),
+                JsonStr(&reaction.emoji),
+                reaction.count
+            );
+        }
+        (Protocol::Json, Outgoing::Disconnect(reason, text)) => {
+            let _ = write!(
+                buf,
+                r#"{{"type":"disconnect","reason":"{}","detail":{}}}"#,
+                reason.code(),
+                JsonStr(text)
+            );
+        }
+        (_, Outgoing::Json(value)) => {
+            let _ = write!(buf, "{value}");
+        }
+    }
+    true
+}
+
+// every room with the names of its members. there are no hidden rooms, so everything in the
+// registry is visible to whoever asks
+pub fn roster(registry: &Registry) -> Value {
+    let mut rooms: BTreeMap<&str, Vec<&str>> = registry
+        .rooms
+        .keys()
+        .map(|name| (name.as_str(), Vec::new()))
+        .collect();
+    for client in registry.clients.values() {
+        for room in &client.rooms {
+            rooms
+                .entry(room.as_str())
+                .or_default()
+                .push(client.name.as_str());
+        }
+    }
+    let rooms = rooms
+        .into_iter()
+        .map(|(room, mut names)| {
+            names.sort_unstable();
+            let names = names.into_iter().map(Value::from).collect();
+            (room.to_string(), Value::Array(names))
+        })
+        .collect();
+    Value::object([("type", "roster".into()), ("rooms", Value::Object(rooms))])
+}
@@ -0,0 +1,124 @@
+// how fast a single client may send, as a token bucket. a client that has only just registered
+// gets a bigger bucket, so pasting a few setup lines at once doesn't get it throttled, and the
+// extra room shrinks back to the normal burst over the grace period
+use std::time::{Duration, Instant};
+
+// how many times the normal burst a brand new client may send at once
+const GRACE_BURST_FACTOR: f64 = 4.0;
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    // tokens added per second
+    rate: f64,
+    burst: f64,
+    grace: Duration,
+    registered_at: Instant,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    // a full bucket for a client registering now
+    pub fn new(rate: usize, burst: usize, grace: Duration, now: Instant) -> TokenBucket {
+        let mut bucket = TokenBucket {
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+            grace,
+            registered_at: now,
+            tokens: 0.0,
+            refilled_at: now,
+        };
+        bucket.tokens = bucket.capacity(now);
+        bucket
+    }
+
+    // the grace burst to begin with, falling in a straight line to the normal one
+    fn capacity(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.registered_at);
+        if elapsed >= self.grace {
+            return self.burst;
+        }
+        let left = 1.0 - elapsed.as_secs_f64() / self.grace.as_secs_f64();
+        self.burst + self.burst * (GRACE_BURST_FACTOR - 1.0) * left
+    }
+
+    // uses up a token, or says how long until the next one is there
+    pub fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity(now));
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(5);
+
+    // how many messages go through at once at the given moment
+    fn drain(bucket: &mut TokenBucket, now: Instant) -> usize {
+        let mut sent = 0;
+        while bucket.take(now).is_ok() {
+            sent += 1;
+        }
+        sent
+    }
+
+    #[test]
+    fn a_new_client_gets_the_grace_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, 5, GRACE, start);
+        assert_eq!(drain(&mut bucket, start), 20);
+    }
+
+    #[test]
+    fn after_the_grace_period_the_normal_burst_applies() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, 5, GRACE, start);
+        // a client that sends nothing during the grace period can't save it up
+        assert_eq!(drain(&mut bucket, start + GRACE), 5);
+        assert_eq!(drain(&mut bucket, start + GRACE * 10), 5);
+    }
+
+    #[test]
+    fn the_grace_burst_shrinks_in_a_straight_line() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, 4, Duration::from_secs(4), start);
+        // halfway through the extra room is half gone, 4 + 12 / 2
+        assert_eq!(drain(&mut bucket, start + Duration::from_secs(2)), 10);
+    }
+
+    #[test]
+    fn says_how_long_until_the_next_token() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, 1, Duration::ZERO, start);
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Err(Duration::from_millis(500)));
+        let later = start + Duration::from_millis(250);
+        assert_eq!(bucket.take(later), Err(Duration::from_millis(250)));
+        assert_eq!(bucket.take(start + Duration::from_millis(500)), Ok(()));
+    }
+
+    #[test]
+    fn refills_at_the_rate_up_to_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 3, Duration::ZERO, start);
+        assert_eq!(drain(&mut bucket, start), 3);
+        assert_eq!(drain(&mut bucket, start + Duration::from_millis(200)), 2);
+        assert_eq!(drain(&mut bucket, start + Duration::from_secs(60)), 3);
+    }
+
+    #[test]
+    fn a_zero_burst_still_lets_one_through() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, 0, Duration::ZERO, start);
+        assert_eq!(drain(&mut bucket, start), 1);
+    }
+}
@@ -0,0 +1,72 @@
+// reverse dns for the connection log. there's no async resolver in tokio, so the lookup runs
+// on the blocking pool and is given up on after a couple of seconds
+use std::{ffi::CStr, mem, net::IpAddr, ptr, time::Duration};
+
+use tokio::{task, time::timeout};
+
+use crate::state::Peer;
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// the host name for an address, None if it has none or the lookup was too slow
+pub async fn peer_name(ip: IpAddr) -> Option<String> {
+    let lookup = task::spawn_blocking(move || lookup(ip));
+    timeout(LOOKUP_TIMEOUT, lookup).await.ok()?.ok()?
+}
+
+// prints the connection with its host name once the lookup is done, falling back to the raw
+// address. spawned so the client never waits on it
+pub fn log_connection(peer: Peer) {
+    tokio::spawn(async move {
+        let (addr, transport) = (peer.addr, peer.transport.as_str());
+        match peer_name(addr.ip()).await {
+            Some(host) => println!("{transport} connection from {addr} ({host})"),
+            None => println!("{transport} connection from {addr}"),
+        }
+    });
+}
+
+fn lookup(ip: IpAddr) -> Option<String> {
+    // NI_MAXHOST on glibc, the longest name getnameinfo will write
+    let mut host = [0 as libc::c_char; 1025];
+    // SAFETY: the sockaddr is fully initialised for its family and passed with its own size,
+    // and getnameinfo writes at most host.len() bytes including the terminating nul
+    let rc = unsafe {
+        match ip {
+            IpAddr::V4(v4) => {
+                let mut sa: libc::sockaddr_in = mem::zeroed();
+                sa.sin_family = libc::AF_INET as libc::sa_family_t;
+                sa.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+                libc::getnameinfo(
+                    ptr::addr_of!(sa).cast(),
+                    mem::size_of_val(&sa) as libc::socklen_t,
+                    host.as_mut_ptr(),
+                    host.len() as libc::socklen_t,
+                    ptr::null_mut(),
+                    0,
+                    libc::NI_NAMEREQD,
+                )
+            }
+            IpAddr::V6(v6) => {
+                let mut sa: libc::sockaddr_in6 = mem::zeroed();
+                sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sa.sin6_addr.s6_addr = v6.octets();
+                libc::getnameinfo(
+                    ptr::addr_of!(sa).cast(),
+                    mem::size_of_val(&sa) as libc::socklen_t,
+                    host.as_mut_ptr(),
+                    host.len() as libc::socklen_t,
+                    ptr::null_mut(),
+                    0,
+                    libc::NI_NAMEREQD,
+                )
+            }
+        }
+    };
+    if rc != 0 {
+        return None;
+    }
+    // SAFETY: on success the buffer holds a nul terminated string
+    let host = unsafe { CStr::from_ptr(host.as_ptr()) };
+    host.to_str().ok().map(str::to_string)
+}
@@ -0,0 +1,52 @@
+// --history-ttl: messages are forgotten once they are older than it, from the rooms' history
+// and from the message store. a sweep runs every so often rather than on a timer per message,
+// so one may linger for up to a tenth of the ttl, never more than a minute, past its time.
+// --empty-room-grace rooms are swept the same way
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{sync::watch, time::interval};
+
+use crate::{server::stopped, state::Shared};
+
+const MIN_SWEEP: Duration = Duration::from_secs(1);
+const MAX_SWEEP: Duration = Duration::from_secs(60);
+
+pub async fn expire_every(shared: Arc<Shared>, ttl: Duration, mut shutdown: watch::Receiver<bool>) {
+    let mut ticks = interval((ttl / 10).clamp(MIN_SWEEP, MAX_SWEEP));
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = stopped(&mut shutdown) => return,
+        }
+        {
+            let mut registry = shared.registry();
+            for room in registry.rooms.values_mut() {
+                room.expire_history(ttl);
+            }
+        }
+        // the store goes by wall clock time, the history by how long ago a message was sent
+        let before = SystemTime::now()
+            .checked_sub(ttl)
+            .and_then(|before| before.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |before| before.as_millis() as u64);
+        shared.expire_stored(before);
+    }
+}
+
+pub async fn reclaim_every(
+    shared: Arc<Shared>,
+    grace: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticks = interval((grace / 10).clamp(MIN_SWEEP, MAX_SWEEP));
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = stopped(&mut shutdown) => return,
+        }
+        shared.registry().reclaim_empty_rooms();
+    }
+}
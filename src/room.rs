@@ -0,0 +1,346 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::state::ClientId;
+
+// a chat message as it is kept in a room's history
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    // sequence numbers are global and only ever go up, so they order messages across rooms
+    pub seq: u64,
+    pub room: Arc<str>,
+    pub from: String,
+    pub text: String,
+    pub sent_at: Instant,
+    // the same moment by the wall clock, milliseconds since the epoch. json clients get it as
+    // is, plain text clients only with /tz
+    pub ts: u64,
+    // posted through the http bot endpoint rather than by a person
+    pub bot: bool,
+    // the message in the same room this one answers
+    pub reply_to: Option<u64>,
+}
+
+impl HistoryEntry {
+    // how the message is shown to plain text clients, written into a buffer the caller reuses.
+    // with the room tag it reads [#room] name: text
+    pub fn format_into(&self, room_tag: bool, buf: &mut String) {
+        self.format_at(room_tag, None, buf);
+    }
+
+    // the same with the time in front, [14:05], at offset minutes east of utc
+    pub fn format_at(&self, room_tag: bool, offset: Option<i32>, buf: &mut String) {
+        if let Some(offset) = offset {
+            let minute = ((self.ts / 60_000) as i64 + i64::from(offset)).rem_euclid(24 * 60);
+            let _ = write!(buf, "[{:02}:{:02}] ", minute / 60, minute % 60);
+        }
+        if room_tag {
+            buf.push('[');
+            buf.push_str(&self.room);
+            buf.push_str("] ");
+        }
+        if self.bot {
+            buf.push_str("[bot] ");
+        }
+        buf.push_str(&self.from);
+        buf.push_str(": ");
+        if let Some(parent) = self.reply_to {
+            let _ = write!(buf, "(re: #{parent}) ");
+        }
+        buf.push_str(&self.text);
+    }
+}
+
+// like 2024-03-09 14:05:00, in utc
+pub fn utc_datetime(ms: u64) -> String {
+    let secs = ms / 1000;
+    // days to a civil date, after Howard Hinnant's days_from_civil turned around
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let time = secs % 86_400;
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// someone reacting to a message, with how many times that emoji has been used on it so far
+#[derive(Debug)]
+pub struct Reaction {
+    pub seq: u64,
+    pub room: Arc<str>,
+    pub from: String,
+    pub emoji: String,
+    pub count: usize,
+}
+
+// join and leave notices are collapsed into counts while a room sees a burst of them
+#[derive(Debug, Default)]
+pub struct PresenceBurst {
+    // when the changes inside the current window happened, oldest first
+    recent: VecDeque<Instant>,
+    pub joined: usize,
+    pub left: usize,
+    // a summary is scheduled and everything until then is only counted
+    pub batching: bool,
+}
+
+impl PresenceBurst {
+    // true when this change should be counted rather than announced on its own
+    pub fn note(&mut self, now: Instant, window: Duration, threshold: usize) -> bool {
+        while let Some(oldest) = self.recent.front() {
+            if now.duration_since(*oldest) < window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        if self.recent.len() > threshold {
+            self.batching = true;
+        }
+        self.batching
+    }
+}
+
+// the most messages any room can be set to keep, however it is configured
+pub const MAX_HISTORY: usize = 10_000;
+// pinned messages per room
+pub const MAX_PINS: usize = 10;
+// topic changes each room remembers for /topiclog, the oldest go first
+pub const MAX_TOPIC_LOG: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct TopicChange {
+    pub by: String,
+    pub at: Instant,
+    pub old: Option<String>,
+    pub new: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Room {
+    pub members: usize,
+    pub history: VecDeque<Arc<HistoryEntry>>,
+    // set by /histlimit, None keeps whatever the config says for this room
+    pub history_limit: Option<usize>,
+    // messages an operator pinned, in sequence order. they were in the history when pinned but
+    // stay put when it moves on, and follow edits and deletes of the message
+    pub pins: Vec<Arc<HistoryEntry>>,
+    // reaction tallies for messages still in the history, keyed by sequence number
+    pub reactions: HashMap<u64, HashMap<String, usize>>,
+    // operators can moderate the room, the member that opened it is the first one
+    pub ops: HashSet<ClientId>,
+    // when each member joined, the one who has been here longest takes over from the last
+    // operator to leave
+    pub joined: HashMap<ClientId, Instant>,
+    // when each member last said something here, which is what slow mode is measured from
+    pub last_sent: HashMap<ClientId, Instant>,
+    // the minimum time between two messages from the same member, None when off. ops are exempt
+    pub slow_mode: Option<Duration>,
+    // slow mode was switched on by the flood detector, so it may switch it off again
+    pub auto_slow: bool,
+    pub presence: PresenceBurst,
+    // set by /quietjoins, members come and go without anyone being told
+    pub quiet_joins: bool,
+    pub topic: Option<String>,
+    // who changed the topic and to what, oldest first
+    pub topic_log: VecDeque<TopicChange>,
+    // when the messages inside the current rate window were sent, oldest first
+    recent: VecDeque<Instant>,
+    // when the last member left, for rooms kept around for --empty-room-grace
+    pub empty_since: Option<Instant>,
+}
+
+impl Room {
+    // empties the room for /quitall: its history, pins, topic and slow mode go, the members
+    // and operators stay, and so does what /histlimit and /quietjoins set
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.pins.clear();
+        self.reactions.clear();
+        self.last_sent.clear();
+        self.slow_mode = None;
+        self.auto_slow = false;
+        self.topic = None;
+        self.topic_log.clear();
+        self.recent.clear();
+    }
+
+    pub fn set_topic(&mut self, by: &str, topic: String) {
+        if self.topic_log.len() == MAX_TOPIC_LOG {
+            self.topic_log.pop_front();
+        }
+        self.topic_log.push_back(TopicChange {
+            by: by.to_string(),
+            at: Instant::now(),
+            old: self.topic.replace(topic.clone()),
+            new: topic,
+        });
+    }
+
+    // points the history and pins at the room's new name, so replays, edits and reactions
+    // reach the members under it
+    pub fn rename(&mut self, name: &str) {
+        let name: Arc<str> = Arc::from(name);
+        let renamed = |entry: &Arc<HistoryEntry>| {
+            Arc::new(HistoryEntry {
+                room: name.clone(),
+                ..HistoryEntry::clone(entry)
+            })
+        };
+        self.history = self.history.iter().map(renamed).collect();
+        self.pins = self.pins.iter().map(renamed).collect();
+    }
+
+    // history is kept in sequence order, so a message can be found without a scan
+    pub fn position(&self, seq: u64) -> Option<usize> {
+        self.history
+            .binary_search_by_key(&seq, |entry| entry.seq)
+            .ok()
+    }
+
+    // how long the member still has to wait before it may send again. the fallback applies when
+    // the room itself isn't in slow mode
+    pub fn slow_mode_wait(
+        &self,
+        id: ClientId,
+        now: Instant,
+        fallback: Option<Duration>,
+    ) -> Option<Duration> {
+        let interval = self.slow_mode.or(fallback)?;
+        let last = self.last_sent.get(&id)?;
+        interval
+            .checked_sub(now.duration_since(*last))
+            .filter(|wait| !wait.is_zero())
+    }
+
+    // drops the oldest messages, and their reactions, until at most limit are left
+    pub fn trim_history(&mut self, limit: usize) {
+        while self.history.len() > limit {
+            if let Some(old) = self.history.pop_front() {
+                self.reactions.remove(&old.seq);
+            }
+        }
+    }
+
+    // drops messages older than ttl, with their reactions and pins, and says how many went.
+    // the history is in the order messages were sent, so they all come off the front
+    pub fn expire_history(&mut self, ttl: Duration) -> usize {
+        let mut expired = 0;
+        while let Some(oldest) = self.history.front() {
+            if oldest.sent_at.elapsed() < ttl {
+                break;
+            }
+            if let Some(old) = self.history.pop_front() {
+                self.reactions.remove(&old.seq);
+                expired += 1;
+            }
+        }
+        self.pins.retain(|pin| pin.sent_at.elapsed() < ttl);
+        expired
+    }
+
+    pub fn note_message(&mut self, id: ClientId, now: Instant, window: Duration) {
+        self.last_sent.insert(id, now);
+        self.recent.push_back(now);
+        self.expire_rate(now, window);
+    }
+
+    // forgets message times that have slid out of the window
+    pub fn expire_rate(&mut self, now: Instant, window: Duration) {
+        while let Some(oldest) = self.recent.front() {
+            if now.duration_since(*oldest) < window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    // messages sent within the last window
+    pub fn rate(&self, now: Instant, window: Duration) -> usize {
+        self.recent
+            .iter()
+            .filter(|sent| now.duration_since(**sent) < window)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent_ago(seq: u64, ago: Duration) -> Arc<HistoryEntry> {
+        Arc::new(HistoryEntry {
+            seq,
+            room: Arc::from("#general"),
+            from: "alice".to_string(),
+            text: format!("message {seq}"),
+            sent_at: Instant::now() - ago,
+            ts: 0,
+            bot: false,
+            reply_to: None,
+        })
+    }
+
+    fn seqs<'a>(entries: impl Iterator<Item = &'a Arc<HistoryEntry>>) -> Vec<u64> {
+        entries.map(|entry| entry.seq).collect()
+    }
+
+    #[test]
+    fn expiring_drops_old_messages_with_their_reactions_and_pins() {
+        let mut room = Room::default();
+        for (seq, secs) in [(1, 10), (2, 5), (3, 0)] {
+            let entry = sent_ago(seq, Duration::from_secs(secs));
+            room.reactions
+                .insert(seq, HashMap::from([("👍".to_string(), 1)]));
+            room.pins.push(entry.clone());
+            room.history.push_back(entry);
+        }
+        assert_eq!(room.expire_history(Duration::from_secs(3)), 2);
+        assert_eq!(seqs(room.history.iter()), [3]);
+        assert_eq!(seqs(room.pins.iter()), [3]);
+        assert_eq!(room.reactions.keys().collect::<Vec<_>>(), [&3]);
+        assert_eq!(room.expire_history(Duration::from_secs(3)), 0);
+    }
+
+    #[test]
+    fn utc_datetime_is_a_civil_date_and_time() {
+        assert_eq!(utc_datetime(0), "1970-01-01 00:00:00");
+        assert_eq!(utc_datetime(951_782_400_000), "2000-02-29 00:00:00");
+        assert_eq!(utc_datetime(1_709_251_199_999), "2024-02-29 23:59:59");
+        assert_eq!(utc_datetime(4_102_444_800_000), "2100-01-01 00:00:00");
+    }
+
+    #[test]
+    fn the_topic_log_keeps_the_latest_changes() {
+        let mut room = Room::default();
+        for n in 0..MAX_TOPIC_LOG + 5 {
+            room.set_topic("alice", n.to_string());
+        }
+        assert_eq!(room.topic_log.len(), MAX_TOPIC_LOG);
+        let oldest = &room.topic_log[0];
+        assert_eq!(oldest.old.as_deref(), Some("4"));
+        assert_eq!(oldest.new, "5");
+        assert_eq!(room.topic.as_deref(), Some("24"));
+    }
+}
@@ -0,0 +1,156 @@
+// a quick health check for deployment pipelines, started with --selftest: runs the server on a
+// free local port with the settings it was given, connects two clients, sends a message from
+// one to the other and reports how each step went. listeners other than the chat one and
+// anything that writes outside the process are left off, so it can run next to a live server
+use std::{future::Future, time::Duration};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::timeout,
+};
+
+use crate::{ChatServer, Config};
+
+// for each step, a healthy server answers in milliseconds
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    delimiter: &'static str,
+}
+
+impl Client {
+    async fn connect(addr: &str, delimiter: &'static str) -> Result<Client, String> {
+        let socket = TcpStream::connect(addr)
+            .await
+            .map_err(|err| err.to_string())?;
+        let (reader, writer) = socket.into_split();
+        Ok(Client {
+            reader: BufReader::new(reader),
+            writer,
+            delimiter,
+        })
+    }
+
+    async fn send(&mut self, line: &str) -> Result<(), String> {
+        let line = format!("{line}{}", self.delimiter);
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    // skips whatever comes first, notices and the message of the day among it
+    async fn wait_for(&mut self, wanted: &str) -> Result<(), String> {
+        let last = *self.delimiter.as_bytes().last().unwrap_or(&b'\n');
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = self
+                .reader
+                .read_until(last, &mut line)
+                .await
+                .map_err(|err| err.to_string())?;
+            if n == 0 {
+                return Err("the server closed the connection".to_string());
+            }
+            let text = String::from_utf8_lossy(&line);
+            if text.contains(wanted) {
+                return Ok(());
+            }
+            if let Some(error) = text.strip_prefix("! ") {
+                return Err(error.trim_end().to_string());
+            }
+        }
+    }
+}
+
+// prints a line per step and gives the process exit code, 0 when everything worked
+pub async fn run(mut config: Config) -> i32 {
+    config.listen = "127.0.0.1:0".to_string();
+    config.admin_listen = None;
+    config.http_listen = None;
+    config.metrics_listen = None;
+    config.webhook = None;
+    config.store_file = None;
+    config.state_file = None;
+    config.upload_dir = None;
+    config.lobby = false;
+    config.echo = false;
+    // its clients speak plain text
+    config.min_protocol_version = 0;
+    let delimiter = config.delimiter.as_str();
+    let server = match ChatServer::bind(config).await {
+        Ok(server) => server,
+        Err(err) => return failed("start the server", err),
+    };
+    let addr = match server.local_addr() {
+        Ok(addr) => addr.to_string(),
+        Err(err) => return failed("start the server", err),
+    };
+    passed(&format!("start the server on {addr}"));
+    let handle = server.shutdown_handle();
+    let running = tokio::spawn(async move { server.run().await });
+    let code = exchange(&addr, delimiter).await;
+    handle.shutdown().await;
+    let _ = running.await;
+    code
+}
+
+async fn exchange(addr: &str, delimiter: &'static str) -> i32 {
+    let mut clients = Vec::new();
+    for name in ["selftest1", "selftest2"] {
+        let registered = step(&format!("register {name}"), async {
+            let mut client = Client::connect(addr, delimiter).await?;
+            client.send(name).await?;
+            client.wait_for(&format!("Welcome, {name}!")).await?;
+            Ok(client)
+        })
+        .await;
+        match registered {
+            Some(client) => clients.push(client),
+            None => return 1,
+        }
+    }
+    let marker = format!("selftest message {}", std::process::id());
+    let [sender, receiver] = &mut clients[..] else {
+        return 1;
+    };
+    let delivered = step("deliver a message", async {
+        sender.send(&marker).await?;
+        receiver.wait_for(&marker).await
+    })
+    .await;
+    i32::from(delivered.is_none())
+}
+
+async fn step<T>(what: &str, work: impl Future<Output = Result<T, String>>) -> Option<T> {
+    match timeout(STEP_TIMEOUT, work).await {
+        Ok(Ok(value)) => {
+            passed(what);
+            Some(value)
+        }
+        Ok(Err(err)) => {
+            failed(what, err);
+            None
+        }
+        Err(_) => {
+            failed(what, "timed out");
+            None
+        }
+    }
+}
+
+fn passed(what: &str) {
+    println!("ok      {what}");
+}
+
+fn failed(what: &str, err: impl std::fmt::Display) -> i32 {
+    println!("FAILED  {what}: {err}");
+    1
+}
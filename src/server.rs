@@ -0,0 +1,691 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{lookup_host, TcpListener, TcpSocket, TcpStream},
+    sync::{mpsc, watch},
+    time::{sleep, timeout},
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use crate::{
+    activation,
+    auth::{Authenticator, Role, SessionPolicy},
+    config::{port_range, Config},
+    connection,
+    events::ServerEvents,
+    framing::Delimiter,
+    http, keepalive, metrics,
+    motd::Motd,
+    resolve, retention, snapshot,
+    state::{Peer, ServerStats, Shared, Transport},
+    store::MessageStore,
+    tls,
+};
+
+#[cfg(feature = "sqlite")]
+use crate::store::SqliteStore;
+
+// a bound server, nothing is accepted until run() is called
+pub struct ChatServer {
+    listener: TcpListener,
+    admin_listener: Option<TcpListener>,
+    http_listener: Option<TcpListener>,
+    metrics_listener: Option<TcpListener>,
+    // chat over tls, with what does the handshakes
+    tls_listener: Option<(TcpListener, TlsAcceptor)>,
+    shared: Arc<Shared>,
+    // flipped to true once to stop the accept loops and every connection
+    shutdown: Arc<watch::Sender<bool>>,
+    // true unless run() is going, it is flipped back on every way out of it
+    stopped: watch::Sender<bool>,
+}
+
+// lets another task stop a running server, it can be cloned and kept after run() is started
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+    stopped: watch::Receiver<bool>,
+}
+
+impl ShutdownHandle {
+    // tells the server to stop and waits until run() has returned, by which point every
+    // connection has been told and has finished. returns straight away if run() isn't going,
+    // a run() started afterwards stops as soon as it starts
+    pub async fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        let mut stopped = self.stopped.clone();
+        while !*stopped.borrow() {
+            if stopped.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// flips stopped back when run() is left
+struct Running<'a>(&'a watch::Sender<bool>);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.send_replace(true);
+    }
+}
+
+impl ChatServer {
+    // binds the chat listener and, if configured, the admin, http and metrics listeners. under
+    // systemd socket activation the inherited sockets are used instead, see activation.rs
+    pub async fn bind(config: Config) -> io::Result<ChatServer> {
+        // files first, so a bad path fails before any socket is bound
+        let motd = match &config.motd {
+            Some(path) => Some(Motd::load(path, config.motd_mode).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("failed to load the motd at {}: {err}", path.display()),
+                )
+            })?),
+            None => None,
+        };
+        if let Some(dir) = &config.upload_dir {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("can't create the upload directory {}: {err}", dir.display()),
+                )
+            })?;
+        }
+        let tls = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+            _ => None,
+        };
+        #[cfg(feature = "sqlite")]
+        let store = match &config.store_db {
+            Some(path) => Some(SqliteStore::open(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("can't open the message database {}: {err}", path.display()),
+                )
+            })?),
+            None => None,
+        };
+        let mut inherited = activation::listeners()?;
+        let listener = match inherited.remove("chat") {
+            Some(listener) => TcpListener::from_std(listener)?,
+            None => listen(&config.listen, config.backlog).await?,
+        };
+        let admin_listener = match (inherited.remove("admin"), &config.admin_listen) {
+            (Some(listener), _) => Some(TcpListener::from_std(listener)?),
+            (None, Some(addr)) => Some(listen(addr, config.backlog).await?),
+            (None, None) => None,
+        };
+        let http_listener = match (inherited.remove("http"), &config.http_listen) {
+            (Some(listener), _) => Some(TcpListener::from_std(listener)?),
+            (None, Some(addr)) => Some(listen(addr, config.backlog).await?),
+            (None, None) => None,
+        };
+        let metrics_listener = match (inherited.remove("metrics"), &config.metrics_listen) {
+            (Some(listener), _) => Some(TcpListener::from_std(listener)?),
+            (None, Some(addr)) => Some(listen(addr, config.backlog).await?),
+            (None, None) => None,
+        };
+        let tls_listener = match (inherited.remove("tls"), &config.tls_listen) {
+            (Some(listener), _) => Some(TcpListener::from_std(listener)?),
+            (None, Some(addr)) => Some(listen(addr, config.backlog).await?),
+            (None, None) => None,
+        };
+        let tls_listener = match (tls_listener, tls) {
+            (Some(listener), Some(tls)) => Some((listener, tls)),
+            (Some(_), None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a tls socket was passed in without --tls-cert and --tls-key",
+                ))
+            }
+            (None, _) => None,
+        };
+        let server = ChatServer {
+            listener,
+            admin_listener,
+            http_listener,
+            metrics_listener,
+            tls_listener,
+            shared: Shared::new(config, motd),
+            shutdown: Arc::new(watch::channel(false).0),
+            stopped: watch::channel(true).0,
+        };
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = store {
+            server.set_store(Box::new(store));
+        }
+        Ok(server)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_listener.as_ref()?.local_addr().ok()
+    }
+
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http_listener.as_ref()?.local_addr().ok()
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener.as_ref()?.local_addr().ok()
+    }
+
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.tls_listener.as_ref()?.0.local_addr().ok()
+    }
+
+    // counters for dashboards and the like, see ServerStats for how fresh they are
+    pub fn stats(&self) -> ServerStats {
+        self.shared.snapshot()
+    }
+
+    // a feed of connections, joins, leaves and messages, see ServerEvents for what a slow
+    // subscriber misses. events from before the call aren't included
+    pub fn events(&self) -> ServerEvents {
+        self.shared.events()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown: self.shutdown.clone(),
+            stopped: self.stopped.subscribe(),
+        }
+    }
+
+    // decides who may register from now on, see auth.rs. it can be swapped while running,
+    // clients already connected aren't asked again
+    pub fn set_authenticator(&self, authenticator: impl Authenticator + 'static) {
+        self.shared.set_authenticator(Arc::new(authenticator));
+    }
+
+    // what to do when an authenticated client picks a name that is already connected
+    pub fn set_session_policy(&self, policy: SessionPolicy) {
+        self.shared.set_session_policy(policy);
+    }
+
+    // every message from then on is appended to the store as well as kept in the history, and
+    // the rooms are filled from it whenever they open, the ones open now included
+    pub fn set_store(&self, store: Box<dyn MessageStore>) {
+        self.shared.set_store(Arc::from(store));
+    }
+
+    // same as going through a ShutdownHandle
+    pub async fn shutdown(&self) {
+        self.shutdown_handle().shutdown().await;
+    }
+
+    // accepts clients until shutdown is asked for or the chat listener fails, which includes it
+    // failing over and over. either way it only returns once all the connections it started
+    // have finished, with the listener's error if that is why
+    pub async fn run(&self) -> io::Result<()> {
+        self.stopped.send_replace(false);
+        // also when run() panics or is dropped before it finishes
+        let _running = Running(&self.stopped);
+        // every connection task holds a sender, recv() returns None once the last one is gone
+        let (done, mut all_done) = mpsc::channel::<()>(1);
+        let chat = async {
+            let result = self.serve(&self.listener, Endpoint::Chat, &done).await;
+            // make sure everything else stops too if we got here because the listener failed
+            self.shutdown.send_replace(true);
+            result
+        };
+        // the chat side carries on if the admin or http listener fails
+        let admin = async {
+            if let Some(admin_listener) = &self.admin_listener {
+                if let Err(err) = self.serve(admin_listener, Endpoint::Admin, &done).await {
+                    eprintln!("admin interface stopped: {err}");
+                }
+            }
+        };
+        let http = async {
+            if let Some(http_listener) = &self.http_listener {
+                if let Err(err) = self.serve(http_listener, Endpoint::Http, &done).await {
+                    eprintln!("http interface stopped: {err}");
+                }
+            }
+        };
+        // and with the tls one, the plain chat listener is the one that matters
+        let tls = async {
+            if let Some((tls_listener, _)) = &self.tls_listener {
+                if let Err(err) = self.serve(tls_listener, Endpoint::Tls, &done).await {
+                    eprintln!("tls listener stopped: {err}");
+                }
+            }
+        };
+        let metrics = async {
+            if let Some(metrics_listener) = &self.metrics_listener {
+                if let Err(err) = self.serve(metrics_listener, Endpoint::Metrics, &done).await {
+                    eprintln!("metrics interface stopped: {err}");
+                }
+            }
+        };
+        let retention = async {
+            let ttl = self.shared.config.history_ttl;
+            if !ttl.is_zero() {
+                let shutdown = self.shutdown.subscribe();
+                retention::expire_every(self.shared.clone(), ttl, shutdown).await;
+            }
+        };
+        let reclaim = async {
+            let grace = self.shared.config.empty_room_grace;
+            if !grace.is_zero() {
+                let shutdown = self.shutdown.subscribe();
+                retention::reclaim_every(self.shared.clone(), grace, shutdown).await;
+            }
+        };
+        let snapshot = async {
+            if let Some(path) = &self.shared.config.state_file {
+                let every = self.shared.config.state_interval;
+                let shutdown = self.shutdown.subscribe();
+                snapshot::write_every(self.shared.clone(), path.clone(), every, shutdown).await;
+            }
+        };
+        let (result, (), (), (), (), (), (), ()) =
+            tokio::join!(chat, admin, http, tls, metrics, retention, reclaim, snapshot);
+        drop(done);
+        let _ = all_done.recv().await;
+        result
+    }
+
+    // accepts clients from one listener and hands them to whatever speaks its protocol.
+    // returns Ok when shutting down and Err only when the listener itself has failed
+    async fn serve(
+        &self,
+        listener: &TcpListener,
+        endpoint: Endpoint,
+        done: &mpsc::Sender<()>,
+    ) -> io::Result<()> {
+        let mut shutdown = self.shutdown.subscribe();
+        // errors that are normally harmless, counted for as long as they keep coming one after
+        // another with nothing accepted in between
+        let mut retries = 0;
+        // how long to hold off before the next accept, see accept_delay
+        let mut pause = Duration::ZERO;
+        // every socket fails the same way, so this is only logged for the first
+        let mut keepalive_failed = false;
+        // call accept method on tcp listener
+        // accept() is a method that accepts a new connection from a tcp listener and yields the connection as well as the address of the connection,
+        // similar to bind, accept() returns a future and that future outputs a result
+        // this outer infinite loop allows us to have new clients join our server, however as it is, this solution blocks at the task level
+        loop {
+            // the kernel keeps queueing connections in the meantime, up to the backlog
+            if !pause.is_zero() {
+                tokio::select! {
+                    () = sleep(pause) => {}
+                    _ = stopped(&mut shutdown) => return Ok(()),
+                }
+                pause = Duration::ZERO;
+            }
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = stopped(&mut shutdown) => return Ok(()),
+            };
+            let (socket, addr) = match accepted {
+                Ok(accepted) => {
+                    retries = 0;
+                    accepted
+                }
+                Err(err) => match classify(&err) {
+                    // a listener that has been closed under us can fail like this forever
+                    AcceptError::Retry if retries >= MAX_RETRIES => {
+                        return Err(io::Error::new(
+                            err.kind(),
+                            format!("accept keeps failing, giving up: {err}"),
+                        ))
+                    }
+                    AcceptError::Retry => {
+                        retries += 1;
+                        continue;
+                    }
+                    AcceptError::Backoff => {
+                        eprintln!("accept failed, backing off: {err}");
+                        sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                    AcceptError::Fatal => return Err(err),
+                },
+            };
+            // closed straight away, before anything is written to it
+            if !self.shared.config.allows(addr.ip()) {
+                continue;
+            }
+            // a banned address can't post over http either, the admin listener lets anyone in
+            let banned = endpoint != Endpoint::Admin && self.shared.is_banned(addr.ip());
+            if banned && !endpoint.is_chat() {
+                continue;
+            }
+            let keepalive = self.shared.config.tcp_keepalive;
+            if !keepalive.is_zero() {
+                if let Err(err) = keepalive::enable(&socket, keepalive) {
+                    if !keepalive_failed {
+                        eprintln!("can't turn on tcp keepalive, carrying on without: {err}");
+                        keepalive_failed = true;
+                    }
+                }
+            }
+            let peer = Peer {
+                addr,
+                transport: if endpoint == Endpoint::Tls {
+                    Transport::Tls
+                } else {
+                    Transport::Tcp
+                },
+                role: if endpoint == Endpoint::Admin {
+                    Role::Admin
+                } else {
+                    Role::User
+                },
+                admin_listener: endpoint == Endpoint::Admin,
+            };
+            if self.shared.config.resolve_peers {
+                resolve::log_connection(peer);
+            }
+            let open = &self.shared.stats.open_connections;
+            let tls = self.tls_listener.as_ref().map(|(_, tls)| tls.clone());
+            if endpoint.is_chat() {
+                let config = &self.shared.config;
+                let max = config.max_clients;
+                let refusal = if banned {
+                    Some(BANNED.to_string())
+                } else if max > 0 && open.load(Ordering::Relaxed) >= max {
+                    Some(config.server_full())
+                } else {
+                    None
+                };
+                if let Some(text) = refusal {
+                    let delimiter = config.delimiter;
+                    match endpoint {
+                        Endpoint::Tls => tokio::spawn(async move {
+                            if let Some(stream) = handshake(tls, socket).await {
+                                reject(stream, text, delimiter).await;
+                            }
+                        }),
+                        _ => tokio::spawn(reject(socket, text, delimiter)),
+                    };
+                    continue;
+                }
+                open.fetch_add(1, Ordering::Relaxed);
+            }
+            let delay = if endpoint.is_chat() {
+                accept_delay(&self.shared)
+            } else {
+                Duration::ZERO
+            };
+            let shared = self.shared.clone();
+            let shutdown = self.shutdown.subscribe();
+            let done = done.clone();
+            // async move - is an async block, wraps the code into a separate future
+            tokio::spawn(async move {
+                match endpoint {
+                    Endpoint::Chat => {
+                        connection::handle(socket, peer, shared.clone(), shutdown).await;
+                        shared
+                            .stats
+                            .open_connections
+                            .fetch_sub(1, Ordering::Relaxed);
+                    }
+                    Endpoint::Tls => {
+                        if let Some(stream) = handshake(tls, socket).await {
+                            connection::handle_tls(stream, peer, shared.clone(), shutdown).await;
+                        }
+                        shared
+                            .stats
+                            .open_connections
+                            .fetch_sub(1, Ordering::Relaxed);
+                    }
+                    // connections from the admin listener get admin rights
+                    Endpoint::Admin => connection::handle(socket, peer, shared, shutdown).await,
+                    Endpoint::Http => http::handle(socket, shared, shutdown).await,
+                    Endpoint::Metrics => metrics::handle(socket, shared).await,
+                }
+                drop(done);
+            });
+            pause = delay;
+        }
+    }
+}
+
+// nothing until half of --max-clients is taken, then growing in a straight line to the whole
+// --accept-delay at the cap. an overloaded server waits the whole of it whatever the count
+fn accept_delay(shared: &Shared) -> Duration {
+    let max_delay = shared.config.accept_delay;
+    if max_delay.is_zero() {
+        return Duration::ZERO;
+    }
+    if shared.registry().load.overloaded {
+        return max_delay;
+    }
+    let max = shared.config.max_clients;
+    let open = shared.stats.open_connections.load(Ordering::Relaxed);
+    if max == 0 || open * 2 <= max {
+        return Duration::ZERO;
+    }
+    let load = (open * 2 - max) as f64 / max as f64;
+    max_delay.mul_f64(load.min(1.0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Endpoint {
+    Chat,
+    Admin,
+    Http,
+    Metrics,
+    Tls,
+}
+
+impl Endpoint {
+    // tls clients chat like any other, they count towards --max-clients and --accept-delay too
+    fn is_chat(self) -> bool {
+        matches!(self, Endpoint::Chat | Endpoint::Tls)
+    }
+}
+
+// None when the client doesn't get through the handshake in time, it is just closed then
+async fn handshake(tls: Option<TlsAcceptor>, socket: TcpStream) -> Option<TlsStream<TcpStream>> {
+    let accepted = timeout(tls::HANDSHAKE_TIMEOUT, tls?.accept(socket)).await;
+    accepted.ok()?.ok()
+}
+
+// binds the first free port of a range, or just the one port when there's no range
+async fn listen(addr: &str, backlog: u32) -> io::Result<TcpListener> {
+    let ports = port_range(addr).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let Some((host, ports)) = ports else {
+        return listen_on(addr, backlog).await;
+    };
+    for port in ports {
+        match listen_on(&format!("{host}:{port}"), backlog).await {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            result => return result,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("every port in {addr} is in use"),
+    ))
+}
+
+// TcpListener::bind with a backlog of our choosing instead of the standard library's 128.
+// like bind, every address the name resolves to is tried in turn until one works
+async fn listen_on(addr: &str, backlog: u32) -> io::Result<TcpListener> {
+    let mut last_err = None;
+    for addr in lookup_host(addr).await? {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        // what bind does too, so a restart doesn't have to wait out old connections
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        match socket.bind(addr).and_then(|()| socket.listen(backlog)) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{addr} doesn't resolve to any address"),
+        )
+    }))
+}
+
+// a client turned away because the server is full or its address is banned gets told why,
+// as long as that doesn't take long
+async fn reject<S>(mut socket: S, text: String, delimiter: Delimiter)
+where
+    S: AsyncWrite + Unpin,
+{
+    let text = text + delimiter.as_str();
+    let write = async {
+        socket.write_all(text.as_bytes()).await?;
+        socket.shutdown().await
+    };
+    let _ = timeout(REJECT_TIMEOUT, write).await;
+}
+
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const BANNED: &str = "*** you are banned from this server ***";
+
+// resolves once the shutdown flag is set, or if the server behind it is gone altogether
+pub async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    while !*shutdown.borrow_and_update() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+// what to do about an error from accept()
+#[derive(Debug, PartialEq, Eq)]
+enum AcceptError {
+    // the failed connection is gone but the listener is fine, carry on
+    Retry,
+    // out of file descriptors or memory, accepting again straight away would just spin
+    Backoff,
+    Fatal,
+}
+
+fn classify(err: &io::Error) -> AcceptError {
+    match err.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::TimedOut => return AcceptError::Retry,
+        _ => {}
+    }
+    match err.raw_os_error() {
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => AcceptError::Backoff,
+        // the peer went away or broke the handshake before we got to it
+        Some(libc::EPROTO | libc::EPERM | libc::ENETDOWN | libc::EHOSTUNREACH) => {
+            AcceptError::Retry
+        }
+        _ => AcceptError::Fatal,
+    }
+}
+
+// harmless accept errors in a row before the listener is given up on. a busy server sees a
+// few at a time when clients give up early, not this many with nothing getting through
+const MAX_RETRIES: u32 = 1000;
+
+// how long to stop accepting after running out of resources, connections closing in the
+// meantime give some back
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delay_with_open(max_clients: usize, open: usize) -> Duration {
+        let shared = Shared::new(
+            Config {
+                max_clients,
+                accept_delay: Duration::from_millis(100),
+                ..Config::default()
+            },
+            None,
+        );
+        shared.stats.open_connections.store(open, Ordering::Relaxed);
+        accept_delay(&shared)
+    }
+
+    #[tokio::test]
+    async fn the_accept_delay_grows_past_half_the_cap() {
+        assert_eq!(delay_with_open(10, 0), Duration::ZERO);
+        assert_eq!(delay_with_open(10, 5), Duration::ZERO);
+        assert_eq!(delay_with_open(10, 8), Duration::from_millis(60));
+        assert_eq!(delay_with_open(10, 10), Duration::from_millis(100));
+        // never past the most it is set to
+        assert_eq!(delay_with_open(10, 20), Duration::from_millis(100));
+        // without a cap there is nothing to measure against
+        assert_eq!(delay_with_open(0, 1000), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn an_overloaded_server_waits_the_whole_delay() {
+        let shared = Shared::new(
+            Config {
+                accept_delay: Duration::from_millis(100),
+                ..Config::default()
+            },
+            None,
+        );
+        assert_eq!(accept_delay(&shared), Duration::ZERO);
+        shared.registry().load.overloaded = true;
+        assert_eq!(accept_delay(&shared), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn no_accept_delay_unless_asked_for() {
+        let shared = Shared::new(Config::default(), None);
+        shared.registry().load.overloaded = true;
+        assert_eq!(accept_delay(&shared), Duration::ZERO);
+    }
+
+    fn os(code: i32) -> AcceptError {
+        classify(&io::Error::from_raw_os_error(code))
+    }
+
+    #[test]
+    fn a_connection_that_went_away_is_retried() {
+        for kind in [io::ErrorKind::ConnectionAborted, io::ErrorKind::Interrupted] {
+            assert_eq!(classify(&io::Error::from(kind)), AcceptError::Retry);
+        }
+        for code in [
+            libc::ECONNABORTED,
+            libc::EPROTO,
+            libc::EPERM,
+            libc::ENETDOWN,
+        ] {
+            assert_eq!(os(code), AcceptError::Retry, "{code}");
+        }
+    }
+
+    #[test]
+    fn running_out_of_resources_backs_off() {
+        for code in [libc::EMFILE, libc::ENFILE, libc::ENOBUFS, libc::ENOMEM] {
+            assert_eq!(os(code), AcceptError::Backoff, "{code}");
+        }
+    }
+
+    // what accept gives for a descriptor that was closed or shut down under the server
+    #[test]
+    fn a_broken_listener_is_fatal() {
+        for code in [libc::EBADF, libc::EINVAL, libc::ENOTSOCK] {
+            assert_eq!(os(code), AcceptError::Fatal, "{code}");
+        }
+    }
+}
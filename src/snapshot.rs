@@ -0,0 +1,78 @@
+// writes a json file with the rooms, their member counts and the connection totals every so
+// often, so monitoring can read the state off disk instead of holding a connection open. the
+// file is written next to where it goes and renamed into place, a reader never sees half of it
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{fs, sync::watch, time::interval};
+
+use crate::{json::Value, server::stopped, state::Shared};
+
+pub async fn write_every(
+    shared: Arc<Shared>,
+    path: PathBuf,
+    every: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticks = interval(every);
+    // what was last written, nothing is written again until it changes
+    let mut last = String::new();
+    let mut failing = false;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = stopped(&mut shutdown) => return,
+        }
+        let state = render(&shared);
+        if state == last {
+            continue;
+        }
+        match write_atomically(&path, &state).await {
+            Ok(()) => {
+                failing = false;
+                last = state;
+            }
+            // logged once per outage, it is retried on every tick
+            Err(err) if !failing => {
+                eprintln!("can't write {}: {err}", path.display());
+                failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+// leaves out the uptime and anything else that changes on its own, so an idle server doesn't
+// rewrite the file on every tick
+fn render(shared: &Shared) -> String {
+    let stats = shared.snapshot();
+    let mut rooms: Vec<(String, usize)> = shared
+        .registry()
+        .rooms
+        .iter()
+        .map(|(name, room)| (name.clone(), room.members))
+        .collect();
+    rooms.sort_unstable();
+    let rooms = rooms
+        .into_iter()
+        .map(|(name, members)| Value::object([("name", name.into()), ("members", members.into())]))
+        .collect::<Vec<_>>();
+    let state = Value::object([
+        ("connections", stats.active_connections.into()),
+        ("connections_total", stats.connections_total.into()),
+        ("messages_total", stats.messages_total.into()),
+        ("overloaded", stats.overloaded.into()),
+        ("rooms", rooms.into()),
+    ]);
+    format!("{state}\n")
+}
+
+async fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, contents).await?;
+    fs::rename(&temp, path).await
+}
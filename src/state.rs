@@ -0,0 +1,2026 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet, VecDeque},
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, watch, Notify},
+    time::sleep,
+};
+
+use crate::{
+    auth::{Authenticator, NoAuth, Role, SessionPolicy},
+    config::Config,
+    events::{ServerEvent, ServerEvents, EVENTS_CAPACITY},
+    load::LagMonitor,
+    motd::Motd,
+    outbound::OutboundHandle,
+    ratelimit::TokenBucket,
+    room::{unix_millis, HistoryEntry, Reaction, Room},
+    store::{Archive, FileStore, MessageStore, StoredMessage},
+    upload::Uploads,
+    webhook::Webhook,
+};
+
+// every client starts out in this room, and it can never be deleted
+pub const DEFAULT_ROOM: &str = "#general";
+
+// how long a json client's message id is remembered, and how many of them at most
+const DEDUP_WINDOW: Duration = Duration::from_secs(60);
+const MAX_MESSAGE_IDS: usize = 100;
+
+pub type ClientId = u64;
+
+// what goes out over the broadcast channel, each connection task picks out what concerns it
+#[derive(Debug, Clone)]
+pub enum Event {
+    // a chat line for everyone in a room except the sender
+    // shared pointers, so handing the event to every receiver doesn't copy the text
+    Message {
+        from: ClientId,
+        entry: Arc<HistoryEntry>,
+        // who in the room is to get it, worked out by the broadcaster once somebody there
+        // ignores the sender. None leaves it to every member, which each connection can tell
+        // from its own session without going near the registry
+        to: Option<Arc<HashSet<ClientId>>>,
+    },
+    // a message in a room's history was changed by its sender, the entry has the new text
+    Edit {
+        entry: Arc<HistoryEntry>,
+    },
+    Delete {
+        room: Arc<str>,
+        seq: u64,
+    },
+    Reaction(Arc<Reaction>),
+    // a server notice for everyone in a room, except possibly the client it is about
+    Notice {
+        room: Arc<str>,
+        text: String,
+        except: Option<ClientId>,
+    },
+    // a room was deleted and its members have been moved to the default room
+    RoomClosed {
+        room: String,
+    },
+    // a room goes by another name from now on, its members with it
+    RoomRenamed {
+        old: String,
+        new: String,
+    },
+}
+
+// how a client reached us, plain tcp or tls from --tls-listen. other ways in get a variant
+// here as they are added. whatever they are, the reader they hand to a connection should only
+// come back empty once the client is really gone. Framed::next reads again to make sure, but a
+// reader that keeps coming back empty looks just like a closed one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Tls,
+}
+
+impl Transport {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+        }
+    }
+
+    // whether the connection is encrypted, which plain tcp never is
+    pub fn is_secure(self) -> bool {
+        match self {
+            Transport::Tcp => false,
+            Transport::Tls => true,
+        }
+    }
+}
+
+// where a connection came from, as known when it was accepted
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub transport: Transport,
+    // what the connection starts out as, admin for the admin listener and user otherwise.
+    // the authenticator can give a client more
+    pub role: Role,
+    // came in through the admin listener rather than the chat one. only these connections are
+    // answered admin queries, whatever role they end up with
+    pub admin_listener: bool,
+}
+
+// what the server knows about a single connected client
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub addr: SocketAddr,
+    pub transport: Transport,
+    pub name: String,
+    // the room plain chat lines go to, always one of the rooms below
+    pub room: String,
+    // every room the client is a member of and hears messages from, never empty
+    pub rooms: HashSet<String>,
+    pub role: Role,
+    // for writing to the client directly rather than through the broadcast
+    pub outbound: OutboundHandle,
+    pub connected_at: Instant,
+    // handed to the client at registration, lets it pick up where it left off after a drop
+    pub resume_token: String,
+    // the previous chat line and how many times in a row it has been sent
+    pub last_message: String,
+    pub repeat_count: usize,
+    // None when there is no rate limit
+    pub bucket: Option<TokenBucket>,
+    // do not disturb, direct messages to this client are refused
+    pub dnd: bool,
+    // names whose messages this client doesn't want to see, kept by name so it survives the
+    // other side reconnecting
+    pub ignored: HashSet<String>,
+    // the client's own command shortcuts, from the name without its slash to what it stands for
+    pub aliases: HashMap<String, String>,
+    // told when someone else signs in under the name and takes over, or it is kicked out
+    pub removed: Arc<Removal>,
+    // ids json clients gave their recent messages and when, oldest first
+    pub message_ids: VecDeque<(String, Instant)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    Joined,
+    Left,
+}
+
+impl Presence {
+    fn verb(self) -> &'static str {
+        match self {
+            Presence::Joined => "joined",
+            Presence::Left => "left",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeleteRoomError {
+    NoSuchRoom,
+    DefaultRoom,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenameRoomError {
+    NoSuchRoom,
+    DefaultRoom,
+    NameTaken,
+    // the new name is for secure connections only and the room wasn't
+    Secure,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum JoinError {
+    // the client is already in as many rooms as it may be
+    TooManyRooms,
+    // the room is for secure connections only
+    Insecure,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LeaveError {
+    NotInRoom,
+    // every client is in at least one room
+    LastRoom,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OpError {
+    NoSuchUser,
+    // only members of a room can run it
+    NotInRoom,
+    // taking it away would leave the room without an operator
+    LastOp,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    NameTaken,
+    // the name's current session has been told to go, it is free once that has happened
+    Replacing,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KickError {
+    NoSuchUser,
+    // only clients below the kicker's own role can be thrown out
+    Outranked,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PostError {
+    // the same line was sent more times in a row than the config allows
+    Repeated,
+    // the room is in slow mode and the client has to wait this long
+    SlowMode(Duration),
+    // the client is sending faster than the rate limit and has to wait this long
+    RateLimited(Duration),
+    // the message it replies to isn't in the room's history
+    NoSuchParent(u64),
+    // longer than max_message_len
+    TooLong,
+    // a message with the same client id was sent within the dedup window
+    Duplicate,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EditError {
+    // not in the history of the client's room, or already dropped from it
+    NoSuchMessage,
+    NotYours,
+    // the edit window has passed
+    TooOld,
+    // the new text is longer than max_message_len
+    TooLong,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HttpPostError {
+    NoSuchRoom,
+    // http posts can't borrow the name of someone who is connected
+    NameTaken,
+    TooLong,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResumeError {
+    // unknown, already used, or expired, we don't tell them apart
+    InvalidToken,
+}
+
+// kept for a while after a client with a resume token disconnects
+#[derive(Debug)]
+struct PendingResume {
+    name: String,
+    // a role the authenticator gave out stays with the token
+    role: Role,
+    room: String,
+    rooms: HashSet<String>,
+    ignored: HashSet<String>,
+    aliases: HashMap<String, String>,
+    // a resend after reconnecting is the most likely kind
+    message_ids: VecDeque<(String, Instant)>,
+    // the last message sent before the disconnect, anything newer is replayed
+    last_seq: u64,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct Registry {
+    pub clients: HashMap<ClientId, ClientInfo>,
+    pub rooms: HashMap<String, Room>,
+    resumes: HashMap<String, PendingResume>,
+    pub load: LagMonitor,
+    last_seq: u64,
+    history_size: usize,
+    // history sizes from the config for particular rooms, used instead of history_size
+    room_history: HashMap<String, usize>,
+    max_rooms: usize,
+    secure_rooms: HashSet<String>,
+    empty_room_grace: Duration,
+    // the latest disconnects, newest at the back, for /recent
+    pub recent: VecDeque<Disconnect>,
+    // when each name last sent a message or disconnected, for /seen
+    pub last_seen: HashMap<String, Instant>,
+    // addresses /ban turned away, until the server restarts
+    banned: HashSet<IpAddr>,
+    // connected clients ignoring someone. while there are none, every member of a room gets
+    // all of its messages and the broadcaster doesn't have to look at who is in it
+    ignoring: usize,
+    // rooms that just opened, for the loader to fill from the store
+    opened: mpsc::UnboundedSender<String>,
+}
+
+impl Registry {
+    fn new(config: &Config, opened: mpsc::UnboundedSender<String>) -> Registry {
+        let mut rooms = HashMap::new();
+        rooms.insert(DEFAULT_ROOM.to_string(), Room::default());
+        Registry {
+            clients: HashMap::new(),
+            rooms,
+            resumes: HashMap::new(),
+            recent: VecDeque::with_capacity(RECENT_DISCONNECTS),
+            last_seen: HashMap::new(),
+            banned: HashSet::new(),
+            ignoring: 0,
+            load: LagMonitor::default(),
+            last_seq: 0,
+            history_size: config.history_size,
+            room_history: config.room_history.clone(),
+            max_rooms: config.max_rooms,
+            secure_rooms: config.secure_rooms.clone(),
+            empty_room_grace: config.empty_room_grace,
+            opened,
+        }
+    }
+
+    // names held for a pending resume count as taken, so nobody can grab them in the meantime
+    fn name_taken(&self, name: &str) -> bool {
+        self.clients.values().any(|client| client.name == name)
+            || self.resumes.values().any(|pending| pending.name == name)
+    }
+
+    fn insert_client(&mut self, id: ClientId, client: ClientInfo) {
+        for room in &client.rooms {
+            self.enter(id, room);
+        }
+        self.clients.insert(id, client);
+    }
+
+    // whoever opens a room gets to run it, the default room is left to the admins
+    fn enter(&mut self, id: ClientId, room: &str) {
+        if !self.rooms.contains_key(room) {
+            let _ = self.opened.send(room.to_string());
+        }
+        let entry = self.rooms.entry(room.to_string()).or_default();
+        if entry.members == 0 && room != DEFAULT_ROOM {
+            entry.ops.insert(id);
+        }
+        entry.members += 1;
+        entry.joined.insert(id, Instant::now());
+        entry.empty_since = None;
+    }
+
+    // makes a room the client's current one, joining it first if need be and creating it on
+    // first use. true when the client wasn't a member yet
+    pub fn join(&mut self, id: ClientId, room: &str) -> Result<bool, JoinError> {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return Ok(false);
+        };
+        let joined = !client.rooms.contains(room);
+        if joined && client.rooms.len() >= self.max_rooms {
+            return Err(JoinError::TooManyRooms);
+        }
+        if joined && !client.transport.is_secure() && self.secure_rooms.contains(room) {
+            return Err(JoinError::Insecure);
+        }
+        client.room = room.to_string();
+        if joined {
+            client.rooms.insert(room.to_string());
+            self.enter(id, room);
+        }
+        Ok(joined)
+    }
+
+    // makes one of the client's rooms the current one, false when it isn't in that room
+    pub fn switch(&mut self, id: ClientId, room: &str) -> bool {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return false;
+        };
+        if !client.rooms.contains(room) {
+            return false;
+        }
+        client.room = room.to_string();
+        true
+    }
+
+    // takes a client out of one of its rooms. leaving the current room makes the default room
+    // current if the client is in it, or else whichever is first by name. returns the
+    // current room afterwards, and who became an operator if the client was the last one
+    pub fn leave_room(
+        &mut self,
+        id: ClientId,
+        room: &str,
+    ) -> Result<(String, Option<String>), LeaveError> {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return Err(LeaveError::NotInRoom);
+        };
+        if !client.rooms.contains(room) {
+            return Err(LeaveError::NotInRoom);
+        }
+        if client.rooms.len() == 1 {
+            return Err(LeaveError::LastRoom);
+        }
+        client.rooms.remove(room);
+        if client.room == room {
+            client.room = match client.rooms.get(DEFAULT_ROOM) {
+                Some(default) => default.clone(),
+                None => client.rooms.iter().min().cloned().unwrap_or_default(),
+            };
+        }
+        let current = client.room.clone();
+        let promoted = self.leave(id, room);
+        Ok((current, promoted))
+    }
+
+    // moderators and admins can run any room, everyone else needs to be one of its operators
+    pub fn is_op(&self, id: ClientId, room: &str) -> bool {
+        self.clients
+            .get(&id)
+            .is_some_and(|client| client.role >= Role::Moderator)
+            || self.rooms.get(room).is_some_and(|r| r.ops.contains(&id))
+    }
+
+    // makes the named member an operator of the room or takes that away again, false when
+    // nothing changed. the default room can do without, admins run it anyway
+    pub fn set_op(&mut self, room: &str, name: &str, op: bool) -> Result<bool, OpError> {
+        let (&id, client) = self
+            .clients
+            .iter()
+            .find(|(_, client)| client.name == name)
+            .ok_or(OpError::NoSuchUser)?;
+        if !client.rooms.contains(room) {
+            return Err(OpError::NotInRoom);
+        }
+        let Some(entry) = self.rooms.get_mut(room) else {
+            return Err(OpError::NotInRoom);
+        };
+        if op {
+            return Ok(entry.ops.insert(id));
+        }
+        if !entry.ops.contains(&id) {
+            return Ok(false);
+        }
+        if entry.ops.len() == 1 && room != DEFAULT_ROOM {
+            return Err(OpError::LastOp);
+        }
+        Ok(entry.ops.remove(&id))
+    }
+
+    // rooms other than the default one go away once the last member has left, or with
+    // --empty-room-grace once they have been empty that long, see reclaim_empty_rooms
+    // gives the name of the member made an operator in the client's place, when it was the
+    // last one and somebody is left to take over
+    fn leave(&mut self, id: ClientId, room: &str) -> Option<String> {
+        let entry = self.rooms.get_mut(room)?;
+        // the room may have been dropped by /quitall and opened again since
+        entry.joined.remove(&id)?;
+        entry.members = entry.members.saturating_sub(1);
+        entry.last_sent.remove(&id);
+        if entry.members == 0 && room != DEFAULT_ROOM {
+            if self.empty_room_grace.is_zero() {
+                self.rooms.remove(room);
+            } else {
+                entry.ops.remove(&id);
+                entry.empty_since = Some(Instant::now());
+            }
+            return None;
+        }
+        if !entry.ops.remove(&id) || !entry.ops.is_empty() {
+            return None;
+        }
+        let (&heir, _) = entry.joined.iter().min_by_key(|(_, &since)| since)?;
+        entry.ops.insert(heir);
+        self.clients.get(&heir).map(|client| client.name.clone())
+    }
+
+    // removes the rooms that have been empty for longer than the grace period
+    pub fn reclaim_empty_rooms(&mut self) {
+        let grace = self.empty_room_grace;
+        self.rooms
+            .retain(|_, room| room.empty_since.is_none_or(|since| since.elapsed() < grace));
+    }
+
+    // removes a room and takes everyone out of it in one go, so the member counts never
+    // disagree with the clients' rooms. whoever was chatting in it is moved to the default
+    // room, joining it if they weren't in there already. sessions waiting to be resumed are
+    // moved the same way, so nobody comes back into a room that is gone
+    pub fn delete_room(&mut self, room: &str) -> Result<usize, DeleteRoomError> {
+        if room == DEFAULT_ROOM {
+            return Err(DeleteRoomError::DefaultRoom);
+        }
+        let removed = self.rooms.remove(room).ok_or(DeleteRoomError::NoSuchRoom)?;
+        let mut moved = Vec::new();
+        for (&id, client) in &mut self.clients {
+            if !client.rooms.remove(room) || client.room != room {
+                continue;
+            }
+            client.room = DEFAULT_ROOM.to_string();
+            if client.rooms.insert(DEFAULT_ROOM.to_string()) {
+                moved.push(id);
+            }
+        }
+        // the default room's member count goes up when they come back, not now
+        for pending in self.resumes.values_mut() {
+            if pending.rooms.remove(room) && pending.room == room {
+                pending.room = DEFAULT_ROOM.to_string();
+                pending.rooms.insert(DEFAULT_ROOM.to_string());
+            }
+        }
+        let default = self.rooms.entry(DEFAULT_ROOM.to_string()).or_default();
+        for id in moved {
+            default.members += 1;
+            default.joined.insert(id, Instant::now());
+        }
+        Ok(removed.members)
+    }
+
+    // moves a room and everything about it to a new name, its members included. the history
+    // size the config gave the old name stays with the room
+    pub fn rename_room(&mut self, old: &str, new: &str) -> Result<(), RenameRoomError> {
+        if old == DEFAULT_ROOM {
+            return Err(RenameRoomError::DefaultRoom);
+        }
+        if new == DEFAULT_ROOM || self.rooms.contains_key(new) {
+            return Err(RenameRoomError::NameTaken);
+        }
+        if self.secure_rooms.contains(new) && !self.secure_rooms.contains(old) {
+            return Err(RenameRoomError::Secure);
+        }
+        let limit = self.history_limit(old);
+        let mut room = self.rooms.remove(old).ok_or(RenameRoomError::NoSuchRoom)?;
+        room.history_limit = Some(limit);
+        room.rename(new);
+        self.rooms.insert(new.to_string(), room);
+        // the old name stays secure too, nobody can open an insecure room under it later
+        if self.secure_rooms.contains(old) {
+            self.secure_rooms.insert(new.to_string());
+        }
+        let clients = self
+            .clients
+            .values_mut()
+            .map(|client| (&mut client.rooms, &mut client.room));
+        let resumes = self
+            .resumes
+            .values_mut()
+            .map(|pending| (&mut pending.rooms, &mut pending.room));
+        for (rooms, current) in clients.chain(resumes) {
+            if rooms.remove(old) {
+                rooms.insert(new.to_string());
+            }
+            if current == old {
+                *current = new.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    // gives a message the next sequence number and stores it in the room's history
+    fn record(
+        &mut self,
+        room: &str,
+        from: &str,
+        text: String,
+        bot: bool,
+        reply_to: Option<u64>,
+    ) -> Arc<HistoryEntry> {
+        self.last_seq += 1;
+        let entry = Arc::new(HistoryEntry {
+            seq: self.last_seq,
+            room: room.into(),
+            from: from.to_string(),
+            text,
+            sent_at: Instant::now(),
+            ts: unix_millis(),
+            bot,
+            reply_to,
+        });
+        let limit = self.history_limit(room);
+        if let Some(room) = self.rooms.get_mut(room) {
+            room.history.push_back(entry.clone());
+            room.trim_history(limit);
+        }
+        if !bot {
+            self.saw(from);
+        }
+        entry
+    }
+
+    // --secure-room, along with whatever such a room has been renamed to
+    pub fn is_secure_room(&self, room: &str) -> bool {
+        self.secure_rooms.contains(room)
+    }
+
+    // fills the history of a room that just opened with what the store kept of it, but only
+    // while the history is still empty, a message sent in the meantime would otherwise end up
+    // in front of older ones. the stored messages get new sequence numbers, theirs were handed
+    // out by an earlier run and would clash with this one's
+    fn seed(&mut self, room: &str, messages: Vec<StoredMessage>) {
+        let limit = self.history_limit(room);
+        let Some(state) = self.rooms.get_mut(room) else {
+            return;
+        };
+        if !state.history.is_empty() {
+            return;
+        }
+        let now = unix_millis();
+        // a reply keeps its parent when that was read back too
+        let mut renumbered = HashMap::new();
+        for message in messages {
+            self.last_seq += 1;
+            renumbered.insert(message.seq, self.last_seq);
+            let age = Duration::from_millis(now.saturating_sub(message.ts));
+            state.history.push_back(Arc::new(HistoryEntry {
+                seq: self.last_seq,
+                room: room.into(),
+                from: message.from,
+                text: message.text,
+                sent_at: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+                ts: message.ts,
+                bot: message.bot,
+                reply_to: message
+                    .reply_to
+                    .and_then(|parent| renumbered.get(&parent).copied()),
+            }));
+        }
+        state.trim_history(limit);
+    }
+
+    // the oldest name goes once there are too many, so a stream of throwaway names can't grow
+    // the map without bound
+    fn saw(&mut self, name: &str) {
+        if self.last_seen.len() >= MAX_LAST_SEEN && !self.last_seen.contains_key(name) {
+            let oldest = self
+                .last_seen
+                .iter()
+                .min_by_key(|(_, at)| **at)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                self.last_seen.remove(&oldest);
+            }
+        }
+        self.last_seen.insert(name.to_string(), Instant::now());
+    }
+
+    // adds the name to the client's ignore list or takes it off, keeping count of who has one
+    pub fn set_ignored(&mut self, id: ClientId, name: &str, ignored: bool) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        let had_any = !client.ignored.is_empty();
+        if ignored {
+            client.ignored.insert(name.to_string());
+        } else {
+            client.ignored.remove(name);
+        }
+        match (had_any, client.ignored.is_empty()) {
+            (false, false) => self.ignoring += 1,
+            (true, true) => self.ignoring -= 1,
+            _ => {}
+        }
+    }
+
+    // the members of the room who are to get a message from the name, when one of them
+    // ignores it. the room is only gone through when somebody somewhere ignores someone
+    fn recipients(&self, room: &str, from: &str) -> Option<Arc<HashSet<ClientId>>> {
+        if self.ignoring == 0 {
+            return None;
+        }
+        let members = &self.rooms.get(room)?.joined;
+        let ignores = |id: &ClientId| {
+            self.clients
+                .get(id)
+                .is_some_and(|client| client.ignored.contains(from))
+        };
+        if !members.keys().any(ignores) {
+            return None;
+        }
+        let to = members.keys().filter(|id| !ignores(id)).copied().collect();
+        Some(Arc::new(to))
+    }
+
+    // how many messages a room keeps: what /histlimit set, or else the config's size for it
+    fn history_limit(&self, room: &str) -> usize {
+        self.rooms
+            .get(room)
+            .and_then(|state| state.history_limit)
+            .or_else(|| self.room_history.get(room).copied())
+            .unwrap_or(self.history_size)
+    }
+
+    // which of the client's rooms still has a message in its history. sequence numbers are
+    // global, so at most one can
+    fn member_room_with(&self, id: ClientId, seq: u64) -> Option<String> {
+        let client = self.clients.get(&id)?;
+        client
+            .rooms
+            .iter()
+            .find(|room| {
+                self.rooms
+                    .get(*room)
+                    .is_some_and(|state| state.position(seq).is_some())
+            })
+            .cloned()
+    }
+
+    fn expire_resumes(&mut self) {
+        let now = Instant::now();
+        self.resumes.retain(|_, pending| pending.expires_at > now);
+    }
+}
+
+// counters that only ever go up, bumped from the connection tasks without taking a lock
+#[derive(Debug, Default)]
+pub struct Stats {
+    // chat connections open right now, including ones still at the name prompt
+    pub open_connections: AtomicUsize,
+    pub connections_total: AtomicU64,
+    pub messages_total: AtomicU64,
+    // bytes read from clients, line terminators included
+    pub bytes_total: AtomicU64,
+    // times a client fell behind the broadcast and missed messages
+    pub lag_events: AtomicU64,
+    // writes to client sockets, one syscall each. every connection's writer task holds a
+    // clone, which is why this one is shared
+    pub writes_total: Arc<AtomicU64>,
+}
+
+// a point in time copy of the counters. each value is read separately, so under load they may
+// be a message or two apart and are stale as soon as they are returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerStats {
+    // registered clients, connections still at the name prompt aren't counted
+    pub active_connections: usize,
+    pub connections_total: u64,
+    pub messages_total: u64,
+    pub bytes_total: u64,
+    pub lag_events: u64,
+    // writes to client sockets, comparing it with the lines sent shows what batching saves
+    pub writes_total: u64,
+    // so many clients are falling behind that everyone has been put in slow mode
+    pub overloaded: bool,
+    pub rooms: usize,
+    pub uptime: Duration,
+}
+
+// a client coming back with a resume token, along with what it missed while it was gone
+pub struct Resumed {
+    pub registration: Registration,
+    pub rx: broadcast::Receiver<Event>,
+    pub name: String,
+    pub role: Role,
+    pub room: String,
+    pub rooms: HashSet<String>,
+    pub ignored: HashSet<String>,
+    pub aliases: HashMap<String, String>,
+    pub missed: Vec<Arc<HistoryEntry>>,
+}
+
+// what gets handed to the broadcaster task
+enum Publish {
+    // a chat line still to be given its sequence number and stored, the sequence number is
+    // sent back if someone is waiting for it
+    Message {
+        from: ClientId,
+        room: String,
+        name: String,
+        text: String,
+        bot: bool,
+        reply_to: Option<u64>,
+        seq: Option<oneshot::Sender<u64>>,
+    },
+    Event(Event),
+}
+
+// how many queued items the broadcaster handles per registry lock
+const PUBLISH_BATCH: usize = 64;
+// events a receiver may fall behind by. the broadcaster sends whole batches at a time, so this
+// has to leave receivers room for a few of them
+const BROADCAST_CAPACITY: usize = 256;
+
+// state shared by every connection task, handed around behind an arc
+pub struct Shared {
+    pub config: Config,
+    // only the broadcaster sends on this, everyone else goes through publish()
+    tx: broadcast::Sender<Event>,
+    // unbounded so publishing never waits, the per client limits keep it from running away
+    publisher: mpsc::UnboundedSender<Publish>,
+    // for embedders, nothing inside the server listens on this
+    events: broadcast::Sender<ServerEvent>,
+    webhook: Option<Webhook>,
+    // feeds the message store, if there is one
+    archive: RwLock<Option<Archive>>,
+    pub uploads: Option<Uploads>,
+    // every connection watches this, a new value is the text of a /quitall
+    evict: watch::Sender<Arc<str>>,
+    pub motd: Option<Motd>,
+    pub stats: Stats,
+    pub started_at: Instant,
+    registry: Mutex<Registry>,
+    next_id: AtomicU64,
+    authenticator: RwLock<Arc<dyn Authenticator>>,
+    // SessionPolicy::Replace
+    replace_sessions: AtomicBool,
+}
+
+impl Shared {
+    // also starts the broadcaster, so this has to be called from inside the runtime
+    pub fn new(config: Config, motd: Option<Motd>) -> Arc<Shared> {
+        let (tx, _rx) = broadcast::channel(BROADCAST_CAPACITY);
+        let (publisher, queue) = mpsc::unbounded_channel();
+        let (events, _rx) = broadcast::channel(EVENTS_CAPACITY);
+        let (opened, rooms) = mpsc::unbounded_channel();
+        let webhook = config.webhook.clone().map(Webhook::spawn);
+        let uploads = Uploads::new(&config);
+        let archive = config
+            .store_file
+            .clone()
+            .map(|path| Archive::spawn(Arc::new(FileStore::new(path))));
+        let stored = archive.is_some();
+        let shared = Arc::new_cyclic(|shared| {
+            tokio::spawn(broadcaster(shared.clone(), queue));
+            tokio::spawn(loader(shared.clone(), rooms));
+            Shared {
+                registry: Mutex::new(Registry::new(&config, opened)),
+                config,
+                tx,
+                publisher,
+                events,
+                webhook,
+                archive: RwLock::new(archive),
+                uploads,
+                evict: watch::channel(Arc::from("")).0,
+                motd,
+                stats: Stats::default(),
+                started_at: Instant::now(),
+                next_id: AtomicU64::new(1),
+                authenticator: RwLock::new(Arc::new(NoAuth)),
+                replace_sessions: AtomicBool::new(false),
+            }
+        });
+        if stored {
+            shared.load_rooms();
+        }
+        shared
+    }
+
+    // cloned out, so nobody holds the lock while an authentication is under way
+    pub fn authenticator(&self) -> Arc<dyn Authenticator> {
+        self.authenticator
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub fn set_authenticator(&self, authenticator: Arc<dyn Authenticator>) {
+        *self
+            .authenticator
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = authenticator;
+    }
+
+    pub fn set_session_policy(&self, policy: SessionPolicy) {
+        self.replace_sessions
+            .store(policy == SessionPolicy::Replace, Ordering::Relaxed);
+    }
+
+    // queued behind whatever the store still has to append
+    pub fn expire_stored(&self, before: u64) {
+        if let Some(archive) = &*self
+            .archive
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+        {
+            archive.expire(before);
+        }
+    }
+
+    // messages already queued for the old store still go to it
+    pub fn set_store(&self, store: Arc<dyn MessageStore>) {
+        *self
+            .archive
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Archive::spawn(store));
+        self.load_rooms();
+    }
+
+    // the rooms open now are filled from the store as if they had just opened, the ones that
+    // already have messages are left alone
+    fn load_rooms(&self) {
+        let registry = self.registry();
+        for room in registry.rooms.keys() {
+            let _ = registry.opened.send(room.clone());
+        }
+    }
+
+    // counted in characters rather than bytes, so the limit doesn't depend on the script
+    pub fn too_long(&self, text: &str) -> bool {
+        let max = self.config.max_message_len;
+        max > 0 && text.chars().count() > max
+    }
+
+    // a message that fits the limit as it is, or cut down to it with --truncate-long-messages.
+    // None when it is too long and has to be turned down. the cut falls where the first
+    // character past the limit starts, so it never splits one
+    fn fit(&self, mut text: String) -> Option<String> {
+        if !self.too_long(&text) {
+            return Some(text);
+        }
+        if !self.config.truncate_long_messages {
+            return None;
+        }
+        let max = self.config.max_message_len;
+        let end = text
+            .char_indices()
+            .nth(max)
+            .map_or(text.len(), |(at, _)| at);
+        text.truncate(end);
+        Some(text)
+    }
+
+    // hands an event to the broadcaster, receivers get events in the order they were published
+    pub fn publish(&self, event: Event) {
+        let _ = self.publisher.send(Publish::Event(event));
+    }
+
+    pub fn events(&self) -> ServerEvents {
+        ServerEvents::new(self.events.subscribe())
+    }
+
+    // the event is only built when someone is subscribed, so without one this costs nothing
+    fn emit(&self, event: impl FnOnce() -> ServerEvent) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+
+    // the registry lock is only taken to count clients and rooms, everything else is atomics
+    pub fn snapshot(&self) -> ServerStats {
+        let (active_connections, rooms, overloaded) = {
+            let registry = self.registry();
+            let overloaded = registry.load.overloaded;
+            (registry.clients.len(), registry.rooms.len(), overloaded)
+        };
+        ServerStats {
+            active_connections,
+            connections_total: self.stats.connections_total.load(Ordering::Relaxed),
+            messages_total: self.stats.messages_total.load(Ordering::Relaxed),
+            bytes_total: self.stats.bytes_total.load(Ordering::Relaxed),
+            lag_events: self.stats.lag_events.load(Ordering::Relaxed),
+            writes_total: self.stats.writes_total.load(Ordering::Relaxed),
+            overloaded,
+            rooms,
+            uptime: self.started_at.elapsed(),
+        }
+    }
+
+    // the lock is only ever held for short synchronous sections, never across an await.
+    // a poisoned lock is still usable since every update leaves the maps consistent
+    pub fn registry(&self) -> MutexGuard<'_, Registry> {
+        self.registry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // adds a client to the registry, it is removed again when the returned guard drops
+    pub fn register(
+        self: &Arc<Self>,
+        peer: Peer,
+        out: OutboundHandle,
+        name: &str,
+    ) -> Result<(Registration, broadcast::Receiver<Event>), RegisterError> {
+        let mut registry = self.registry();
+        registry.expire_resumes();
+        if registry.name_taken(name) {
+            if !self.replace_sessions.load(Ordering::Relaxed) {
+                return Err(RegisterError::NameTaken);
+            }
+            // a connected client has to leave first, a name only held for a resume is let go
+            if let Some(client) = registry.clients.values().find(|client| client.name == name) {
+                let notice = "*** you signed in from somewhere else ***".to_string();
+                client.removed.remove(DisconnectReason::Replaced, notice);
+                return Err(RegisterError::Replacing);
+            }
+            registry.resumes.retain(|_, pending| pending.name != name);
+        }
+        let rooms = HashSet::from([DEFAULT_ROOM.to_string()]);
+        let registration = self.insert(&mut registry, peer, out, name, DEFAULT_ROOM, rooms);
+        let rx = self.tx.subscribe();
+        drop(registry);
+        self.announce(registration.id, DEFAULT_ROOM, name, Presence::Joined);
+        Ok((registration, rx))
+    }
+
+    // swaps a resume token for the name and room it was issued for
+    pub fn resume(
+        self: &Arc<Self>,
+        token: &str,
+        peer: Peer,
+        out: OutboundHandle,
+    ) -> Result<Resumed, ResumeError> {
+        let mut registry = self.registry();
+        registry.expire_resumes();
+        let mut pending = registry
+            .resumes
+            .remove(token)
+            .ok_or(ResumeError::InvalidToken)?;
+        // a token from a tls session picked up over plain tcp doesn't get the secure rooms back
+        if !peer.transport.is_secure() {
+            pending
+                .rooms
+                .retain(|room| !registry.secure_rooms.contains(room));
+            if !pending.rooms.contains(&pending.room) {
+                pending.room = DEFAULT_ROOM.to_string();
+                pending.rooms.insert(DEFAULT_ROOM.to_string());
+            }
+        }
+        let peer = Peer {
+            role: peer.role.max(pending.role),
+            ..peer
+        };
+        let registration = self.insert(
+            &mut registry,
+            peer,
+            out,
+            &pending.name,
+            &pending.room,
+            pending.rooms.clone(),
+        );
+        if let Some(client) = registry.clients.get_mut(&registration.id) {
+            client.aliases.clone_from(&pending.aliases);
+            client.message_ids.clone_from(&pending.message_ids);
+        }
+        for name in &pending.ignored {
+            registry.set_ignored(registration.id, name, true);
+        }
+        // sequence numbers are global, so sorting by them interleaves the rooms as they happened
+        let mut missed: Vec<_> = pending
+            .rooms
+            .iter()
+            .filter_map(|room| registry.rooms.get(room))
+            .flat_map(|room| room.history.iter())
+            .filter(|entry| entry.seq > pending.last_seq)
+            .cloned()
+            .collect();
+        missed.sort_unstable_by_key(|entry| entry.seq);
+        // subscribing under the lock means nothing falls between the replay and the live feed
+        let rx = self.tx.subscribe();
+        drop(registry);
+        for room in &pending.rooms {
+            self.announce(registration.id, room, &pending.name, Presence::Joined);
+        }
+        Ok(Resumed {
+            registration,
+            rx,
+            name: pending.name,
+            role: peer.role,
+            room: pending.room,
+            rooms: pending.rooms,
+            ignored: pending.ignored,
+            aliases: pending.aliases,
+            missed,
+        })
+    }
+
+    fn insert(
+        self: &Arc<Self>,
+        registry: &mut Registry,
+        peer: Peer,
+        outbound: OutboundHandle,
+        name: &str,
+        room: &str,
+        rooms: HashSet<String>,
+    ) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.stats.connections_total.fetch_add(1, Ordering::Relaxed);
+        let token = new_token();
+        let removed = Arc::new(Removal::default());
+        self.emit(|| ServerEvent::Connected {
+            id,
+            name: name.to_string(),
+            addr: peer.addr,
+        });
+        registry.insert_client(
+            id,
+            ClientInfo {
+                addr: peer.addr,
+                transport: peer.transport,
+                name: name.to_string(),
+                room: room.to_string(),
+                rooms,
+                role: peer.role,
+                outbound,
+                connected_at: Instant::now(),
+                resume_token: token.clone(),
+                last_message: String::new(),
+                repeat_count: 0,
+                bucket: (self.config.rate_limit > 0).then(|| {
+                    let config = &self.config;
+                    TokenBucket::new(
+                        config.rate_limit,
+                        config.rate_burst,
+                        config.rate_grace,
+                        Instant::now(),
+                    )
+                }),
+                dnd: false,
+                ignored: HashSet::new(),
+                aliases: HashMap::new(),
+                removed: removed.clone(),
+                message_ids: VecDeque::new(),
+            },
+        );
+        Registration {
+            removed,
+            shared: self.clone(),
+            resumable: true,
+            reason: DisconnectReason::Error,
+            id,
+            token,
+        }
+    }
+
+    // checks a chat line from a client against the room's limits and hands it to the
+    // broadcaster, which stores it and sends it to the client's room. a reply has to answer a
+    // message still in that room's history
+    pub fn post(
+        &self,
+        id: ClientId,
+        text: String,
+        reply_to: Option<u64>,
+        message_id: Option<String>,
+    ) -> Result<(), PostError> {
+        let text = self.fit(text).ok_or(PostError::TooLong)?;
+        let mut registry = self.registry();
+        if let Some(parent) = reply_to {
+            let room = registry.clients.get(&id).map(|client| client.room.as_str());
+            if room
+                .and_then(|room| registry.rooms.get(room))
+                .is_none_or(|state| state.position(parent).is_none())
+            {
+                return Err(PostError::NoSuchParent(parent));
+            }
+        }
+        let Some(client) = registry.clients.get_mut(&id) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        while client
+            .message_ids
+            .front()
+            .is_some_and(|(_, sent_at)| now.duration_since(*sent_at) >= DEDUP_WINDOW)
+        {
+            client.message_ids.pop_front();
+        }
+        if let Some(message_id) = &message_id {
+            if client
+                .message_ids
+                .iter()
+                .any(|(seen, _)| seen == message_id)
+            {
+                return Err(PostError::Duplicate);
+            }
+        }
+        // counted once the message goes out, one held back below isn't a repeat
+        let repeat_count = if client.last_message == text {
+            client.repeat_count + 1
+        } else {
+            1
+        };
+        let max_repeats = self.config.max_repeats;
+        if max_repeats > 0 && repeat_count > max_repeats {
+            return Err(PostError::Repeated);
+        }
+        if let Some(bucket) = &mut client.bucket {
+            bucket.take(now).map_err(PostError::RateLimited)?;
+        }
+        let (room, name) = (client.room.clone(), client.name.clone());
+        let (window, threshold) = (self.config.flood_window, self.config.flood_threshold);
+        let mut notice = None;
+        let exempt = registry.is_op(id, &room);
+        let overload_slow_mode = registry
+            .load
+            .overloaded
+            .then_some(self.config.flood_slow_mode);
+        if let Some(state) = registry.rooms.get_mut(&room) {
+            // an automatic slow mode is lifted once the room has calmed down to half the threshold
+            state.expire_rate(now, window);
+            if state.auto_slow && state.rate(now, window) <= threshold / 2 {
+                state.slow_mode = None;
+                state.auto_slow = false;
+                self.send_notice(&room, "*** slow mode lifted ***".to_string());
+            }
+            if let Some(wait) = state
+                .slow_mode_wait(id, now, overload_slow_mode)
+                .filter(|_| !exempt)
+            {
+                return Err(PostError::SlowMode(wait));
+            }
+            state.note_message(id, now, window);
+            if threshold > 0 && state.slow_mode.is_none() && state.rate(now, window) > threshold {
+                let interval = self.config.flood_slow_mode;
+                state.slow_mode = Some(interval);
+                state.auto_slow = true;
+                notice = Some(format!(
+                    "*** this room is busy, slow mode is on: one message every {}s ***",
+                    interval.as_secs()
+                ));
+            }
+        }
+        // only once the message is certain to go out, a refused one may be sent again as is
+        if let Some(client) = registry.clients.get_mut(&id) {
+            if repeat_count == 1 {
+                client.last_message.clone_from(&text);
+            }
+            client.repeat_count = repeat_count;
+            if let Some(message_id) = message_id {
+                if client.message_ids.len() == MAX_MESSAGE_IDS {
+                    client.message_ids.pop_front();
+                }
+                client.message_ids.push_back((message_id, now));
+            }
+        }
+        drop(registry);
+        let _ = self.publisher.send(Publish::Message {
+            from: id,
+            room: room.clone(),
+            name,
+            text,
+            bot: false,
+            reply_to,
+            seq: None,
+        });
+        if let Some(notice) = notice {
+            self.send_notice(&room, notice);
+        }
+        Ok(())
+    }
+
+    // a message that came in over http, from nobody in particular. it goes through history
+    // and the broadcast like any other but skips the per client limits. returns its sequence number
+    pub async fn post_from_http(
+        &self,
+        room: &str,
+        name: &str,
+        text: String,
+        bot: bool,
+    ) -> Result<u64, HttpPostError> {
+        let text = self.fit(text).ok_or(HttpPostError::TooLong)?;
+        {
+            let registry = self.registry();
+            if registry.clients.values().any(|client| client.name == name) {
+                return Err(HttpPostError::NameTaken);
+            }
+            if !registry.rooms.contains_key(room) {
+                return Err(HttpPostError::NoSuchRoom);
+            }
+        }
+        let (seq, recorded) = oneshot::channel();
+        let _ = self.publisher.send(Publish::Message {
+            // ids start at 1, so no client skips this as its own message
+            from: 0,
+            room: room.to_string(),
+            name: name.to_string(),
+            text,
+            bot,
+            reply_to: None,
+            seq: Some(seq),
+        });
+        // the broadcaster only goes away with the server itself
+        Ok(recorded.await.unwrap_or_default())
+    }
+
+    // what a room has had since a sequence number, and a subscription for whatever comes after.
+    // both are taken under the lock so nothing can fall in between. None if there's no such room
+    pub fn history_since(
+        &self,
+        room: &str,
+        since: u64,
+    ) -> Option<(Vec<Arc<HistoryEntry>>, broadcast::Receiver<Event>)> {
+        let registry = self.registry();
+        let entries = registry
+            .rooms
+            .get(room)?
+            .history
+            .iter()
+            .filter(|entry| entry.seq > since)
+            .cloned()
+            .collect();
+        Some((entries, self.tx.subscribe()))
+    }
+
+    // replaces the text of one of the client's own messages, or deletes it when there is no
+    // new text. only messages still in the history of one of the client's rooms can be changed
+    pub fn edit(&self, id: ClientId, seq: u64, text: Option<String>) -> Result<(), EditError> {
+        let text = text
+            .map(|text| self.fit(text).ok_or(EditError::TooLong))
+            .transpose()?;
+        let mut registry = self.registry();
+        let Some(client) = registry.clients.get(&id) else {
+            return Ok(());
+        };
+        let name = client.name.clone();
+        let room = registry
+            .member_room_with(id, seq)
+            .ok_or(EditError::NoSuchMessage)?;
+        let state = registry
+            .rooms
+            .get_mut(&room)
+            .ok_or(EditError::NoSuchMessage)?;
+        let index = state.position(seq).ok_or(EditError::NoSuchMessage)?;
+        let entry = &state.history[index];
+        if entry.from != name {
+            return Err(EditError::NotYours);
+        }
+        if entry.sent_at.elapsed() > self.config.edit_window {
+            return Err(EditError::TooOld);
+        }
+        let event = match text {
+            Some(text) => {
+                let entry = Arc::new(HistoryEntry {
+                    text,
+                    ..HistoryEntry::clone(entry)
+                });
+                state.history[index] = entry.clone();
+                if let Some(pin) = state.pins.iter_mut().find(|pin| pin.seq == seq) {
+                    *pin = entry.clone();
+                }
+                Event::Edit { entry }
+            }
+            None => {
+                state.history.remove(index);
+                state.reactions.remove(&seq);
+                state.pins.retain(|pin| pin.seq != seq);
+                Event::Delete {
+                    room: room.into(),
+                    seq,
+                }
+            }
+        };
+        self.publish(event);
+        Ok(())
+    }
+
+    // the room's pinned messages as shown to plain text clients, oldest first
+    pub fn pins(&self, room: &str) -> Vec<String> {
+        let registry = self.registry();
+        let Some(room) = registry.rooms.get(room) else {
+            return Vec::new();
+        };
+        room.pins
+            .iter()
+            .map(|pin| {
+                let mut text = format!("[pinned #{}] ", pin.seq);
+                pin.format_into(false, &mut text);
+                text
+            })
+            .collect()
+    }
+
+    // adds to the tally for a message in one of the client's rooms and tells the room about
+    // it. anyone may react to anything, so the only way this fails is with NoSuchMessage
+    pub fn react(&self, id: ClientId, seq: u64, emoji: String) -> Result<(), EditError> {
+        let mut registry = self.registry();
+        let Some(client) = registry.clients.get(&id) else {
+            return Ok(());
+        };
+        let from = client.name.clone();
+        let room = registry
+            .member_room_with(id, seq)
+            .ok_or(EditError::NoSuchMessage)?;
+        let state = registry
+            .rooms
+            .get_mut(&room)
+            .ok_or(EditError::NoSuchMessage)?;
+        state.position(seq).ok_or(EditError::NoSuchMessage)?;
+        let count = state
+            .reactions
+            .entry(seq)
+            .or_default()
+            .entry(emoji.clone())
+            .or_default();
+        *count += 1;
+        let reaction = Reaction {
+            seq,
+            room: room.into(),
+            from,
+            emoji,
+            count: *count,
+        };
+        self.publish(Event::Reaction(Arc::new(reaction)));
+        Ok(())
+    }
+
+    pub fn promoted(&self, room: &str, name: &str) {
+        self.send_notice(room, format!("*** {name} is now an operator ***"));
+    }
+
+    pub fn send_notice(&self, room: &str, text: String) {
+        self.publish(Event::Notice {
+            room: room.into(),
+            text,
+            except: None,
+        });
+    }
+
+    // tells a room that someone came or went. with a join burst configured, a room that sees
+    // more changes than that within the window gets a single summary at the end of it instead
+    pub fn announce(self: &Arc<Self>, id: ClientId, room: &str, name: &str, presence: Presence) {
+        self.emit(|| {
+            let (name, room) = (name.to_string(), room.to_string());
+            match presence {
+                Presence::Joined => ServerEvent::Join { id, name, room },
+                Presence::Left => ServerEvent::Leave { id, name, room },
+            }
+        });
+        let quiet = self
+            .registry()
+            .rooms
+            .get(room)
+            .is_some_and(|state| state.quiet_joins);
+        if quiet {
+            return;
+        }
+        let threshold = self.config.join_burst;
+        if threshold > 0 {
+            let mut registry = self.registry();
+            if let Some(state) = registry.rooms.get_mut(room) {
+                let was_batching = state.presence.batching;
+                if state
+                    .presence
+                    .note(Instant::now(), self.config.join_window, threshold)
+                {
+                    match presence {
+                        Presence::Joined => state.presence.joined += 1,
+                        Presence::Left => state.presence.left += 1,
+                    }
+                    if !was_batching {
+                        self.summarize_presence(room.to_string());
+                    }
+                    return;
+                }
+            }
+        }
+        self.publish(Event::Notice {
+            room: room.into(),
+            text: format!("*** {name} {} {room} ***", presence.verb()),
+            except: Some(id),
+        });
+    }
+
+    // a client fell behind the broadcast. if enough others have too, everyone is put in slow
+    // mode until nobody has lagged for a whole window
+    pub fn note_lag(self: &Arc<Self>, id: ClientId) {
+        self.stats.lag_events.fetch_add(1, Ordering::Relaxed);
+        let (threshold, window) = (self.config.lag_threshold, self.config.lag_window);
+        if threshold == 0 {
+            return;
+        }
+        let mut registry = self.registry();
+        let clients = registry.clients.len();
+        if !registry
+            .load
+            .note(id, Instant::now(), window, threshold, clients)
+        {
+            return;
+        }
+        drop(registry);
+        self.notice_everywhere("*** server is under heavy load, messages may be delayed ***");
+        let shared = self.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(window).await;
+                if shared.registry().load.calmed(Instant::now(), window) {
+                    break;
+                }
+            }
+            shared.notice_everywhere("*** server load is back to normal ***");
+        });
+    }
+
+    // only /quitall calls after this count, so subscribe before registering
+    pub fn evictions(&self) -> watch::Receiver<Arc<str>> {
+        self.evict.subscribe()
+    }
+
+    // tells every chat connection to send the text and hang up, admins stay. nobody thrown
+    // out can resume and the default room starts over, the rooms everyone leaves go with
+    // them. returns how many registered clients are being disconnected
+    // throws the named client out for /kick or /ban, telling it and its rooms who did it. a
+    // ban also turns its address away at accept from then on, which lasts until a restart
+    pub fn kick(
+        &self,
+        (by, role): (&str, Role),
+        name: &str,
+        reason: Option<&str>,
+        ban: bool,
+    ) -> Result<(), KickError> {
+        let mut registry = self.registry();
+        let (&id, client) = registry
+            .clients
+            .iter()
+            .find(|(_, client)| client.name == name)
+            .ok_or(KickError::NoSuchUser)?;
+        if client.role >= role {
+            return Err(KickError::Outranked);
+        }
+        let (why, verb) = match ban {
+            true => (DisconnectReason::Ban, "banned"),
+            false => (DisconnectReason::Kick, "kicked"),
+        };
+        let because = reason
+            .map(|reason| format!(": {reason}"))
+            .unwrap_or_default();
+        let notice = format!("*** you were {verb} by {by}{because} ***");
+        client.removed.remove(why, notice);
+        let rooms: Vec<String> = client.rooms.iter().cloned().collect();
+        let addr = client.addr.ip();
+        if ban {
+            registry.banned.insert(addr);
+        }
+        drop(registry);
+        for room in rooms {
+            self.publish(Event::Notice {
+                room: room.as_str().into(),
+                text: format!("*** {name} was {verb} by {by}{because} ***"),
+                except: Some(id),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.registry().banned.contains(&addr)
+    }
+
+    pub fn quit_all(&self, text: String) -> usize {
+        let mut registry = self.registry();
+        let evicted = registry
+            .clients
+            .values()
+            .filter(|client| client.role != Role::Admin)
+            .count();
+        registry.resumes.clear();
+        registry.last_seen.clear();
+        // the rooms the admins staying on are in are emptied out, the others go, along with
+        // any that --empty-room-grace is keeping for later
+        let staying: HashSet<String> = registry
+            .clients
+            .values()
+            .filter(|client| client.role == Role::Admin)
+            .flat_map(|client| client.rooms.iter().cloned())
+            .collect();
+        registry
+            .rooms
+            .retain(|name, _| name == DEFAULT_ROOM || staying.contains(name));
+        for room in registry.rooms.values_mut() {
+            room.clear();
+        }
+        drop(registry);
+        self.evict.send_replace(text.into());
+        evicted
+    }
+
+    fn notice_everywhere(&self, text: &str) {
+        let rooms: Vec<String> = self.registry().rooms.keys().cloned().collect();
+        for room in rooms {
+            self.send_notice(&room, text.to_string());
+        }
+    }
+
+    fn summarize_presence(self: &Arc<Self>, room: String) {
+        let shared = self.clone();
+        tokio::spawn(async move {
+            sleep(shared.config.join_window).await;
+            let mut registry = shared.registry();
+            // the room may have emptied out in the meantime, then there's nobody to tell
+            let Some(state) = registry.rooms.get_mut(&room) else {
+                return;
+            };
+            let joined = std::mem::take(&mut state.presence.joined);
+            let left = std::mem::take(&mut state.presence.left);
+            state.presence.batching = false;
+            drop(registry);
+            for (count, presence) in [(joined, Presence::Joined), (left, Presence::Left)] {
+                let users = if count == 1 { "user" } else { "users" };
+                if count > 0 {
+                    let text = format!("*** {count} {users} {} ***", presence.verb());
+                    shared.send_notice(&room, text);
+                }
+            }
+        });
+    }
+}
+
+// the one task that sends on the broadcast channel. messages are numbered, stored and sent in
+// one step under the registry lock, so sequence numbers reach everyone in order and a client
+// subscribing under the lock never misses one or sees one twice. a message normally goes to
+// the whole room and the connections pick it out by themselves, only when someone in there
+// ignores the sender does the broadcaster go through the members to say who gets it
+async fn broadcaster(shared: Weak<Shared>, mut queue: mpsc::UnboundedReceiver<Publish>) {
+    let mut batch = Vec::with_capacity(PUBLISH_BATCH);
+    while let Some(first) = queue.recv().await {
+        batch.push(first);
+        while batch.len() < PUBLISH_BATCH {
+            let Ok(next) = queue.try_recv() else {
+                break;
+            };
+            batch.push(next);
+        }
+        let Some(shared) = shared.upgrade() else {
+            break;
+        };
+        let mut registry = shared.registry();
+        for publish in batch.drain(..) {
+            let event = match publish {
+                Publish::Message {
+                    from,
+                    room,
+                    name,
+                    text,
+                    bot,
+                    reply_to,
+                    seq,
+                } => {
+                    let to = registry.recipients(&room, &name);
+                    let entry = registry.record(&room, &name, text, bot, reply_to);
+                    shared.stats.messages_total.fetch_add(1, Ordering::Relaxed);
+                    if let Some(webhook) = &shared.webhook {
+                        webhook.deliver(&entry);
+                    }
+                    if let Some(archive) = &*shared
+                        .archive
+                        .read()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    {
+                        archive.keep(&entry);
+                    }
+                    shared.emit(|| ServerEvent::Message {
+                        seq: entry.seq,
+                        room: entry.room.to_string(),
+                        from: entry.from.clone(),
+                        text: entry.text.clone(),
+                    });
+                    if let Some(seq) = seq {
+                        let _ = seq.send(entry.seq);
+                    }
+                    Event::Message { from, entry, to }
+                }
+                Publish::Event(event) => event,
+            };
+            let _ = shared.tx.send(event);
+        }
+    }
+}
+
+// reads each room that opens back from the store, one at a time and in the order they opened,
+// and hands what it got to Registry::seed. the store is never waited on with the registry
+// locked
+async fn loader(shared: Weak<Shared>, mut rooms: mpsc::UnboundedReceiver<String>) {
+    while let Some(room) = rooms.recv().await {
+        let recent = {
+            let Some(shared) = shared.upgrade() else {
+                break;
+            };
+            let count = shared.registry().history_limit(&room);
+            let archive = shared
+                .archive
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match &*archive {
+                Some(archive) if count > 0 => archive.recent(&room, count),
+                _ => continue,
+            }
+        };
+        let Ok(messages) = recent.await else {
+            continue;
+        };
+        let Some(shared) = shared.upgrade() else {
+            break;
+        };
+        shared.registry().seed(&room, messages);
+    }
+}
+
+// resume tokens only need to be hard to guess for the few minutes they are valid,
+// the randomly keyed std hasher gives us that without another dependency
+pub fn new_token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut token = String::with_capacity(32);
+    for _ in 0..2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        let _ = write!(token, "{:016x}", hasher.finish());
+    }
+    token
+}
+
+// names are shown in front of every message, so keep them to a single word made of letters,
+// digits and printable ascii. that leaves out control characters, zero width spaces and
+// direction overrides, which can make one name look like another or garble the line it's on
+pub fn validate_name(name: &str, max_len: usize) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Name can't be empty".to_string());
+    }
+    if name.chars().count() > max_len {
+        return Err(format!("Name can't be longer than {max_len} characters"));
+    }
+    if name.chars().any(char::is_whitespace) {
+        return Err("Name can't contain spaces".to_string());
+    }
+    if name.starts_with('/') {
+        return Err("Name can't start with /".to_string());
+    }
+    if name.starts_with('#') {
+        return Err("Name can't start with #, that's for rooms".to_string());
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|&c| !(c.is_ascii_graphic() || c.is_alphanumeric()))
+    {
+        return Err(format!("Name can't contain {}", c.escape_unicode()));
+    }
+    Ok(())
+}
+
+pub const MAX_ROOM_NAME_CHARS: usize = 30;
+
+// a # and then up to 30 ascii letters, digits, dashes and underscores
+pub fn validate_room_name(room: &str) -> Result<(), String> {
+    let Some(name) = room.strip_prefix('#') else {
+        return Err("Room names start with #".to_string());
+    };
+    if name.is_empty() {
+        return Err("Room name can't be just #".to_string());
+    }
+    if name.len() > MAX_ROOM_NAME_CHARS {
+        return Err(format!(
+            "Room name can't be longer than {MAX_ROOM_NAME_CHARS} characters after the #"
+        ));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|&c| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    {
+        return Err(format!(
+            "Room name can't contain {c:?}, only letters, digits, - and _"
+        ));
+    }
+    Ok(())
+}
+
+// doing the cleanup in drop means a panicking connection task still leaves the registry
+pub struct Registration {
+    shared: Arc<Shared>,
+    pub id: ClientId,
+    pub token: String,
+    // cleared for clients that were thrown out, they don't get to come back with the token
+    pub resumable: bool,
+    // set by the connection on its way out, anything it doesn't know better is an error
+    pub reason: DisconnectReason,
+    // the same as in the client's info
+    pub removed: Arc<Removal>,
+}
+
+// how a client is thrown out by somebody else: a new session under its name, /kick or /ban.
+// the connection waits for it and hangs up with the reason and notice it was given
+#[derive(Debug, Default)]
+pub struct Removal {
+    notify: Notify,
+    why: Mutex<Option<(DisconnectReason, String)>>,
+}
+
+impl Removal {
+    pub fn remove(&self, reason: DisconnectReason, notice: String) {
+        *self
+            .why
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((reason, notice));
+        self.notify.notify_one();
+    }
+
+    // safe to use in select!, a removal happening in the meantime is kept for the next call
+    pub async fn removed(&self) -> (DisconnectReason, String) {
+        loop {
+            self.notify.notified().await;
+            let why = self
+                .why
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .take();
+            if let Some(why) = why {
+                return why;
+            }
+        }
+    }
+}
+
+// why a connection ended, as kept for /recent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    // the client hung up, or reading from it failed
+    Closed,
+    // writing to it failed
+    Error,
+    Quit,
+    // thrown out by a /quitall emptying the server
+    Drain,
+    // someone else signed in under the name
+    Replaced,
+    // thrown out by a moderator with /kick
+    Kick,
+    // the same with /ban, which keeps the address out too
+    Ban,
+    // went over the frame limit
+    Flooding,
+    Idle,
+    // reached --max-lifetime
+    Expired,
+    // stopped halfway through a message
+    Stalled,
+    Shutdown,
+}
+
+impl DisconnectReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisconnectReason::Closed => "closed",
+            DisconnectReason::Error => "error",
+            DisconnectReason::Quit => "quit",
+            DisconnectReason::Drain => "drained",
+            DisconnectReason::Replaced => "replaced",
+            DisconnectReason::Kick => "kicked",
+            DisconnectReason::Ban => "banned",
+            DisconnectReason::Flooding => "flooding",
+            DisconnectReason::Idle => "idle",
+            DisconnectReason::Expired => "expired",
+            DisconnectReason::Stalled => "stalled",
+            DisconnectReason::Shutdown => "shutdown",
+        }
+    }
+
+    // the reason in the disconnect frame json clients get last, which is about what the client
+    // might do next rather than what happened: reconnecting makes sense after a shutdown or an
+    // expired session, not while the server is being drained
+    pub fn code(self) -> &'static str {
+        match self {
+            DisconnectReason::Shutdown => "shutdown",
+            DisconnectReason::Drain => "drain",
+            DisconnectReason::Replaced => "replaced",
+            DisconnectReason::Kick => "kick",
+            DisconnectReason::Ban => "ban",
+            DisconnectReason::Flooding => "flood",
+            DisconnectReason::Idle => "idle",
+            DisconnectReason::Expired => "expired",
+            DisconnectReason::Quit => "quit",
+            DisconnectReason::Closed | DisconnectReason::Error | DisconnectReason::Stalled => {
+                "error"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Disconnect {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub connected_for: Duration,
+    pub reason: DisconnectReason,
+}
+
+// how many disconnects are remembered
+pub const RECENT_DISCONNECTS: usize = 50;
+// how many names /seen remembers
+const MAX_LAST_SEEN: usize = 10_000;
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut registry = self.shared.registry();
+        let Some(client) = registry.clients.remove(&self.id) else {
+            return;
+        };
+        if !client.ignored.is_empty() {
+            registry.ignoring -= 1;
+        }
+        let promoted: Vec<_> = client
+            .rooms
+            .iter()
+            .filter_map(|room| Some((room, registry.leave(self.id, room)?)))
+            .collect();
+        // /quitall forgets everyone, those it disconnects included
+        if self.reason != DisconnectReason::Drain {
+            registry.saw(&client.name);
+        }
+        if registry.recent.len() == RECENT_DISCONNECTS {
+            registry.recent.pop_front();
+        }
+        registry.recent.push_back(Disconnect {
+            name: client.name.clone(),
+            addr: client.addr,
+            connected_for: client.connected_at.elapsed(),
+            reason: self.reason,
+        });
+        let window = self.shared.config.resume_window;
+        if !window.is_zero() && self.resumable {
+            let last_seq = registry.last_seq;
+            registry.resumes.insert(
+                client.resume_token,
+                PendingResume {
+                    name: client.name.clone(),
+                    role: client.role,
+                    room: client.room.clone(),
+                    rooms: client.rooms.clone(),
+                    ignored: client.ignored.clone(),
+                    aliases: client.aliases.clone(),
+                    message_ids: client.message_ids.clone(),
+                    last_seq,
+                    expires_at: Instant::now() + window,
+                },
+            );
+        }
+        drop(registry);
+        for room in &client.rooms {
+            self.shared
+                .announce(self.id, room, &client.name, Presence::Left);
+        }
+        for (room, name) in promoted {
+            self.shared.promoted(room, &name);
+        }
+        self.shared.emit(|| ServerEvent::Disconnected {
+            id: self.id,
+            name: client.name,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_seen_forgets_the_oldest_name_when_full() {
+        let (opened, _rooms) = mpsc::unbounded_channel();
+        let mut registry = Registry::new(&Config::default(), opened);
+        // a millisecond apart, user0 the longest ago
+        let now = Instant::now();
+        for n in 0..MAX_LAST_SEEN {
+            let ago = Duration::from_millis((MAX_LAST_SEEN - n) as u64);
+            registry.last_seen.insert(format!("user{n}"), now - ago);
+        }
+        // seeing a name again doesn't push anyone out
+        registry.saw("user0");
+        assert_eq!(registry.last_seen.len(), MAX_LAST_SEEN);
+        registry.saw("newcomer");
+        assert_eq!(registry.last_seen.len(), MAX_LAST_SEEN);
+        assert!(registry.last_seen.contains_key("newcomer"));
+        assert!(registry.last_seen.contains_key("user0"));
+        assert!(!registry.last_seen.contains_key("user1"));
+    }
+
+    #[test]
+    fn names_up_to_the_limit_are_fine() {
+        assert_eq!(validate_name("alice", 32), Ok(()));
+        assert_eq!(validate_name("a", 1), Ok(()));
+        assert_eq!(validate_name(&"x".repeat(32), 32), Ok(()));
+        // the limit counts characters, not bytes
+        assert_eq!(validate_name("zoë_ünal", 8), Ok(()));
+        assert_eq!(validate_name("alice[away]", 32), Ok(()));
+        assert_eq!(validate_name("用户", 32), Ok(()));
+    }
+
+    #[test]
+    fn turns_down_bad_names_with_the_reason() {
+        let cases = [
+            ("", "Name can't be empty"),
+            ("al ice", "Name can't contain spaces"),
+            ("al\tice", "Name can't contain spaces"),
+            ("/quit", "Name can't start with /"),
+            ("#general", "Name can't start with #, that's for rooms"),
+            ("ali\u{7}ce", "Name can't contain \\u{7}"),
+            ("ali\u{200b}ce", "Name can't contain \\u{200b}"),
+            ("\u{202e}ecila", "Name can't contain \\u{202e}"),
+        ];
+        assert_eq!(
+            validate_name("abcdef", 5),
+            Err("Name can't be longer than 5 characters".to_string())
+        );
+        for (name, reason) in cases {
+            assert_eq!(validate_name(name, 32), Err(reason.to_string()), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn room_names_at_the_boundaries() {
+        assert_eq!(validate_room_name("#a"), Ok(()));
+        assert_eq!(validate_room_name("#dev-ops_2"), Ok(()));
+        let longest = format!("#{}", "r".repeat(MAX_ROOM_NAME_CHARS));
+        assert_eq!(validate_room_name(&longest), Ok(()));
+        let too_long = format!("#{}", "r".repeat(MAX_ROOM_NAME_CHARS + 1));
+        assert_eq!(
+            validate_room_name(&too_long),
+            Err("Room name can't be longer than 30 characters after the #".to_string())
+        );
+    }
+
+    #[test]
+    fn turns_down_bad_room_names_with_the_reason() {
+        let only = ", only letters, digits, - and _";
+        let cases = [
+            ("general", "Room names start with #".to_string()),
+            ("", "Room names start with #".to_string()),
+            ("#", "Room name can't be just #".to_string()),
+            ("#dev ops", format!("Room name can't contain ' '{only}")),
+            ("##dev", format!("Room name can't contain '#'{only}")),
+            ("#dév", format!("Room name can't contain 'é'{only}")),
+            ("#dev\u{0}", format!("Room name can't contain '\\0'{only}")),
+        ];
+        for (room, reason) in cases {
+            assert_eq!(validate_room_name(room), Err(reason), "{room:?}");
+        }
+    }
+}
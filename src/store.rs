@@ -0,0 +1,551 @@
+// keeps every chat message somewhere that outlives the in-memory history, behind a trait so an
+// embedding program can put them wherever it likes. like the webhook the broadcaster only drops
+// messages into a bounded queue, a separate task hands them to the store one at a time, in order.
+// expiring old messages for --history-ttl goes through the same queue, so it never races an
+// append, and so does reading back a room's latest messages when it opens
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot,
+    },
+};
+
+use crate::{
+    json::{self, Value},
+    room::HistoryEntry,
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+// messages waiting to be stored, past this new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMessage {
+    pub seq: u64,
+    pub room: String,
+    pub from: String,
+    pub text: String,
+    pub bot: bool,
+    pub reply_to: Option<u64>,
+    // milliseconds since the epoch when the message was sent
+    pub ts: u64,
+}
+
+impl StoredMessage {
+    fn from_entry(entry: &HistoryEntry) -> StoredMessage {
+        StoredMessage {
+            seq: entry.seq,
+            room: entry.room.to_string(),
+            from: entry.from.clone(),
+            text: entry.text.clone(),
+            bot: entry.bot,
+            reply_to: entry.reply_to,
+            ts: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut value = Value::object([
+            ("seq", self.seq.into()),
+            ("room", self.room.as_str().into()),
+            ("sender", self.from.as_str().into()),
+            ("body", self.text.as_str().into()),
+            ("bot", self.bot.into()),
+            ("ts", self.ts.into()),
+        ]);
+        if let (Some(parent), Value::Object(fields)) = (self.reply_to, &mut value) {
+            fields.push(("reply_to".to_string(), parent.into()));
+        }
+        value
+    }
+
+    fn from_json(value: &Value) -> Option<StoredMessage> {
+        Some(StoredMessage {
+            seq: value.get("seq")?.as_u64()?,
+            room: value.get("room")?.as_str()?.to_string(),
+            from: value.get("sender")?.as_str()?.to_string(),
+            text: value.get("body")?.as_str()?.to_string(),
+            bot: matches!(value.get("bot"), Some(Value::Bool(true))),
+            reply_to: value.get("reply_to").and_then(Value::as_u64),
+            ts: value.get("ts")?.as_u64()?,
+        })
+    }
+}
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+// the server appends every message and asks for a room's recent ones when the room opens, to
+// fill its history. search is there for whoever embeds it. both answer oldest first with at
+// most count messages, the newest ones when there are more
+pub trait MessageStore: Send + Sync {
+    fn append<'a>(&'a self, message: &'a StoredMessage) -> StoreFuture<'a, ()>;
+    fn recent<'a>(&'a self, room: &'a str, count: usize) -> StoreFuture<'a, Vec<StoredMessage>>;
+    // messages in the room containing the text, ignoring case
+    fn search<'a>(
+        &'a self,
+        room: &'a str,
+        text: &'a str,
+        count: usize,
+    ) -> StoreFuture<'a, Vec<StoredMessage>>;
+    // forgets every message sent before the time, in milliseconds since the epoch like ts,
+    // and answers how many went
+    fn expire<'a>(&'a self, before: u64) -> StoreFuture<'a, usize>;
+}
+
+// so an embedder can keep a handle on the store it gave the server
+impl<T: MessageStore + ?Sized> MessageStore for Arc<T> {
+    fn append<'a>(&'a self, message: &'a StoredMessage) -> StoreFuture<'a, ()> {
+        (**self).append(message)
+    }
+
+    fn recent<'a>(&'a self, room: &'a str, count: usize) -> StoreFuture<'a, Vec<StoredMessage>> {
+        (**self).recent(room, count)
+    }
+
+    fn search<'a>(
+        &'a self,
+        room: &'a str,
+        text: &'a str,
+        count: usize,
+    ) -> StoreFuture<'a, Vec<StoredMessage>> {
+        (**self).search(room, text, count)
+    }
+
+    fn expire<'a>(&'a self, before: u64) -> StoreFuture<'a, usize> {
+        (**self).expire(before)
+    }
+}
+
+fn matches(message: &StoredMessage, text: &str) -> bool {
+    message.text.to_lowercase().contains(&text.to_lowercase())
+}
+
+// keeps the count newest, in the order they came
+fn newest(messages: impl Iterator<Item = StoredMessage>, count: usize) -> Vec<StoredMessage> {
+    let mut kept = VecDeque::new();
+    for message in messages {
+        if kept.len() == count {
+            kept.pop_front();
+        }
+        if count > 0 {
+            kept.push_back(message);
+        }
+    }
+    kept.into()
+}
+
+// gone with the process, but keeps far more than the history does
+#[derive(Debug)]
+pub struct InMemoryStore {
+    per_room: usize,
+    rooms: Mutex<HashMap<String, VecDeque<StoredMessage>>>,
+}
+
+impl InMemoryStore {
+    // per_room is how many messages each room keeps, the oldest go first
+    pub fn new(per_room: usize) -> InMemoryStore {
+        InMemoryStore {
+            per_room,
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn room(&self, room: &str) -> Vec<StoredMessage> {
+        self.rooms
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(room)
+            .map(|messages| messages.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl MessageStore for InMemoryStore {
+    fn append<'a>(&'a self, message: &'a StoredMessage) -> StoreFuture<'a, ()> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let messages = rooms.entry(message.room.clone()).or_default();
+        messages.push_back(message.clone());
+        while messages.len() > self.per_room {
+            messages.pop_front();
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn recent<'a>(&'a self, room: &'a str, count: usize) -> StoreFuture<'a, Vec<StoredMessage>> {
+        let messages = newest(self.room(room).into_iter(), count);
+        Box::pin(async { Ok(messages) })
+    }
+
+    fn search<'a>(
+        &'a self,
+        room: &'a str,
+        text: &'a str,
+        count: usize,
+    ) -> StoreFuture<'a, Vec<StoredMessage>> {
+        let found = self.room(room).into_iter().filter(|m| matches(m, text));
+        let messages = newest(found, count);
+        Box::pin(async { Ok(messages) })
+    }
+
+    fn expire<'a>(&'a self, before: u64) -> StoreFuture<'a, usize> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut expired = 0;
+        for messages in rooms.values_mut() {
+            let kept = messages.len();
+            messages.retain(|message| message.ts >= before);
+            expired += kept - messages.len();
+        }
+        rooms.retain(|_, messages| !messages.is_empty());
+        Box::pin(async move { Ok(expired) })
+    }
+}
+
+// one json object per line, the same fields the webhook posts plus the sequence number. reads go
+// through the whole file, which is fine for a transcript and not much more
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<PathBuf>) -> FileStore {
+        FileStore { path: path.into() }
+    }
+
+    // lines that don't parse are skipped, a crash can leave half of one at the end
+    async fn room(&self, room: &str) -> io::Result<Vec<StoredMessage>> {
+        let contents = match fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(contents
+            .lines()
+            .filter_map(|line| json::parse(line).ok())
+            .filter_map(|value| StoredMessage::from_json(&value))
+            .filter(|message| message.room == room)
+            .collect())
+    }
+}
+
+impl MessageStore for FileStore {
+    fn append<'a>(&'a self, message: &'a StoredMessage) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(format!("{}\n", message.to_json()).as_bytes())
+                .await
+        })
+    }
+
+    fn recent<'a>(&'a self, room: &'a str, count: usize) -> StoreFuture<'a, Vec<StoredMessage>> {
+        Box::pin(async move { Ok(newest(self.room(room).await?.into_iter(), count)) })
+    }
+
+    fn search<'a>(
+        &'a self,
+        room: &'a str,
+        text: &'a str,
+        count: usize,
+    ) -> StoreFuture<'a, Vec<StoredMessage>> {
+        Box::pin(async move {
+            let found = self
+                .room(room)
+                .await?
+                .into_iter()
+                .filter(|m| matches(m, text));
+            Ok(newest(found, count))
+        })
+    }
+
+    // rewrites the file without them, through a temporary file so a crash halfway leaves the
+    // old one in place
+    fn expire<'a>(&'a self, before: u64) -> StoreFuture<'a, usize> {
+        Box::pin(async move {
+            let contents = match fs::read_to_string(&self.path).await {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+                Err(err) => return Err(err),
+            };
+            let mut kept = String::with_capacity(contents.len());
+            let mut expired = 0;
+            for line in contents.lines() {
+                let message = json::parse(line)
+                    .ok()
+                    .and_then(|value| StoredMessage::from_json(&value));
+                match message {
+                    Some(message) if message.ts < before => expired += 1,
+                    // half a line from a crash goes as well
+                    None => {}
+                    Some(_) => {
+                        kept.push_str(line);
+                        kept.push('\n');
+                    }
+                }
+            }
+            if expired == 0 {
+                return Ok(0);
+            }
+            let mut temp = self.path.as_os_str().to_owned();
+            temp.push(".tmp");
+            fs::write(&temp, kept).await?;
+            fs::rename(&temp, &self.path).await?;
+            Ok(expired)
+        })
+    }
+}
+
+// what the task feeding the store is asked to do, in the order it was asked
+enum Job {
+    Append(StoredMessage),
+    // milliseconds since the epoch, see MessageStore::expire
+    Expire(u64),
+    Recent {
+        room: String,
+        count: usize,
+        reply: oneshot::Sender<Vec<StoredMessage>>,
+    },
+}
+
+pub struct Archive {
+    tx: mpsc::Sender<Job>,
+    // set while the queue is full, so a stuck store logs once instead of once per message
+    dropping: AtomicBool,
+}
+
+impl Archive {
+    // starts the task feeding the store, so this has to be called from inside the runtime
+    pub fn spawn(store: Arc<dyn MessageStore>) -> Archive {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(append_all(store, rx));
+        Archive {
+            tx,
+            dropping: AtomicBool::new(false),
+        }
+    }
+
+    // never waits, it is called by the broadcaster with the registry locked
+    pub fn keep(&self, entry: &HistoryEntry) {
+        match self
+            .tx
+            .try_send(Job::Append(StoredMessage::from_entry(entry)))
+        {
+            Ok(()) => self.dropping.store(false, Ordering::Relaxed),
+            Err(TrySendError::Full(_)) => {
+                if !self.dropping.swap(true, Ordering::Relaxed) {
+                    eprintln!("message store is falling behind, dropping messages");
+                }
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    // a full queue skips this round, the next sweep catches up
+    pub fn expire(&self, before: u64) {
+        let _ = self.tx.try_send(Job::Expire(before));
+    }
+
+    // the room's latest messages, read after every append queued before the call. never waits
+    // either, a full queue or a failing store answers with nothing
+    pub fn recent(&self, room: &str, count: usize) -> oneshot::Receiver<Vec<StoredMessage>> {
+        let (reply, rx) = oneshot::channel();
+        let _ = self.tx.try_send(Job::Recent {
+            room: room.to_string(),
+            count,
+            reply,
+        });
+        rx
+    }
+}
+
+// ends once the archive is dropped, which is when another store replaces this one
+async fn append_all(store: Arc<dyn MessageStore>, mut rx: mpsc::Receiver<Job>) {
+    // logged once per outage, like the snapshot file
+    let mut failing = false;
+    while let Some(job) = rx.recv().await {
+        match job {
+            Job::Append(message) => match store.append(&message).await {
+                Ok(()) => failing = false,
+                Err(err) if !failing => {
+                    eprintln!("can't store message {}: {err}", message.seq);
+                    failing = true;
+                }
+                Err(_) => {}
+            },
+            Job::Expire(before) => {
+                if let Err(err) = store.expire(before).await {
+                    eprintln!("can't expire old messages from the store: {err}");
+                }
+            }
+            Job::Recent { room, count, reply } => match store.recent(&room, count).await {
+                Ok(messages) => {
+                    let _ = reply.send(messages);
+                }
+                Err(err) => eprintln!("can't read {room} back from the store: {err}"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64, room: &str, text: &str, ts: u64) -> StoredMessage {
+        StoredMessage {
+            seq,
+            room: room.to_string(),
+            from: "alice".to_string(),
+            text: text.to_string(),
+            bot: false,
+            reply_to: None,
+            ts,
+        }
+    }
+
+    fn seqs(messages: &[StoredMessage]) -> Vec<u64> {
+        messages.iter().map(|message| message.seq).collect()
+    }
+
+    // a file of its own per test, so they can run side by side
+    fn temp_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("chat-store-{}-{name}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn newest_keeps_the_last_ones_in_order() {
+        let all = (1..=5).map(|seq| message(seq, "#general", "x", seq));
+        assert_eq!(seqs(&newest(all.clone(), 2)), [4, 5]);
+        assert_eq!(seqs(&newest(all.clone(), 10)), [1, 2, 3, 4, 5]);
+        assert!(newest(all, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_keeps_per_room_at_most() {
+        let store = InMemoryStore::new(3);
+        for seq in 1..=5 {
+            store
+                .append(&message(seq, "#general", "x", seq))
+                .await
+                .unwrap();
+        }
+        store.append(&message(6, "#other", "x", 6)).await.unwrap();
+        assert_eq!(
+            seqs(&store.recent("#general", 10).await.unwrap()),
+            [3, 4, 5]
+        );
+        assert_eq!(seqs(&store.recent("#general", 1).await.unwrap()), [5]);
+        assert_eq!(seqs(&store.recent("#other", 10).await.unwrap()), [6]);
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_searches_and_expires() {
+        let store = InMemoryStore::new(10);
+        for (seq, text) in [(1, "Hello there"), (2, "bye"), (3, "HELLO again")] {
+            store
+                .append(&message(seq, "#general", text, seq * 10))
+                .await
+                .unwrap();
+        }
+        let found = store.search("#general", "hello", 10).await.unwrap();
+        assert_eq!(seqs(&found), [1, 3]);
+        assert_eq!(store.expire(25).await.unwrap(), 2);
+        assert_eq!(seqs(&store.recent("#general", 10).await.unwrap()), [3]);
+        assert_eq!(store.expire(100).await.unwrap(), 1);
+        assert!(store.recent("#general", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn file_store_round_trips_every_field() {
+        let path = temp_file("round-trip");
+        let store = FileStore::new(&path);
+        let mut reply = message(2, "#general", "a \"quoted\"\nline", 20);
+        reply.bot = true;
+        reply.reply_to = Some(1);
+        store
+            .append(&message(1, "#general", "hello", 10))
+            .await
+            .unwrap();
+        store.append(&reply).await.unwrap();
+        store.append(&message(3, "#other", "x", 30)).await.unwrap();
+        let recent = FileStore::new(&path).recent("#general", 10).await.unwrap();
+        assert_eq!(recent, vec![message(1, "#general", "hello", 10), reply]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn file_store_skips_half_a_line() {
+        let path = temp_file("half-line");
+        let store = FileStore::new(&path);
+        for (seq, ts) in [(1, 10), (2, 30)] {
+            store
+                .append(&message(seq, "#general", "x", ts))
+                .await
+                .unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).await.unwrap();
+        file.write_all(b"{\"seq\":3,\"room\":\"#gen").await.unwrap();
+        assert_eq!(seqs(&store.recent("#general", 10).await.unwrap()), [1, 2]);
+        // expiring rewrites the file without it
+        assert_eq!(store.expire(20).await.unwrap(), 1);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert_eq!(seqs(&store.recent("#general", 10).await.unwrap()), [2]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn file_store_without_a_file_is_empty() {
+        let store = FileStore::new(temp_file("missing"));
+        assert!(store.recent("#general", 10).await.unwrap().is_empty());
+        assert_eq!(store.expire(u64::MAX).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn archive_reads_back_after_the_queued_appends() {
+        let store = Arc::new(InMemoryStore::new(10));
+        let archive = Archive::spawn(store.clone());
+        for seq in 1..=3 {
+            let queued = archive
+                .tx
+                .try_send(Job::Append(message(seq, "#general", "x", seq)));
+            assert!(queued.is_ok());
+        }
+        let recent = archive.recent("#general", 2).await.unwrap();
+        assert_eq!(seqs(&recent), [2, 3]);
+    }
+}
@@ -0,0 +1,246 @@
+// messages in a sqlite database, a row each, so a restart can pick up a room's latest messages
+// without reading through everything like the FileStore does. rusqlite blocks, so every call
+// runs on the blocking pool with the connection behind a mutex
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{params, Connection, Row};
+use tokio::task;
+
+use super::{matches, MessageStore, StoreFuture, StoredMessage};
+
+// rows come back in the order they went in, sequence numbers start over with every run so they
+// can't be used for that
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        id INTEGER PRIMARY KEY,
+        seq INTEGER NOT NULL,
+        room TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        body TEXT NOT NULL,
+        bot INTEGER NOT NULL,
+        reply_to INTEGER,
+        ts INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_room ON messages (room, id);
+    CREATE INDEX IF NOT EXISTS messages_ts ON messages (ts);
+";
+
+const COLUMNS: &str = "seq, room, sender, body, bot, reply_to, ts";
+
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    // creates the database and its table if they aren't there yet
+    pub fn open(path: impl AsRef<Path>) -> io::Result<SqliteStore> {
+        SqliteStore::init(Connection::open(path).map_err(io::Error::other)?)
+    }
+
+    // gone with the process, like the InMemoryStore but going through the same queries
+    pub fn in_memory() -> io::Result<SqliteStore> {
+        SqliteStore::init(Connection::open_in_memory().map_err(io::Error::other)?)
+    }
+
+    fn init(db: Connection) -> io::Result<SqliteStore> {
+        db.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        Ok(SqliteStore {
+            db: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    async fn with<T: Send + 'static>(
+        &self,
+        query: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let db = self.db.clone();
+        task::spawn_blocking(move || {
+            let db = db.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            query(&db).map_err(io::Error::other)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+fn message(row: &Row) -> rusqlite::Result<StoredMessage> {
+    Ok(StoredMessage {
+        seq: row.get::<_, i64>(0)? as u64,
+        room: row.get(1)?,
+        from: row.get(2)?,
+        text: row.get(3)?,
+        bot: row.get(4)?,
+        reply_to: row.get::<_, Option<i64>>(5)?.map(|seq| seq as u64),
+        ts: row.get::<_, i64>(6)? as u64,
+    })
+}
+
+impl MessageStore for SqliteStore {
+    fn append<'a>(&'a self, message: &'a StoredMessage) -> StoreFuture<'a, ()> {
+        let message = message.clone();
+        Box::pin(self.with(move |db| {
+            db.execute(
+                &format!("INSERT INTO messages ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"),
+                params![
+                    message.seq as i64,
+                    message.room,
+                    message.from,
+                    message.text,
+                    message.bot,
+                    message.reply_to.map(|seq| seq as i64),
+                    message.ts as i64,
+                ],
+            )?;
+            Ok(())
+        }))
+    }
+
+    fn recent<'a>(&'a self, room: &'a str, count: usize) -> StoreFuture<'a, Vec<StoredMessage>> {
+        let room = room.to_string();
+        Box::pin(self.with(move |db| {
+            let mut newest_first = db.prepare(&format!(
+                "SELECT {COLUMNS} FROM messages WHERE room = ?1 ORDER BY id DESC LIMIT ?2"
+            ))?;
+            let mut messages = newest_first
+                .query_map(params![room, count as i64], message)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            messages.reverse();
+            Ok(messages)
+        }))
+    }
+
+    // the matching is done here rather than in sql, so case is ignored the same way as in the
+    // other stores, beyond ascii too
+    fn search<'a>(
+        &'a self,
+        room: &'a str,
+        text: &'a str,
+        count: usize,
+    ) -> StoreFuture<'a, Vec<StoredMessage>> {
+        let (room, text) = (room.to_string(), text.to_string());
+        Box::pin(self.with(move |db| {
+            let mut newest_first = db.prepare(&format!(
+                "SELECT {COLUMNS} FROM messages WHERE room = ?1 ORDER BY id DESC"
+            ))?;
+            let mut found = Vec::new();
+            for message in newest_first.query_map(params![room], message)? {
+                if found.len() == count {
+                    break;
+                }
+                let message = message?;
+                if matches(&message, &text) {
+                    found.push(message);
+                }
+            }
+            found.reverse();
+            Ok(found)
+        }))
+    }
+
+    fn expire<'a>(&'a self, before: u64) -> StoreFuture<'a, usize> {
+        Box::pin(self.with(move |db| {
+            db.execute("DELETE FROM messages WHERE ts < ?1", params![before as i64])
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64, room: &str, text: &str, ts: u64) -> StoredMessage {
+        StoredMessage {
+            seq,
+            room: room.to_string(),
+            from: "alice".to_string(),
+            text: text.to_string(),
+            bot: false,
+            reply_to: None,
+            ts,
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_back_what_was_appended() {
+        let store = SqliteStore::in_memory().unwrap();
+        let mut reply = message(2, "#general", "hi alice", 20);
+        reply.reply_to = Some(1);
+        reply.bot = true;
+        for stored in [message(1, "#general", "hello", 10), reply.clone()] {
+            store.append(&stored).await.unwrap();
+        }
+        let recent = store.recent("#general", 10).await.unwrap();
+        assert_eq!(recent, vec![message(1, "#general", "hello", 10), reply]);
+    }
+
+    #[tokio::test]
+    async fn recent_is_the_newest_oldest_first() {
+        let store = SqliteStore::in_memory().unwrap();
+        for seq in 1..=5 {
+            store
+                .append(&message(seq, "#general", "x", seq))
+                .await
+                .unwrap();
+        }
+        store.append(&message(6, "#other", "x", 6)).await.unwrap();
+        let seqs: Vec<u64> = store
+            .recent("#general", 3)
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.seq)
+            .collect();
+        assert_eq!(seqs, [3, 4, 5]);
+        assert!(store.recent("#nowhere", 3).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_ignores_case_beyond_ascii() {
+        let store = SqliteStore::in_memory().unwrap();
+        for (seq, text) in [(1, "Ärger im Büro"), (2, "nothing"), (3, "mehr ärger")] {
+            store
+                .append(&message(seq, "#general", text, seq))
+                .await
+                .unwrap();
+        }
+        let found = store.search("#general", "ÄRGER", 10).await.unwrap();
+        assert_eq!(found.iter().map(|m| m.seq).collect::<Vec<_>>(), [1, 3]);
+        let newest = store.search("#general", "ärger", 1).await.unwrap();
+        assert_eq!(newest.iter().map(|m| m.seq).collect::<Vec<_>>(), [3]);
+    }
+
+    #[tokio::test]
+    async fn expire_forgets_older_messages() {
+        let store = SqliteStore::in_memory().unwrap();
+        for seq in 1..=4 {
+            store
+                .append(&message(seq, "#general", "x", seq * 10))
+                .await
+                .unwrap();
+        }
+        assert_eq!(store.expire(30).await.unwrap(), 2);
+        let recent = store.recent("#general", 10).await.unwrap();
+        assert_eq!(recent.iter().map(|m| m.seq).collect::<Vec<_>>(), [3, 4]);
+    }
+
+    #[tokio::test]
+    async fn messages_outlive_the_connection() {
+        let path = std::env::temp_dir().join(format!("chat-store-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = SqliteStore::open(&path).unwrap();
+        store
+            .append(&message(1, "#general", "kept", 10))
+            .await
+            .unwrap();
+        drop(store);
+        let reopened = SqliteStore::open(&path).unwrap();
+        let recent = reopened.recent("#general", 10).await.unwrap();
+        assert_eq!(recent, vec![message(1, "#general", "kept", 10)]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,105 @@
+// the chat protocol over tls, for --tls-listen. the certificate and key are read when the
+// server is bound, so a bad file stops it before it listens anywhere instead of failing the
+// first client that connects
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem, pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+// how long a client gets to finish the handshake, none of the other timeouts have started yet
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// cert is the chain in pem, the server's own certificate first, and key its private key in pem
+pub fn acceptor(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| failed("cert", cert, err))?;
+    if chain.is_empty() {
+        return Err(failed("cert", cert, pem::Error::NoItemsFound));
+    }
+    let private = PrivateKeyDer::from_pem_file(key).map_err(|err| failed("key", key, err))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, private)
+        .map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to load TLS key at {}: {err}", key.display()),
+            )
+        })?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn failed(what: &str, path: &Path, err: pem::Error) -> io::Error {
+    let kind = match &err {
+        pem::Error::Io(err) => err.kind(),
+        _ => io::ErrorKind::InvalidData,
+    };
+    let reason = match err {
+        pem::Error::NoItemsFound => format!("no {what} in it"),
+        err => err.to_string(),
+    };
+    io::Error::new(
+        kind,
+        format!("failed to load TLS {what} at {}: {reason}", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn loads_a_matching_cert_and_key() {
+        assert!(acceptor(&fixture("localhost.crt"), &fixture("localhost.key")).is_ok());
+    }
+
+    #[test]
+    fn a_missing_cert_names_the_file() {
+        let missing = fixture("missing.crt");
+        let err = acceptor(&missing, &fixture("localhost.key"))
+            .err()
+            .expect("an error");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let expected = format!("failed to load TLS cert at {}: ", missing.display());
+        assert!(err.to_string().starts_with(&expected), "{err}");
+    }
+
+    #[test]
+    fn a_file_without_a_cert_is_turned_down() {
+        // the key file is pem, just not a certificate
+        let key = fixture("localhost.key");
+        let err = acceptor(&key, &key).err().expect("an error");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().ends_with("no cert in it"), "{err}");
+    }
+
+    #[test]
+    fn a_key_for_another_cert_is_turned_down() {
+        let other = fixture("other.key");
+        let err = acceptor(&fixture("localhost.crt"), &other)
+            .err()
+            .expect("an error");
+        let expected = format!("failed to load TLS key at {}: ", other.display());
+        assert!(err.to_string().starts_with(&expected), "{err}");
+    }
+
+    #[test]
+    fn a_cert_as_the_key_is_turned_down() {
+        let cert = fixture("localhost.crt");
+        let err = acceptor(&cert, &cert).err().expect("an error");
+        let expected = format!("failed to load TLS key at {}: ", cert.display());
+        assert!(err.to_string().starts_with(&expected), "{err}");
+    }
+}
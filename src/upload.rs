@@ -0,0 +1,142 @@
+// files shared through the http interface, to keep bulk transfers out of the chat stream. a
+// client posts the bytes to /upload and gets a token back, /share token tells its room, and
+// everyone fetches the file from /files/token. files are kept in a directory of their own
+// until they expire, within a cap on how much space they take altogether
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tokio::fs;
+
+use crate::{config::Config, state::new_token};
+
+// the longest file name kept, it is only ever shown and sent back with the download
+const MAX_NAME_CHARS: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub name: String,
+    pub size: usize,
+    stored_at: Instant,
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    // over --upload-max-bytes
+    TooLarge,
+    // storing it would go over --upload-total-bytes
+    Full,
+    BadName,
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub struct Uploads {
+    dir: PathBuf,
+    pub max_file: usize,
+    max_total: usize,
+    ttl: Duration,
+    // by token. an entry is made before its file is written, so the space is claimed up front
+    files: Mutex<HashMap<String, Upload>>,
+}
+
+impl Uploads {
+    // None when uploads are off
+    pub fn new(config: &Config) -> Option<Uploads> {
+        Some(Uploads {
+            dir: config.upload_dir.clone()?,
+            max_file: config.upload_max_bytes,
+            max_total: config.upload_total_bytes,
+            ttl: config.upload_ttl,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn files(&self) -> MutexGuard<'_, HashMap<String, Upload>> {
+        self.files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // files are named after their token on disk, the name the client gave only goes in the map
+    pub async fn store(&self, name: &str, bytes: &[u8]) -> Result<String, UploadError> {
+        if name.is_empty()
+            || name.chars().count() > MAX_NAME_CHARS
+            || name
+                .chars()
+                .any(|c| c.is_control() || matches!(c, '"' | '/' | '\\'))
+        {
+            return Err(UploadError::BadName);
+        }
+        if bytes.len() > self.max_file {
+            return Err(UploadError::TooLarge);
+        }
+        self.expire().await;
+        let token = new_token();
+        {
+            let mut files = self.files();
+            let used: usize = files.values().map(|upload| upload.size).sum();
+            if used + bytes.len() > self.max_total {
+                return Err(UploadError::Full);
+            }
+            files.insert(
+                token.clone(),
+                Upload {
+                    name: name.to_string(),
+                    size: bytes.len(),
+                    stored_at: Instant::now(),
+                },
+            );
+        }
+        if let Err(err) = fs::write(self.dir.join(&token), bytes).await {
+            self.files().remove(&token);
+            return Err(UploadError::Io(err));
+        }
+        Ok(token)
+    }
+
+    pub fn get(&self, token: &str) -> Option<Upload> {
+        self.files()
+            .get(token)
+            .filter(|upload| upload.stored_at.elapsed() < self.ttl)
+            .cloned()
+    }
+
+    pub async fn read(&self, token: &str) -> Option<(Upload, Vec<u8>)> {
+        let upload = self.get(token)?;
+        let bytes = fs::read(self.dir.join(token)).await.ok()?;
+        Some((upload, bytes))
+    }
+
+    // expired files are cleared out whenever a new one comes in, nothing runs in between
+    async fn expire(&self) {
+        let expired: Vec<String> = {
+            let mut files = self.files();
+            let expired = files
+                .iter()
+                .filter(|(_, upload)| upload.stored_at.elapsed() >= self.ttl)
+                .map(|(token, _)| token.clone())
+                .collect::<Vec<_>>();
+            for token in &expired {
+                files.remove(token);
+            }
+            expired
+        };
+        for token in expired {
+            let _ = fs::remove_file(self.dir.join(token)).await;
+        }
+    }
+}
+
+// for people rather than machines, to one decimal place past a kilobyte
+pub fn size(bytes: usize) -> String {
+    match bytes {
+        0..=1023 => format!("{bytes} bytes"),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
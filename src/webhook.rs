@@ -0,0 +1,253 @@
+// posts every chat message as json to an outside url, for logging, bots and the like. the
+// broadcaster only drops messages into a bounded queue, a separate task does the slow part.
+// there is no tls, so only http:// urls work, put a local proxy in front for anything else
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::{self, error::TrySendError},
+    time::{sleep, timeout},
+};
+
+use crate::{json::Value, room::HistoryEntry};
+
+// messages waiting to be posted, past this new ones are dropped
+const QUEUE_CAPACITY: usize = 256;
+// attempts per message, with a growing pause between them
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+// for connecting, sending and getting the status line back, each attempt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    // an ipv6 address is kept without its brackets
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(format!(
+                "webhook urls have to start with http://, got {url}"
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        // an ipv6 address is in brackets, its colons aren't the port's
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => match port.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(format!("bad port in webhook url {url}")),
+                },
+                None => return Err(format!("unclosed [ in webhook url {url}")),
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("bad port in webhook url {url}"))?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(format!("no host in webhook url {url}"));
+        }
+        Ok(WebhookUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl WebhookUrl {
+    // host:port the way it goes in a url or a host header
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.path)
+    }
+}
+
+pub struct Webhook {
+    tx: mpsc::Sender<Arc<HistoryEntry>>,
+    // set while the queue is full, so an outage logs once instead of once per message
+    dropping: AtomicBool,
+}
+
+impl Webhook {
+    // starts the delivery task, so this has to be called from inside the runtime
+    pub fn spawn(url: WebhookUrl) -> Webhook {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(deliver_all(url, rx));
+        Webhook {
+            tx,
+            dropping: AtomicBool::new(false),
+        }
+    }
+
+    // never waits, it is called by the broadcaster with the registry locked
+    pub fn deliver(&self, entry: &Arc<HistoryEntry>) {
+        match self.tx.try_send(entry.clone()) {
+            Ok(()) => self.dropping.store(false, Ordering::Relaxed),
+            Err(TrySendError::Full(_)) => {
+                if !self.dropping.swap(true, Ordering::Relaxed) {
+                    eprintln!("webhook is falling behind, dropping messages");
+                }
+            }
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+async fn deliver_all(url: WebhookUrl, mut rx: mpsc::Receiver<Arc<HistoryEntry>>) {
+    while let Some(entry) = rx.recv().await {
+        // when the message was sent, however long it waited in the queue or for retries
+        let mut body = Value::object([
+            ("room", (*entry.room).into()),
+            ("sender", entry.from.as_str().into()),
+            ("body", entry.text.as_str().into()),
+            ("bot", entry.bot.into()),
+            ("ts", entry.ts.into()),
+        ]);
+        if let (Some(parent), Value::Object(fields)) = (entry.reply_to, &mut body) {
+            fields.push(("reply_to".to_string(), parent.into()));
+        }
+        let body = body.to_string();
+        let mut attempt = 1;
+        loop {
+            let result = match timeout(REQUEST_TIMEOUT, post(&url, &body)).await {
+                Ok(result) => result,
+                Err(_) => Err("timed out".to_string()),
+            };
+            match result {
+                Ok(()) => break,
+                Err(err) if attempt == ATTEMPTS => {
+                    eprintln!(
+                        "webhook gave up on message {} after {ATTEMPTS} attempts: {err}",
+                        entry.seq
+                    );
+                    break;
+                }
+                Err(_) => {
+                    sleep(RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+// one request per connection, anything but a 2xx answer counts as a failure
+async fn post(url: &WebhookUrl, body: &str) -> Result<(), String> {
+    let mut socket = TcpStream::connect((url.host.as_str(), url.port))
+        .await
+        .map_err(|err| err.to_string())?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        url.path,
+        url.authority(),
+        body.len()
+    );
+    socket
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut status_line = String::new();
+    BufReader::new(socket)
+        .read_line(&mut status_line)
+        .await
+        .map_err(|err| err.to_string())?;
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(status) => Err(format!("answered with {status}")),
+        None => Err("no http status in the answer".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(host: &str, port: u16, path: &str) -> WebhookUrl {
+        WebhookUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_the_host_port_and_path() {
+        assert_eq!(
+            "http://hooks.local:8080/chat/in".parse(),
+            Ok(url("hooks.local", 8080, "/chat/in"))
+        );
+        assert_eq!(
+            "http://hooks.local".parse(),
+            Ok(url("hooks.local", 80, "/"))
+        );
+        assert_eq!("http://10.0.0.1:81".parse(), Ok(url("10.0.0.1", 81, "/")));
+    }
+
+    #[test]
+    fn takes_the_brackets_off_an_ipv6_address() {
+        let parsed: WebhookUrl = "http://[::1]:8080/hook".parse().unwrap();
+        assert_eq!(parsed, url("::1", 8080, "/hook"));
+        // and puts them back wherever it is written out
+        assert_eq!(parsed.to_string(), "http://[::1]:8080/hook");
+        assert_eq!(parsed.authority(), "[::1]:8080");
+        assert_eq!("http://[fe80::2]".parse(), Ok(url("fe80::2", 80, "/")));
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_post_to() {
+        for (bad, why) in [
+            (
+                "https://hooks.local/",
+                "webhook urls have to start with http://",
+            ),
+            ("hooks.local", "webhook urls have to start with http://"),
+            ("http://hooks.local:http/", "bad port"),
+            ("http://hooks.local:99999/", "bad port"),
+            ("http://[::1]8080/", "bad port"),
+            ("http://[::1/", "unclosed ["),
+            ("http:///hook", "no host"),
+            ("http://:8080/", "no host"),
+            ("http://[]:8080/", "no host"),
+        ] {
+            let err = bad.parse::<WebhookUrl>().unwrap_err();
+            assert!(err.starts_with(why), "{bad}: {err}");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+fn delayed(max_clients: usize) -> Config {
+    Config {
+        max_clients,
+        accept_delay: Duration::from_millis(800),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn accepts_slow_down_near_the_cap() {
+    let server = TestServer::start(delayed(2)).await;
+    let (_alice, _) = server.join("alice").await;
+    let (_bob, _) = server.join("bob").await;
+    // two of two open, the listener waits the whole delay before the cap turns carol away
+    let started = Instant::now();
+    let mut carol = server.connect().await;
+    let last = carol.expect_closed().await;
+    assert_eq!(last.as_deref(), Some("Server full, try again later"));
+    assert!(
+        started.elapsed() >= Duration::from_millis(400),
+        "{:?}",
+        started.elapsed()
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn under_half_the_cap_nothing_waits() {
+    let server = TestServer::start(delayed(10)).await;
+    let started = Instant::now();
+    for name in ["alice", "bob", "carol", "dave"] {
+        server.join(name).await;
+    }
+    assert!(
+        started.elapsed() < Duration::from_millis(400),
+        "{:?}",
+        started.elapsed()
+    );
+    server.shutdown().await;
+}
@@ -0,0 +1,58 @@
+// starts the real binary the way a systemd .socket unit would, with the chat socket already
+// listening on descriptor 3
+#![cfg(unix)]
+
+use std::{
+    io::{BufRead, BufReader},
+    net::{TcpListener, TcpStream},
+    os::unix::{io::AsRawFd, process::CommandExt},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+#[test]
+fn serves_on_an_inherited_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rustlang-chat-server"));
+    // the address given is never bound, the inherited socket wins
+    command
+        .args(["--listen", "127.0.0.1:1"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // LISTEN_PID has to be the child's own pid, which is only known once it has forked
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 onto itself leaves close-on-exec set, so that case has to clear it
+            let moved = if fd == 3 {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if moved < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let pid = format!("{}\0", libc::getpid());
+            libc::setenv(c"LISTEN_PID".as_ptr(), pid.as_ptr().cast(), 1);
+            libc::setenv(c"LISTEN_FDS".as_ptr(), c"1".as_ptr(), 1);
+            libc::setenv(c"LISTEN_FDNAMES".as_ptr(), c"chat".as_ptr(), 1);
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().unwrap();
+    drop(listener);
+
+    // the socket was listening before the server started, so connecting can't fail, and the
+    // prompt comes once the server has adopted it
+    let socket = TcpStream::connect(addr).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut prompt = String::new();
+    let read = BufReader::new(socket).read_line(&mut prompt);
+    child.kill().unwrap();
+    child.wait().unwrap();
+    read.unwrap();
+    assert!(prompt.contains("enter your name"), "{prompt:?}");
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+fn with_admin_listener() -> Config {
+    Config {
+        admin_listen: Some("127.0.0.1:0".to_string()),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn the_admin_listener_answers_queries() {
+    let server = TestServer::start(with_admin_listener()).await;
+    let (_alice, _) = server.join("alice").await;
+    let mut admin = server.connect_admin().await;
+    admin.register("root").await;
+
+    admin.send("STATS").await;
+    let stats = admin.expect(r#""connections":2"#).await;
+    assert!(stats.starts_with('{'), "{stats}");
+    admin.send("LIST CLIENTS").await;
+    admin.expect("alice").await;
+    admin.send("LIST NOTHING").await;
+    admin.expect("unknown query: LIST NOTHING").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn an_admin_role_on_the_chat_listener_gets_no_queries() {
+    let server = TestServer::with_admins(with_admin_listener()).await;
+    let (mut admin, _) = server.join("admin").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    // it is only chat from here
+    admin.send("STATS").await;
+    bob.expect("admin: STATS").await;
+    // the role still counts for commands
+    admin.send("/quitall").await;
+    bob.expect_closed().await;
+    server.shutdown().await;
+}
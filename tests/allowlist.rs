@@ -0,0 +1,46 @@
+mod common;
+
+use common::{Client, TestServer};
+use rustlang_chat_server::Config;
+
+fn allowing(ranges: &[&str]) -> Config {
+    Config {
+        allow: ranges.iter().map(|range| range.parse().unwrap()).collect(),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn clients_in_range_get_in() {
+    let server = TestServer::start(allowing(&["127.0.0.0/8"])).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn clients_out_of_range_are_closed_without_a_word() {
+    let server = TestServer::start(allowing(&["127.0.0.2/32", "10.0.0.0/8"])).await;
+    let mut outsider = Client::connect_from(&server.addr, "127.0.0.1")
+        .await
+        .expect("the connection is accepted before it is checked");
+    assert_eq!(outsider.expect_closed().await, None);
+
+    let mut insider = Client::connect_from(&server.addr, "127.0.0.2")
+        .await
+        .unwrap();
+    insider.register("bob").await;
+    assert_eq!(server.server.stats().active_connections, 1);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn no_allowlist_lets_everyone_in() {
+    let server = TestServer::start(Config::default()).await;
+    for (local, name) in [("127.0.0.1", "alice"), ("127.0.0.3", "bob")] {
+        let mut client = Client::connect_from(&server.addr, local).await.unwrap();
+        client.register(name).await;
+    }
+    server.shutdown().await;
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use common::{Client, TestServer};
+use std::time::Duration;
+
+use rustlang_chat_server::{AuthFuture, AuthResult, Authenticator, Config, Role, SessionPolicy};
+
+const ACCOUNTS: [(&str, &str, Role); 3] = [
+    ("alice", "s3cret", Role::User),
+    ("mod", "gavel", Role::Moderator),
+    ("root", "hunter2", Role::Admin),
+];
+
+struct Accounts;
+
+impl Authenticator for Accounts {
+    fn authenticate<'a>(&'a self, name: &'a str, secret: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            match ACCOUNTS.iter().find(|(user, _, _)| *user == name) {
+                Some((_, password, role)) if *password == secret => AuthResult::Allow(*role),
+                _ => AuthResult::Deny("Unknown name or wrong password".to_string()),
+            }
+        })
+    }
+}
+
+// a plain text client sends its secret after its name, gives back the resume token
+async fn sign_in(client: &mut Client, name: &str, secret: &str) -> String {
+    client.send(&format!("{name} {secret}")).await;
+    let welcome = client.expect(&format!("Welcome, {name}!")).await;
+    welcome.rsplit(' ').next().unwrap_or_default().to_string()
+}
+
+async fn with_accounts() -> TestServer {
+    let server = TestServer::start(Config::default()).await;
+    server.server.set_authenticator(Accounts);
+    server
+}
+
+#[tokio::test]
+async fn a_wrong_secret_is_turned_away_and_can_try_again() {
+    let server = with_accounts().await;
+    let mut alice = server.connect().await;
+    alice.send("alice").await;
+    alice
+        .expect("! AUTH_FAILED Unknown name or wrong password")
+        .await;
+    alice.send("alice guess").await;
+    alice
+        .expect("! AUTH_FAILED Unknown name or wrong password")
+        .await;
+    alice.send("alice s3cret").await;
+    alice.expect("Welcome, alice!").await;
+    assert_eq!(server.server.stats().active_connections, 1);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_clients_send_the_secret_in_their_hello() {
+    let server = with_accounts().await;
+    let mut alice = server.connect().await;
+    alice
+        .send(r#"{"type":"hello","name":"alice","secret":"nope"}"#)
+        .await;
+    alice.expect(r#""code":"AUTH_FAILED""#).await;
+    alice
+        .send(r#"{"type":"hello","name":"alice","secret":"s3cret"}"#)
+        .await;
+    alice.expect(r#""type":"welcome""#).await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn roles_decide_which_commands_work() {
+    let server = with_accounts().await;
+    let mut alice = server.connect().await;
+    sign_in(&mut alice, "alice", "s3cret").await;
+    let mut moderator = server.connect().await;
+    sign_in(&mut moderator, "mod", "gavel").await;
+    let mut root = server.connect().await;
+    sign_in(&mut root, "root", "hunter2").await;
+
+    // a user runs nothing but their own rooms
+    alice.send("/slowmode 5").await;
+    alice
+        .expect("! PERMISSION_DENIED You are not an operator of #general")
+        .await;
+    alice.send("/whois mod").await;
+    alice.expect("! PERMISSION_DENIED Permission denied").await;
+    // a moderator is an operator of every room, but no admin
+    moderator.send("/slowmode 5").await;
+    alice
+        .expect("*** slow mode set to one message every 5s by mod ***")
+        .await;
+    moderator.send("/whois alice").await;
+    moderator
+        .expect("! PERMISSION_DENIED Permission denied")
+        .await;
+    // and an admin can do both
+    root.send("/whois mod").await;
+    root.expect("mod (moderator) connected over tcp").await;
+    root.send("/slowmode 0").await;
+    alice.expect("*** slow mode turned off by root ***").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_authenticator_can_be_swapped_while_running() {
+    let server = TestServer::start(Config::default()).await;
+    // nobody is asked for anything to begin with
+    let (_bob, _) = server.join("bob").await;
+    server.server.set_authenticator(Accounts);
+    let mut carol = server.connect().await;
+    carol.send("carol").await;
+    carol
+        .expect("! AUTH_FAILED Unknown name or wrong password")
+        .await;
+    carol.send("alice s3cret").await;
+    carol.expect("Welcome, alice!").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_resume_isnt_asked_again() {
+    let server = with_accounts().await;
+    let mut root = server.connect().await;
+    let token = sign_in(&mut root, "root", "hunter2").await;
+    drop(root);
+    server.until(|s| s.stats().active_connections == 0).await;
+
+    let mut root = server.connect().await;
+    root.send(&format!("RESUME {token}")).await;
+    root.expect("Welcome back, root!").await;
+    // still an admin
+    root.send("/whois root").await;
+    root.expect("root (admin)").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_second_login_is_turned_away_by_default() {
+    let server = with_accounts().await;
+    let mut first = server.connect().await;
+    sign_in(&mut first, "alice", "s3cret").await;
+    let mut second = server.connect().await;
+    second.send("alice s3cret").await;
+    second
+        .expect("! NAME_TAKEN The name alice is taken, please pick another")
+        .await;
+    // the first one never noticed
+    first.send("/echo on").await;
+    first.expect("Echo is on").await;
+    // the second one is still at the prompt and can pick another name
+    assert_eq!(server.server.stats().active_connections, 1);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_replace_policy_signs_the_old_session_out() {
+    let server = with_accounts().await;
+    server.server.set_session_policy(SessionPolicy::Replace);
+    let mut root = server.connect().await;
+    sign_in(&mut root, "root", "hunter2").await;
+    let mut first = server.connect().await;
+    let token = sign_in(&mut first, "alice", "s3cret").await;
+
+    // a wrong secret doesn't get to throw anyone out
+    let mut impostor = server.connect().await;
+    impostor.send("alice guess").await;
+    impostor.expect("! AUTH_FAILED").await;
+    first
+        .expect_nothing("somewhere else", Duration::from_millis(100))
+        .await;
+
+    let mut second = server.connect().await;
+    sign_in(&mut second, "alice", "s3cret").await;
+    assert_eq!(
+        first.expect_closed().await.as_deref(),
+        Some("*** you signed in from somewhere else ***")
+    );
+    root.send("/recent 1").await;
+    let recent = root.expect("Recent disconnects: alice (127.0.0.1, ").await;
+    assert!(recent.ends_with(", replaced)"), "{recent}");
+
+    // and the old session can't be picked up again
+    let mut resumed = server.connect().await;
+    resumed.send(&format!("RESUME {token}")).await;
+    resumed
+        .expect("! INVALID_TOKEN Invalid or expired resume token")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_replace_policy_frees_a_name_kept_for_a_resume() {
+    let server = with_accounts().await;
+    server.server.set_session_policy(SessionPolicy::Replace);
+    let mut first = server.connect().await;
+    let token = sign_in(&mut first, "alice", "s3cret").await;
+    first.finish().await;
+    server.until(|s| s.stats().active_connections == 0).await;
+
+    let mut second = server.connect().await;
+    sign_in(&mut second, "alice", "s3cret").await;
+    let mut resumed = server.connect().await;
+    resumed.send(&format!("RESUME {token}")).await;
+    resumed
+        .expect("! INVALID_TOKEN Invalid or expired resume token")
+        .await;
+    server.shutdown().await;
+}
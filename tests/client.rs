@@ -0,0 +1,65 @@
+// the --connect client of the real binary against an in-process server
+mod common;
+
+use std::process::Stdio;
+
+use common::{within, TestServer};
+use rustlang_chat_server::Config;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, Command},
+};
+
+fn client(addr: &str) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_rustlang-chat-server"))
+        .args(["--connect", addr])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("start the client")
+}
+
+#[tokio::test]
+async fn closing_stdin_leaves_cleanly() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut child = client(&server.addr);
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(b"alice\nhello from the client\n")
+        .await
+        .unwrap();
+    bob.expect("[#general] alice: hello from the client").await;
+    drop(stdin);
+    let status = within(child.wait()).await.unwrap();
+    assert_eq!(status.code(), Some(0));
+    // and the server saw the connection go
+    bob.expect("alice left #general").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn losing_the_server_exits_non_zero() {
+    let server = TestServer::start(Config::default()).await;
+    let mut child = client(&server.addr);
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"alice\n").await.unwrap();
+    server
+        .until(|server| server.stats().active_connections == 1)
+        .await;
+    server.shutdown().await;
+    // stdin stays open, it is the socket closing that has to end it
+    let status = within(child.wait()).await.unwrap();
+    assert_eq!(status.code(), Some(1));
+    let mut stderr = String::new();
+    let mut pipe = child.stderr.take().unwrap();
+    pipe.read_to_string(&mut stderr).await.unwrap();
+    assert!(stderr.contains("connection closed by server"), "{stderr}");
+    let mut stdout = String::new();
+    let mut pipe = child.stdout.take().unwrap();
+    pipe.read_to_string(&mut stdout).await.unwrap();
+    assert!(stdout.contains("the server is shutting down"), "{stdout}");
+    drop(stdin);
+}
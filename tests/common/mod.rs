@@ -0,0 +1,266 @@
+// shared by the integration tests: starts a ChatServer on a free local port and talks to it
+// the way a plain text client would. not every test file uses every helper
+#![allow(dead_code)]
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use rustlang_chat_server::{
+    AuthFuture, AuthResult, Authenticator, ChatServer, Config, Role, ShutdownHandle,
+};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpSocket, TcpStream},
+    time::{sleep, timeout, Instant},
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+// long enough for a loaded ci machine, short enough that a hang fails quickly
+pub const WAIT: Duration = Duration::from_secs(5);
+
+pub struct TestServer {
+    pub server: Arc<ChatServer>,
+    pub addr: String,
+    handle: ShutdownHandle,
+}
+
+impl TestServer {
+    pub async fn start(mut config: Config) -> TestServer {
+        config.listen = "127.0.0.1:0".to_string();
+        let server = Arc::new(
+            ChatServer::bind(config)
+                .await
+                .expect("bind the test server"),
+        );
+        let addr = server.local_addr().unwrap().to_string();
+        let handle = server.shutdown_handle();
+        let running = server.clone();
+        tokio::spawn(async move { running.run().await });
+        TestServer {
+            server,
+            addr,
+            handle,
+        }
+    }
+
+    // names starting with admin get in as admins, everyone else as a user
+    pub async fn with_admins(config: Config) -> TestServer {
+        let server = TestServer::start(config).await;
+        server.server.set_authenticator(Admins);
+        server
+    }
+
+    pub async fn connect(&self) -> Client {
+        Client::connect(&self.addr).await
+    }
+
+    // the config needs an admin_listen address for this, 127.0.0.1:0 will do
+    pub async fn connect_admin(&self) -> Client {
+        let addr = self.server.admin_addr().expect("an admin listener");
+        Client::connect(&addr.to_string()).await
+    }
+
+    // the config needs the tls settings from with_tls for this
+    pub async fn connect_tls(&self) -> Client {
+        let addr = self.server.tls_addr().expect("a tls listener");
+        Client::connect_tls(&addr.to_string()).await
+    }
+
+    // connects and registers, giving back the client and its resume token
+    pub async fn join(&self, name: &str) -> (Client, String) {
+        let mut client = self.connect().await;
+        let token = client.register(name).await;
+        (client, token)
+    }
+
+    pub async fn shutdown(&self) {
+        self.handle.shutdown().await;
+    }
+
+    // waits for the server to catch up with something that happened on another connection
+    pub async fn until(&self, mut done: impl FnMut(&ChatServer) -> bool) {
+        let deadline = Instant::now() + WAIT;
+        while !done(&self.server) {
+            assert!(Instant::now() < deadline, "the server never caught up");
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+// a tls listener on a free port, with the certificate from tests/fixtures. it is made out to
+// localhost and 127.0.0.1 and good for a century
+pub fn with_tls(config: Config) -> Config {
+    Config {
+        tls_listen: Some("127.0.0.1:0".to_string()),
+        tls_cert: Some(fixture("localhost.crt")),
+        tls_key: Some(fixture("localhost.key")),
+        ..config
+    }
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+struct Admins;
+
+impl Authenticator for Admins {
+    fn authenticate<'a>(&'a self, name: &'a str, _secret: &'a str) -> AuthFuture<'a> {
+        let role = if name.starts_with("admin") {
+            Role::Admin
+        } else {
+            Role::User
+        };
+        Box::pin(async move { AuthResult::Allow(role) })
+    }
+}
+
+// boxed halves, so plain and tls clients are the same type
+pub struct Client {
+    reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+}
+
+impl Client {
+    pub async fn connect(addr: &str) -> Client {
+        let socket = TcpStream::connect(addr)
+            .await
+            .expect("connect to the server");
+        let (reader, writer) = socket.into_split();
+        Client::over(reader, writer)
+    }
+
+    // connects from the given local address, any of 127.0.0.0/8 works on linux
+    pub async fn connect_from(addr: &str, local: &str) -> io::Result<Client> {
+        let socket = TcpSocket::new_v4()?;
+        socket.bind(format!("{local}:0").parse().unwrap())?;
+        let socket = socket.connect(addr.parse().unwrap()).await?;
+        let (reader, writer) = socket.into_split();
+        Ok(Client::over(reader, writer))
+    }
+
+    // trusts nothing but the test certificate
+    pub async fn connect_tls(addr: &str) -> Client {
+        let mut roots = RootCertStore::empty();
+        let cert = CertificateDer::from_pem_file(fixture("localhost.crt")).expect("the test cert");
+        roots.add(cert).expect("a usable test cert");
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let socket = TcpStream::connect(addr)
+            .await
+            .expect("connect to the server");
+        let name = ServerName::try_from("localhost").unwrap();
+        let stream = within(TlsConnector::from(Arc::new(config)).connect(name, socket))
+            .await
+            .expect("the tls handshake");
+        let (reader, writer) = io::split(stream);
+        Client::over(reader, writer)
+    }
+
+    fn over(
+        reader: impl AsyncRead + Unpin + Send + 'static,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> Client {
+        Client {
+            reader: BufReader::new(Box::new(reader)),
+            writer: Box::new(writer),
+        }
+    }
+
+    pub async fn send(&mut self, line: &str) {
+        self.send_raw(format!("{line}\n").as_bytes()).await;
+    }
+
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        self.writer
+            .write_all(bytes)
+            .await
+            .expect("write to the server");
+        // tls buffers what is written until it is flushed
+        self.writer.flush().await.expect("write to the server");
+    }
+
+    // stops sending without closing the read side
+    pub async fn finish(&mut self) {
+        self.writer
+            .shutdown()
+            .await
+            .expect("shut down the write side");
+    }
+
+    // the next line without its line ending, None once the server has closed the connection
+    pub async fn line(&mut self) -> Option<String> {
+        let mut line = Vec::new();
+        let n = within(self.reader.read_until(b'\n', &mut line))
+            .await
+            .expect("read from the server");
+        if n == 0 {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&line);
+        Some(text.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    // skips lines until one contains the text and gives that one back
+    pub async fn expect(&mut self, wanted: &str) -> String {
+        loop {
+            match self.line().await {
+                Some(line) if line.contains(wanted) => return line,
+                Some(_) => {}
+                None => panic!("the connection closed before {wanted:?} came"),
+            }
+        }
+    }
+
+    // skips lines until the server closes the connection, giving back the last one
+    pub async fn expect_closed(&mut self) -> Option<String> {
+        let mut last = None;
+        while let Some(line) = self.line().await {
+            last = Some(line);
+        }
+        last
+    }
+
+    // fails if anything containing the text turns up within the time given
+    pub async fn expect_nothing(&mut self, unwanted: &str, quiet: Duration) {
+        let mut line = Vec::new();
+        let deadline = Instant::now() + quiet;
+        loop {
+            line.clear();
+            let left = deadline.saturating_duration_since(Instant::now());
+            match timeout(left, self.reader.read_until(b'\n', &mut line)).await {
+                Err(_) | Ok(Ok(0)) => return,
+                Ok(Ok(_)) => {
+                    let text = String::from_utf8_lossy(&line);
+                    assert!(!text.contains(unwanted), "got {text:?}");
+                }
+                Ok(Err(err)) => panic!("read from the server: {err}"),
+            }
+        }
+    }
+
+    // sends the name and waits for the welcome, giving back the resume token
+    pub async fn register(&mut self, name: &str) -> String {
+        self.send(name).await;
+        let welcome = self.expect(&format!("Welcome, {name}!")).await;
+        // the client is registered by the time it is welcomed
+        welcome.rsplit(' ').next().unwrap_or_default().to_string()
+    }
+}
+
+pub async fn within<T>(work: impl Future<Output = T>) -> T {
+    timeout(WAIT, work).await.expect("timed out")
+}
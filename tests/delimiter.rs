@@ -0,0 +1,106 @@
+mod common;
+
+use common::{within, TestServer};
+use rustlang_chat_server::{Config, Delimiter};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+// the common client only knows about newlines, this one frames on whatever the server was
+// started with and keeps the delimiter on what it reads
+struct Raw {
+    socket: BufReader<TcpStream>,
+    delimiter: &'static [u8],
+}
+
+impl Raw {
+    async fn connect(server: &TestServer, delimiter: Delimiter) -> Raw {
+        let socket = TcpStream::connect(&server.addr).await.unwrap();
+        Raw {
+            socket: BufReader::new(socket),
+            delimiter: delimiter.as_str().as_bytes(),
+        }
+    }
+
+    async fn send(&mut self, message: &str) {
+        let mut bytes = message.as_bytes().to_vec();
+        bytes.extend_from_slice(self.delimiter);
+        self.socket.get_mut().write_all(&bytes).await.unwrap();
+    }
+
+    // skips messages until one contains the text, checking each ends with the delimiter
+    async fn expect(&mut self, wanted: &str) -> String {
+        let last = *self.delimiter.last().unwrap();
+        loop {
+            let mut message = Vec::new();
+            let n = within(self.socket.read_until(last, &mut message))
+                .await
+                .unwrap();
+            assert!(n > 0, "the connection closed before {wanted:?} came");
+            let text = message
+                .strip_suffix(self.delimiter)
+                .unwrap_or_else(|| panic!("{message:?} doesn't end with {:?}", self.delimiter));
+            let text = String::from_utf8(text.to_vec()).unwrap();
+            assert!(!text.contains(['\0', '\r']), "{text:?}");
+            if text.contains(wanted) {
+                return text;
+            }
+        }
+    }
+}
+
+async fn chat_over(delimiter: Delimiter) {
+    let server = TestServer::start(Config {
+        delimiter,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = Raw::connect(&server, delimiter).await;
+    alice.send("alice").await;
+    alice.expect("Welcome, alice!").await;
+    let mut bob = Raw::connect(&server, delimiter).await;
+    bob.send("bob").await;
+    bob.expect("Welcome, bob!").await;
+
+    alice.send("hello bob").await;
+    bob.expect("[#general] alice: hello bob").await;
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn lf_frames_both_ways() {
+    chat_over(Delimiter::Lf).await;
+}
+
+#[tokio::test]
+async fn crlf_frames_both_ways() {
+    chat_over(Delimiter::Crlf).await;
+}
+
+#[tokio::test]
+async fn nul_frames_both_ways() {
+    chat_over(Delimiter::Nul).await;
+}
+
+#[tokio::test]
+async fn a_newline_is_part_of_a_nul_terminated_message() {
+    let server = TestServer::start(Config {
+        delimiter: Delimiter::Nul,
+        ..Config::default()
+    })
+    .await;
+    let mut alice = Raw::connect(&server, Delimiter::Nul).await;
+    alice.send("alice").await;
+    alice.expect("Welcome, alice!").await;
+    let mut bob = Raw::connect(&server, Delimiter::Nul).await;
+    bob.send("bob").await;
+    bob.expect("Welcome, bob!").await;
+
+    alice.send("one\ntwo").await;
+    let got = bob.expect("alice: one").await;
+    assert!(got.ends_with("two"), "{got:?}");
+    server.shutdown().await;
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+const QUIET: Duration = Duration::from_millis(100);
+
+#[tokio::test]
+async fn a_direct_message_reaches_only_its_recipient() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+
+    alice.send("/msg bob just between us").await;
+    alice.expect("[dm to bob] just between us").await;
+    assert_eq!(
+        bob.expect("just between us").await,
+        "[dm] alice: just between us"
+    );
+    carol.expect_nothing("just between us", QUIET).await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn direct_messages_to_nobody_or_a_busy_user_are_refused() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send("/msg zed hello").await;
+    alice.expect("! NO_SUCH_USER No such user: zed").await;
+    bob.send("/dnd on").await;
+    bob.expect("Do not disturb is on").await;
+    alice.send("/msg bob hello").await;
+    alice
+        .expect("! DND bob is not accepting direct messages")
+        .await;
+    bob.expect_nothing("hello", QUIET).await;
+    server.shutdown().await;
+}
+
+// the recipient's writer is looked up when the message is sent, so it follows a resume
+#[tokio::test]
+async fn a_direct_message_finds_the_recipients_new_connection() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (bob, token) = server.join("bob").await;
+    drop(bob);
+    alice.expect("bob left").await;
+
+    let mut bob = server.connect().await;
+    bob.send(&format!("RESUME {token}")).await;
+    bob.expect("Welcome back, bob!").await;
+    alice.send("/msg bob welcome back").await;
+    alice.expect("[dm to bob] welcome back").await;
+    bob.expect("[dm] alice: welcome back").await;
+    server.shutdown().await;
+}
+
+// nothing goes over the broadcast, so a client that has stopped reading doesn't hold it up
+#[tokio::test]
+async fn a_stuck_client_doesnt_hold_up_direct_messages() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (_stuck, _) = server.join("stuck").await;
+    for n in 0..200 {
+        alice.send(&format!("/msg bob number {n}")).await;
+    }
+    bob.expect("[dm] alice: number 199").await;
+    alice.expect("[dm to bob] number 199").await;
+    server.shutdown().await;
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+#[tokio::test]
+async fn quitall_tells_json_clients_the_server_is_draining() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut admin, _) = server.join("admin").await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+    let (mut bob, _) = server.join("bob").await;
+
+    admin.send("/quitall back soon").await;
+    let last = alice.expect_closed().await.unwrap_or_default();
+    assert!(last.contains(r#""type":"disconnect""#), "{last}");
+    assert!(last.contains(r#""reason":"drain""#), "{last}");
+    assert!(last.contains("back soon"), "{last}");
+    assert_eq!(bob.expect_closed().await.as_deref(), Some("back soon"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutting_down_says_so() {
+    let server = TestServer::start(Config::default()).await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+
+    server.shutdown().await;
+    let last = alice.expect_closed().await.unwrap_or_default();
+    assert!(last.contains(r#""reason":"shutdown""#), "{last}");
+}
+
+#[tokio::test]
+async fn quitall_evicts_everyone_and_keeps_accepting() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut admin, _) = server.join("admin").await;
+    let (mut alice, token) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+    alice.send("before maintenance").await;
+    admin.expect("alice: before maintenance").await;
+    // someone still at the name prompt goes too
+    let mut waiting = server.connect().await;
+
+    admin.send("/quitall").await;
+    admin.expect("*** disconnecting 2 clients ***").await;
+    let cleared = "*** the server is being cleared for maintenance, please reconnect later ***";
+    assert_eq!(alice.expect_closed().await.as_deref(), Some(cleared));
+    assert_eq!(bob.expect_closed().await.as_deref(), Some(cleared));
+    assert_eq!(waiting.expect_closed().await.as_deref(), Some(cleared));
+    // only the admin is left, and #dev went with its last member
+    server
+        .until(|s| s.stats().active_connections == 1 && s.stats().rooms == 1)
+        .await;
+
+    // the listener is still up and the names are free again
+    let mut again = server.connect().await;
+    again.send(&format!("RESUME {token}")).await;
+    again
+        .expect("! INVALID_TOKEN Invalid or expired resume token")
+        .await;
+    again.register("alice").await;
+    again.send("/export").await;
+    again
+        .expect("Nothing to export, #general has no history")
+        .await;
+    let (_bob, _) = server.join("bob").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn quitall_clears_every_room_and_forgets_who_was_seen() {
+    let server = TestServer::with_admins(Config {
+        empty_room_grace: Duration::from_secs(60),
+        ..Config::default()
+    })
+    .await;
+    // a json admin, to see the sequence number of what it pins
+    let mut admin = server.connect().await;
+    admin.send(r#"{"type":"hello","name":"admin"}"#).await;
+    admin.expect(r#""type":"welcome""#).await;
+    admin.send("/echo on").await;
+    admin.expect("Echo is on").await;
+    admin.send("/join #ops").await;
+    admin.expect("you joined #ops").await;
+    admin.send("/topic maintenance tonight").await;
+    admin.expect("maintenance tonight").await;
+    admin.send("remember this").await;
+    let frame = admin.expect(r#""body":"remember this""#).await;
+    let seq = frame.split(r#""seq":"#).nth(1).unwrap();
+    let seq = seq.split(',').next().unwrap();
+    admin.send(&format!("/pin {seq}")).await;
+    admin.expect("pinned").await;
+
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    alice.send("/topic the dev room").await;
+    alice.expect("the dev room").await;
+    // #lair is empty, kept around by the grace period
+    alice.send("/join #lair").await;
+    alice.expect("you joined #lair").await;
+    alice.send("/leave #lair").await;
+    alice.expect("#lair").await;
+    alice.send("/quit").await;
+    alice.expect_closed().await;
+    let (mut bob, _) = server.join("bob").await;
+    server.until(|s| s.stats().rooms == 4).await;
+
+    admin.send("/quitall").await;
+    bob.expect_closed().await;
+    server
+        .until(|s| s.stats().active_connections == 1 && s.stats().rooms == 2)
+        .await;
+    admin.send("/topic").await;
+    admin.expect("#ops has no topic").await;
+    admin.send("/pins").await;
+    admin.expect("Nothing is pinned in #ops").await;
+    admin.send("/export").await;
+    admin.expect("Nothing to export, #ops has no history").await;
+    for name in ["alice", "bob"] {
+        admin.send(&format!("/seen {name}")).await;
+        admin
+            .expect(&format!("{name} hasn't been seen since the server started"))
+            .await;
+    }
+
+    // #dev opens again as a new room, run by whoever opens it
+    let (mut carol, _) = server.join("carol").await;
+    carol.send("/join #dev").await;
+    carol.expect("you joined #dev").await;
+    carol.send("/topic").await;
+    carol.expect("#dev has no topic").await;
+    carol.send("/slowmode 5").await;
+    carol.expect("slow mode set").await;
+    server.shutdown().await;
+}
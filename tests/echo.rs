@@ -0,0 +1,38 @@
+mod common;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+fn echo() -> Config {
+    Config {
+        echo: true,
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn each_line_comes_back_to_its_sender_only() {
+    let server = TestServer::start(echo()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    // no name prompt, no rooms, no commands
+    alice.send("hello").await;
+    assert_eq!(alice.line().await.as_deref(), Some("hello"));
+    alice.send("/join #dev").await;
+    assert_eq!(alice.line().await.as_deref(), Some("/join #dev"));
+    bob.send("bob here").await;
+    assert_eq!(bob.line().await.as_deref(), Some("bob here"));
+    alice.finish().await;
+    assert_eq!(alice.expect_closed().await, None);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutting_down_ends_echo_sessions() {
+    let server = TestServer::start(echo()).await;
+    let mut alice = server.connect().await;
+    alice.send("ping").await;
+    assert_eq!(alice.line().await.as_deref(), Some("ping"));
+    server.shutdown().await;
+    alice.expect_closed().await;
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{Client, TestServer};
+use rustlang_chat_server::{Config, Role};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+// a line of the transcript is 2024-03-01 12:34:56 UTC and then the message
+fn message_of(line: &str) -> &str {
+    let (date, rest) = line.split_at(10);
+    let (time, message) = rest[1..].split_at(8);
+    assert!(
+        date.as_bytes()[4] == b'-' && date.as_bytes()[7] == b'-',
+        "{line}"
+    );
+    assert!(
+        time.as_bytes()[2] == b':' && time.as_bytes()[5] == b':',
+        "{line}"
+    );
+    message
+        .strip_prefix(" UTC ")
+        .unwrap_or_else(|| panic!("{line}"))
+}
+
+async fn say(client: &mut Client, server: &TestServer, lines: usize) {
+    for n in 1..=lines {
+        client.send(&format!("line {n}")).await;
+    }
+    let total = lines as u64;
+    server.until(|s| s.stats().messages_total == total).await;
+}
+
+#[tokio::test]
+async fn a_small_room_is_exported_inline() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("hello").await;
+    bob.send("hi alice").await;
+    server.until(|s| s.stats().messages_total == 2).await;
+
+    bob.send("/export #general").await;
+    bob.expect("Transcript of #general, 2 messages:").await;
+    let first = bob.line().await.unwrap();
+    assert_eq!(message_of(&first), "alice: hello");
+    let second = bob.line().await.unwrap();
+    assert_eq!(message_of(&second), "bob: hi alice");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn only_members_export_a_room_unless_they_moderate() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut admin, _) = server.join("admin").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    alice.send("secret plans").await;
+    server.until(|s| s.stats().messages_total == 1).await;
+
+    bob.send("/export #dev").await;
+    bob.expect("! NOT_IN_ROOM You are not in #dev").await;
+    admin.send("/export #dev").await;
+    admin.expect("Transcript of #dev, 1 message:").await;
+    admin.expect("alice: secret plans").await;
+    admin.send("/export #nowhere").await;
+    admin.expect("! NO_SUCH_ROOM No such room: #nowhere").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn export_role_decides_who_can_export() {
+    let server = TestServer::with_admins(Config {
+        export_role: Role::Admin,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut admin, _) = server.join("admin").await;
+    alice.send("/export").await;
+    alice.expect("! PERMISSION_DENIED Permission denied").await;
+    admin.send("/export").await;
+    admin
+        .expect("Nothing to export, #general has no history")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_long_transcript_without_uploads_is_cut_to_the_end() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    say(&mut alice, &server, 55).await;
+    alice.send("/export").await;
+    alice
+        .expect("Transcript of #general, the last 50 of 55 messages:")
+        .await;
+    let first = alice.line().await.unwrap();
+    assert_eq!(message_of(&first), "alice: line 6");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_long_transcript_becomes_a_download() {
+    let dir = std::env::temp_dir().join(format!("chat-export-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server = TestServer::start(Config {
+        upload_dir: Some(dir.clone()),
+        http_listen: Some("127.0.0.1:0".to_string()),
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    say(&mut alice, &server, 55).await;
+    alice.send("/export").await;
+    let reply = alice.expect("Transcript of #general, 55 messages: ").await;
+    let path = reply.split_once("/files/").map(|(_, token)| token).unwrap();
+
+    let http = server.server.http_addr().unwrap();
+    let mut socket = TcpStream::connect(http).await.unwrap();
+    let request = format!("GET /files/{path} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    socket.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    socket.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 55);
+    assert_eq!(message_of(lines[0]), "alice: line 1");
+    assert_eq!(message_of(lines[54]), "alice: line 55");
+    server.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn a_last_line_without_a_newline_is_dropped() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send_raw(b"first\nunfinished").await;
+    drop(alice);
+    bob.expect("alice: first").await;
+    bob.expect("alice left").await;
+    bob.expect_nothing("unfinished", Duration::from_millis(200))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn keep_unterminated_sends_the_last_line() {
+    let config = Config {
+        keep_unterminated: true,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send_raw(b"first\nunfinished").await;
+    drop(alice);
+    bob.expect("alice: first").await;
+    bob.expect("alice: unfinished").await;
+    server.shutdown().await;
+}
+
+// over tcp the end of the stream is the client shutting down its sending half, which ends the
+// session straight away even though the client could still read
+#[tokio::test]
+async fn a_half_closed_connection_is_the_end_of_it() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send("bye").await;
+    alice.finish().await;
+    bob.expect("alice: bye").await;
+    bob.expect("alice left").await;
+    // the server closes its side as well
+    alice.expect_closed().await;
+    server.shutdown().await;
+}
+
+fn partial_timeout(secs: u64) -> Config {
+    Config {
+        partial_timeout: Duration::from_secs(secs),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn a_half_written_message_times_out() {
+    let server = TestServer::start(partial_timeout(1)).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send_raw(b"this never ends").await;
+    assert_eq!(
+        alice.expect_closed().await.as_deref(),
+        Some("Incomplete message timed out")
+    );
+    bob.expect("alice left").await;
+    bob.expect_nothing("this never ends", Duration::from_millis(100))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_half_written_name_times_out_too() {
+    let server = TestServer::start(partial_timeout(1)).await;
+    let mut client = server.connect().await;
+    client.send_raw(b"ali").await;
+    assert_eq!(
+        client.expect_closed().await.as_deref(),
+        Some("Incomplete message timed out")
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_message_that_arrives_in_pieces_in_time_goes_through() {
+    let server = TestServer::start(partial_timeout(1)).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    // the clock runs from the first byte of the message, not from the last one
+    alice.send_raw(b"slow").await;
+    sleep(Duration::from_millis(400)).await;
+    alice.send_raw(b" but").await;
+    sleep(Duration::from_millis(400)).await;
+    alice.send_raw(b" steady\n").await;
+    bob.expect("alice: slow but steady").await;
+    // and starts again with the next message
+    alice.send_raw(b"next").await;
+    sleep(Duration::from_millis(700)).await;
+    alice.send_raw(b" one\n").await;
+    bob.expect("alice: next one").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn an_idle_client_with_nothing_half_written_stays() {
+    let server = TestServer::start(partial_timeout(1)).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    sleep(Duration::from_millis(1500)).await;
+    alice.send("still here").await;
+    bob.expect("alice: still here").await;
+    server.shutdown().await;
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{Client, TestServer, WAIT};
+use rustlang_chat_server::{Config, InMemoryStore, MessageStore};
+use tokio::time::{sleep, Instant};
+
+// the lines /export gives back for the current room, without the header
+async fn exported(client: &mut Client) -> Vec<String> {
+    client.send("/export").await;
+    let header = loop {
+        let line = client.line().await.expect("an answer to /export");
+        if line.starts_with("Nothing to export") {
+            return Vec::new();
+        }
+        if line.starts_with("Transcript of") {
+            break line;
+        }
+    };
+    let count: usize = header
+        .rsplit(", ")
+        .next()
+        .and_then(|rest| rest.split(' ').next())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_else(|| panic!("{header:?}"));
+    let mut lines = Vec::new();
+    for _ in 0..count {
+        let line = client.line().await.expect("an exported message");
+        lines.push(
+            line.split_once(" UTC ")
+                .map_or(line.clone(), |(_, m)| m.to_string()),
+        );
+    }
+    lines
+}
+
+#[tokio::test]
+async fn ops_can_clear_a_rooms_history() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    bob.send("/join #dev").await;
+    alice.expect("bob joined #dev").await;
+    alice.send("spam").await;
+    bob.send("more spam").await;
+    alice.expect("bob: more spam").await;
+    assert_eq!(exported(&mut bob).await, ["alice: spam", "bob: more spam"]);
+
+    bob.send("/clearhistory").await;
+    bob.expect("! PERMISSION_DENIED You are not an operator of #dev")
+        .await;
+    alice.send("/clearhistory").await;
+    bob.expect("*** history cleared by an operator ***").await;
+    assert!(exported(&mut bob).await.is_empty());
+
+    // a new member gets nothing replayed until there's something new
+    let (mut carol, _) = server.join("carol").await;
+    carol.send("/join #dev").await;
+    carol.expect("you joined #dev").await;
+    carol
+        .expect_nothing("spam", Duration::from_millis(100))
+        .await;
+    alice.send("fresh").await;
+    carol.expect("alice: fresh").await;
+    assert_eq!(exported(&mut carol).await, ["alice: fresh"]);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn admins_can_clear_any_room() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut admin, _) = server.join("admin").await;
+    let (mut bob, _) = server.join("bob").await;
+    bob.send("hello").await;
+    admin.expect("bob: hello").await;
+    bob.send("/clearhistory").await;
+    bob.expect("! PERMISSION_DENIED").await;
+    admin.send("/clearhistory").await;
+    bob.expect("*** history cleared by an operator ***").await;
+    assert!(exported(&mut bob).await.is_empty());
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn histlimit_shrinks_what_a_room_replays() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut alice, token) = server.join("alice").await;
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+    alice.send("/join #dev").await;
+    bob.expect("alice joined #dev").await;
+    for n in 1..=4 {
+        bob.send(&format!("old {n}")).await;
+    }
+    alice.expect("bob: old 4").await;
+
+    alice.send("/histlimit 2").await;
+    alice
+        .expect("! PERMISSION_DENIED You are not an operator of #dev")
+        .await;
+    bob.send("/histlimit 2").await;
+    alice
+        .expect("*** #dev now keeps the last 2 messages, set by bob ***")
+        .await;
+    // shrinking drops the oldest straight away
+    assert_eq!(exported(&mut bob).await, ["bob: old 3", "bob: old 4"]);
+
+    drop(alice);
+    bob.expect("alice left").await;
+    for n in 1..=3 {
+        bob.send(&format!("missed {n}")).await;
+    }
+    // messages reach the history on their way to the room, not before bob's next line
+    server.until(|s| s.stats().messages_total == 7).await;
+    let mut alice = server.connect().await;
+    alice.send(&format!("RESUME {token}")).await;
+    alice.expect("Welcome back, alice!").await;
+    // only the last two of what was missed are still there to replay
+    assert_eq!(alice.expect("missed").await, "[#dev] bob: missed 2");
+    assert_eq!(alice.line().await.as_deref(), Some("[#dev] bob: missed 3"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn rooms_can_be_given_their_own_size_in_the_config() {
+    let mut config = Config::default();
+    config.room_history.insert("#general".to_string(), 3);
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    for n in 1..=5 {
+        alice.send(&format!("general {n}")).await;
+    }
+    bob.expect("alice: general 5").await;
+    assert_eq!(
+        exported(&mut bob).await,
+        ["alice: general 3", "alice: general 4", "alice: general 5"]
+    );
+
+    // other rooms keep the global size
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+    for n in 1..=5 {
+        bob.send(&format!("dev {n}")).await;
+    }
+    server.until(|s| s.stats().messages_total == 10).await;
+    assert_eq!(exported(&mut bob).await.len(), 5);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn histlimit_has_a_ceiling() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut admin, _) = server.join("admin").await;
+    admin.send("/histlimit 10001").await;
+    admin
+        .expect("! USAGE History can be at most 10000 messages")
+        .await;
+    admin.send("/histlimit lots").await;
+    admin.expect("! USAGE Usage: /histlimit N").await;
+    admin.send("/histlimit 10000").await;
+    admin
+        .expect("*** #general now keeps the last 10000 messages, set by admin ***")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn history_ttl_purges_old_messages() {
+    let config = Config {
+        history_ttl: Duration::from_secs(1),
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let store = Arc::new(InMemoryStore::new(100));
+    server.server.set_store(Box::new(store.clone()));
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("old").await;
+    bob.expect("alice: old").await;
+    server.until(|s| s.stats().messages_total == 1).await;
+    assert_eq!(exported(&mut bob).await, ["alice: old"]);
+
+    // the store is fed in the background, and the sweep gets to it within a second or so of
+    // it turning a second old
+    let deadline = Instant::now() + WAIT;
+    while store.recent("#general", 10).await.unwrap().is_empty() {
+        assert!(Instant::now() < deadline, "the store was never fed");
+        sleep(Duration::from_millis(10)).await;
+    }
+    while !store.recent("#general", 10).await.unwrap().is_empty() {
+        assert!(Instant::now() < deadline, "the store was never swept");
+        sleep(Duration::from_millis(50)).await;
+    }
+    alice.send("new").await;
+    bob.expect("alice: new").await;
+    server.until(|s| s.stats().messages_total == 2).await;
+    assert_eq!(exported(&mut bob).await, ["alice: new"]);
+    server.shutdown().await;
+}
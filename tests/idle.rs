@@ -0,0 +1,78 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+use tokio::time::{sleep, Instant};
+
+fn idle(timeout: u64, warning: u64) -> Config {
+    Config {
+        idle_timeout: Duration::from_secs(timeout),
+        idle_warning: Duration::from_secs(warning),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn a_quiet_client_is_warned_and_then_dropped() {
+    let server = TestServer::start(idle(2, 1)).await;
+    let started = Instant::now();
+    let (mut alice, _) = server.join("alice").await;
+
+    alice
+        .expect("*** you will be disconnected in 1s due to inactivity ***")
+        .await;
+    let warned = started.elapsed();
+    assert!(warned >= Duration::from_secs(1), "{warned:?}");
+    assert!(warned < Duration::from_secs(2), "{warned:?}");
+    assert_eq!(
+        alice.expect_closed().await.as_deref(),
+        Some("*** disconnected for inactivity ***")
+    );
+    assert!(started.elapsed() >= Duration::from_secs(2));
+    server.until(|s| s.stats().active_connections == 0).await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn activity_puts_off_the_warning_and_the_disconnect() {
+    let server = TestServer::start(idle(2, 1)).await;
+    let (mut alice, _) = server.join("alice").await;
+    for _ in 0..4 {
+        sleep(Duration::from_millis(600)).await;
+        alice.send("/version").await;
+        alice.expect("rustlang-chat-server").await;
+    }
+    // 2.4s in, never warned
+    alice.send("/help").await;
+    let help = alice.expect("Commands:").await;
+    assert!(!help.contains("inactivity"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn activity_after_the_warning_cancels_the_disconnect() {
+    let server = TestServer::start(idle(2, 1)).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.expect("you will be disconnected in 1s").await;
+    alice.send("still here").await;
+    // the disconnect was due a second after the warning, the next warning a second after this
+    alice
+        .expect_nothing("disconnected for inactivity", Duration::from_millis(1500))
+        .await;
+    alice.send("/version").await;
+    alice.expect("rustlang-chat-server").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn no_warning_without_a_lead_time() {
+    let server = TestServer::start(idle(1, 0)).await;
+    let (mut alice, _) = server.join("alice").await;
+    assert_eq!(
+        alice.expect_closed().await.as_deref(),
+        Some("*** disconnected for inactivity ***")
+    );
+    server.shutdown().await;
+}
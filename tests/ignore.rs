@@ -0,0 +1,128 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+const QUIET: Duration = Duration::from_millis(100);
+
+#[tokio::test]
+async fn an_ignored_users_messages_are_not_delivered() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+
+    alice.send("/ignore bob").await;
+    alice
+        .expect("Ignoring bob, their messages won't be shown to you")
+        .await;
+    bob.send("can anyone hear me").await;
+    bob.send("/msg alice psst").await;
+    bob.expect("[dm to alice] psst").await;
+    // everyone else still hears bob
+    carol.expect("bob: can anyone hear me").await;
+    carol.send("I can").await;
+    alice.expect("carol: I can").await;
+    alice.expect_nothing("bob:", QUIET).await;
+
+    alice.send("/unignore bob").await;
+    alice.expect("No longer ignoring bob").await;
+    bob.send("now?").await;
+    alice.expect("[#general] bob: now?").await;
+    bob.send("/msg alice hello again").await;
+    alice.expect("[dm] bob: hello again").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn ignores_lists_who_and_unignore_checks() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+
+    alice.send("/ignores").await;
+    alice.expect("You aren't ignoring anyone").await;
+    alice.send("/ignore zed").await;
+    alice.expect("Ignoring zed").await;
+    alice.send("/ignore bob").await;
+    alice.expect("Ignoring bob").await;
+    alice.send("/ignores").await;
+    alice.expect("Ignoring: bob, zed").await;
+    alice.send("/ignore alice").await;
+    alice.expect("! USAGE You can't ignore yourself").await;
+    alice.send("/unignore carol").await;
+    alice
+        .expect("! NO_SUCH_USER You aren't ignoring carol")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_ignore_list_carries_over_a_resume() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, token) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/ignore bob").await;
+    alice.expect("Ignoring bob").await;
+    drop(alice);
+    bob.expect("alice left").await;
+
+    let mut alice = server.connect().await;
+    alice.send(&format!("RESUME {token}")).await;
+    alice.expect("Welcome back, alice!").await;
+    bob.send("did she come back").await;
+    bob.send("/msg alice hi").await;
+    bob.expect("[dm to alice] hi").await;
+    alice.expect_nothing("bob:", QUIET).await;
+    alice.send("/ignores").await;
+    alice.expect("Ignoring: bob").await;
+    server.shutdown().await;
+}
+
+// once anyone in the room ignores the sender the broadcaster picks out who gets the message,
+// which has to come to what every member would have picked for itself
+#[tokio::test]
+async fn the_rest_of_the_room_hears_a_sender_someone_ignores() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/ignore bob").await;
+    alice.expect("Ignoring bob").await;
+    // joined after the ignore, and talking in another room
+    let (mut carol, _) = server.join("carol").await;
+    carol.send("/join #dev").await;
+    carol.expect("you joined #dev").await;
+    bob.send("/echo on").await;
+    bob.expect("Echo is on").await;
+
+    bob.send("hello all").await;
+    bob.expect("bob: hello all").await;
+    carol.expect("bob: hello all").await;
+    alice.expect_nothing("bob:", QUIET).await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_room_hears_everyone_again_once_the_ignorer_is_gone() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+    alice.send("/ignore bob").await;
+    alice.expect("Ignoring bob").await;
+    alice.send("/quit").await;
+    carol.expect("alice left").await;
+    carol.send("/ignore zed").await;
+    carol.expect("Ignoring zed").await;
+    carol.send("/unignore zed").await;
+    carol.expect("No longer ignoring zed").await;
+
+    bob.send("still here").await;
+    carol.expect("bob: still here").await;
+    carol.send("/ignore bob").await;
+    carol.expect("Ignoring bob").await;
+    bob.send("and now").await;
+    carol.expect_nothing("bob:", QUIET).await;
+    server.shutdown().await;
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+use tokio::time::{sleep, Instant};
+
+fn lifetime(secs: u64) -> Config {
+    Config {
+        max_lifetime: Duration::from_secs(secs),
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn a_connection_is_closed_when_its_lifetime_is_up() {
+    let server = TestServer::start(lifetime(1)).await;
+    let started = Instant::now();
+    let (mut alice, token) = server.join("alice").await;
+    // being busy doesn't put it off
+    alice.send("/version").await;
+    alice.expect("rustlang-chat-server").await;
+
+    assert_eq!(
+        alice.expect_closed().await.as_deref(),
+        Some("Session expired, please reconnect")
+    );
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    // the token went with it, coming back means registering again
+    let mut again = server.connect().await;
+    again.send(&format!("RESUME {token}")).await;
+    again
+        .expect("! INVALID_TOKEN Invalid or expired resume token")
+        .await;
+    again.register("alice").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_lifetime_counts_from_connecting() {
+    let server = TestServer::start(lifetime(1)).await;
+    let mut alice = server.connect().await;
+    sleep(Duration::from_millis(600)).await;
+    let registered = Instant::now();
+    alice.register("alice").await;
+    alice.expect_closed().await;
+    assert!(registered.elapsed() < Duration::from_millis(900));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn without_a_lifetime_connections_stay() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice
+        .expect_nothing("Session expired", Duration::from_millis(1200))
+        .await;
+    alice.send("/version").await;
+    alice.expect("rustlang-chat-server").await;
+    server.shutdown().await;
+}
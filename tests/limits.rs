@@ -0,0 +1,288 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+#[tokio::test]
+async fn an_oversized_plain_line_is_refused_and_the_client_carries_on() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    // 1024 characters of 4 bytes each, 32 for the name and 64 for the command
+    alice.send(&"a".repeat(1024 * 4 + 32 + 64 + 1)).await;
+    alice
+        .expect("! TOO_LARGE Too large, messages can be at most 4192 bytes")
+        .await;
+    alice.send("still here").await;
+    bob.expect("alice: still here").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_long_message_under_the_byte_limit_is_turned_down_by_length() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+
+    alice.send(&"é".repeat(1025)).await;
+    alice
+        .expect("Message too long, at most 1024 characters")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn echo_mode_has_the_same_limit() {
+    let config = Config {
+        echo: true,
+        max_message_len: 10,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let mut client = server.connect().await;
+
+    client.send(&"x".repeat(200)).await;
+    client
+        .expect("! TOO_LARGE Too large, messages can be at most 136 bytes")
+        .await;
+    client.send("short").await;
+    assert_eq!(client.line().await.as_deref(), Some("short"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn an_oversized_json_frame_is_refused_before_parsing() {
+    let config = Config {
+        max_json_bytes: 256,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+
+    let body = "x".repeat(1024 * 1024);
+    alice
+        .send(&format!(r#"{{"type":"message","body":"{body}"}}"#))
+        .await;
+    let error = alice.expect(r#""code":"TOO_LARGE""#).await;
+    assert!(error.contains("at most 256 bytes"), "{error}");
+    alice.send(r#"{"type":"roster"}"#).await;
+    alice.expect("alice").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn truncating_cuts_long_messages_between_characters() {
+    let config = Config {
+        max_message_len: 5,
+        truncate_long_messages: true,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send("héllo wörld").await;
+    assert!(bob.expect("alice: ").await.ends_with("alice: héllo"));
+    alice.send("ab🎉🎉🎉🎉").await;
+    assert!(bob.expect("alice: ").await.ends_with("alice: ab🎉🎉🎉"));
+    alice.send("short").await;
+    assert!(bob.expect("alice: ").await.ends_with("alice: short"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn errors_quote_at_most_a_little_of_the_input() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+
+    alice.send(&format!("/{}", "ü".repeat(500))).await;
+    let error = alice.expect("UNKNOWN_COMMAND").await;
+    assert!(
+        error.ends_with(&format!("Unknown command: /{}...", "ü".repeat(32))),
+        "{error}"
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_new_client_may_paste_a_few_lines_before_the_rate_limit() {
+    let config = Config {
+        rate_limit: 1,
+        rate_burst: 2,
+        rate_grace: Duration::from_secs(60),
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    // four times the burst to begin with
+    for n in 1..=8 {
+        alice.send(&format!("setup {n}")).await;
+    }
+    alice.send("one too many").await;
+    alice.expect("! RATE_LIMITED Too fast: wait").await;
+    bob.expect("alice: setup 8").await;
+    bob.expect_nothing("one too many", Duration::from_millis(100))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn without_a_grace_period_the_normal_burst_applies() {
+    let config = Config {
+        rate_limit: 1,
+        rate_burst: 2,
+        rate_grace: Duration::ZERO,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    for n in 1..=3 {
+        alice.send(&format!("line {n}")).await;
+    }
+    alice.expect("! RATE_LIMITED Too fast: wait").await;
+    bob.expect("alice: line 2").await;
+    bob.expect_nothing("line 3", Duration::from_millis(100))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_message_at_the_limit_goes_through() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    // characters are counted, not bytes
+    let longest = "é".repeat(1024);
+    alice.send(&longest).await;
+    assert_eq!(
+        bob.expect("alice:").await,
+        format!("[#general] alice: {longest}")
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_messages_have_the_same_limit() {
+    let config = Config {
+        max_message_len: 5,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+
+    alice.send(r#"{"type":"message","body":"123456"}"#).await;
+    let error = alice.expect(r#""type":"error""#).await;
+    assert!(error.contains(r#""code":"TOO_LARGE""#), "{error}");
+    assert!(error.contains("at most 5 characters"), "{error}");
+    alice.send(r#"{"type":"message","body":"12345"}"#).await;
+    bob.expect("alice: 12345").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_name_limit_is_its_own() {
+    let config = Config {
+        max_username_len: 5,
+        max_message_len: 0,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let mut client = server.connect().await;
+    client.send("sixsix").await;
+    client
+        .expect("! INVALID_NAME Name can't be longer than 5 characters")
+        .await;
+    client.register("fivéé").await;
+    let (mut bob, _) = server.join("bob").await;
+    // no message limit at all with 0
+    let long = "x".repeat(5000);
+    client.send(&long).await;
+    assert_eq!(
+        bob.expect("fivéé:").await,
+        format!("[#general] fivéé: {long}")
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn going_over_the_frame_limit_hangs_up() {
+    let config = Config {
+        frame_limit: 1,
+        frame_burst: 4,
+        rate_grace: Duration::ZERO,
+        ..Config::default()
+    };
+    let server = TestServer::with_admins(config).await;
+    let (mut admin, _) = server.join("admin").await;
+    let (mut alice, token) = server.join("alice").await;
+    // commands count as much as chat does
+    for line in ["/rooms", "hello", "/rooms", "/pins", "/rooms", "/rooms"] {
+        alice.send(line).await;
+    }
+    admin.expect("alice: hello").await;
+    // the notice is urgent, it can overtake replies that were still queued
+    alice.expect("Too many requests").await;
+    alice.expect_closed().await;
+    server.until(|s| s.stats().active_connections == 1).await;
+    admin.send("/recent 1").await;
+    let recent = admin.expect("Recent disconnects: alice").await;
+    assert!(recent.ends_with(", flooding)"), "{recent}");
+
+    let mut again = server.connect().await;
+    again.send(&format!("RESUME {token}")).await;
+    again
+        .expect("! INVALID_TOKEN Invalid or expired resume token")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_chat_limit_leaves_commands_alone() {
+    let config = Config {
+        rate_limit: 1,
+        rate_burst: 2,
+        rate_grace: Duration::ZERO,
+        frame_limit: 100,
+        frame_burst: 100,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    for n in 1..=3 {
+        alice.send(&format!("line {n}")).await;
+    }
+    alice.expect("! RATE_LIMITED Too fast: wait").await;
+    // told to wait, not thrown out, and commands still work
+    for _ in 0..10 {
+        alice.send("/rooms").await;
+        alice.expect("Rooms, page 1 of 1").await;
+    }
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn without_a_frame_limit_commands_are_not_counted() {
+    let config = Config {
+        rate_grace: Duration::ZERO,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    for _ in 0..100 {
+        alice.send("/rooms").await;
+    }
+    for _ in 0..100 {
+        alice.expect("Rooms, page 1 of 1").await;
+    }
+    server.shutdown().await;
+}
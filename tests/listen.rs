@@ -0,0 +1,46 @@
+mod common;
+
+use std::net::TcpListener;
+
+use common::Client;
+use rustlang_chat_server::{ChatServer, Config};
+
+fn listening_on(listen: String) -> Config {
+    Config {
+        listen,
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn a_range_takes_the_first_free_port() {
+    // held for the length of the test, so the server has to look further
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let first = taken.local_addr().unwrap().port();
+    let last = first.saturating_add(20);
+    let server = ChatServer::bind(listening_on(format!("127.0.0.1:{first}-{last}")))
+        .await
+        .expect("a free port in the range");
+    let port = server.local_addr().unwrap().port();
+    assert!(port > first && port <= last, "{port} in {first}-{last}");
+
+    let handle = server.shutdown_handle();
+    let running = tokio::spawn(async move { server.run().await });
+    let mut alice = Client::connect(&format!("127.0.0.1:{port}")).await;
+    alice.register("alice").await;
+    handle.shutdown().await;
+    running.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn a_range_with_nothing_free_says_so() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let listen = format!("127.0.0.1:{port}-{port}");
+    let err = ChatServer::bind(listening_on(listen.clone()))
+        .await
+        .err()
+        .expect("no free port");
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+    assert_eq!(err.to_string(), format!("every port in {listen} is in use"));
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Client, TestServer};
+use rustlang_chat_server::Config;
+
+fn lobby() -> Config {
+    Config {
+        lobby: true,
+        ..Config::default()
+    }
+}
+
+// in the lobby a plain name is just a chat line, it takes /nick
+async fn nick(server: &TestServer, name: &str) -> Client {
+    let mut client = server.connect().await;
+    client.send(&format!("/nick {name}")).await;
+    client.expect(&format!("Welcome, {name}!")).await;
+    client
+}
+
+#[tokio::test]
+async fn the_lobby_can_look_around() {
+    let path = std::env::temp_dir().join(format!("chat-lobby-motd-{}", std::process::id()));
+    std::fs::write(&path, "be nice\n").unwrap();
+    let server = TestServer::start(Config {
+        motd: Some(path.clone()),
+        ..lobby()
+    })
+    .await;
+    let _bob = nick(&server, "bob").await;
+    let mut visitor = server.connect().await;
+    visitor
+        .expect("Welcome! Look around with /help and /stats, then pick a name with /nick name")
+        .await;
+    visitor.expect("be nice").await;
+
+    visitor.send("/help").await;
+    visitor.expect("You are in the lobby.").await;
+    visitor.send("/stats").await;
+    visitor
+        .expect("1 user online in 1 room, up for 0h 0m")
+        .await;
+    visitor.send("/version").await;
+    visitor.expect("rustlang-chat-server").await;
+    let _ = std::fs::remove_file(path);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_lobby_cant_chat_or_run_other_commands() {
+    let server = TestServer::start(lobby()).await;
+    let mut bob = nick(&server, "bob").await;
+    let mut visitor = server.connect().await;
+
+    visitor.send("hello?").await;
+    visitor
+        .expect("! NOT_REGISTERED Pick a name with /nick name before chatting")
+        .await;
+    visitor.send("/join #dev").await;
+    visitor
+        .expect("! NOT_REGISTERED /join needs a name, pick one with /nick name")
+        .await;
+    visitor.send("/msg bob hi").await;
+    visitor.expect("! NOT_REGISTERED /msg needs a name").await;
+    visitor.send("/nick").await;
+    visitor.expect("! USAGE Usage: /nick name").await;
+    bob.expect_nothing("hello?", Duration::from_millis(100))
+        .await;
+    assert_eq!(server.server.stats().active_connections, 1);
+
+    visitor.send("/nick alice").await;
+    visitor.expect("Welcome, alice!").await;
+    visitor.send("hello now").await;
+    bob.expect("[#general] alice: hello now").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_bad_nick_leaves_the_client_in_the_lobby() {
+    let server = TestServer::start(lobby()).await;
+    let _bob = nick(&server, "bob").await;
+    let mut visitor = server.connect().await;
+    visitor.send("/nick bob").await;
+    visitor.expect("NAME_TAKEN").await;
+    visitor.send("/nick #x").await;
+    visitor.expect("! INVALID_NAME").await;
+    visitor.send("/help").await;
+    visitor.expect("You are in the lobby.").await;
+    visitor.send("/nick carol").await;
+    visitor.expect("Welcome, carol!").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_clients_skip_the_lobby() {
+    let server = TestServer::start(lobby()).await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+    server.shutdown().await;
+}
@@ -0,0 +1,263 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+#[tokio::test]
+async fn blank_lines_are_not_broadcast() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send_raw(b"\n   \n\t\n").await;
+    alice.send("  padded  ").await;
+    // the first thing bob gets from alice is the real message, spaces and all
+    let line = bob.expect("alice:").await;
+    assert_eq!(line, "[#general] alice:   padded  ");
+    // and alice is still connected and not told off for it
+    alice.expect_nothing("!", Duration::from_millis(100)).await;
+    alice.send("still here").await;
+    bob.expect("alice: still here").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn blank_json_messages_are_not_broadcast_either() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+    bob.expect("alice joined").await;
+
+    alice.send(r#"{"type":"message","body":" \t "}"#).await;
+    alice.send(r#"{"type":"message","body":"real"}"#).await;
+    let line = bob.expect("alice:").await;
+    assert_eq!(line, "[#general] alice: real");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn echo_on_sends_a_client_its_own_messages() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send("not for me").await;
+    bob.expect("alice: not for me").await;
+    alice
+        .expect_nothing("not for me", Duration::from_millis(100))
+        .await;
+
+    alice.send("/echo on").await;
+    alice.expect("Echo is on").await;
+    alice.send("back to me").await;
+    assert_eq!(alice.expect("alice:").await, "[#general] alice: back to me");
+    bob.expect("alice: back to me").await;
+
+    alice.send("/echo off").await;
+    alice.expect("Echo is off").await;
+    alice.send("quiet again").await;
+    bob.expect("alice: quiet again").await;
+    alice
+        .expect_nothing("quiet again", Duration::from_millis(100))
+        .await;
+
+    alice.send("/echo maybe").await;
+    alice.expect("Usage: /echo on|off").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn echo_own_makes_echo_the_default() {
+    let server = TestServer::start(Config {
+        echo_own: true,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send("hello").await;
+    alice.expect("alice: hello").await;
+    // bob turning it off doesn't change it for alice
+    bob.send("/echo off").await;
+    bob.expect("Echo is off").await;
+    bob.send("hi").await;
+    alice.expect("bob: hi").await;
+    bob.expect_nothing("bob: hi", Duration::from_millis(100))
+        .await;
+    alice.send("still echoed").await;
+    alice.expect("alice: still echoed").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_resent_message_id_is_dropped() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    let welcome = alice.expect(r#""type":"welcome""#).await;
+    bob.expect("alice joined").await;
+
+    alice
+        .send(r#"{"type":"message","body":"once","id":"m1"}"#)
+        .await;
+    alice
+        .send(r#"{"type":"message","body":"once","id":"m1"}"#)
+        .await;
+    alice
+        .send(r#"{"type":"message","body":"another","id":"m2"}"#)
+        .await;
+    assert_eq!(bob.expect("alice:").await, "[#general] alice: once");
+    assert_eq!(bob.expect("alice:").await, "[#general] alice: another");
+    // the sender isn't told off, its first copy went out
+    alice
+        .expect_nothing(r#""type":"error""#, Duration::from_millis(100))
+        .await;
+    server.until(|s| s.stats().messages_total == 2).await;
+
+    // coming back doesn't make the id new again
+    let token = welcome
+        .split(r#""token":""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .to_string();
+    drop(alice);
+    bob.expect("alice left").await;
+    let mut alice = server.connect().await;
+    alice
+        .send(&format!(r#"{{"type":"resume","token":"{token}"}}"#))
+        .await;
+    alice.expect(r#""resumed":true"#).await;
+    alice
+        .send(r#"{"type":"message","body":"once","id":"m1"}"#)
+        .await;
+    alice
+        .send(r#"{"type":"message","body":"new","id":"m3"}"#)
+        .await;
+    assert_eq!(bob.expect("alice:").await, "[#general] alice: new");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn message_ids_are_checked() {
+    let server = TestServer::start(Config::default()).await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+    let long = "x".repeat(65);
+    for id in [r#""""#.to_string(), "7".to_string(), format!(r#""{long}""#)] {
+        alice
+            .send(&format!(r#"{{"type":"message","body":"hi","id":{id}}}"#))
+            .await;
+        alice
+            .expect(r#""id\" must be a string of 1 to 64 bytes"#)
+            .await;
+    }
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn plain_text_repeats_are_not_taken_for_duplicates() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("again").await;
+    alice.send("again").await;
+    bob.expect("alice: again").await;
+    bob.expect("alice: again").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_bom_and_control_bytes_are_stripped_before_anyone_sees_them() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut alice = server.connect().await;
+    alice.send_raw(b"\xef\xbb\xbfali\x07ce\n").await;
+    alice.expect("Welcome, alice!").await;
+    bob.expect("alice joined").await;
+
+    alice
+        .send_raw(b"\x1b[31mred\x1b[0m and\ttabbed\x1b]0;pwned\x07\r\n")
+        .await;
+    assert_eq!(
+        bob.expect("alice:").await,
+        "[#general] alice: red and\ttabbed"
+    );
+    server.shutdown().await;
+}
+
+// minutes past midnight from the [HH:MM] in front of a message
+fn clock(line: &str) -> i32 {
+    let time = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .map(|(time, _)| time)
+        .unwrap_or_else(|| panic!("no time in {line:?}"));
+    let (hours, minutes) = time.split_once(':').unwrap();
+    hours.parse::<i32>().unwrap() * 60 + minutes.parse::<i32>().unwrap()
+}
+
+#[tokio::test]
+async fn each_client_sees_times_in_its_own_zone() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut utc, _) = server.join("utc").await;
+    let (mut india, _) = server.join("india").await;
+    utc.send("/tz utc").await;
+    utc.expect("Times are shown in UTC").await;
+    india.send("/tz +05:30").await;
+    india.expect("Times are shown at UTC+05:30").await;
+
+    alice.send("what time is it").await;
+    let (at_utc, in_india) = (
+        utc.expect("alice: what time is it").await,
+        india.expect("alice: what time is it").await,
+    );
+    assert!(
+        at_utc.ends_with("] [#general] alice: what time is it"),
+        "{at_utc}"
+    );
+    // unless the minute turned over in between
+    let apart = (clock(&in_india) - clock(&at_utc)).rem_euclid(24 * 60);
+    assert!((330..=331).contains(&apart), "{at_utc} / {in_india}");
+    // and alice, who never asked, gets no time at all
+    utc.send("back at you").await;
+    assert_eq!(alice.expect("utc:").await, "[#general] utc: back at you");
+
+    india.send("/tz off").await;
+    india.expect("Times are off").await;
+    alice.send("no time").await;
+    assert_eq!(
+        india.expect("alice: no time").await,
+        "[#general] alice: no time"
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn timestamps_start_everyone_in_utc_and_json_gets_ts() {
+    let server = TestServer::start(Config {
+        timestamps: true,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut json = server.connect().await;
+    json.send(r#"{"type":"hello","name":"json"}"#).await;
+    json.expect(r#""type":"welcome""#).await;
+
+    alice.send("hi").await;
+    let line = bob.expect("alice: hi").await;
+    clock(&line);
+    let frame = json.expect(r#""body":"hi""#).await;
+    assert!(frame.contains(r#""ts":"#), "{frame}");
+    server.shutdown().await;
+}
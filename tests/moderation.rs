@@ -0,0 +1,157 @@
+// /kick, /ban and /announce, which take a moderator
+mod common;
+
+use common::{Client, TestServer};
+use rustlang_chat_server::{AuthFuture, AuthResult, Authenticator, Config, Role};
+
+// names starting with mod are moderators, root ones admins, everyone else a user
+struct ByName;
+
+impl Authenticator for ByName {
+    fn authenticate<'a>(&'a self, name: &'a str, _: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            AuthResult::Allow(if name.starts_with("mod") {
+                Role::Moderator
+            } else if name.starts_with("root") {
+                Role::Admin
+            } else {
+                Role::User
+            })
+        })
+    }
+}
+
+async fn start() -> TestServer {
+    let server = TestServer::start(Config::default()).await;
+    server.server.set_authenticator(ByName);
+    server
+}
+
+async fn json(server: &TestServer, name: &str) -> Client {
+    let mut client = server.connect().await;
+    client
+        .send(&format!(r#"{{"type":"hello","name":"{name}"}}"#))
+        .await;
+    client.expect(r#""type":"welcome""#).await;
+    client
+}
+
+#[tokio::test]
+async fn users_get_the_same_answer_whatever_they_try() {
+    let server = start().await;
+    let (mut alice, _) = server.join("alice").await;
+    let (_bob, _) = server.join("bob").await;
+    for command in ["/kick bob", "/ban bob spam", "/announce hello"] {
+        alice.send(command).await;
+        alice.expect("! PERMISSION_DENIED Permission denied").await;
+    }
+    assert_eq!(server.server.stats().active_connections, 2);
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_moderator_kicks_a_user_who_can_come_back() {
+    let server = start().await;
+    let (mut moderator, _) = server.join("mod").await;
+    let (mut alice, _) = server.join("alice").await;
+    let mut bob = json(&server, "bob").await;
+    alice.expect("bob joined").await;
+
+    moderator.send("/kick bob wrong room").await;
+    moderator.expect("bob was kicked").await;
+    alice
+        .expect("*** bob was kicked by mod: wrong room ***")
+        .await;
+    let last = bob.expect_closed().await.unwrap_or_default();
+    assert!(last.contains(r#""reason":"kick""#), "{last}");
+    assert!(
+        last.contains("you were kicked by mod: wrong room"),
+        "{last}"
+    );
+
+    moderator.send("/kick nobody").await;
+    moderator
+        .expect("! NO_SUCH_USER No such user: nobody")
+        .await;
+    let (_bob, _) = server.join("bob").await;
+    let (mut root, _) = server.join("root").await;
+    root.send("/recent 1").await;
+    root.expect("Recent disconnects: bob (127.0.0.1, 0s, kicked)")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn only_clients_below_the_kickers_role_can_be_thrown_out() {
+    let server = start().await;
+    let (mut moderator, _) = server.join("mod").await;
+    let (_other, _) = server.join("moderna").await;
+    let (mut root, _) = server.join("root").await;
+    for target in ["moderna", "root", "mod"] {
+        moderator.send(&format!("/kick {target}")).await;
+        moderator
+            .expect("! PERMISSION_DENIED Permission denied")
+            .await;
+    }
+    // an admin can kick a moderator
+    root.send("/kick moderna").await;
+    root.expect("moderna was kicked").await;
+    server.until(|s| s.stats().active_connections == 2).await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_ban_keeps_the_address_out() {
+    let server = start().await;
+    let (mut moderator, _) = server.join("mod").await;
+    let mut bob = Client::connect_from(&server.addr, "127.0.0.2")
+        .await
+        .unwrap();
+    bob.register("bob").await;
+    let mut carol = Client::connect_from(&server.addr, "127.0.0.3")
+        .await
+        .unwrap();
+    carol.register("carol").await;
+
+    moderator.send("/ban bob").await;
+    moderator.expect("bob was banned").await;
+    assert_eq!(
+        bob.expect_closed().await.as_deref(),
+        Some("*** you were banned by mod ***")
+    );
+    carol.expect("*** bob was banned by mod ***").await;
+    // under any name, from that address and no other
+    let mut again = Client::connect_from(&server.addr, "127.0.0.2")
+        .await
+        .unwrap();
+    assert_eq!(
+        again.expect_closed().await.as_deref(),
+        Some("*** you are banned from this server ***")
+    );
+    let mut elsewhere = Client::connect_from(&server.addr, "127.0.0.4")
+        .await
+        .unwrap();
+    elsewhere.register("bob").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn an_announcement_reaches_every_room_and_ignores_nothing() {
+    let server = start().await;
+    let (mut moderator, _) = server.join("mod").await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    alice.send("/leave #general").await;
+    alice.expect("you left #general").await;
+    alice.send("/ignore mod").await;
+    alice.expect("Ignoring mod").await;
+    let mut bob = json(&server, "bob").await;
+
+    moderator.send("/announce restart at noon").await;
+    let text = "*** announcement from mod: restart at noon ***";
+    moderator.expect(text).await;
+    alice.expect(text).await;
+    bob.expect("restart at noon").await;
+    server.shutdown().await;
+}
@@ -0,0 +1,118 @@
+// the broadcaster is the one place messages are put in order: senders racing each other still
+// have every listener see the same sequence, each sender's messages in the order it sent them,
+// and the history agree with what went out
+mod common;
+
+use common::{Client, TestServer};
+use rustlang_chat_server::Config;
+
+const SENDERS: usize = 4;
+const MESSAGES: usize = 50;
+
+// a string or number field of a json frame, good enough for the names and bodies sent here
+fn field<'a>(frame: &'a str, name: &str) -> &'a str {
+    let key = format!("\"{name}\":");
+    let (_, rest) = frame
+        .split_once(&key)
+        .unwrap_or_else(|| panic!("no {name} in {frame}"));
+    match rest.strip_prefix('"') {
+        Some(text) => text.split('"').next().unwrap(),
+        None => rest.split([',', '}']).next().unwrap(),
+    }
+}
+
+async fn listener(server: &TestServer, name: &str) -> Client {
+    let mut client = server.connect().await;
+    client
+        .send(&format!(r#"{{"type":"hello","name":"{name}"}}"#))
+        .await;
+    client.expect(r#""type":"welcome""#).await;
+    client
+}
+
+// (seq, from, body) of every message, in the order they arrived
+async fn received(mut client: Client) -> Vec<(u64, String, String)> {
+    let mut messages = Vec::new();
+    while messages.len() < SENDERS * MESSAGES {
+        let frame = client.expect(r#""type":"message""#).await;
+        messages.push((
+            field(&frame, "seq").parse().unwrap(),
+            field(&frame, "from").to_string(),
+            field(&frame, "body").to_string(),
+        ));
+    }
+    messages
+}
+
+#[tokio::test]
+async fn racing_senders_are_seen_in_one_order_by_everyone() {
+    let server = TestServer::start(Config::default()).await;
+    let mut listeners = Vec::new();
+    for n in 0..3 {
+        listeners.push(listener(&server, &format!("listener{n}")).await);
+    }
+    // a sender waits for its own message before the next, or the listeners' queues would
+    // overflow and drop lines, which is a different thing from getting them out of order
+    let mut senders = Vec::new();
+    for n in 0..SENDERS {
+        let (mut sender, _) = server.join(&format!("sender{n}")).await;
+        sender.send("/echo on").await;
+        sender.expect("Echo is on").await;
+        senders.push(sender);
+    }
+    let listening: Vec<_> = listeners
+        .into_iter()
+        .map(|client| tokio::spawn(received(client)))
+        .collect();
+    let sending: Vec<_> = senders
+        .into_iter()
+        .enumerate()
+        .map(|(n, mut client)| {
+            tokio::spawn(async move {
+                for m in 1..=MESSAGES {
+                    client.send(&format!("{n}.{m}")).await;
+                    client.expect(&format!("sender{n}: {n}.{m}")).await;
+                }
+                client
+            })
+        })
+        .collect();
+    let mut senders = Vec::new();
+    for sending in sending {
+        senders.push(sending.await.unwrap());
+    }
+    let mut seen = Vec::new();
+    for listening in listening {
+        seen.push(listening.await.unwrap());
+    }
+
+    let first = &seen[0];
+    for other in &seen[1..] {
+        assert_eq!(other, first);
+    }
+    assert!(first.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    for n in 0..SENDERS {
+        let from = format!("sender{n}");
+        let bodies: Vec<&str> = first
+            .iter()
+            .filter(|(_, sender, _)| *sender == from)
+            .map(|(_, _, body)| body.as_str())
+            .collect();
+        let sent: Vec<String> = (1..=MESSAGES).map(|m| format!("{n}.{m}")).collect();
+        assert_eq!(bodies, sent);
+    }
+
+    // the history is what went out, in the same order
+    let total = (SENDERS * MESSAGES) as u64;
+    server.until(|s| s.stats().messages_total == total).await;
+    let exporter = &mut senders[0];
+    exporter.send("/export").await;
+    exporter
+        .expect("Transcript of #general, the last 50 of 100")
+        .await;
+    for (_, from, body) in &first[first.len() - 50..] {
+        let line = exporter.line().await.unwrap();
+        assert!(line.ends_with(&format!(" UTC {from}: {body}")), "{line}");
+    }
+    server.shutdown().await;
+}
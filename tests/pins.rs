@@ -0,0 +1,118 @@
+mod common;
+
+use common::{Client, TestServer};
+use rustlang_chat_server::Config;
+
+// a json client in #dev, which sees the sequence number of every message
+async fn watcher(server: &TestServer) -> Client {
+    let mut watcher = server.connect().await;
+    watcher.send(r#"{"type":"hello","name":"watcher"}"#).await;
+    watcher.expect(r#""type":"welcome""#).await;
+    watcher.send("/join #dev").await;
+    watcher.expect("you joined #dev").await;
+    watcher
+}
+
+async fn seq_of(watcher: &mut Client, body: &str) -> u64 {
+    let line = watcher.expect(&format!(r#""body":"{body}""#)).await;
+    let (_, rest) = line.split_once(r#""seq":"#).expect("a seq");
+    rest.split(',').next().unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn ops_pin_and_unpin_and_anyone_can_list() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    let mut watcher = watcher(&server).await;
+    let (mut bob, _) = server.join("bob").await;
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+    bob.send("read the rules").await;
+    let seq = seq_of(&mut watcher, "read the rules").await;
+
+    bob.send(&format!("/pin {seq}")).await;
+    bob.expect("! PERMISSION_DENIED You are not an operator of #dev")
+        .await;
+    alice.send(&format!("/pin {seq}")).await;
+    bob.expect(&format!("*** alice pinned #{seq} ***")).await;
+    alice.send(&format!("/pin #{seq}")).await;
+    alice.expect(&format!("#{seq} is already pinned")).await;
+    alice.send("/pin 9999").await;
+    alice
+        .expect("! NO_SUCH_MESSAGE No message #9999 in #dev")
+        .await;
+    bob.send("/pins").await;
+    bob.expect(&format!(
+        "Pinned in #dev: [pinned #{seq}] bob: read the rules"
+    ))
+    .await;
+
+    // someone joining sees it straight after being let in
+    let (mut carol, _) = server.join("carol").await;
+    carol.send("/join #dev").await;
+    carol.expect("you joined #dev").await;
+    assert_eq!(
+        carol.line().await.unwrap(),
+        format!("[pinned #{seq}] bob: read the rules")
+    );
+
+    bob.send(&format!("/unpin {seq}")).await;
+    bob.expect("! PERMISSION_DENIED").await;
+    alice.send(&format!("/unpin {seq}")).await;
+    bob.expect(&format!("*** alice unpinned #{seq} ***")).await;
+    bob.send("/pins").await;
+    bob.expect("Nothing is pinned in #dev").await;
+    alice.send(&format!("/unpin {seq}")).await;
+    alice
+        .expect(&format!("! NO_SUCH_MESSAGE #{seq} isn't pinned in #dev"))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_room_has_at_most_ten_pins() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    let mut watcher = watcher(&server).await;
+    let mut seqs = Vec::new();
+    for n in 1..=11 {
+        let body = format!("notice {n}");
+        alice.send(&body).await;
+        seqs.push(seq_of(&mut watcher, &body).await);
+    }
+    for seq in &seqs[..10] {
+        alice.send(&format!("/pin {seq}")).await;
+        alice.expect(&format!("pinned #{seq}")).await;
+    }
+    alice.send(&format!("/pin {}", seqs[10])).await;
+    alice
+        .expect("! TOO_MANY_PINS A room can have at most 10 pinned messages")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_pin_outlives_the_history() {
+    let mut config = Config::default();
+    config.room_history.insert("#dev".to_string(), 1);
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    let mut watcher = watcher(&server).await;
+    alice.send("keep this").await;
+    let seq = seq_of(&mut watcher, "keep this").await;
+    alice.send(&format!("/pin {seq}")).await;
+    alice.expect("pinned").await;
+    alice.send("pushes it out of the history").await;
+    seq_of(&mut watcher, "pushes it out of the history").await;
+    alice.send("/pins").await;
+    alice
+        .expect(&format!("Pinned in #dev: [pinned #{seq}] alice: keep this"))
+        .await;
+    server.shutdown().await;
+}
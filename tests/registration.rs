@@ -0,0 +1,181 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::{Config, GreetingStep};
+
+#[tokio::test]
+async fn lines_pipelined_after_the_name_are_handled_once_registered() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    bob.send("/join #x").await;
+    bob.expect("you joined #x").await;
+
+    let mut alice = server.connect().await;
+    alice
+        .send_raw(b"alice\nhello there\n/join #x\nin x\n")
+        .await;
+    alice.expect("Welcome, alice!").await;
+    alice.expect("you joined #x").await;
+    bob.expect("alice: hello there").await;
+    let line = bob.expect("alice: in x").await;
+    assert!(line.contains("[#x]"), "{line}");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_requests_pipelined_after_the_hello_are_handled_too() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+
+    let mut alice = server.connect().await;
+    alice
+        .send_raw(
+            concat!(
+                r#"{"type":"hello","name":"alice"}"#,
+                "\n",
+                r#"{"type":"message","body":"first"}"#,
+                "\n",
+                r#"{"type":"message","body":"second"}"#,
+                "\n",
+            )
+            .as_bytes(),
+        )
+        .await;
+    alice.expect(r#""type":"welcome""#).await;
+    bob.expect("alice: first").await;
+    bob.expect("alice: second").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_taken_name_is_asked_again() {
+    let server = TestServer::start(Config::default()).await;
+    let (_alice, _) = server.join("alice").await;
+    let mut other = server.connect().await;
+    other.send("alice").await;
+    other.expect("NAME_TAKEN").await;
+    other.register("alice2").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_bad_name_is_turned_down_with_the_reason() {
+    let server = TestServer::start(Config::default()).await;
+    let mut client = server.connect().await;
+    client.send("#general").await;
+    client
+        .expect("! INVALID_NAME Name can't start with #, that's for rooms")
+        .await;
+    client.send("ali\u{200b}ce").await;
+    client
+        .expect("! INVALID_NAME Name can't contain \\u{200b}")
+        .await;
+    client.send(&"x".repeat(33)).await;
+    client
+        .expect("! INVALID_NAME Name can't be longer than 32 characters")
+        .await;
+    // still waiting for a name
+    client.register("zoë").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_bad_room_name_is_turned_down_with_the_reason() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #dev.ops").await;
+    alice
+        .expect("! INVALID_ROOM_NAME Room name can't contain '.', only letters, digits, - and _")
+        .await;
+    let too_long = format!("#{}", "r".repeat(31));
+    alice.send(&format!("/join {too_long}")).await;
+    alice
+        .expect("! INVALID_ROOM_NAME Room name can't be longer than 30 characters after the #")
+        .await;
+    let longest = format!("#{}", "r".repeat(30));
+    alice.send(&format!("/join {longest}")).await;
+    alice.expect(&format!("you joined {longest}")).await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_greeting_follows_the_configured_steps() {
+    let config = Config {
+        greeting: Some(vec![
+            GreetingStep::Password,
+            GreetingStep::Banner,
+            GreetingStep::Name,
+            GreetingStep::Occupancy,
+        ]),
+        max_clients: 5,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let mut alice = server.connect().await;
+    assert_eq!(
+        alice.line().await.as_deref(),
+        Some("Follow your name with your password, like: alice s3cret")
+    );
+    let banner = alice.line().await.unwrap();
+    assert_eq!(
+        banner,
+        format!("rustlang-chat-server {}", env!("CARGO_PKG_VERSION"))
+    );
+    // no prompt, it was left out
+    alice.expect_nothing("", Duration::from_millis(100)).await;
+    alice.send("alice").await;
+    assert!(alice.line().await.unwrap().starts_with("Welcome, alice!"));
+    assert_eq!(
+        alice.line().await.as_deref(),
+        Some("There is 1 user online (cap 5).")
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_greeting_of_just_the_name_sends_nothing_first() {
+    let config = Config {
+        greeting: Some(vec![GreetingStep::Name]),
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let mut alice = server.connect().await;
+    alice.expect_nothing("", Duration::from_millis(100)).await;
+    alice.register("alice").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn min_protocol_version_turns_plain_text_away() {
+    let server = TestServer::start(Config {
+        min_protocol_version: 1,
+        ..Config::default()
+    })
+    .await;
+    let mut plain = server.connect().await;
+    plain.send("alice").await;
+    let last = plain.expect_closed().await.unwrap_or_default();
+    assert!(
+        last.starts_with("! PROTOCOL_TOO_OLD This server needs protocol version 1 or later"),
+        "{last}"
+    );
+
+    // version 1 is just enough
+    let mut json = server.connect().await;
+    json.send(r#"{"type":"hello","name":"alice"}"#).await;
+    let welcome = json.expect(r#""type":"welcome""#).await;
+    assert!(welcome.contains(r#""version":1"#), "{welcome}");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn by_default_every_version_gets_in() {
+    let server = TestServer::start(Config::default()).await;
+    let mut json = server.connect().await;
+    json.send(r#"{"type":"hello","name":"bob"}"#).await;
+    json.expect(r#""type":"welcome""#).await;
+    let (_alice, _) = server.join("alice").await;
+    server.shutdown().await;
+}
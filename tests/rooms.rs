@@ -0,0 +1,424 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+#[tokio::test]
+async fn deleting_a_room_moves_sessions_waiting_to_resume() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut admin, _) = server.join("admin").await;
+    let (mut alice, token) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    for client in [&mut alice, &mut bob] {
+        client.send("/join #doomed").await;
+        client.expect("you joined #doomed").await;
+    }
+    // bob keeps the room from going away on its own when alice drops
+    drop(alice);
+    server.until(|s| s.stats().active_connections == 2).await;
+
+    admin.send("/delroom #doomed").await;
+    admin.expect("#doomed deleted").await;
+
+    let mut alice = server.connect().await;
+    alice.send(&format!("RESUME {token}")).await;
+    alice
+        .expect("Welcome back, alice! You are in #general.")
+        .await;
+    alice.send("/rooms").await;
+    let rooms = alice.expect("#general").await;
+    assert!(!rooms.contains("#doomed"), "{rooms}");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn operators_can_give_and_take_op() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #den").await;
+    alice.expect("you joined #den").await;
+    bob.send("/join #den").await;
+    bob.expect("you joined #den").await;
+
+    bob.send("/topic mine now").await;
+    bob.expect("PERMISSION_DENIED").await;
+    alice.send("/giveop bob").await;
+    bob.expect("*** bob is now an operator ***").await;
+    bob.send("/deop alice").await;
+    alice.expect("*** alice is no longer an operator ***").await;
+    bob.send("/deop bob").await;
+    bob.expect("bob is the last operator of #den").await;
+    alice.send("/giveop alice").await;
+    alice.expect("PERMISSION_DENIED").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_longest_member_takes_over_from_the_last_operator() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+    for client in [&mut alice, &mut bob, &mut carol] {
+        client.send("/join #den").await;
+        client.expect("you joined #den").await;
+    }
+
+    alice.send("/leave #den").await;
+    bob.expect("*** bob is now an operator ***").await;
+    carol.expect("*** bob is now an operator ***").await;
+    drop(bob);
+    carol.expect("*** carol is now an operator ***").await;
+    carol.send("/topic carol's den").await;
+    carol.expect("carol's den").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_member_of_two_rooms_can_tell_them_apart() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    carol.send("/join #dev").await;
+    alice.expect("carol joined #dev").await;
+
+    bob.send("over in general").await;
+    carol.send("over in dev").await;
+    assert_eq!(
+        alice.expect("over in general").await,
+        "[#general] bob: over in general"
+    );
+    assert_eq!(
+        alice.expect("over in dev").await,
+        "[#dev] carol: over in dev"
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_clients_get_the_room_as_a_field() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+    // commands are sent as plain lines
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+
+    bob.send("hi").await;
+    let line = alice.expect(r#""type":"message""#).await;
+    assert!(line.contains(r##""room":"#dev""##), "{line}");
+    assert!(line.contains(r#""body":"hi""#), "{line}");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn room_tags_can_be_turned_off() {
+    let config = Config {
+        room_tags: false,
+        ..Config::default()
+    };
+    let server = TestServer::start(config).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    bob.send("untagged").await;
+    assert_eq!(alice.expect("untagged").await, "bob: untagged");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn switch_picks_where_plain_messages_go() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+    carol.send("/join #rust").await;
+    carol.expect("you joined #rust").await;
+    alice.send("/join #rust").await;
+    alice.expect("you joined #rust").await;
+
+    // joining made #rust the current room
+    alice.send("one").await;
+    carol.expect("[#rust] alice: one").await;
+    alice.send("/switch #general").await;
+    alice.expect("Now chatting in #general").await;
+    alice.send("two").await;
+    bob.expect("[#general] alice: two").await;
+    alice.send("/switch #rust").await;
+    alice.expect("Now chatting in #rust").await;
+    alice.send("three").await;
+    carol.expect("[#rust] alice: three").await;
+    // each message went only to the room it was sent to, and alice is still in both
+    bob.expect_nothing("alice: three", Duration::from_millis(100))
+        .await;
+    bob.send("still hear me?").await;
+    alice.expect("[#general] bob: still hear me?").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn switch_needs_the_room_to_be_joined() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut carol, _) = server.join("carol").await;
+    bob.send("/join #rust").await;
+    bob.expect("you joined #rust").await;
+
+    alice.send("/switch #rust").await;
+    alice
+        .expect("! NOT_IN_ROOM You are not in #rust, /join it first")
+        .await;
+    alice.send("/switch").await;
+    alice.expect("! USAGE Usage: /switch #room").await;
+    // nothing changed, and nothing was joined either
+    alice.send("hello").await;
+    carol.expect("[#general] alice: hello").await;
+    bob.expect_nothing("[#rust] alice", Duration::from_millis(100))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn quietjoins_keeps_joins_and_leaves_out_of_a_room() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #den").await;
+    alice.expect("you joined #den").await;
+
+    bob.send("/quietjoins on").await;
+    bob.expect("! PERMISSION_DENIED You are not an operator of #general")
+        .await;
+    alice.send("/quietjoins on").await;
+    alice
+        .expect("*** join and leave notices turned off by alice ***")
+        .await;
+    bob.send("/join #den").await;
+    bob.expect("you joined #den").await;
+    bob.send("/leave #den").await;
+    bob.expect("you left #den").await;
+    bob.send("/join #den").await;
+    bob.expect("you joined #den").await;
+    // bob is still counted, only nobody was told
+    alice.send("/rooms").await;
+    alice.expect("#den (2)").await;
+    alice
+        .expect_nothing("bob", Duration::from_millis(100))
+        .await;
+
+    alice.send("/quietjoins off").await;
+    bob.expect("*** join and leave notices turned on by alice ***")
+        .await;
+    bob.send("/leave #den").await;
+    alice.expect("*** bob left #den ***").await;
+    alice.send("/quietjoins maybe").await;
+    alice.expect("Usage: /quietjoins on|off").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn topiclog_lists_the_changes_newest_first() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #den").await;
+    alice.expect("you joined #den").await;
+    bob.send("/join #den").await;
+    bob.expect("you joined #den").await;
+
+    alice.send("/topiclog").await;
+    alice
+        .expect("The topic of #den has never been changed")
+        .await;
+    alice.send("/topic first").await;
+    bob.expect("*** alice set the topic to: first ***").await;
+    alice.send("/topic second").await;
+    bob.expect("*** alice set the topic to: second ***").await;
+    bob.send("/topic").await;
+    bob.expect("The topic of #den is: second").await;
+
+    bob.send("/topiclog").await;
+    bob.expect("! PERMISSION_DENIED You are not an operator of #den")
+        .await;
+    alice.send("/topiclog").await;
+    assert_eq!(
+        alice.expect("Topic changes").await,
+        "Topic changes in #den: 0s ago alice: first -> second | 0s ago alice: (none) -> first"
+    );
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn renaming_a_room_takes_its_members_and_state_along() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    let (mut dave, token) = server.join("dave").await;
+    for client in [&mut alice, &mut bob, &mut dave] {
+        client.send("/join #den").await;
+        client.expect("you joined #den").await;
+    }
+    alice.send("/topic plotting").await;
+    bob.expect("set the topic to: plotting").await;
+    alice.send("before the move").await;
+    bob.expect("alice: before the move").await;
+    // dave is waiting to resume while it happens
+    drop(dave);
+    bob.expect("dave left #den").await;
+
+    bob.send("/renameroom #den #lair").await;
+    bob.expect("! PERMISSION_DENIED You are not an operator of #den")
+        .await;
+    alice.send("/renameroom #den #lair").await;
+    alice.expect("*** #den is now #lair ***").await;
+    bob.expect("*** #den is now #lair ***").await;
+
+    // plain lines follow the room to its new name, and so do the topic and the ops
+    bob.send("after the move").await;
+    assert_eq!(alice.expect("bob:").await, "[#lair] bob: after the move");
+    bob.send("/topic").await;
+    bob.expect("The topic of #lair is: plotting").await;
+    alice.send("/topic still mine").await;
+    bob.expect("*** alice set the topic to: still mine ***")
+        .await;
+    bob.send("/rooms").await;
+    let rooms = bob.expect("Rooms, page").await;
+    assert!(
+        rooms.contains("#lair (2)") && !rooms.contains("#den"),
+        "{rooms}"
+    );
+
+    // and the history along with them
+    let (mut carol, _) = server.join("carol").await;
+    carol.send("/join #lair").await;
+    carol.expect("you joined #lair").await;
+    server.until(|s| s.stats().messages_total == 2).await;
+    carol.send("/export").await;
+    carol.expect("Transcript of #lair, 2 messages:").await;
+    carol.expect("alice: before the move").await;
+    let mut dave = server.connect().await;
+    dave.send(&format!("RESUME {token}")).await;
+    dave.expect("Welcome back, dave! You are in #lair, also #general.")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_rename_is_checked() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut admin, _) = server.join("admin").await;
+    let (mut alice, _) = server.join("alice").await;
+    for room in ["#other", "#den"] {
+        alice.send(&format!("/join {room}")).await;
+        alice.expect(&format!("you joined {room}")).await;
+    }
+    alice.send("/renameroom #den #other").await;
+    alice.expect("#other is already taken").await;
+    alice.send("/renameroom #den #general").await;
+    alice.expect("#general is already taken").await;
+    alice.send("/renameroom #den").await;
+    alice.expect("Usage: /renameroom #old #new").await;
+    admin.send("/renameroom #general #lobby").await;
+    admin
+        .expect("! PROTECTED_ROOM #general can't be renamed")
+        .await;
+    admin.send("/renameroom #nowhere #x").await;
+    admin.expect("! NO_SUCH_ROOM No such room: #nowhere").await;
+    // an admin running someone else's room is told it worked
+    admin.send("/renameroom #den #lair").await;
+    admin.expect("*** #den is now #lair ***").await;
+    alice.expect("*** #den is now #lair ***").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn rooms_come_a_page_at_a_time_busiest_first() {
+    let server = TestServer::start(Config {
+        rooms_page_size: 2,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    for room in ["#c", "#b", "#a"] {
+        alice.send(&format!("/join {room}")).await;
+        alice.expect(&format!("you joined {room}")).await;
+    }
+    bob.send("/join #b").await;
+    bob.expect("you joined #b").await;
+
+    // ties go by name
+    bob.send("/rooms").await;
+    assert_eq!(
+        bob.line().await.as_deref(),
+        Some("Rooms, page 1 of 2: #b (2), #general (2) (/rooms 2 for more)")
+    );
+    bob.send("/rooms 2").await;
+    assert_eq!(
+        bob.line().await.as_deref(),
+        Some("Rooms, page 2 of 2: #a (1), #c (1)")
+    );
+    bob.send("/rooms 3").await;
+    bob.expect("! USAGE There are only 2 pages of rooms").await;
+    bob.send("/rooms 0").await;
+    bob.expect("Usage: /rooms [page]").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn an_empty_room_lingers_for_the_grace_period() {
+    let server = TestServer::start(Config {
+        empty_room_grace: Duration::from_secs(1),
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #den").await;
+    alice.expect("you joined #den").await;
+    alice.send("/topic still here").await;
+    alice.expect("set the topic to: still here").await;
+    alice.send("/leave").await;
+    alice.expect("you left #den").await;
+    assert_eq!(server.server.stats().rooms, 2);
+
+    // a quick rejoin finds it as it was, and the first one back runs it
+    bob.send("/join #den").await;
+    bob.expect("you joined #den").await;
+    bob.send("/topic").await;
+    bob.expect("The topic of #den is: still here").await;
+    bob.send("/topic bob's now").await;
+    bob.expect("set the topic to: bob's now").await;
+    bob.send("/leave").await;
+    bob.expect("you left #den").await;
+
+    server.until(|s| s.stats().rooms == 1).await;
+    alice.send("/join #den").await;
+    alice.expect("you joined #den").await;
+    alice.send("/topic").await;
+    alice.expect("#den has no topic").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn without_a_grace_period_an_empty_room_goes_at_once() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("/join #den").await;
+    alice.expect("you joined #den").await;
+    assert_eq!(server.server.stats().rooms, 2);
+    alice.send("/leave").await;
+    alice.expect("you left #den").await;
+    assert_eq!(server.server.stats().rooms, 1);
+    server.shutdown().await;
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{with_tls, Client, TestServer};
+use rustlang_chat_server::Config;
+
+fn vault() -> Config {
+    Config {
+        secure_rooms: ["#vault".to_string()].into(),
+        http_listen: Some("127.0.0.1:0".to_string()),
+        ..with_tls(Config::default())
+    }
+}
+
+#[tokio::test]
+async fn only_tls_clients_get_into_a_secure_room() {
+    let server = TestServer::start(vault()).await;
+    let (mut plain, _) = server.join("bob").await;
+    plain.send("/join #vault").await;
+    plain
+        .expect("! SECURE_ONLY This room requires a secure connection")
+        .await;
+
+    let mut secure = server.connect_tls().await;
+    secure.register("alice").await;
+    secure.send("/join #vault").await;
+    secure.expect("you joined #vault").await;
+    secure.send("hush").await;
+    plain
+        .expect_nothing("hush", Duration::from_millis(200))
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn resuming_over_plain_tcp_leaves_the_secure_rooms_behind() {
+    let server = TestServer::start(vault()).await;
+    let mut secure = server.connect_tls().await;
+    let token = secure.register("alice").await;
+    secure.send("/join #vault").await;
+    secure.expect("you joined #vault").await;
+    drop(secure);
+    server
+        .until(|server| server.stats().active_connections == 0)
+        .await;
+
+    let mut plain = server.connect().await;
+    plain.send(&format!("RESUME {token}")).await;
+    plain
+        .expect("Welcome back, alice! You are in #general.")
+        .await;
+    plain.send("/leave #vault").await;
+    plain.expect("! NOT_IN_ROOM").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_http_interface_stays_out_of_secure_rooms() {
+    let server = TestServer::start(vault()).await;
+    let addr = server.server.http_addr().unwrap().to_string();
+    for request in [
+        "GET /poll?room=%23vault HTTP/1.1\r\n\r\n",
+        "POST /send?room=%23vault&name=eve HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi",
+    ] {
+        let mut http = Client::connect(&addr).await;
+        http.send_raw(request.as_bytes()).await;
+        let status = http.line().await.expect("a response");
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        http.expect("This room requires a secure connection").await;
+    }
+    server.shutdown().await;
+}
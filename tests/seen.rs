@@ -0,0 +1,42 @@
+mod common;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+#[tokio::test]
+async fn seen_tells_online_from_gone_from_never_here() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    alice.send("/seen bob").await;
+    alice.expect("bob is online").await;
+    alice.send("/seen carol").await;
+    alice
+        .expect("carol hasn't been seen since the server started")
+        .await;
+
+    bob.send("bye").await;
+    alice.expect("bob: bye").await;
+    drop(bob);
+    alice.expect("bob left").await;
+    alice.send("/seen bob").await;
+    alice.expect("bob was last seen 0s ago").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn last_seen_lasts_through_a_reconnect() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (bob, _) = server.join("bob").await;
+    drop(bob);
+    alice.expect("bob left").await;
+    // bob is back under another name, the old one is still remembered
+    let (_bob, _) = server.join("bobby").await;
+    alice.send("/seen bob").await;
+    alice.expect("bob was last seen").await;
+    alice.send("/seen bobby").await;
+    alice.expect("bobby is online").await;
+    server.shutdown().await;
+}
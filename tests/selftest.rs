@@ -0,0 +1,59 @@
+// the selftest subcommand of the real binary, a step per line and the exit code at the end
+use std::{
+    net::TcpListener,
+    process::{Command, Output},
+};
+
+fn selftest(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rustlang-chat-server"))
+        .arg("selftest")
+        .args(args)
+        .output()
+        .expect("run the selftest")
+}
+
+#[test]
+fn a_healthy_server_passes() {
+    let output = selftest(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    let steps: Vec<&str> = stdout.lines().collect();
+    assert_eq!(steps.len(), 4, "{stdout}");
+    assert!(steps[0].starts_with("ok      start the server on 127.0.0.1:"));
+    assert_eq!(
+        &steps[1..],
+        [
+            "ok      register selftest1",
+            "ok      register selftest2",
+            "ok      deliver a message",
+        ]
+    );
+}
+
+#[test]
+fn it_keeps_clear_of_the_configured_listeners() {
+    // a live server would have these, the selftest runs next to it on a port of its own
+    let live = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = live.local_addr().unwrap().to_string();
+    let output = selftest(&[
+        "--listen",
+        &addr,
+        "--admin-listen",
+        &addr,
+        "--delimiter",
+        "crlf",
+    ]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+}
+
+#[test]
+fn a_step_that_fails_is_reported_and_exits_1() {
+    // the second client finds the server full
+    let output = selftest(&["--max-clients", "1"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("ok      register selftest1"), "{stdout}");
+    assert!(stdout.contains("FAILED  register selftest2: "), "{stdout}");
+    assert!(!stdout.contains("deliver a message"), "{stdout}");
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{within, Client};
+use rustlang_chat_server::{ChatServer, Config};
+use tokio::{net::TcpStream, task};
+
+async fn bind() -> Arc<ChatServer> {
+    let config = Config {
+        listen: "127.0.0.1:0".to_string(),
+        ..Config::default()
+    };
+    Arc::new(ChatServer::bind(config).await.expect("bind the server"))
+}
+
+#[tokio::test]
+async fn run_returns_once_shut_down() {
+    let server = bind().await;
+    let addr = server.local_addr().unwrap().to_string();
+    let running = server.clone();
+    let run = task::spawn(async move { running.run().await });
+
+    let mut alice = Client::connect(&addr).await;
+    alice.register("alice").await;
+    let mut bob = Client::connect(&addr).await;
+    bob.register("bob").await;
+
+    within(server.shutdown()).await;
+    let result = within(run).await.expect("run() didn't panic");
+    assert!(result.is_ok(), "{result:?}");
+    // everyone still connected is told and then let go
+    assert_eq!(
+        alice.expect_closed().await.as_deref(),
+        Some("*** the server is shutting down ***")
+    );
+    assert!(bob.expect_closed().await.is_some());
+    assert_eq!(server.stats().active_connections, 0);
+}
+
+#[tokio::test]
+async fn a_handle_stops_the_server_from_another_task() {
+    let server = bind().await;
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    let running = server.clone();
+    let run = task::spawn(async move { running.run().await });
+
+    let mut alice = Client::connect(&addr.to_string()).await;
+    alice.register("alice").await;
+    let stopper = handle.clone();
+    within(task::spawn(async move { stopper.shutdown().await }))
+        .await
+        .unwrap();
+    assert!(within(run).await.unwrap().is_ok());
+    alice.expect_closed().await;
+    // shutting down again once it has stopped returns straight away
+    within(handle.shutdown()).await;
+    // dropping the server closes the listener for good
+    drop(server);
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn shutting_down_before_run_starts_returns_straight_away() {
+    let server = bind().await;
+    let handle = server.shutdown_handle();
+    let running = server.clone();
+    let stopper = task::spawn(async move { handle.shutdown().await });
+    // the flag is set before run() gets to accept anything
+    task::yield_now().await;
+    assert!(within(running.run()).await.is_ok());
+    within(stopper).await.unwrap();
+}
+
+#[tokio::test]
+async fn shutting_down_a_server_that_never_ran_returns_straight_away() {
+    let server = bind().await;
+    within(server.shutdown()).await;
+    within(server.shutdown_handle().shutdown()).await;
+    // and a run() after that stops as soon as it starts
+    assert!(within(server.run()).await.is_ok());
+}
+
+#[tokio::test]
+async fn shutting_down_after_run_was_dropped_returns_straight_away() {
+    let server = bind().await;
+    let addr = server.local_addr().unwrap().to_string();
+    let running = server.clone();
+    let run = task::spawn(async move { running.run().await });
+    let mut alice = Client::connect(&addr).await;
+    alice.register("alice").await;
+
+    run.abort();
+    assert!(run.await.unwrap_err().is_cancelled());
+    within(server.shutdown()).await;
+}
+
+// the descriptor the server listens on, found by the port it is bound to. the server's side of
+// each connection has that port too, but isn't listening
+fn listening_fd(port: u16) -> libc::c_int {
+    (0..1024)
+        .find(|&fd| {
+            let mut listening: libc::c_int = 0;
+            let mut size = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let asked = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    libc::SO_ACCEPTCONN,
+                    &mut listening as *mut _ as *mut libc::c_void,
+                    &mut size,
+                )
+            };
+            if asked != 0 || listening == 0 {
+                return false;
+            }
+            let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+            let mut len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+            let found = unsafe {
+                libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len)
+            };
+            found == 0
+                && addr.sin_family == libc::AF_INET as libc::sa_family_t
+                && u16::from_be(addr.sin_port) == port
+        })
+        .expect("the listening socket")
+}
+
+#[tokio::test]
+async fn run_returns_the_error_when_the_listener_is_closed_under_it() {
+    let server = bind().await;
+    let addr = server.local_addr().unwrap();
+    let running = server.clone();
+    let run = task::spawn(async move { running.run().await });
+    let mut alice = Client::connect(&addr.to_string()).await;
+    alice.register("alice").await;
+
+    // a listening socket that is shut down fails every accept from then on
+    let fd = listening_fd(addr.port());
+    assert_eq!(unsafe { libc::shutdown(fd, libc::SHUT_RDWR) }, 0);
+    let result = within(run).await.expect("run() didn't panic");
+    assert!(result.is_err(), "{result:?}");
+    // and the connections it had are let go as they would be on shutdown
+    alice.expect_closed().await;
+    assert_eq!(server.stats().active_connections, 0);
+    // there is nothing left to wait for
+    within(server.shutdown()).await;
+}
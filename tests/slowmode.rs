@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::Duration;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn slow_mode_holds_members_back_but_not_ops() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    // alice opens the room, so she runs it
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    bob.send("/join #dev").await;
+    alice.expect("bob joined #dev").await;
+
+    bob.send("/slowmode 5").await;
+    bob.expect("! PERMISSION_DENIED You are not an operator of #dev")
+        .await;
+    alice.send("/slowmode 1").await;
+    bob.expect("*** slow mode set to one message every 1s by alice ***")
+        .await;
+
+    bob.send("first").await;
+    alice.expect("[#dev] bob: first").await;
+    bob.send("too soon").await;
+    bob.expect("! SLOW_MODE Slow mode: wait 1 seconds").await;
+    alice.send("ops").await;
+    alice.send("are exempt").await;
+    bob.expect("[#dev] alice: ops").await;
+    bob.expect("[#dev] alice: are exempt").await;
+
+    sleep(Duration::from_millis(1100)).await;
+    bob.send("after waiting").await;
+    alice.expect("[#dev] bob: after waiting").await;
+
+    alice.send("/slowmode 0").await;
+    bob.expect("*** slow mode turned off by alice ***").await;
+    bob.send("quick").await;
+    bob.send("quicker").await;
+    alice.expect("[#dev] bob: quick").await;
+    alice.expect("[#dev] bob: quicker").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_message_held_back_by_slow_mode_is_not_a_repeat() {
+    let server = TestServer::start(Config {
+        max_repeats: 2,
+        ..Config::default()
+    })
+    .await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    bob.send("/join #dev").await;
+    alice.expect("bob joined #dev").await;
+    alice.send("/slowmode 1").await;
+    bob.expect("slow mode set").await;
+
+    bob.send("again").await;
+    alice.expect("[#dev] bob: again").await;
+    bob.send("again").await;
+    bob.expect("! SLOW_MODE").await;
+    // only the one that went out counts, so this is the second in a row
+    sleep(Duration::from_millis(1100)).await;
+    bob.send("again").await;
+    alice.expect("[#dev] bob: again").await;
+    sleep(Duration::from_millis(1100)).await;
+    bob.send("again").await;
+    bob.expect("! REPEATED Stop repeating yourself").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn slow_mode_is_per_room() {
+    let server = TestServer::start(Config::default()).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    bob.send("/join #dev").await;
+    alice.expect("bob joined #dev").await;
+    alice.send("/slowmode 60").await;
+    bob.expect("slow mode set").await;
+    bob.send("in dev").await;
+    alice.expect("[#dev] bob: in dev").await;
+    bob.send("/switch #general").await;
+    bob.expect("#general").await;
+    bob.send("in general").await;
+    bob.send("still in general").await;
+    alice.expect("[#general] bob: in general").await;
+    alice.expect("[#general] bob: still in general").await;
+    server.shutdown().await;
+}
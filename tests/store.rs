@@ -0,0 +1,85 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{Client, TestServer, WAIT};
+use rustlang_chat_server::{Config, InMemoryStore, MessageStore};
+use tokio::time::{sleep, Instant};
+
+// until the store has count messages for the room, it is fed in the background
+async fn stored(store: &InMemoryStore, room: &str, count: usize) {
+    let deadline = Instant::now() + WAIT;
+    while store.recent(room, count).await.unwrap().len() < count {
+        assert!(Instant::now() < deadline, "the store never caught up");
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+// the room is filled in the background as well, so /export until the message is there
+async fn exported(client: &mut Client, room: &str, text: &str) {
+    let deadline = Instant::now() + WAIT;
+    loop {
+        client.send(&format!("/export {room}")).await;
+        let line = client.line().await.expect("an answer to /export");
+        if line.starts_with("Transcript of") {
+            client.expect(text).await;
+            return;
+        }
+        assert!(line.contains("Nothing to export"), "unexpected {line:?}");
+        assert!(Instant::now() < deadline, "{room} was never filled");
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn a_restarted_server_fills_its_rooms_from_the_store() {
+    let store = Arc::new(InMemoryStore::new(100));
+    let before = TestServer::start(Config::default()).await;
+    before.server.set_store(Box::new(store.clone()));
+    let (mut alice, _) = before.join("alice").await;
+    alice.send("hello from before").await;
+    alice.send("/join #dev").await;
+    alice.expect("you joined #dev").await;
+    alice.send("in dev").await;
+    stored(&store, "#general", 1).await;
+    stored(&store, "#dev", 1).await;
+    before.shutdown().await;
+
+    // #general is open from the start, #dev only once someone joins it
+    let after = TestServer::start(Config::default()).await;
+    after.server.set_store(Box::new(store.clone()));
+    let (mut bob, _) = after.join("bob").await;
+    exported(&mut bob, "#general", "alice: hello from before").await;
+    bob.send("/join #dev").await;
+    bob.expect("you joined #dev").await;
+    exported(&mut bob, "#dev", "alice: in dev").await;
+    after.shutdown().await;
+}
+
+#[tokio::test]
+async fn the_store_file_is_read_back_at_startup() {
+    let path = std::env::temp_dir().join(format!("chat-store-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = Config {
+        store_file: Some(path.clone()),
+        ..Config::default()
+    };
+    let before = TestServer::start(config.clone()).await;
+    let (mut alice, _) = before.join("alice").await;
+    alice.send("written down").await;
+    let deadline = Instant::now() + WAIT;
+    while !std::fs::read_to_string(&path).is_ok_and(|s| s.contains("written down")) {
+        assert!(
+            Instant::now() < deadline,
+            "the message never reached the file"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+    before.shutdown().await;
+
+    let after = TestServer::start(config).await;
+    let (mut bob, _) = after.join("bob").await;
+    exported(&mut bob, "#general", "alice: written down").await;
+    after.shutdown().await;
+    let _ = std::fs::remove_file(&path);
+}
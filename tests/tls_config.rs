@@ -0,0 +1,56 @@
+// the real binary with tls files that won't load: it has to stop with the path in the error
+// rather than start and fail on the first client
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn run(args: &[&str], cert: &Path, key: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rustlang-chat-server"))
+        .args(["--listen", "127.0.0.1:0", "--tls-listen", "127.0.0.1:0"])
+        .arg("--tls-cert")
+        .arg(cert)
+        .arg("--tls-key")
+        .arg(key)
+        .args(args)
+        .output()
+        .expect("run the server")
+}
+
+#[test]
+fn a_missing_cert_stops_the_server_before_it_listens() {
+    let missing = fixture("missing.crt");
+    let output = run(&[], &missing, &fixture("localhost.key"));
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let expected = format!("failed to load TLS cert at {}: ", missing.display());
+    assert!(stderr.contains(&expected), "{stderr}");
+    // nothing was bound, so nothing was announced
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("listening on"));
+}
+
+#[test]
+fn a_key_that_doesnt_fit_stops_the_server() {
+    let other = fixture("other.key");
+    let output = run(&[], &fixture("localhost.crt"), &other);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let expected = format!("failed to load TLS key at {}: ", other.display());
+    assert!(stderr.contains(&expected), "{stderr}");
+}
+
+#[test]
+fn check_config_reports_a_bad_cert() {
+    let missing = fixture("missing.crt");
+    let output = run(&["--check-config"], &missing, &fixture("localhost.key"));
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let expected = format!("problem: failed to load TLS cert at {}", missing.display());
+    assert!(stderr.contains(&expected), "{stderr}");
+}
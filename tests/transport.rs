@@ -0,0 +1,77 @@
+mod common;
+
+use common::{with_tls, TestServer};
+use rustlang_chat_server::Config;
+
+#[tokio::test]
+async fn each_client_is_tagged_with_how_it_connected() {
+    let config = Config {
+        admin_listen: Some("127.0.0.1:0".to_string()),
+        ..with_tls(Config::default())
+    };
+    let server = TestServer::with_admins(config).await;
+    let (mut admin, _) = server.join("admin").await;
+    let mut secure = server.connect_tls().await;
+    secure.register("alice").await;
+    admin.expect("alice joined #general").await;
+
+    admin.send("/whois alice").await;
+    admin
+        .expect("alice (user) connected over tls from 127.0.0.1:")
+        .await;
+    admin.send("/whois admin").await;
+    admin
+        .expect("admin (admin) connected over tcp from 127.0.0.1:")
+        .await;
+
+    let mut queries = server.connect_admin().await;
+    queries.register("root").await;
+    queries.send("LIST CLIENTS").await;
+    let listed = queries.expect("\"clients\"").await;
+    assert!(listed.contains("\"transport\":\"tls\""), "{listed}");
+    assert!(listed.contains("\"transport\":\"tcp\""), "{listed}");
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn tls_clients_chat_with_plain_ones() {
+    let server = TestServer::start(with_tls(Config::default())).await;
+    let (mut plain, _) = server.join("bob").await;
+    let mut secure = server.connect_tls().await;
+    secure.register("alice").await;
+    plain.expect("alice joined #general").await;
+    secure.send("hello over tls").await;
+    plain.expect("[#general] alice: hello over tls").await;
+    plain.send("hello in the clear").await;
+    secure.expect("[#general] bob: hello in the clear").await;
+    // the server ends the session with a close_notify, which the client checks for
+    secure.send("/quit").await;
+    assert_eq!(secure.expect_closed().await.as_deref(), Some("Goodbye!"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn whois_is_for_admins() {
+    let server = TestServer::with_admins(Config::default()).await;
+    let (mut bob, _) = server.join("bob").await;
+    bob.send("/whois bob").await;
+    bob.expect("! PERMISSION_DENIED").await;
+    let (mut admin, _) = server.join("admin").await;
+    admin.send("/whois nobody").await;
+    admin.expect("! NO_SUCH_USER No such user: nobody").await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn a_full_server_turns_tls_clients_away_over_tls() {
+    let config = Config {
+        max_clients: 1,
+        ..with_tls(Config::default())
+    };
+    let server = TestServer::start(config).await;
+    let (_bob, _) = server.join("bob").await;
+    let mut secure = server.connect_tls().await;
+    let last = secure.expect_closed().await;
+    assert_eq!(last.as_deref(), Some("Server full, try again later"));
+    server.shutdown().await;
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{within, TestServer};
+use rustlang_chat_server::Config;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+// one request off a connection to the sink: its host header and body
+async fn request(socket: &mut tokio::net::TcpStream) -> (String, String) {
+    let mut reader = BufReader::new(socket);
+    let (mut host, mut length) = (String::new(), 0);
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Host: ") {
+            host = value.to_string();
+        } else if let Some(value) = line.strip_prefix("Content-Length: ") {
+            length = value.parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.unwrap();
+    (host, String::from_utf8(body).unwrap())
+}
+
+#[tokio::test]
+async fn a_retried_post_carries_the_time_the_message_was_sent() {
+    let sink = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = sink.local_addr().unwrap().port();
+    let server = TestServer::start(Config {
+        webhook: Some(format!("http://127.0.0.1:{port}/hook").parse().unwrap()),
+        ..Config::default()
+    })
+    .await;
+    let mut json = server.connect().await;
+    json.send(r#"{"type":"hello","name":"json"}"#).await;
+    json.expect(r#""type":"welcome""#).await;
+    let (mut alice, _) = server.join("alice").await;
+    alice.send("hi").await;
+    let frame = json.expect(r#""body":"hi""#).await;
+    let ts = frame.split(r#""ts":"#).nth(1).unwrap();
+    let ts = ts.split([',', '}']).next().unwrap();
+
+    // the first attempt gets no answer, so the message goes again a second later
+    let (mut first, _) = within(sink.accept()).await.unwrap();
+    let (_, body) = request(&mut first).await;
+    drop(first);
+    assert!(body.contains(&format!(r#""ts":{ts}"#)), "{body}");
+    let (mut second, _) = within(sink.accept()).await.unwrap();
+    let (host, body) = request(&mut second).await;
+    second.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+    assert_eq!(host, format!("127.0.0.1:{port}"));
+    assert!(body.contains(r#""sender":"alice","body":"hi""#), "{body}");
+    assert!(body.contains(&format!(r#""ts":{ts}"#)), "{body}");
+    server.shutdown().await;
+}
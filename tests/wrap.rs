@@ -0,0 +1,64 @@
+mod common;
+
+use common::TestServer;
+use rustlang_chat_server::Config;
+
+fn wrapped(width: usize) -> Config {
+    Config {
+        wrap_width: width,
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn server_text_is_wrapped_at_the_width() {
+    let server = TestServer::start(wrapped(20)).await;
+    let mut alice = server.connect().await;
+    alice.send("alice").await;
+    assert_eq!(alice.expect("Welcome,").await, "Welcome, alice! Your");
+    assert_eq!(alice.line().await.as_deref(), Some("resume token is"));
+    // the token is longer than the width, so it gets a line to itself, uncut
+    let token = alice.line().await.unwrap();
+    assert_eq!(token.len(), 32, "{token}");
+
+    alice.send("/join #dev").await;
+    assert_eq!(alice.expect("***").await, "*** you joined #dev");
+    assert_eq!(alice.line().await.as_deref(), Some("***"));
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn chat_and_errors_are_left_alone() {
+    let server = TestServer::start(wrapped(20)).await;
+    let (mut alice, _) = server.join("alice").await;
+    let (mut bob, _) = server.join("bob").await;
+
+    let long = "a message from a person, which is nobody's business to wrap";
+    bob.send(long).await;
+    assert_eq!(
+        alice.expect("bob:").await,
+        format!("[#general] bob: {long}")
+    );
+    alice
+        .send("/msg bob this one is also far longer than twenty")
+        .await;
+    bob.expect("[dm] alice: this one is also far longer than twenty")
+        .await;
+    alice.send("/nosuchcommand").await;
+    alice
+        .expect("! UNKNOWN_COMMAND Unknown command: /nosuchcommand")
+        .await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn json_clients_are_never_wrapped() {
+    let server = TestServer::start(wrapped(20)).await;
+    let mut alice = server.connect().await;
+    alice.send(r#"{"type":"hello","name":"alice"}"#).await;
+    alice.expect(r#""type":"welcome""#).await;
+    alice.send("/join #dev").await;
+    let notice = alice.expect(r#""type":"notice""#).await;
+    assert!(notice.contains("*** you joined #dev ***"), "{notice}");
+    server.shutdown().await;
+}